@@ -0,0 +1,750 @@
+use crate::pacing::paginate;
+use crate::{error::DingTalkError, organization::OrgApp};
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate};
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+use tracing::info;
+
+/// The attendance APIs accept at most 50 users per request.
+const MAX_USERS_PER_REQUEST: usize = 50;
+/// The attendance APIs accept a date range of at most 7 days per request.
+const MAX_DAYS_PER_REQUEST: i64 = 7;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CheckType {
+    OnDuty,
+    OffDuty,
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeResult {
+    Normal,
+    Early,
+    Late,
+    SeriousLate,
+    Absenteeism,
+    NotSigned,
+    #[serde(other)]
+    Unknown,
+}
+
+/// A punch result of the attendance API.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AttendanceResult {
+    pub id: i64,
+    #[serde(rename = "userId")]
+    pub user_id: String,
+    #[serde(rename = "checkType")]
+    pub check_type: CheckType,
+    #[serde(rename = "timeResult")]
+    pub time_result: TimeResult,
+    #[serde(rename = "locationResult", default)]
+    pub location_result: String,
+    /// The scheduled punch time as a millisecond timestamp.
+    #[serde(rename = "baseCheckTime")]
+    pub base_check_time: i64,
+    /// The actual punch time as a millisecond timestamp.
+    #[serde(rename = "userCheckTime")]
+    pub user_check_time: i64,
+    /// The work day as a millisecond timestamp.
+    #[serde(rename = "workDate")]
+    pub work_date: i64,
+    #[serde(rename = "sourceType", default)]
+    pub source_type: String,
+    #[serde(rename = "groupId", default)]
+    pub group_id: Option<i64>,
+    #[serde(rename = "planId", default)]
+    pub plan_id: Option<i64>,
+    #[serde(rename = "recordId", default)]
+    pub record_id: Option<i64>,
+}
+
+impl AttendanceResult {
+    /// Returns the work day in China Standard Time, the timezone of the attendance APIs.
+    pub fn work_day(&self) -> Option<NaiveDate> {
+        let offset = FixedOffset::east_opt(8 * 3600)?;
+        DateTime::from_timestamp_millis(self.work_date)
+            .map(|t| t.with_timezone(&offset).date_naive())
+    }
+
+    fn deviation_minutes(&self) -> i64 {
+        (self.user_check_time - self.base_check_time).abs() / 60_000
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AttendancePage {
+    #[serde(rename = "recordresult", default)]
+    pub records: Vec<AttendanceResult>,
+    #[serde(rename = "hasMore", default)]
+    pub has_more: bool,
+}
+
+/// A window of [OrgApp::collect_attendance_results] which could not be retrieved.
+#[derive(Debug)]
+pub struct ChunkFailure {
+    pub user_ids: Vec<String>,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub error: DingTalkError,
+}
+
+/// The merged results of [OrgApp::collect_attendance_results].
+#[derive(Debug, Default)]
+pub struct AttendanceReport {
+    pub records: Vec<AttendanceResult>,
+    pub failures: Vec<ChunkFailure>,
+}
+
+impl AttendanceReport {
+    /// Returns whether every window was retrieved.
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum AnomalyKind {
+    /// A scheduled punch was not recorded on a day with other punches.
+    MissingPunch { check_type: CheckType },
+    /// The on-duty punch was late by the given number of minutes.
+    Late { minutes: i64, serious: bool },
+    /// The off-duty punch was early by the given number of minutes.
+    EarlyLeave { minutes: i64 },
+    /// No scheduled punch of the day was recorded.
+    Absent,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Anomaly {
+    pub user_id: String,
+    pub work_date: NaiveDate,
+    pub kind: AnomalyKind,
+}
+
+impl OrgApp {
+    /// Retrieves one page of attendance results.
+    ///
+    /// [获取打卡结果](https://open.dingtalk.com/document/orgapp/open-attendance-clock-in-data)
+    ///
+    /// # Arguments
+    ///
+    /// * `user_ids` - Up to 50 user IDs.
+    /// * `from` - The first work day, at most 7 days before `to`.
+    /// * `to` - The last work day.
+    /// * `offset` - The offset of the page.
+    /// * `limit` - The size of the page, at most 50.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing an `AttendancePage` with the results and whether more pages are
+    /// available.
+    pub async fn list_attendance_results(
+        &self,
+        user_ids: &[String],
+        from: NaiveDate,
+        to: NaiveDate,
+        offset: i64,
+        limit: i64,
+    ) -> Result<AttendancePage, DingTalkError> {
+        let params = serde_json::json!({
+            "workDateFrom": format_work_date(from),
+            "workDateTo": format_work_date(to),
+            "userIdList": user_ids,
+            "offset": offset,
+            "limit": limit,
+        });
+
+        self.post_oapi_body("attendance/list", &params).await
+    }
+
+    /// Retrieves all attendance results of the users within the range.
+    ///
+    /// The request is split into windows of 50 users and 7 days, each paged through completely.
+    /// A failing window does not stop the others; its users and days are reported in
+    /// [AttendanceReport::failures] instead.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_ids` - Any number of user IDs.
+    /// * `range` - The work days, of any length.
+    ///
+    /// # Returns
+    ///
+    /// An `AttendanceReport` with the merged results and the failed windows.
+    pub async fn collect_attendance_results(
+        &self,
+        user_ids: &[String],
+        range: RangeInclusive<NaiveDate>,
+    ) -> AttendanceReport {
+        let mut report = AttendanceReport::default();
+
+        for users in user_ids.chunks(MAX_USERS_PER_REQUEST) {
+            let mut from = *range.start();
+            while from <= *range.end() {
+                let to = (from + Duration::days(MAX_DAYS_PER_REQUEST - 1)).min(*range.end());
+
+                match self.collect_attendance_window(users, from, to).await {
+                    Ok(records) => report.records.extend(records),
+                    Err(error) => report.failures.push(ChunkFailure {
+                        user_ids: users.to_vec(),
+                        from,
+                        to,
+                        error,
+                    }),
+                }
+
+                from = to + Duration::days(1);
+            }
+        }
+
+        report
+    }
+
+    async fn collect_attendance_window(
+        &self,
+        user_ids: &[String],
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<AttendanceResult>, DingTalkError> {
+        paginate(self.pace, 0, |offset| async move {
+            let page = self
+                .list_attendance_results(user_ids, from, to, offset, 50)
+                .await?;
+            let fetched = page.records.len() as i64;
+            let next = (page.has_more && fetched > 0).then(|| offset + fetched);
+            Ok((page.records, next))
+        })
+        .try_collect()
+        .await
+    }
+
+    /// Finds missing punches, late arrivals, early leaves and absent days.
+    ///
+    /// The anomalies are derived from the attendance results of DingTalk, so only days where the
+    /// users were scheduled to work are taken into account.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_ids` - The users to check.
+    /// * `range` - The work days to check.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the anomalies ordered by user and work day.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the first failed window, as anomalies of partial data would be
+    /// misleading.
+    pub async fn find_anomalies(
+        &self,
+        user_ids: &[String],
+        range: RangeInclusive<NaiveDate>,
+    ) -> Result<Vec<Anomaly>, DingTalkError> {
+        let report = self.collect_attendance_results(user_ids, range).await;
+        if let Some(failure) = report.failures.into_iter().next() {
+            return Err(failure.error);
+        }
+        let results = report.records;
+        info!("find_anomalies: {} attendance results", results.len());
+
+        let mut days: BTreeMap<(String, NaiveDate), Vec<AttendanceResult>> = BTreeMap::new();
+        for result in results {
+            if let Some(day) = result.work_day() {
+                days.entry((result.user_id.clone(), day))
+                    .or_default()
+                    .push(result);
+            }
+        }
+
+        let mut anomalies = Vec::new();
+        for ((user_id, work_date), mut punches) in days {
+            punches.sort_by_key(|punch| (punch.base_check_time, punch.check_type));
+
+            if punches
+                .iter()
+                .all(|punch| punch.time_result == TimeResult::NotSigned)
+            {
+                anomalies.push(Anomaly {
+                    user_id,
+                    work_date,
+                    kind: AnomalyKind::Absent,
+                });
+                continue;
+            }
+
+            for punch in punches {
+                let kind = match punch.time_result {
+                    TimeResult::NotSigned => AnomalyKind::MissingPunch {
+                        check_type: punch.check_type,
+                    },
+                    TimeResult::Late => AnomalyKind::Late {
+                        minutes: punch.deviation_minutes(),
+                        serious: false,
+                    },
+                    TimeResult::SeriousLate | TimeResult::Absenteeism => AnomalyKind::Late {
+                        minutes: punch.deviation_minutes(),
+                        serious: true,
+                    },
+                    TimeResult::Early => AnomalyKind::EarlyLeave {
+                        minutes: punch.deviation_minutes(),
+                    },
+                    TimeResult::Normal | TimeResult::Unknown => continue,
+                };
+                anomalies.push(Anomaly {
+                    user_id: user_id.clone(),
+                    work_date,
+                    kind,
+                });
+            }
+        }
+
+        Ok(anomalies)
+    }
+}
+
+/// An attendance statistics column such as 工时 or 迟到分钟.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AttendanceColumn {
+    pub id: i64,
+    pub name: String,
+    #[serde(default)]
+    pub alias: String,
+    #[serde(rename = "type", default)]
+    pub column_type: i32,
+    #[serde(default)]
+    pub status: i32,
+}
+
+/// The value of a column on a single day.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ColumnValue {
+    /// The day, formatted as `yyyy-MM-dd HH:mm:ss`.
+    pub date: String,
+    pub value: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ColumnValues {
+    pub column_id: i64,
+    pub values: Vec<ColumnValue>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LeaveTimes {
+    /// The name of the leave type, e.g. 年假.
+    pub name: String,
+    pub values: Vec<ColumnValue>,
+}
+
+fn format_work_date(date: NaiveDate) -> String {
+    format!("{} 00:00:00", date.format("%Y-%m-%d"))
+}
+
+impl OrgApp {
+    /// Retrieves the attendance statistics columns of the organization.
+    ///
+    /// [获取考勤报表列定义](https://open.dingtalk.com/document/orgapp/queries-the-enterprise-attendance-report-column)
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the columns, whose IDs can be passed to
+    /// [OrgApp::get_attendance_column_values].
+    pub async fn list_attendance_columns(&self) -> Result<Vec<AttendanceColumn>, DingTalkError> {
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Response {
+            columns: Vec<AttendanceColumn>,
+        }
+
+        let res: Response = self
+            .post_oapi("topapi/attendance/getattcolumns", &serde_json::json!({}))
+            .await?;
+
+        Ok(res.columns)
+    }
+
+    /// Retrieves the computed values of attendance statistics columns for a user.
+    ///
+    /// [获取考勤报表列值](https://open.dingtalk.com/document/orgapp/queries-the-column-value-of-the-attendance-report)
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID of the employee.
+    /// * `column_ids` - The IDs of the columns to retrieve.
+    /// * `from` - The first day, at most 31 days before `to`.
+    /// * `to` - The last day.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the daily values of every requested column.
+    pub async fn get_attendance_column_values(
+        &self,
+        user_id: String,
+        column_ids: &[i64],
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<ColumnValues>, DingTalkError> {
+        let params = serde_json::json!({
+            "userid": user_id,
+            "column_id_list": column_ids
+                .iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<String>>()
+                .join(","),
+            "from_date": format_work_date(from),
+            "to_date": format_work_date(to),
+        });
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct ColumnVo {
+            id: i64,
+        }
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Column {
+            column_vo: ColumnVo,
+            #[serde(default)]
+            column_vals: Vec<ColumnValue>,
+        }
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Response {
+            #[serde(default)]
+            column_vals: Vec<Column>,
+        }
+
+        let res: Response = self
+            .post_oapi("topapi/attendance/getcolumnval", &params)
+            .await?;
+
+        Ok(res
+            .column_vals
+            .into_iter()
+            .map(|column| ColumnValues {
+                column_id: column.column_vo.id,
+                values: column.column_vals,
+            })
+            .collect())
+    }
+
+    /// Retrieves the leave durations of a user by leave type.
+    ///
+    /// [获取假期时长](https://open.dingtalk.com/document/orgapp/obtains-the-holiday-data-from-the-attendance-report)
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID of the employee.
+    /// * `leave_names` - The names of the leave types, e.g. 年假 or 事假.
+    /// * `from` - The first day, at most 31 days before `to`.
+    /// * `to` - The last day.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the daily leave durations of every requested leave type.
+    pub async fn get_leave_time_by_names(
+        &self,
+        user_id: String,
+        leave_names: &[String],
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<LeaveTimes>, DingTalkError> {
+        let params = serde_json::json!({
+            "userid": user_id,
+            "leave_names": leave_names.join(","),
+            "from_date": format_work_date(from),
+            "to_date": format_work_date(to),
+        });
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct ColumnVo {
+            name: String,
+        }
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Column {
+            columnvo: ColumnVo,
+            #[serde(default)]
+            columnvals: Vec<ColumnValue>,
+        }
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Response {
+            #[serde(default)]
+            columns: Vec<Column>,
+        }
+
+        let res: Response = self
+            .post_oapi("topapi/attendance/getleavetimebynames", &params)
+            .await?;
+
+        Ok(res
+            .columns
+            .into_iter()
+            .map(|column| LeaveTimes {
+                name: column.columnvo.name,
+                values: column.columnvals,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::Mock;
+    use serde_json::{json, Value};
+
+    fn users(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("user{}", i)).collect()
+    }
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 7, day).unwrap()
+    }
+
+    /// Returns the users, first and last day of the requested windows.
+    fn windows(bodies: &[Value]) -> Vec<(usize, String, String)> {
+        bodies
+            .iter()
+            .map(|body| {
+                (
+                    body["userIdList"].as_array().unwrap().len(),
+                    body["workDateFrom"].as_str().unwrap().to_string(),
+                    body["workDateTo"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect()
+    }
+
+    fn window(users: usize, from: &str, to: &str) -> (usize, String, String) {
+        (
+            users,
+            format!("2024-07-{} 00:00:00", from),
+            format!("2024-07-{} 00:00:00", to),
+        )
+    }
+
+    #[tokio::test]
+    async fn fifty_users_and_seven_days_are_one_window() {
+        let mock = Mock::new();
+        let app = mock.app().await;
+
+        let report = app
+            .collect_attendance_results(&users(50), date(1)..=date(7))
+            .await;
+
+        assert!(report.failures.is_empty());
+        assert_eq!(
+            windows(&mock.bodies("attendance/list")),
+            vec![window(50, "01", "07")]
+        );
+    }
+
+    #[tokio::test]
+    async fn larger_requests_are_split_at_the_limits() {
+        let mock = Mock::new();
+        let app = mock.app().await;
+
+        let report = app
+            .collect_attendance_results(&users(51), date(1)..=date(8))
+            .await;
+
+        assert!(report.failures.is_empty());
+        assert_eq!(
+            windows(&mock.bodies("attendance/list")),
+            vec![
+                window(50, "01", "07"),
+                window(50, "08", "08"),
+                window(1, "01", "07"),
+                window(1, "08", "08"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_windows_do_not_stop_the_others() {
+        let mock = Mock::new();
+        mock.respond(
+            "attendance/list",
+            json!({ "errcode": 0, "recordresult": [], "hasMore": false }),
+        );
+        mock.respond(
+            "attendance/list",
+            json!({ "errcode": 88, "errmsg": "系统错误" }),
+        );
+        mock.respond(
+            "attendance/list",
+            json!({ "errcode": 0, "recordresult": [], "hasMore": false }),
+        );
+        let app = mock.app().await;
+
+        let report = app
+            .collect_attendance_results(&users(1), date(1)..=date(21))
+            .await;
+
+        assert_eq!(mock.bodies("attendance/list").len(), 3);
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].user_ids, users(1));
+        assert_eq!(
+            (report.failures[0].from, report.failures[0].to),
+            (date(8), date(14))
+        );
+    }
+
+    /// Returns a China Standard Time instant of July 2024 as a millisecond timestamp.
+    fn china_millis(day: u32, hour: u32, minute: u32) -> i64 {
+        use chrono::TimeZone;
+        FixedOffset::east_opt(8 * 3600)
+            .unwrap()
+            .with_ymd_and_hms(2024, 7, day, hour, minute, 0)
+            .unwrap()
+            .timestamp_millis()
+    }
+
+    /// A punch of `user_id` on the given day, scheduled at `base` and done at `actual`
+    /// (hour, minute).
+    fn punch(
+        user_id: &str,
+        day: u32,
+        check_type: &str,
+        time_result: &str,
+        base: (u32, u32),
+        actual: (u32, u32),
+    ) -> Value {
+        json!({
+            "id": 1,
+            "userId": user_id,
+            "checkType": check_type,
+            "timeResult": time_result,
+            "baseCheckTime": china_millis(day, base.0, base.1),
+            "userCheckTime": china_millis(day, actual.0, actual.1),
+            "workDate": china_millis(day, 0, 0),
+        })
+    }
+
+    async fn anomalies(punches: Vec<Value>) -> Vec<Anomaly> {
+        let mock = Mock::new();
+        mock.respond(
+            "attendance/list",
+            json!({ "errcode": 0, "recordresult": punches, "hasMore": false }),
+        );
+        let app = mock.app().await;
+
+        app.find_anomalies(&users(1), date(1)..=date(7))
+            .await
+            .unwrap()
+    }
+
+    fn anomaly(user_id: &str, day: u32, kind: AnomalyKind) -> Anomaly {
+        Anomaly {
+            user_id: user_id.to_string(),
+            work_date: date(day),
+            kind,
+        }
+    }
+
+    #[tokio::test]
+    async fn days_without_any_punch_are_absent() {
+        let found = anomalies(vec![
+            punch("zhangsan", 1, "OnDuty", "NotSigned", (9, 0), (9, 0)),
+            punch("zhangsan", 1, "OffDuty", "NotSigned", (18, 0), (18, 0)),
+        ])
+        .await;
+
+        assert_eq!(found, vec![anomaly("zhangsan", 1, AnomalyKind::Absent)]);
+    }
+
+    #[tokio::test]
+    async fn single_missing_punches_are_reported() {
+        let found = anomalies(vec![
+            punch("zhangsan", 1, "OnDuty", "Normal", (9, 0), (8, 55)),
+            punch("zhangsan", 1, "OffDuty", "NotSigned", (18, 0), (18, 0)),
+        ])
+        .await;
+
+        assert_eq!(
+            found,
+            vec![anomaly(
+                "zhangsan",
+                1,
+                AnomalyKind::MissingPunch {
+                    check_type: CheckType::OffDuty
+                }
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn serious_lateness_and_absenteeism_are_serious() {
+        let found = anomalies(vec![
+            punch("lisi", 1, "OnDuty", "Late", (9, 0), (9, 5)),
+            punch("lisi", 2, "OnDuty", "SeriousLate", (9, 0), (9, 40)),
+            punch("lisi", 3, "OnDuty", "Absenteeism", (9, 0), (11, 0)),
+        ])
+        .await;
+
+        assert_eq!(
+            found,
+            vec![
+                anomaly(
+                    "lisi",
+                    1,
+                    AnomalyKind::Late {
+                        minutes: 5,
+                        serious: false
+                    }
+                ),
+                anomaly(
+                    "lisi",
+                    2,
+                    AnomalyKind::Late {
+                        minutes: 40,
+                        serious: true
+                    }
+                ),
+                anomaly(
+                    "lisi",
+                    3,
+                    AnomalyKind::Late {
+                        minutes: 120,
+                        serious: true
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn early_leaves_count_the_minutes_before_the_schedule() {
+        let found = anomalies(vec![
+            punch("wangwu", 1, "OnDuty", "Normal", (9, 0), (9, 0)),
+            punch("wangwu", 1, "OffDuty", "Early", (18, 0), (17, 30)),
+        ])
+        .await;
+
+        assert_eq!(
+            found,
+            vec![anomaly(
+                "wangwu",
+                1,
+                AnomalyKind::EarlyLeave { minutes: 30 }
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn punches_are_grouped_by_china_work_day() {
+        // The work day of July 2 starts at 16:00 UTC on July 1, which must not be merged with
+        // the punches of July 1.
+        let found = anomalies(vec![
+            punch("zhaoliu", 1, "OnDuty", "Normal", (9, 0), (9, 0)),
+            punch("zhaoliu", 1, "OffDuty", "Normal", (18, 0), (18, 0)),
+            punch("zhaoliu", 2, "OnDuty", "NotSigned", (9, 0), (9, 0)),
+            punch("zhaoliu", 2, "OffDuty", "NotSigned", (18, 0), (18, 0)),
+        ])
+        .await;
+
+        assert_eq!(found, vec![anomaly("zhaoliu", 2, AnomalyKind::Absent)]);
+    }
+}
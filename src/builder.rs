@@ -0,0 +1,370 @@
+#[cfg(not(feature = "redis-cache"))]
+use crate::store::MemoryStore;
+#[cfg(feature = "redis-cache")]
+use crate::store::RedisStore;
+use crate::{
+    cache::ProfileCache,
+    config::{Endpoints, LogDetail, TokenConfig},
+    error::DingTalkError,
+    store::TokenStore,
+    DingTalk,
+};
+#[cfg(feature = "redis-cache")]
+use deadpool_redis::{Config, Runtime};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+#[cfg(feature = "redis-cache")]
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The Redis server used when neither [DingTalkBuilder::redis_url] nor `REDIS_URL` is set.
+#[cfg(feature = "redis-cache")]
+const DEFAULT_REDIS_URL: &str = "redis://:@127.0.0.1:6379/1";
+
+/// The product token of the crate, appended to every `User-Agent` set with
+/// [DingTalkBuilder::client_info].
+const CRATE_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+/// The header identifying the integrating service, see [DingTalkBuilder::client_info].
+const CLIENT_INFO_HEADER: &str = "x-client-info";
+
+/// The language of names and titles returned by DingTalk unless configured otherwise.
+pub(crate) const DEFAULT_LANGUAGE: &str = "zh_CN";
+
+/// Configures and creates a [DingTalk] instance.
+///
+/// Tokens are cached in Redis with the feature `redis-cache` (default), otherwise in a
+/// [MemoryStore](crate::MemoryStore), unless another store is given.
+///
+/// ```no_run
+/// use async_dingtalk::DingTalk;
+/// use std::time::Duration;
+///
+/// let dt = DingTalk::builder()
+///     .credentials("appid", "app_secret")
+///     .timeout(Duration::from_secs(10))
+///     .key_prefix("myapp:")
+///     .language("en_US")
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Default)]
+pub struct DingTalkBuilder {
+    appid: Option<String>,
+    app_secret: Option<String>,
+    #[cfg(feature = "redis-cache")]
+    redis: Option<RedisTarget>,
+    store: Option<Arc<dyn TokenStore>>,
+    client: Option<reqwest::Client>,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    user_agent: Option<String>,
+    client_info: Option<String>,
+    headers: Vec<(String, String)>,
+    key_prefix: Option<String>,
+    language: Option<String>,
+    token_config: TokenConfig,
+    endpoints: Endpoints,
+    profile_cache: Option<Arc<ProfileCache>>,
+    log_detail: LogDetail,
+}
+
+impl DingTalk {
+    /// Returns a builder for configuring a `DingTalk` instance.
+    pub fn builder() -> DingTalkBuilder {
+        DingTalkBuilder::default()
+    }
+}
+
+impl DingTalkBuilder {
+    /// Sets the app ID and app secret issued by DingTalk.
+    pub fn credentials(mut self, appid: impl Into<String>, app_secret: impl Into<String>) -> Self {
+        self.appid = Some(appid.into());
+        self.app_secret = Some(app_secret.into());
+        self
+    }
+
+    /// Sets the Redis server caching the tokens, overriding the `REDIS_URL` environment variable.
+    ///
+    /// `rediss://` URLs connect with TLS and need the feature `redis-tls`.
+    #[cfg(feature = "redis-cache")]
+    pub fn redis_url(mut self, redis_url: impl Into<String>) -> Self {
+        self.redis = Some(RedisTarget::Url(redis_url.into()));
+        self
+    }
+
+    /// Caches the tokens in a Redis Cluster reachable through the given node URLs.
+    #[cfg(feature = "redis-cluster")]
+    pub fn redis_cluster(mut self, urls: Vec<String>) -> Self {
+        self.redis = Some(RedisTarget::Cluster(urls));
+        self
+    }
+
+    /// Caches the tokens in the Redis master `master_name`, as found by the given sentinels.
+    #[cfg(feature = "redis-sentinel")]
+    pub fn redis_sentinel(mut self, urls: Vec<String>, master_name: impl Into<String>) -> Self {
+        self.redis = Some(RedisTarget::Sentinel {
+            urls,
+            master_name: master_name.into(),
+        });
+        self
+    }
+
+    /// Caches tokens in the given store instead of the default one.
+    pub fn store(mut self, store: Arc<dyn TokenStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Sends requests with the given client, e.g. one configured with a proxy or custom TLS.
+    ///
+    /// The client is used as is, so [DingTalkBuilder::timeout], [DingTalkBuilder::connect_timeout],
+    /// [DingTalkBuilder::user_agent], [DingTalkBuilder::client_info] and
+    /// [DingTalkBuilder::header] have no effect.
+    pub fn client(mut self, client: reqwest::Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Sets the total timeout of each HTTP request.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the timeout for connecting to DingTalk.
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = Some(connect_timeout);
+        self
+    }
+
+    /// Sets the `User-Agent` header of all requests, `async-dingtalk/{version}` by default.
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Identifies the service sending the requests, e.g. for gateway logs or DingTalk support:
+    /// sends `X-Client-Info: {name}/{version}` and, unless [DingTalkBuilder::user_agent] is set,
+    /// the `User-Agent` `{name}/{version} async-dingtalk/{crate version}`.
+    pub fn client_info(mut self, name: impl Into<String>, version: impl Into<String>) -> Self {
+        self.client_info = Some(format!("{}/{}", name.into(), version.into()));
+        self
+    }
+
+    /// Adds a header to all requests, e.g. a tracing or tenant header of a gateway.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Sets the prefix of the token store keys, `dingtalk:token:` by default.
+    pub fn key_prefix(mut self, key_prefix: impl Into<String>) -> Self {
+        self.key_prefix = Some(key_prefix.into());
+        self
+    }
+
+    /// Sets the language of returned names and titles, `zh_CN` (default) or `en_US`.
+    pub fn language(mut self, language: impl Into<String>) -> Self {
+        self.language = Some(language.into());
+        self
+    }
+
+    /// Sets how tokens are cached.
+    pub fn token_config(mut self, token_config: TokenConfig) -> Self {
+        self.token_config = token_config;
+        self
+    }
+
+    /// Sets how much of the read user data is logged, only IDs by default.
+    pub fn log_detail(mut self, log_detail: LogDetail) -> Self {
+        self.log_detail = log_detail;
+        self
+    }
+
+    /// Sends requests to the given endpoints, e.g. a mock server or a private cloud gateway.
+    pub fn endpoints(mut self, endpoints: Endpoints) -> Self {
+        self.endpoints = endpoints;
+        self
+    }
+
+    /// Caches read profiles in the given cache.
+    pub fn profile_cache(mut self, profile_cache: Arc<ProfileCache>) -> Self {
+        self.profile_cache = Some(profile_cache);
+        self
+    }
+
+    /// Creates the configured `DingTalk` instance.
+    ///
+    /// # Errors
+    ///
+    /// Returns [DingTalkError::Config] if the credentials are missing, the Redis URL, an endpoint
+    /// or a header is invalid, or the HTTP client cannot be created.
+    pub fn build(self) -> Result<DingTalk, DingTalkError> {
+        let appid = self
+            .appid
+            .ok_or_else(|| DingTalkError::Config("Missing app ID".to_string()))?;
+        let app_secret = self
+            .app_secret
+            .ok_or_else(|| DingTalkError::Config("Missing app secret".to_string()))?;
+        self.endpoints.validate()?;
+
+        let store: Arc<dyn TokenStore> = match self.store {
+            Some(store) => store,
+            #[cfg(feature = "redis-cache")]
+            None => {
+                let target = self.redis.unwrap_or_else(|| {
+                    RedisTarget::Url(
+                        env::var("REDIS_URL").unwrap_or_else(|_| DEFAULT_REDIS_URL.to_string()),
+                    )
+                });
+                Arc::new(target.connect()?)
+            }
+            #[cfg(not(feature = "redis-cache"))]
+            None => Arc::new(MemoryStore::new()),
+        };
+
+        let client = match self.client {
+            Some(client) => client,
+            None => {
+                let mut client = reqwest::Client::builder();
+                if let Some(timeout) = self.timeout {
+                    client = client.timeout(timeout);
+                }
+                if let Some(connect_timeout) = self.connect_timeout {
+                    client = client.connect_timeout(connect_timeout);
+                }
+                let user_agent = match (self.user_agent, &self.client_info) {
+                    (Some(user_agent), _) => user_agent,
+                    (None, Some(info)) => format!("{} {}", info, CRATE_USER_AGENT),
+                    (None, None) => CRATE_USER_AGENT.to_string(),
+                };
+                client = client
+                    .user_agent(user_agent)
+                    .default_headers(default_headers(self.client_info, self.headers)?);
+                client.build().map_err(|e| {
+                    DingTalkError::Config(format!("Failed to create HTTP client: {}", e))
+                })?
+            }
+        };
+
+        let mut dt = DingTalk::with_store(appid, app_secret, store);
+        dt.client = client;
+        if let Some(key_prefix) = self.key_prefix {
+            dt.key_prefix = key_prefix;
+        }
+        dt.language = self
+            .language
+            .unwrap_or_else(|| DEFAULT_LANGUAGE.to_string());
+        dt.token_config = self.token_config;
+        dt.endpoints = self.endpoints;
+        dt.profile_cache = self.profile_cache;
+        dt.log_detail = self.log_detail;
+
+        Ok(dt)
+    }
+}
+
+fn default_headers(
+    client_info: Option<String>,
+    headers: Vec<(String, String)>,
+) -> Result<HeaderMap, DingTalkError> {
+    let client_info = client_info.map(|info| (CLIENT_INFO_HEADER.to_string(), info));
+
+    let mut map = HeaderMap::new();
+    for (name, value) in client_info.into_iter().chain(headers) {
+        let invalid = |e: &dyn std::fmt::Display| {
+            DingTalkError::Config(format!("Invalid header {}: {}", name, e))
+        };
+        let header = HeaderName::from_bytes(name.as_bytes()).map_err(|e| invalid(&e))?;
+        let value = HeaderValue::from_str(&value).map_err(|e| invalid(&e))?;
+        map.insert(header, value);
+    }
+    Ok(map)
+}
+
+/// Where [DingTalkBuilder] connects to Redis.
+#[cfg(feature = "redis-cache")]
+enum RedisTarget {
+    Url(String),
+    #[cfg(feature = "redis-cluster")]
+    Cluster(Vec<String>),
+    #[cfg(feature = "redis-sentinel")]
+    Sentinel {
+        urls: Vec<String>,
+        master_name: String,
+    },
+}
+
+#[cfg(feature = "redis-cache")]
+impl RedisTarget {
+    fn connect(self) -> Result<RedisStore, DingTalkError> {
+        let pool_error = |e: deadpool_redis::CreatePoolError| {
+            DingTalkError::Config(format!("Failed to create Redis pool: {}", e))
+        };
+
+        match self {
+            RedisTarget::Url(url) => Config::from_url(url)
+                .create_pool(Some(Runtime::Tokio1))
+                .map(RedisStore::new)
+                .map_err(pool_error),
+            #[cfg(feature = "redis-cluster")]
+            RedisTarget::Cluster(urls) => deadpool_redis::cluster::Config::from_urls(urls)
+                .create_pool(Some(Runtime::Tokio1))
+                .map(RedisStore::cluster)
+                .map_err(pool_error),
+            #[cfg(feature = "redis-sentinel")]
+            RedisTarget::Sentinel { urls, master_name } => {
+                deadpool_redis::sentinel::Config::from_urls(
+                    urls,
+                    master_name,
+                    deadpool_redis::sentinel::SentinelServerType::Master,
+                )
+                .create_pool(Some(Runtime::Tokio1))
+                .map(RedisStore::sentinel)
+                .map_err(pool_error)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryStore;
+
+    #[test]
+    fn client_info_is_sent_as_a_header() {
+        let headers = default_headers(
+            Some("hr-sync/1.2.0".to_string()),
+            vec![("x-tenant".to_string(), "acme".to_string())],
+        )
+        .unwrap();
+        assert_eq!(headers[CLIENT_INFO_HEADER], "hr-sync/1.2.0");
+        assert_eq!(headers["x-tenant"], "acme");
+
+        let invalid = default_headers(None, vec![("bad header".to_string(), "x".to_string())]);
+        assert!(matches!(invalid, Err(DingTalkError::Config(_))));
+    }
+
+    #[test]
+    fn endpoints_must_be_urls() {
+        let builder = || {
+            DingTalk::builder()
+                .credentials("app".to_string(), "secret".to_string())
+                .store(Arc::new(MemoryStore::new()))
+        };
+        assert!(builder()
+            .endpoints(Endpoints::with_base("http://localhost:8080"))
+            .build()
+            .is_ok());
+
+        let endpoints = Endpoints {
+            login: "not a url".to_string(),
+            ..Endpoints::default()
+        };
+        match builder().endpoints(endpoints).build() {
+            Err(DingTalkError::Config(message)) => assert!(message.contains("login")),
+            other => panic!("expected a config error, got {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use crate::{error::DingTalkError, store::TokenStore};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::warn;
+
+/// The kinds of objects the [ProfileCache] can hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProfileKind {
+    /// Employee profiles returned by [crate::OrgApp::get_employee_userinfo].
+    Employee,
+    /// Contact profiles returned by [DingTalk::get_contact_userinfo](crate::DingTalk::get_contact_userinfo).
+    Contact,
+    /// Department details.
+    Department,
+    /// The job number index of [crate::OrgApp::get_job_number_index], one entry per
+    /// organization with the ID `all`.
+    JobNumbers,
+}
+
+impl ProfileKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ProfileKind::Employee => "employee",
+            ProfileKind::Contact => "contact",
+            ProfileKind::Department => "department",
+            ProfileKind::JobNumbers => "job_numbers",
+        }
+    }
+}
+
+/// An opt-in cache for objects which change rarely but are read constantly.
+///
+/// Entries are kept in a [TokenStore] with a TTL per [ProfileKind]. A kind without TTL is not
+/// cached. Cache failures are logged and fall back to calling DingTalk.
+pub struct ProfileCache {
+    store: Arc<dyn TokenStore>,
+    ttls: RwLock<HashMap<ProfileKind, Duration>>,
+}
+
+impl ProfileCache {
+    /// Creates a cache keeping employees and contacts for 10 minutes, and departments and the job
+    /// number index for 30.
+    pub fn new(store: Arc<dyn TokenStore>) -> Self {
+        let mut ttls = HashMap::new();
+        ttls.insert(ProfileKind::Employee, Duration::from_secs(600));
+        ttls.insert(ProfileKind::Contact, Duration::from_secs(600));
+        ttls.insert(ProfileKind::Department, Duration::from_secs(1800));
+        ttls.insert(ProfileKind::JobNumbers, Duration::from_secs(1800));
+
+        ProfileCache {
+            store,
+            ttls: RwLock::new(ttls),
+        }
+    }
+
+    /// Sets the TTL of a kind, or stops caching it with `None`.
+    pub fn with_ttl(self, kind: ProfileKind, ttl: Option<Duration>) -> Self {
+        self.set_ttl(kind, ttl);
+        self
+    }
+
+    pub fn set_ttl(&self, kind: ProfileKind, ttl: Option<Duration>) {
+        let mut ttls = self.ttls.write().unwrap();
+        match ttl {
+            Some(ttl) => ttls.insert(kind, ttl),
+            None => ttls.remove(&kind),
+        };
+    }
+
+    pub fn ttl(&self, kind: ProfileKind) -> Option<Duration> {
+        self.ttls.read().unwrap().get(&kind).copied()
+    }
+
+    /// Returns the store key of an entry, below the key prefix of the owning `DingTalk` or
+    /// `OrgApp` like its tokens.
+    fn key(prefix: &str, kind: ProfileKind, scope: &str, id: &str) -> String {
+        format!("{}profile:{}:{}:{}", prefix, kind.as_str(), scope, id)
+    }
+
+    pub(crate) async fn get<T: DeserializeOwned>(
+        &self,
+        prefix: &str,
+        kind: ProfileKind,
+        scope: &str,
+        id: &str,
+    ) -> Option<T> {
+        self.ttl(kind)?;
+
+        match self
+            .store
+            .get(&ProfileCache::key(prefix, kind, scope, id))
+            .await
+        {
+            Ok(value) => value.and_then(|value| serde_json::from_str(&value).ok()),
+            Err(e) => {
+                warn!("profile cache get {}: {}", kind.as_str(), e);
+                None
+            }
+        }
+    }
+
+    pub(crate) async fn set<T: Serialize>(
+        &self,
+        prefix: &str,
+        kind: ProfileKind,
+        scope: &str,
+        id: &str,
+        value: &T,
+    ) {
+        let ttl = match self.ttl(kind) {
+            Some(ttl) => ttl,
+            None => return,
+        };
+        let value = match serde_json::to_string(value) {
+            Ok(value) => value,
+            Err(_) => return,
+        };
+
+        if let Err(e) = self
+            .store
+            .set(
+                &ProfileCache::key(prefix, kind, scope, id),
+                &value,
+                Some(ttl),
+            )
+            .await
+        {
+            warn!("profile cache set {}: {}", kind.as_str(), e);
+        }
+    }
+
+    /// Removes an entry, e.g. after the object was changed.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - The key prefix of the owning `DingTalk` or `OrgApp`, see
+    ///   [DingTalkBuilder::key_prefix](crate::DingTalkBuilder::key_prefix).
+    /// * `kind` - The kind of the object.
+    /// * `scope` - The corp ID for employees and departments, the app ID for contacts.
+    /// * `id` - The user ID, union ID or department ID of the object.
+    pub async fn invalidate(
+        &self,
+        prefix: &str,
+        kind: ProfileKind,
+        scope: &str,
+        id: &str,
+    ) -> Result<(), DingTalkError> {
+        self.store
+            .delete(&ProfileCache::key(prefix, kind, scope, id))
+            .await
+            .map_err(DingTalkError::Cache)
+    }
+
+    /// Removes an entry after a successful write, logging failures instead of returning them so
+    /// that the write is not reported as failed.
+    pub(crate) async fn discard(&self, prefix: &str, kind: ProfileKind, scope: &str, id: &str) {
+        if let Err(e) = self.invalidate(prefix, kind, scope, id).await {
+            warn!("profile cache invalidate {}: {}", kind.as_str(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryStore;
+
+    #[tokio::test]
+    async fn entries_are_kept_below_the_owner_prefix() {
+        let store = Arc::new(MemoryStore::new());
+        let cache = ProfileCache::new(store.clone());
+
+        cache
+            .set("acme:", ProfileKind::Employee, "corp", "zhangsan", &"张三")
+            .await;
+
+        let key = "acme:profile:employee:corp:zhangsan";
+        assert_eq!(store.get(key).await.unwrap().as_deref(), Some("\"张三\""));
+        let cached: Option<String> = cache
+            .get("other:", ProfileKind::Employee, "corp", "zhangsan")
+            .await;
+        assert_eq!(cached, None);
+
+        cache
+            .invalidate("acme:", ProfileKind::Employee, "corp", "zhangsan")
+            .await
+            .unwrap();
+        assert_eq!(store.get(key).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,324 @@
+//! The HTTP event callbacks of DingTalk: their subscription and the encryption of their messages.
+//!
+//! DingTalk signs and encrypts every event pushed to the callback URL of an app with the token
+//! and the `aes_key` configured in the developer console, and expects an encrypted `success` in
+//! the response body:
+//!
+//! ```no_run
+//! # fn handle(
+//! #     signature: &str, timestamp: &str, nonce: &str, encrypt: &str,
+//! # ) -> Result<(), async_dingtalk::DingTalkError> {
+//! use async_dingtalk::CallbackCrypto;
+//!
+//! let crypto = CallbackCrypto::new("token", "aes_key_of_43_characters", "corp_id_or_suite_key")?;
+//! let event = crypto.decrypt(signature, timestamp, nonce, encrypt)?;
+//! let response = crypto.success_response(timestamp, nonce)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{crypto, error::DingTalkError, organization::OrgApp};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The encrypted body of a callback request or response.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EncryptedMessage {
+    pub msg_signature: String,
+    #[serde(rename = "timeStamp")]
+    pub timestamp: String,
+    pub nonce: String,
+    pub encrypt: String,
+}
+
+/// Verifies, decrypts and encrypts the messages of the HTTP event callbacks.
+///
+/// [回调加解密](https://open.dingtalk.com/document/orgapp/callback-encryption-and-decryption)
+///
+/// The primitives are also available as the functions of [crate::crypto].
+pub struct CallbackCrypto {
+    token: String,
+    key: [u8; 32],
+    owner_key: String,
+}
+
+impl std::fmt::Debug for CallbackCrypto {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CallbackCrypto")
+            .field("owner_key", &self.owner_key)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CallbackCrypto {
+    /// Creates the crypto of a callback.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - The signing token of the callback.
+    /// * `aes_key` - The 43 characters long `aes_key` of the callback.
+    /// * `owner_key` - The corp ID of internal apps, the app key of apps registered in the
+    ///   developer console or the suite key of ISV suites.
+    ///
+    /// # Errors
+    ///
+    /// Returns [DingTalkError::Config] if `aes_key` is not 43 characters of base64.
+    pub fn new(token: &str, aes_key: &str, owner_key: &str) -> Result<Self, DingTalkError> {
+        Ok(CallbackCrypto {
+            token: token.to_string(),
+            key: crypto::decode_aes_key(aes_key)?,
+            owner_key: owner_key.to_string(),
+        })
+    }
+
+    /// Returns the `msg_signature` of an encrypted message, see [crypto::msg_signature].
+    pub fn signature(&self, timestamp: &str, nonce: &str, encrypt: &str) -> String {
+        crypto::msg_signature(&self.token, timestamp, nonce, encrypt)
+    }
+
+    /// Checks the `msg_signature` of a callback request, in constant time.
+    ///
+    /// # Errors
+    ///
+    /// Returns [DingTalkError::Callback] if the signature does not match.
+    pub fn verify(
+        &self,
+        msg_signature: &str,
+        timestamp: &str,
+        nonce: &str,
+        encrypt: &str,
+    ) -> Result<(), DingTalkError> {
+        let expected = self.signature(timestamp, nonce, encrypt);
+        if !crypto::signatures_match(&expected, msg_signature) {
+            return Err(DingTalkError::Callback("signature mismatch".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Verifies and decrypts the `encrypt` field of a callback request.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg_signature`, `timestamp`, `nonce` - The query parameters of the request.
+    /// * `encrypt` - The `encrypt` field of the JSON body.
+    ///
+    /// # Returns
+    ///
+    /// The JSON of the event.
+    ///
+    /// # Errors
+    ///
+    /// Returns [DingTalkError::Callback] if the signature does not match, the ciphertext is
+    /// malformed or the message was encrypted for another owner key.
+    pub fn decrypt(
+        &self,
+        msg_signature: &str,
+        timestamp: &str,
+        nonce: &str,
+        encrypt: &str,
+    ) -> Result<String, DingTalkError> {
+        self.verify(msg_signature, timestamp, nonce, encrypt)?;
+
+        let (message, owner_key) = crypto::decrypt_message(&self.key, encrypt)?;
+        if owner_key != self.owner_key {
+            return Err(DingTalkError::Callback(
+                "message encrypted for another owner key".to_string(),
+            ));
+        }
+
+        Ok(message)
+    }
+
+    /// Encrypts and signs a message.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The plaintext, e.g. `success`.
+    /// * `timestamp` - The Unix time in milliseconds, usually the one of the request.
+    /// * `nonce` - A random string, usually the one of the request.
+    pub fn encrypt(
+        &self,
+        message: &str,
+        timestamp: &str,
+        nonce: &str,
+    ) -> Result<EncryptedMessage, DingTalkError> {
+        let random: [u8; crypto::RANDOM_LEN] = rand::random();
+        let encrypt = crypto::encrypt_message(&self.key, &random, message, &self.owner_key)?;
+
+        Ok(EncryptedMessage {
+            msg_signature: self.signature(timestamp, nonce, &encrypt),
+            timestamp: timestamp.to_string(),
+            nonce: nonce.to_string(),
+            encrypt,
+        })
+    }
+
+    /// Returns the body acknowledging a callback request, the encrypted `success`.
+    pub fn success_response(
+        &self,
+        timestamp: &str,
+        nonce: &str,
+    ) -> Result<EncryptedMessage, DingTalkError> {
+        self.encrypt("success", timestamp, nonce)
+    }
+}
+
+/// The subscription of an HTTP event callback.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CallbackRegistration {
+    /// The subscribed events, e.g. `user_add_org` or `org_dept_modify`.
+    #[serde(rename = "call_back_tag")]
+    pub tags: Vec<String>,
+    /// The signing token of [CallbackCrypto::new].
+    pub token: String,
+    /// The `aes_key` of [CallbackCrypto::new].
+    pub aes_key: String,
+    /// The callback URL, which must answer the `check_url` event before it is registered.
+    pub url: String,
+}
+
+/// An event push which the callback URL did not acknowledge.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FailedCallback {
+    #[serde(rename = "call_back_tag")]
+    pub tag: String,
+    /// The Unix time of the event in milliseconds.
+    pub event_time: i64,
+    /// The payload of the event, keyed by its tag.
+    #[serde(flatten)]
+    pub data: HashMap<String, serde_json::Value>,
+}
+
+/// A page of [OrgApp::get_callback_failed_results].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FailedCallbacks {
+    pub has_more: bool,
+    #[serde(rename = "failed_list", default)]
+    pub failed: Vec<FailedCallback>,
+}
+
+impl OrgApp {
+    /// Subscribes the organization's events to a callback URL.
+    ///
+    /// [注册事件回调接口](https://open.dingtalk.com/document/orgapp/register-callback-interface)
+    ///
+    /// # Arguments
+    ///
+    /// * `registration` - The events, credentials and URL of the callback.
+    ///
+    /// # Errors
+    ///
+    /// Returns [DingTalkError::Api] if the URL did not answer the `check_url` event, or the
+    /// callback is registered already.
+    pub async fn register_callback(
+        &self,
+        registration: &CallbackRegistration,
+    ) -> Result<(), DingTalkError> {
+        let _: serde_json::Value = self
+            .post_oapi_body("call_back/register_call_back", registration)
+            .await?;
+        Ok(())
+    }
+
+    /// Replaces the registered callback.
+    ///
+    /// [更新事件回调接口](https://open.dingtalk.com/document/orgapp/update-event-callback-interface)
+    ///
+    /// # Arguments
+    ///
+    /// * `registration` - The new events, credentials and URL of the callback.
+    pub async fn update_callback(
+        &self,
+        registration: &CallbackRegistration,
+    ) -> Result<(), DingTalkError> {
+        let _: serde_json::Value = self
+            .post_oapi_body("call_back/update_call_back", registration)
+            .await?;
+        Ok(())
+    }
+
+    /// Removes the registered callback.
+    ///
+    /// [删除事件回调接口](https://open.dingtalk.com/document/orgapp/delete-event-callback-interface)
+    pub async fn delete_callback(&self) -> Result<(), DingTalkError> {
+        let _: serde_json::Value = self
+            .get_oapi_body("call_back/delete_call_back", &[])
+            .await?;
+        Ok(())
+    }
+
+    /// Retrieves the event pushes which the callback URL failed to acknowledge, to replay them.
+    ///
+    /// [获取回调失败的结果](https://open.dingtalk.com/document/orgapp/obtain-the-push-failure-result)
+    ///
+    /// DingTalk removes returned failures, so they should be handled before the next call; call
+    /// again while `has_more` is `true`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing up to 200 failed pushes.
+    pub async fn get_callback_failed_results(&self) -> Result<FailedCallbacks, DingTalkError> {
+        self.get_oapi_body("call_back/get_call_back_failed_result", &[])
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AES_KEY: &str = "o1w0aum42yaptlz8alnhwikjd3jenzt9cb9wmzptgus";
+
+    #[test]
+    fn messages_round_trip() {
+        let crypto = CallbackCrypto::new("token", AES_KEY, "suite4xxxxxxxxxxxxxxx").unwrap();
+        let encrypted = crypto
+            .encrypt(r#"{"EventType":"check_url"}"#, "1700000000000", "nonce")
+            .unwrap();
+
+        let message = crypto
+            .decrypt(
+                &encrypted.msg_signature,
+                &encrypted.timestamp,
+                &encrypted.nonce,
+                &encrypted.encrypt,
+            )
+            .unwrap();
+        assert_eq!(message, r#"{"EventType":"check_url"}"#);
+
+        let other = CallbackCrypto::new("token", AES_KEY, "another").unwrap();
+        assert!(other
+            .decrypt(
+                &encrypted.msg_signature,
+                &encrypted.timestamp,
+                &encrypted.nonce,
+                &encrypted.encrypt,
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn messages_encrypted_by_dingtalk_are_decrypted() {
+        let crypto = CallbackCrypto::new("token", AES_KEY, "suite4xxxxxxxxxxxxxxx").unwrap();
+        let encrypt = "WSbGdl4cHPt1ALTmzUYV7oBCDHiYH2fZs8dtP5DYJ0xPkVfG6dOcCFfq0Hd/StSkhtn0RycABVLxkXkoT8JNxZqLh/KuNxIsqIFVKWl5mfhZP3L+GYzBQK2gQ3z7Ck6v";
+
+        let message = crypto
+            .decrypt(
+                "7fdf9e1e8a6e428c76453f7c69e5068b0d6376e8",
+                "1700000000000",
+                "nonce",
+                encrypt,
+            )
+            .unwrap();
+        assert_eq!(message, r#"{"EventType":"check_url"}"#);
+    }
+
+    #[test]
+    fn tampered_messages_are_rejected() {
+        let crypto = CallbackCrypto::new("token", AES_KEY, "corp").unwrap();
+        let encrypted = crypto.success_response("1700000000000", "nonce").unwrap();
+
+        let result = crypto.decrypt("0000", "1700000000000", "nonce", &encrypted.encrypt);
+        assert!(matches!(result, Err(DingTalkError::Callback(_))));
+    }
+}
@@ -0,0 +1,796 @@
+//! A machine-readable registry of the DingTalk endpoints the crate wraps.
+//!
+//! New `oapi.dingtalk.com` endpoints which take a flat JSON body and answer with the usual
+//! `errcode`/`result` envelope can be declared with the crate-internal `oapi_endpoint!` macro:
+//!
+//! ```ignore
+//! impl OrgApp {
+//!     oapi_endpoint! {
+//!         /// Retrieves the user IDs of the direct members of a department.
+//!         pub fn list_department_user_ids(dept_id: i64) -> Vec<String>
+//!             = "topapi/user/listid" => userid_list;
+//!     }
+//! }
+//! ```
+//!
+//! Every wrapped endpoint must also be listed in [CATALOG]; a test checks the paths passed to
+//! the request helpers and declared with the macro against it.
+
+/// The HTTP method of an endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Post,
+}
+
+/// The API an endpoint belongs to, see [crate::Endpoints].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Host {
+    /// `api.dingtalk.com`, authenticated with the `x-acs-dingtalk-access-token` header.
+    Api,
+    /// `oapi.dingtalk.com`, authenticated with the `access_token` query parameter.
+    Oapi,
+    /// `login.dingtalk.com`, opened by the user in the browser.
+    Login,
+}
+
+/// The credential an endpoint is called with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenType {
+    /// No token, e.g. when the endpoint issues one.
+    None,
+    /// The user access token of [crate::DingTalk::set_app_access_token].
+    User,
+    /// The organization access token of an [crate::OrgApp].
+    Corp,
+    /// A robot webhook access token.
+    Robot,
+    /// The suite access token of [crate::DingTalk::get_suite_access_token].
+    Suite,
+}
+
+/// How the payload of an endpoint is wrapped in the response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Envelope {
+    /// `{"errcode": 0, "errmsg": "ok", "result": ...}`, the payload is `result`.
+    Result,
+    /// `{"errcode": 0, "errmsg": "ok", ...}`, the payload sits next to `errcode`.
+    Body,
+    /// The body is the payload; errors are reported with the HTTP status.
+    Plain,
+}
+
+/// An endpoint wrapped by the crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EndpointSpec {
+    /// The method wrapping the endpoint.
+    pub name: &'static str,
+    pub method: Method,
+    pub host: Host,
+    /// The path below the base URL of the host; `{}` marks a path parameter.
+    pub path: &'static str,
+    pub token: TokenType,
+    pub envelope: Envelope,
+}
+
+const fn spec(
+    name: &'static str,
+    method: Method,
+    host: Host,
+    path: &'static str,
+    token: TokenType,
+    envelope: Envelope,
+) -> EndpointSpec {
+    EndpointSpec {
+        name,
+        method,
+        host,
+        path,
+        token,
+        envelope,
+    }
+}
+
+use Envelope::{Body, Plain, Result};
+use Host::{Api, Login, Oapi};
+use Method::{Get, Post};
+
+/// The endpoints wrapped by the crate.
+pub const CATALOG: &[EndpointSpec] = &[
+    spec(
+        "DingTalk::get_redirect_url",
+        Get,
+        Login,
+        "oauth2/auth",
+        TokenType::None,
+        Plain,
+    ),
+    spec(
+        "DingTalk::set_app_access_token",
+        Post,
+        Api,
+        "v1.0/oauth2/userAccessToken",
+        TokenType::None,
+        Plain,
+    ),
+    spec(
+        "DingTalk::health_check",
+        Post,
+        Api,
+        "v1.0/oauth2/accessToken",
+        TokenType::None,
+        Plain,
+    ),
+    spec(
+        "DingTalk::get_contact_userinfo",
+        Get,
+        Api,
+        "v1.0/contact/users/{}",
+        TokenType::User,
+        Plain,
+    ),
+    spec(
+        "OrgApp::get_access_token",
+        Post,
+        Api,
+        "v1.0/oauth2/{}/token",
+        TokenType::None,
+        Plain,
+    ),
+    spec(
+        "OrgApp::get_access_token",
+        Get,
+        Oapi,
+        "gettoken",
+        TokenType::None,
+        Body,
+    ),
+    spec(
+        "OrgApp::get_access_token",
+        Post,
+        Api,
+        "v1.0/oauth2/corpAccessToken",
+        TokenType::None,
+        Plain,
+    ),
+    spec(
+        "DingTalk::get_suite_access_token",
+        Post,
+        Oapi,
+        "service/get_suite_token",
+        TokenType::None,
+        Body,
+    ),
+    spec(
+        "DingTalk::get_permanent_code",
+        Post,
+        Oapi,
+        "service/get_permanent_code",
+        TokenType::Suite,
+        Body,
+    ),
+    spec(
+        "DingTalk::activate_suite",
+        Post,
+        Oapi,
+        "service/activate_suite",
+        TokenType::Suite,
+        Body,
+    ),
+    spec(
+        "DingTalk::get_auth_info",
+        Post,
+        Oapi,
+        "service/get_auth_info",
+        TokenType::Suite,
+        Body,
+    ),
+    spec(
+        "DingTalk::list_unactivated_corps",
+        Post,
+        Oapi,
+        "service/get_unactive_corp",
+        TokenType::Suite,
+        Body,
+    ),
+    spec(
+        "OrgApp::register_callback",
+        Post,
+        Oapi,
+        "call_back/register_call_back",
+        TokenType::Corp,
+        Body,
+    ),
+    spec(
+        "OrgApp::update_callback",
+        Post,
+        Oapi,
+        "call_back/update_call_back",
+        TokenType::Corp,
+        Body,
+    ),
+    spec(
+        "OrgApp::delete_callback",
+        Get,
+        Oapi,
+        "call_back/delete_call_back",
+        TokenType::Corp,
+        Body,
+    ),
+    spec(
+        "OrgApp::get_callback_failed_results",
+        Get,
+        Oapi,
+        "call_back/get_call_back_failed_result",
+        TokenType::Corp,
+        Body,
+    ),
+    spec(
+        "OrgApp::get_jsapi_ticket",
+        Get,
+        Oapi,
+        "get_jsapi_ticket",
+        TokenType::Corp,
+        Body,
+    ),
+    spec(
+        "StreamClient::run",
+        Post,
+        Api,
+        "v1.0/gateway/connections/open",
+        TokenType::None,
+        Plain,
+    ),
+    spec(
+        "OrgApp::get_organization",
+        Get,
+        Api,
+        "v1.0/contact/organizations/authInfos",
+        TokenType::Corp,
+        Plain,
+    ),
+    spec(
+        "OrgApp::query_off_job_employees",
+        Get,
+        Api,
+        "v1.0/hrm/employees/dismissions",
+        TokenType::Corp,
+        Plain,
+    ),
+    spec(
+        "OrgApp::get_userinfo",
+        Post,
+        Oapi,
+        "topapi/v2/user/getuserinfo",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::get_employee_userinfo",
+        Post,
+        Oapi,
+        "topapi/v2/user/get",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::get_employee_count",
+        Post,
+        Oapi,
+        "topapi/user/count",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::query_on_job_employees",
+        Post,
+        Oapi,
+        "topapi/smartwork/hrm/employee/queryonjob",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::create_user",
+        Post,
+        Oapi,
+        "topapi/v2/user/create",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::update_user",
+        Post,
+        Oapi,
+        "topapi/v2/user/update",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::list_admins",
+        Post,
+        Oapi,
+        "topapi/user/listadmin",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::get_admin_scope",
+        Get,
+        Oapi,
+        "topapi/user/get_admin_scope",
+        TokenType::Corp,
+        Body,
+    ),
+    spec(
+        "OrgApp::list_inactive_users",
+        Post,
+        Oapi,
+        "topapi/inactive/user/v2/get",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::list_department_users_simple",
+        Post,
+        Oapi,
+        "topapi/user/listsimple",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::get_userid_by_mobile",
+        Post,
+        Oapi,
+        "topapi/v2/user/getbymobile",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::list_department_users",
+        Post,
+        Oapi,
+        "topapi/v2/user/list",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::list_sub_department_ids",
+        Post,
+        Oapi,
+        "topapi/v2/department/listsubid",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::welcome_employee",
+        Post,
+        Oapi,
+        "chat/update",
+        TokenType::Corp,
+        Body,
+    ),
+    spec(
+        "OrgApp::welcome_employee",
+        Post,
+        Api,
+        "v1.0/todo/users/{}/tasks",
+        TokenType::Corp,
+        Plain,
+    ),
+    spec(
+        "OrgApp::get_employee_roster",
+        Post,
+        Oapi,
+        "topapi/smartwork/hrm/employee/v2/list",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::update_employee_roster",
+        Post,
+        Oapi,
+        "topapi/smartwork/hrm/employee/v2/update",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::add_pre_entry_employee",
+        Post,
+        Oapi,
+        "topapi/smartwork/hrm/employee/addpreentry",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::list_hrm_field_definitions",
+        Post,
+        Oapi,
+        "topapi/smartwork/hrm/roster/meta/get",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::dismiss_employee",
+        Post,
+        Oapi,
+        "topapi/smartwork/hrm/employee/dismission",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::get_unionid_by_openid",
+        Post,
+        Oapi,
+        "topapi/user/getunionidbyopenid",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::list_external_contacts",
+        Post,
+        Oapi,
+        "topapi/extcontact/list",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::get_external_contact",
+        Post,
+        Oapi,
+        "topapi/extcontact/get",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::create_external_contact",
+        Post,
+        Oapi,
+        "topapi/extcontact/create",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::update_external_contact",
+        Post,
+        Oapi,
+        "topapi/extcontact/update",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::delete_external_contact",
+        Post,
+        Oapi,
+        "topapi/extcontact/delete",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::list_external_contact_label_groups",
+        Post,
+        Oapi,
+        "topapi/extcontact/listlabelgroups",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::add_roles_to_users",
+        Post,
+        Oapi,
+        "topapi/role/addrolesforemps",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::set_role_member_manage_scope",
+        Post,
+        Oapi,
+        "topapi/role/scope/update",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::remove_roles_from_users",
+        Post,
+        Oapi,
+        "topapi/role/removerolesforemps",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::delete_user",
+        Post,
+        Oapi,
+        "topapi/v2/user/delete",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::create_department",
+        Post,
+        Oapi,
+        "topapi/v2/department/create",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::update_department",
+        Post,
+        Oapi,
+        "topapi/v2/department/update",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::delete_department",
+        Post,
+        Oapi,
+        "topapi/v2/department/delete",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::get_parent_departments_by_dept",
+        Post,
+        Oapi,
+        "topapi/v2/department/listparentbydept",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::get_parent_departments_by_user",
+        Post,
+        Oapi,
+        "topapi/v2/department/listparentbyuser",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::get_department",
+        Post,
+        Oapi,
+        "topapi/v2/department/get",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::resolve_recipients",
+        Post,
+        Oapi,
+        "topapi/user/listid",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::resolve_recipients",
+        Post,
+        Oapi,
+        "topapi/role/simplelist",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::get_userid_by_unionid",
+        Post,
+        Oapi,
+        "topapi/user/getbyunionid",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::list_departments",
+        Post,
+        Oapi,
+        "topapi/v2/department/listsub",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "Exporter::run",
+        Post,
+        Oapi,
+        "topapi/v2/user/list",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "Exporter::run",
+        Post,
+        Oapi,
+        "topapi/role/list",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::send_work_notification",
+        Post,
+        Oapi,
+        "topapi/message/corpconversation/asyncsend_v2",
+        TokenType::Corp,
+        Body,
+    ),
+    spec(
+        "OrgApp::list_attendance_results",
+        Post,
+        Oapi,
+        "attendance/list",
+        TokenType::Corp,
+        Body,
+    ),
+    spec(
+        "OrgApp::list_attendance_columns",
+        Post,
+        Oapi,
+        "topapi/attendance/getattcolumns",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::get_attendance_column_values",
+        Post,
+        Oapi,
+        "topapi/attendance/getcolumnval",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::get_leave_time_by_names",
+        Post,
+        Oapi,
+        "topapi/attendance/getleavetimebynames",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "OrgApp::diagnose",
+        Post,
+        Oapi,
+        "topapi/user/count",
+        TokenType::Corp,
+        Result,
+    ),
+    spec(
+        "RobotPool::send",
+        Post,
+        Oapi,
+        "robot/send",
+        TokenType::Robot,
+        Body,
+    ),
+];
+
+/// Returns the catalog path matching a request path, with path parameters kept as `{}`, for
+/// labels of bounded cardinality.
+pub fn endpoint_path(path: &str) -> Option<&'static str> {
+    let path = path.trim_start_matches('/');
+    CATALOG.iter().map(|spec| spec.path).find(|pattern| {
+        let mut segments = path.split('/');
+        pattern.split('/').all(|expected| match segments.next() {
+            Some(segment) => expected == "{}" || expected == segment,
+            None => false,
+        }) && segments.next().is_none()
+    })
+}
+
+/// Declares an `OrgApp` method calling an `oapi.dingtalk.com` endpoint with the
+/// [Envelope::Result] envelope.
+///
+/// The arguments are sent as a JSON object keyed by their names. With `=> field`, the method
+/// returns that field of `result` instead of `result` itself.
+macro_rules! oapi_endpoint {
+    (
+        $(#[$meta:meta])*
+        $vis:vis fn $name:ident($($arg:ident: $ty:ty),* $(,)?) -> $ret:ty = $path:literal => $field:ident;
+    ) => {
+        $(#[$meta])*
+        $vis async fn $name(&self, $($arg: $ty),*) -> ::std::result::Result<$ret, $crate::DingTalkError> {
+            #[derive(serde::Deserialize)]
+            struct Response {
+                $field: $ret,
+            }
+
+            let response: Response = oapi_endpoint!(@call self, $path, $($arg),*);
+            Ok(response.$field)
+        }
+    };
+    (
+        $(#[$meta:meta])*
+        $vis:vis fn $name:ident($($arg:ident: $ty:ty),* $(,)?) -> $ret:ty = $path:literal;
+    ) => {
+        $(#[$meta])*
+        $vis async fn $name(&self, $($arg: $ty),*) -> ::std::result::Result<$ret, $crate::DingTalkError> {
+            Ok(oapi_endpoint!(@call self, $path, $($arg),*))
+        }
+    };
+    (@call $self:ident, $path:literal, $($arg:ident),*) => {{
+        #[allow(unused_mut)]
+        let mut params = serde_json::Map::new();
+        $(params.insert(stringify!($arg).to_string(), serde_json::to_value(&$arg)?);)*
+        $self.post_oapi($path, &params).await?
+    }};
+}
+
+pub(crate) use oapi_endpoint;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_parameters_match_the_catalog_path() {
+        assert_eq!(
+            endpoint_path("/v1.0/contact/users/union-id"),
+            Some("v1.0/contact/users/{}")
+        );
+        assert_eq!(
+            endpoint_path("/topapi/v2/user/get"),
+            Some("topapi/v2/user/get")
+        );
+        assert_eq!(endpoint_path("/topapi/v2/user/get/extra"), None);
+    }
+
+    /// Returns the string literal at the start of `source`, skipping whitespace and a
+    /// `&format!(` wrapper, or `None` if the argument is not a literal.
+    fn leading_literal(source: &str) -> Option<&str> {
+        let source = source.trim_start();
+        let source = source.strip_prefix("&format!(").unwrap_or(source);
+        let source = source.strip_prefix('"')?;
+        source.find('"').map(|end| &source[..end])
+    }
+
+    /// Collects the literal paths passed to the request helpers and declared with
+    /// `oapi_endpoint!` in the non-test code of `source`.
+    fn called_paths(source: &str) -> Vec<&str> {
+        const CALLS: [&str; 5] = [
+            "post_oapi(",
+            "post_oapi::<serde_json::Value>(",
+            "post_oapi_body(",
+            "post_oapi_body::<serde_json::Value>(",
+            "get_oapi_body(",
+        ];
+        const HOSTS: [&str; 2] = ["endpoints.api(", "endpoints.oapi("];
+
+        let mut paths = Vec::new();
+        for needle in CALLS.iter().chain(HOSTS.iter()) {
+            for (start, _) in source.match_indices(needle) {
+                paths.extend(leading_literal(&source[start + needle.len()..]));
+            }
+        }
+        for (start, _) in source.match_indices("oapi_endpoint! {") {
+            let block = &source[start..];
+            let block = &block[..block.find("\n    }").unwrap_or(block.len())];
+            if let Some(declared) = block.find(") -> ") {
+                let declared = &block[declared..];
+                if let Some(assigned) = declared.find("= \"") {
+                    paths.extend(leading_literal(&declared[assigned + 1..]));
+                }
+            }
+        }
+        paths
+    }
+
+    #[test]
+    fn called_endpoints_are_in_the_catalog() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/src");
+        let mut checked = 0;
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().map_or(true, |extension| extension != "rs") {
+                continue;
+            }
+            let source = std::fs::read_to_string(&path).unwrap();
+            let source = source.split("#[cfg(test)]\nmod tests").next().unwrap();
+            for called in called_paths(source) {
+                let called = called.split('?').next().unwrap();
+                assert!(
+                    endpoint_path(called).is_some(),
+                    "{} calls {}, which is missing from the catalog",
+                    path.display(),
+                    called
+                );
+                checked += 1;
+            }
+        }
+        assert!(checked > 50, "only {} calls found", checked);
+    }
+}
@@ -0,0 +1,131 @@
+use chrono::DateTime;
+use reqwest::header::{HeaderMap, DATE};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The source of timestamps used to sign requests and verify callbacks.
+///
+/// DingTalk rejects signatures whose timestamp is too far from its own clock. When the server
+/// clock drifts, a correction offset can be configured manually or derived from the `Date` header
+/// of a DingTalk response with [Clock::calibrate].
+#[derive(Debug, Default)]
+pub struct Clock {
+    offset_millis: AtomicI64,
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        Clock::default()
+    }
+
+    /// Creates a clock running `offset_millis` ahead of the system clock (behind if negative).
+    pub fn with_offset(offset_millis: i64) -> Self {
+        Clock {
+            offset_millis: AtomicI64::new(offset_millis),
+        }
+    }
+
+    pub fn offset_millis(&self) -> i64 {
+        self.offset_millis.load(Ordering::Relaxed)
+    }
+
+    pub fn set_offset_millis(&self, offset_millis: i64) {
+        self.offset_millis.store(offset_millis, Ordering::Relaxed);
+    }
+
+    /// Returns the corrected current time as a millisecond timestamp.
+    pub fn now_millis(&self) -> i64 {
+        system_millis() + self.offset_millis()
+    }
+
+    /// Compares the corrected clock with the `Date` header of a DingTalk response.
+    ///
+    /// # Returns
+    ///
+    /// The corrected local time minus the server time in milliseconds, or `None` if the headers
+    /// contain no valid `Date`. As `Date` has a resolution of one second, so does the result.
+    pub fn skew_millis(&self, headers: &HeaderMap) -> Option<i64> {
+        server_millis(headers).map(|server| self.now_millis() - server)
+    }
+
+    /// Adjusts the offset so that the clock agrees with the `Date` header of a DingTalk response.
+    ///
+    /// # Returns
+    ///
+    /// The new offset in milliseconds, or `None` if the headers contain no valid `Date`.
+    pub fn calibrate(&self, headers: &HeaderMap) -> Option<i64> {
+        let offset = server_millis(headers)? - system_millis();
+        self.set_offset_millis(offset);
+        Some(offset)
+    }
+}
+
+fn system_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+fn server_millis(headers: &HeaderMap) -> Option<i64> {
+    let date = headers.get(DATE)?.to_str().ok()?;
+    DateTime::parse_from_rfc2822(date)
+        .ok()
+        .map(|date| date.timestamp_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    const DATE_HEADER: &str = "Wed, 15 Oct 2025 08:00:00 GMT";
+    const DATE_MILLIS: i64 = 1_760_515_200_000;
+
+    fn headers(date: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(DATE, HeaderValue::from_str(date).unwrap());
+        headers
+    }
+
+    #[test]
+    fn date_headers_are_parsed() {
+        assert_eq!(server_millis(&headers(DATE_HEADER)), Some(DATE_MILLIS));
+    }
+
+    #[test]
+    fn skews_are_positive_when_the_local_clock_is_ahead() {
+        let headers = headers(DATE_HEADER);
+
+        // The system clock is past the fixed date.
+        assert!(Clock::new().skew_millis(&headers).unwrap() > 0);
+
+        // Corrected to a minute before the server.
+        let clock = Clock::with_offset(DATE_MILLIS - system_millis() - 60_000);
+        let skew = clock.skew_millis(&headers).unwrap();
+        assert!((-61_000..=-59_000).contains(&skew), "{}", skew);
+    }
+
+    #[test]
+    fn calibration_sets_the_offset_to_the_server_clock() {
+        let clock = Clock::new();
+        let headers = headers(DATE_HEADER);
+
+        let offset = clock.calibrate(&headers).unwrap();
+
+        assert_eq!(clock.offset_millis(), offset);
+        assert!(offset < 0);
+        assert!(clock.skew_millis(&headers).unwrap().abs() < 1_000);
+    }
+
+    #[test]
+    fn missing_or_invalid_dates_are_ignored() {
+        let clock = Clock::with_offset(5_000);
+
+        for headers in [HeaderMap::new(), headers("yesterday")] {
+            assert_eq!(clock.skew_millis(&headers), None);
+            assert_eq!(clock.calibrate(&headers), None);
+        }
+        assert_eq!(clock.offset_millis(), 5_000);
+    }
+}
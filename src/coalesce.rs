@@ -0,0 +1,212 @@
+use crate::error::DingTalkError;
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::OnceCell;
+
+/// Groups of read endpoints whose identical in-flight calls can be coalesced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EndpointClass {
+    /// Employee profiles of an organization.
+    User,
+    /// Department details.
+    Department,
+    /// Organization authentication info.
+    Organization,
+    /// Contact profiles looked up by union ID.
+    Contact,
+    /// Access token refreshes of an organization or of the user, so that a burst of calls after
+    /// the expiry fetches a single token.
+    Token,
+}
+
+type Outcome = Result<Arc<dyn Any + Send + Sync>, Arc<DingTalkError>>;
+
+/// Coalesces identical in-flight reads into a single upstream call.
+///
+/// When a call is issued while an identical one (same endpoint and parameters) is still running,
+/// it waits for and shares the result of the running call instead of requesting DingTalk again.
+/// Results are not cached once the call completes. Coalescing is enabled for every
+/// [EndpointClass] by default.
+pub struct Coalescer {
+    disabled: RwLock<HashSet<EndpointClass>>,
+    calls: Mutex<HashMap<String, Arc<OnceCell<Outcome>>>>,
+}
+
+impl Default for Coalescer {
+    fn default() -> Self {
+        Coalescer::new()
+    }
+}
+
+impl Coalescer {
+    pub fn new() -> Self {
+        Coalescer {
+            disabled: RwLock::new(HashSet::new()),
+            calls: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn set_enabled(&self, class: EndpointClass, enabled: bool) {
+        let mut disabled = self.disabled.write().unwrap();
+        if enabled {
+            disabled.remove(&class);
+        } else {
+            disabled.insert(class);
+        }
+    }
+
+    pub fn is_enabled(&self, class: EndpointClass) -> bool {
+        !self.disabled.read().unwrap().contains(&class)
+    }
+
+    /// Runs `fetch`, or joins an identical call already in flight.
+    ///
+    /// `key` must identify the endpoint and all of its parameters, including the tenant.
+    pub(crate) async fn run<T, F, Fut>(
+        &self,
+        class: EndpointClass,
+        key: String,
+        fetch: F,
+    ) -> Result<T, DingTalkError>
+    where
+        T: Clone + Send + Sync + 'static,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, DingTalkError>>,
+    {
+        if !self.is_enabled(class) {
+            return fetch().await;
+        }
+
+        let cell = self
+            .calls
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone();
+
+        // Only the first caller runs its `fetch`; the others wait for its outcome. Should the
+        // first caller be cancelled, the next waiting caller runs its own `fetch` instead.
+        let outcome = cell
+            .get_or_init(|| async {
+                match fetch().await {
+                    Ok(value) => Ok(Arc::new(value) as Arc<dyn Any + Send + Sync>),
+                    Err(e) => Err(Arc::new(e)),
+                }
+            })
+            .await
+            .clone();
+
+        {
+            let mut calls = self.calls.lock().unwrap();
+            if matches!(calls.get(&key), Some(c) if Arc::ptr_eq(c, &cell)) {
+                calls.remove(&key);
+            }
+        }
+
+        match outcome {
+            Ok(value) => match value.downcast_ref::<T>() {
+                Some(value) => Ok(value.clone()),
+                None => Err(DingTalkError::Other(format!(
+                    "Coalesced call {} returned another type",
+                    key
+                ))),
+            },
+            Err(e) => Err(e.duplicate()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    async fn fetch(calls: &AtomicUsize, value: i64) -> Result<i64, DingTalkError> {
+        calls.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        Ok(value)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn identical_calls_share_one_fetch() {
+        let coalescer = Coalescer::new();
+        let calls = AtomicUsize::new(0);
+
+        let (a, b) = futures::join!(
+            coalescer.run(EndpointClass::User, "k".to_string(), || fetch(&calls, 1)),
+            coalescer.run(EndpointClass::User, "k".to_string(), || fetch(&calls, 2)),
+        );
+        assert_eq!((a.unwrap(), b.unwrap()), (1, 1));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Completed calls are not cached.
+        let c = coalescer.run(EndpointClass::User, "k".to_string(), || fetch(&calls, 3));
+        assert_eq!(c.await.unwrap(), 3);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn disabled_classes_are_not_coalesced() {
+        let coalescer = Coalescer::new();
+        coalescer.set_enabled(EndpointClass::Department, false);
+        assert!(!coalescer.is_enabled(EndpointClass::Department));
+        assert!(coalescer.is_enabled(EndpointClass::User));
+        let calls = AtomicUsize::new(0);
+
+        let (a, b) = futures::join!(
+            coalescer.run(EndpointClass::Department, "k".to_string(), || fetch(
+                &calls, 1
+            )),
+            coalescer.run(EndpointClass::Department, "k".to_string(), || fetch(
+                &calls, 2
+            )),
+        );
+        assert_eq!((a.unwrap(), b.unwrap()), (1, 2));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn errors_are_shared() {
+        let coalescer = Coalescer::new();
+        let calls = AtomicUsize::new(0);
+        let failing = || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Err::<i64, _>(DingTalkError::from_errcode(
+                60011,
+                "没有调用该接口的权限".to_string(),
+                None,
+            ))
+        };
+
+        let (a, b) = futures::join!(
+            coalescer.run(EndpointClass::User, "k".to_string(), failing),
+            coalescer.run(EndpointClass::User, "k".to_string(), failing),
+        );
+        assert_eq!(a.unwrap_err().errcode(), Some(60011));
+        assert_eq!(b.unwrap_err().errcode(), Some(60011));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn cancelled_first_callers_hand_over_to_the_next() {
+        let coalescer = Coalescer::new();
+        let mut first = Box::pin(
+            coalescer.run(EndpointClass::User, "k".to_string(), || async {
+                futures::future::pending::<Result<i64, DingTalkError>>().await
+            }),
+        );
+        let mut second =
+            Box::pin(coalescer.run(EndpointClass::User, "k".to_string(), || async { Ok(2) }));
+
+        assert!(futures::poll!(&mut first).is_pending());
+        assert!(futures::poll!(&mut second).is_pending());
+        drop(first);
+
+        assert_eq!(second.await.unwrap(), 2);
+    }
+}
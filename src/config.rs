@@ -0,0 +1,157 @@
+use crate::error::DingTalkError;
+use std::time::Duration;
+use url::Url;
+
+/// The prefix of the token store keys unless configured otherwise.
+pub(crate) const DEFAULT_KEY_PREFIX: &str = "dingtalk:token:";
+
+/// Settings for caching access tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenConfig {
+    /// How long before the `expires_in` reported by DingTalk a cached token is dropped, so that a
+    /// token is never used right as it expires. Defaults to 5 minutes.
+    pub expiry_margin: Duration,
+}
+
+impl Default for TokenConfig {
+    fn default() -> Self {
+        TokenConfig {
+            expiry_margin: Duration::from_secs(300),
+        }
+    }
+}
+
+impl TokenConfig {
+    pub fn with_expiry_margin(mut self, expiry_margin: Duration) -> Self {
+        self.expiry_margin = expiry_margin;
+        self
+    }
+
+    /// Returns how long a token with the given `expires_in` seconds may be cached, or `None` if
+    /// it expires within the margin and should not be cached at all.
+    pub fn cache_ttl(&self, expires_in: i64) -> Option<Duration> {
+        let expires_in = Duration::from_secs(expires_in.max(0) as u64);
+        match expires_in.checked_sub(self.expiry_margin) {
+            Some(ttl) if !ttl.is_zero() => Some(ttl),
+            _ => None,
+        }
+    }
+}
+
+/// How much of the user data read from DingTalk is logged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogDetail {
+    /// Only the IDs of users, the default.
+    #[default]
+    Ids,
+    /// The whole profiles at `debug` level, including names, mobiles and emails, e.g. while
+    /// debugging an integration. Not for production.
+    Payloads,
+}
+
+/// Logs a user read from DingTalk at `debug` level by its ID, with the whole payload for
+/// [LogDetail::Payloads]:
+///
+/// ```ignore
+/// log_user!(self.log_detail, user_id = user.user_id, &user, "fetched employee");
+/// ```
+macro_rules! log_user {
+    ($detail:expr, $id:ident = $value:expr, $payload:expr, $message:literal) => {
+        match $detail {
+            $crate::config::LogDetail::Ids => tracing::debug!($id = %$value, $message),
+            $crate::config::LogDetail::Payloads => {
+                tracing::debug!($id = %$value, payload = ?$payload, $message)
+            }
+        }
+    };
+}
+
+pub(crate) use log_user;
+
+/// The base URLs of the DingTalk APIs, e.g. to use a mock server in tests or a private cloud
+/// gateway.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoints {
+    /// The new API, `https://api.dingtalk.com` by default.
+    pub api: String,
+    /// The old API, `https://oapi.dingtalk.com` by default.
+    pub oapi: String,
+    /// The login pages, `https://login.dingtalk.com` by default.
+    pub login: String,
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Endpoints {
+            api: "https://api.dingtalk.com".to_string(),
+            oapi: "https://oapi.dingtalk.com".to_string(),
+            login: "https://login.dingtalk.com".to_string(),
+        }
+    }
+}
+
+impl Endpoints {
+    /// Uses the same base URL for all APIs, as a mock server usually serves all of them.
+    pub fn with_base(base: impl Into<String>) -> Self {
+        let base = base.into();
+        Endpoints {
+            api: base.clone(),
+            oapi: base.clone(),
+            login: base,
+        }
+    }
+
+    /// Serves every API from the built-in responses of the [sandbox](crate::sandbox), without
+    /// calling DingTalk.
+    #[cfg(feature = "sandbox")]
+    pub fn sandbox() -> Self {
+        Endpoints::with_base(crate::sandbox::base_url(None))
+    }
+
+    /// Like [Endpoints::sandbox], preferring the response files found in `dir`.
+    #[cfg(feature = "sandbox")]
+    pub fn sandbox_dir(dir: impl AsRef<std::path::Path>) -> Self {
+        Endpoints::with_base(crate::sandbox::base_url(Some(dir.as_ref())))
+    }
+
+    /// Checks that every base is an absolute URL.
+    ///
+    /// # Errors
+    ///
+    /// Returns [DingTalkError::Config] naming the first invalid base.
+    pub(crate) fn validate(&self) -> Result<(), DingTalkError> {
+        for (name, base) in [
+            ("api", &self.api),
+            ("oapi", &self.oapi),
+            ("login", &self.login),
+        ] {
+            Url::parse(base).map_err(|e| {
+                DingTalkError::Config(format!("Invalid {} endpoint {}: {}", name, base, e))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Returns the URL of `path` on the new API.
+    pub(crate) fn api(&self, path: &str) -> String {
+        join(&self.api, path)
+    }
+
+    /// Returns the URL of `path` on the old API.
+    pub(crate) fn oapi(&self, path: &str) -> String {
+        join(&self.oapi, path)
+    }
+
+    /// Returns the URL of `path` on the login pages.
+    pub(crate) fn login(&self, path: &str) -> String {
+        join(&self.login, path)
+    }
+}
+
+fn join(base: &str, path: &str) -> String {
+    format!(
+        "{}/{}",
+        base.trim_end_matches('/'),
+        path.trim_start_matches('/')
+    )
+}
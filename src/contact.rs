@@ -1,9 +1,15 @@
-use crate::DingTalk;
-use log::info;
+use crate::cache::ProfileKind;
+use crate::config::log_user;
+use crate::{coalesce::EndpointClass, error::DingTalkError, DingTalk};
+use futures::stream::{self, StreamExt};
 use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 
-#[derive(Serialize, Deserialize, Debug)]
+/// How many contact profiles are requested at the same time.
+const MAX_CONCURRENT_REQUESTS: usize = 8;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct UserInfo {
     pub email: Option<String>,
     pub mobile: Option<String>,
@@ -18,6 +24,54 @@ pub struct UserInfo {
     pub visitor: Option<bool>,
 }
 
+/// The kind of a DingTalk account, as seen by the organization of the app.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountKind {
+    /// A member of the organization.
+    Internal,
+    /// A visitor (访客), e.g. who logged in to an ISV app without joining the organization.
+    Visitor,
+    /// An external contact (外部联系人) of the organization.
+    External,
+}
+
+impl UserInfo {
+    /// Returns the kind of the account.
+    ///
+    /// The contact API reports visitors only, so every other account is [AccountKind::Internal];
+    /// external contacts are reported by the `contact_type` of the organization APIs.
+    pub fn account_kind(&self) -> AccountKind {
+        if self.visitor == Some(true) {
+            AccountKind::Visitor
+        } else {
+            AccountKind::Internal
+        }
+    }
+}
+
+/// The result of [DingTalk::get_contact_userinfos], keyed by union ID.
+#[derive(Debug, Default)]
+pub struct ContactBatch {
+    pub users: HashMap<String, UserInfo>,
+    pub errors: HashMap<String, DingTalkError>,
+}
+
+impl ContactBatch {
+    /// Keeps the users of the given kinds, e.g. to leave visitors out of a member directory.
+    pub fn retain_kinds(&mut self, kinds: &[AccountKind]) {
+        self.users
+            .retain(|_, user| kinds.contains(&user.account_kind()));
+    }
+
+    /// Returns the users of the given kind.
+    pub fn users_of_kind(&self, kind: AccountKind) -> impl Iterator<Item = &UserInfo> {
+        self.users
+            .values()
+            .filter(move |user| user.account_kind() == kind)
+    }
+}
+
 impl DingTalk {
     /// Get the user info of the given union ID.
     ///
@@ -29,11 +83,103 @@ impl DingTalk {
     ///
     /// # Returns
     ///
-    /// A `Result` containing the user info if successful, otherwise an error string.
-    pub async fn get_contact_userinfo(
-        &self,
-        union_id: String,
-    ) -> Result<UserInfo, Box<dyn std::error::Error>> {
+    /// A `Result` containing the user info if successful, otherwise a `DingTalkError`.
+    pub async fn get_contact_userinfo(&self, union_id: String) -> Result<UserInfo, DingTalkError> {
+        let cache_id = union_id.clone();
+        if let Some(cache) = &self.profile_cache {
+            if let Some(user) = cache
+                .get(
+                    &self.key_prefix,
+                    ProfileKind::Contact,
+                    &self.appid,
+                    &cache_id,
+                )
+                .await
+            {
+                return Ok(user);
+            }
+        }
+
+        let key = format!("{}:v1.0/contact/users:{}", self.appid, union_id);
+        let user: UserInfo = self
+            .coalescer
+            .run(EndpointClass::Contact, key, || {
+                self.fetch_contact_userinfo(union_id)
+            })
+            .await?;
+
+        if let Some(cache) = &self.profile_cache {
+            cache
+                .set(
+                    &self.key_prefix,
+                    ProfileKind::Contact,
+                    &self.appid,
+                    &cache_id,
+                    &user,
+                )
+                .await;
+        }
+
+        Ok(user)
+    }
+
+    /// Get the user info of many union IDs, requesting up to 8 at the same time.
+    ///
+    /// [Documents](https://open.dingtalk.com/document/isvapp/get-user-info)
+    ///
+    /// # Arguments
+    ///
+    /// * `union_ids` - The union IDs of the DingTalk users; duplicates are requested once.
+    ///
+    /// # Returns
+    ///
+    /// A `ContactBatch` with the user info of every union ID which could be retrieved and the
+    /// error of every other one.
+    pub async fn get_contact_userinfos(&self, union_ids: &[String]) -> ContactBatch {
+        let unique: HashSet<&String> = union_ids.iter().collect();
+
+        let results: Vec<(String, Result<UserInfo, DingTalkError>)> = stream::iter(unique)
+            .map(|union_id| async move {
+                let result = self.get_contact_userinfo(union_id.clone()).await;
+                (union_id.clone(), result)
+            })
+            .buffer_unordered(MAX_CONCURRENT_REQUESTS)
+            .collect()
+            .await;
+
+        let mut batch = ContactBatch::default();
+        for (union_id, result) in results {
+            match result {
+                Ok(user) => {
+                    batch.users.insert(union_id, user);
+                }
+                Err(e) => {
+                    batch.errors.insert(union_id, e);
+                }
+            }
+        }
+
+        batch
+    }
+
+    /// Removes a cached contact profile.
+    pub async fn invalidate_contact(&self, union_id: &str) -> Result<(), DingTalkError> {
+        match &self.profile_cache {
+            Some(cache) => {
+                cache
+                    .invalidate(
+                        &self.key_prefix,
+                        ProfileKind::Contact,
+                        &self.appid,
+                        union_id,
+                    )
+                    .await
+            }
+            None => Ok(()),
+        }
+    }
+
+    async fn fetch_contact_userinfo(&self, union_id: String) -> Result<UserInfo, DingTalkError> {
         let mut headers = HeaderMap::new();
         match self.get_app_access_token().await {
             Ok(at) => headers.insert(
@@ -43,17 +189,67 @@ impl DingTalk {
             Err(e) => return Err(e),
         };
 
-        let url: String = format!("https://api.dingtalk.com/v1.0/contact/users/{}", union_id);
-        let response = self.client.get(&url).headers(headers).send().await?;
+        let url: String = self
+            .endpoints
+            .api(&format!("v1.0/contact/users/{}", union_id));
+        let request = self.client.get(&url).headers(headers);
+        let result: UserInfo = self
+            .send_and_parse(request, "Failed to get user info")
+            .await?;
 
-        if !response.status().is_success() {
-            return Err(format!("Failed to get user info: {}", response.status()).into());
-        }
+        log_user!(
+            self.log_detail,
+            union_id = union_id,
+            &result,
+            "fetched contact user info"
+        );
 
-        let result = response.json::<UserInfo>().await?;
+        Ok(result)
+    }
+}
 
-        info!("union_id: {union_id}, fetch user info: {:#?}", &result);
+#[cfg(test)]
+mod tests {
+    use crate::mock::Mock;
+    use crate::ApiErrorKind;
+    use serde_json::json;
 
-        Ok(result)
+    #[tokio::test]
+    async fn batches_report_users_and_errors_per_union_id() {
+        let mock = Mock::new();
+        mock.respond(
+            "v1.0/contact/users/union-zhangsan",
+            json!({ "nick": "张三", "unionId": "union-zhangsan", "stateCode": "86" }),
+        );
+        mock.respond(
+            "v1.0/contact/users/union-missing",
+            json!({ "errcode": 60121, "errmsg": "找不到该用户" }),
+        );
+        let dt = mock.dingtalk();
+        let token = json!({
+            "accessToken": "token",
+            "refreshToken": "refresh",
+            "expireIn": 7200,
+            "expiresAt": dt.clock.now_millis() + 3_600_000,
+        });
+        dt.store
+            .set(&dt.token_key(), &token.to_string(), None)
+            .await
+            .unwrap();
+
+        let union_ids = ["union-zhangsan", "union-missing", "union-zhangsan"]
+            .map(String::from)
+            .to_vec();
+        let batch = dt.get_contact_userinfos(&union_ids).await;
+
+        assert_eq!(batch.users.len(), 1);
+        assert_eq!(batch.users["union-zhangsan"].username, "张三");
+        assert_eq!(batch.errors.len(), 1);
+        assert_eq!(
+            batch.errors["union-missing"].kind(),
+            Some(ApiErrorKind::UserNotFound)
+        );
+        // GET requests have no body, but are recorded all the same.
+        assert_eq!(mock.bodies("v1.0/contact/users/union-zhangsan").len(), 1);
     }
 }
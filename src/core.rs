@@ -1,8 +1,12 @@
 use std::collections::HashMap;
 
-use crate::DingTalk;
-use deadpool_redis::redis::cmd;
-use serde::{Deserialize, Serialize};
+use crate::{coalesce::EndpointClass, deadline, error::DingTalkError, telemetry, DingTalk};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{debug, error, field, info_span, Instrument, Span};
 use url::{form_urlencoded, Url};
 
 impl DingTalk {
@@ -19,8 +23,6 @@ impl DingTalk {
     ///
     /// The redirect URL as a string.
     pub fn get_redirect_url(&self, redirect_uri: String, state: Option<String>) -> String {
-        let mut url = Url::parse("https://login.dingtalk.com/oauth2/auth").unwrap();
-
         let query = form_urlencoded::Serializer::new(String::new())
             .append_pair("redirect_uri", &redirect_uri)
             .append_pair("response_type", "code")
@@ -30,9 +32,7 @@ impl DingTalk {
             .append_pair("prompt", "consent")
             .finish();
 
-        url.set_query(Some(&query));
-
-        url.to_string()
+        format!("{}?{}", self.endpoints.login("oauth2/auth"), query)
     }
 
     /// Obtain the access token for the application.
@@ -55,83 +55,674 @@ impl DingTalk {
     /// # Errors
     ///
     /// Returns an error if the response status is not successful, or if the request fails.
-    pub async fn set_app_access_token(
+    pub async fn set_app_access_token(&self, code: String) -> Result<String, DingTalkError> {
+        let at = self
+            .request_user_access_token("authorization_code", &code, "")
+            .await?;
+
+        Ok(at.corp_id) // 企业corpId
+    }
+
+    /// Get the access token for the application.
+    ///
+    /// The access token is stored in the token store by calling [set_app_access_token]. When it
+    /// expires within the [TokenConfig::expiry_margin](crate::TokenConfig::expiry_margin), it is
+    /// refreshed with the stored refresh token first.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the access token as a string if the access token exists, otherwise a `DingTalkError`.
+    pub async fn get_app_access_token(&self) -> Result<String, DingTalkError> {
+        // Unlike app tokens, a user token cannot be fetched again without the user.
+        let value = self.stored_user_access_token().await?;
+        if !self.is_expiring(&value) {
+            telemetry::record_token_cache("user", true);
+            return Ok(value.access_token);
+        }
+
+        telemetry::record_token_cache("user", false);
+        // DingTalk rotates refresh tokens, so concurrent calls must share a single refresh.
+        self.coalescer
+            .run(EndpointClass::Token, self.token_key(), || {
+                self.refresh_user_access_token(false)
+            })
+            .await
+    }
+
+    /// Refreshes the stored user access token with its refresh token, regardless of its expiry.
+    ///
+    /// [Documents](https://open.dingtalk.com/document/isvapp/obtain-identity-credentials)
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the new access token if successful, otherwise a `DingTalkError`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [DingTalkError::TokenMissing] if no token was stored by [set_app_access_token].
+    pub async fn refresh_app_access_token(&self) -> Result<String, DingTalkError> {
+        self.coalescer
+            .run(EndpointClass::Token, self.token_key(), || {
+                self.refresh_user_access_token(true)
+            })
+            .await
+    }
+
+    /// Reads the user access token stored by [set_app_access_token].
+    async fn stored_user_access_token(&self) -> Result<UserAccessToken, DingTalkError> {
+        let value = self
+            .store
+            .get(&self.token_key())
+            .await
+            .map_err(DingTalkError::Cache)?
+            .ok_or_else(|| {
+                DingTalkError::TokenMissing(
+                    "no user access token stored, call set_app_access_token first".to_string(),
+                )
+            })?;
+
+        Ok(serde_json::from_str(&value)?)
+    }
+
+    /// Whether a user access token expires within the
+    /// [TokenConfig::expiry_margin](crate::TokenConfig::expiry_margin). Tokens stored by older
+    /// versions have no expiry and are refreshed once.
+    fn is_expiring(&self, value: &UserAccessToken) -> bool {
+        let margin = self.token_config.expiry_margin.as_millis() as i64;
+        value.expires_at.map_or(true, |expires_at| {
+            self.clock.now_millis() + margin >= expires_at
+        })
+    }
+
+    /// Exchanges the stored refresh token for a new user access token.
+    ///
+    /// The stored token is read again, so that a call queued behind a refresh that has just
+    /// completed does not send the rotated refresh token. Unless `force`, a token refreshed in
+    /// the meantime is returned as is.
+    async fn refresh_user_access_token(&self, force: bool) -> Result<String, DingTalkError> {
+        let value = self.stored_user_access_token().await?;
+        if !force && !self.is_expiring(&value) {
+            return Ok(value.access_token);
+        }
+
+        let at = self
+            .request_user_access_token("refresh_token", "", &value.refresh_token)
+            .await?;
+
+        Ok(at.access_token)
+    }
+
+    /// Returns the token store key of the user access token, `{prefix}user:{appid}`.
+    pub(crate) fn token_key(&self) -> String {
+        format!("{}user:{}", self.key_prefix, self.appid)
+    }
+
+    /// Requests a user access token with the given grant and stores it.
+    async fn request_user_access_token(
         &self,
-        code: String,
-    ) -> Result<String, Box<dyn std::error::Error>> {
+        grant_type: &str,
+        code: &str,
+        refresh_token: &str,
+    ) -> Result<UserAccessToken, DingTalkError> {
         let mut params = HashMap::new();
         params.insert("clientId", self.appid.clone());
         params.insert("clientSecret", self.app_secret.clone());
-        params.insert("code", code.clone());
-        params.insert("refreshToken", "".to_string());
-        params.insert("grantType", "authorization_code".to_string());
+        params.insert("code", code.to_string());
+        params.insert("refreshToken", refresh_token.to_string());
+        params.insert("grantType", grant_type.to_string());
 
-        let response = self
+        let request = self
             .client
-            .post("https://api.dingtalk.com/v1.0/oauth2/userAccessToken")
-            .json(&params)
-            .send()
+            .post(self.endpoints.api("v1.0/oauth2/userAccessToken"))
+            .json(&params);
+        let mut at: UserAccessToken = self
+            .send_and_parse(request, "Failed to get access token")
             .await?;
+        at.expires_at = Some(self.clock.now_millis() + at.expire_in * 1000);
+
+        // The refresh token outlives the access token, so the entry is kept without TTL.
+        self.store
+            .set(&self.token_key(), &serde_json::to_string(&at)?, None)
+            .await
+            .map_err(DingTalkError::Cache)?;
+
+        Ok(at)
+    }
+}
+
+impl DingTalk {
+    /// Sends a request of the app itself rather than of an organization, e.g. for user or suite
+    /// tokens, counting it in the [UsageTracker](crate::UsageTracker).
+    pub(crate) async fn send_and_parse<T: DeserializeOwned>(
+        &self,
+        request: reqwest::RequestBuilder,
+        context: &str,
+    ) -> Result<T, DingTalkError> {
+        let (client, request) = request.build_split();
+        let request = request?;
+        self.usage.record_app(request.url().path());
+
+        dispatch(&client, self.transport.as_ref(), request, context).await
+    }
+}
+
+/// The future of a [Transport].
+pub(crate) type TransportFuture =
+    Pin<Box<dyn Future<Output = Result<serde_json::Value, DingTalkError>> + Send>>;
+
+/// Sends the requests of a `DingTalk` or `OrgApp` instead of its HTTP client, e.g. through a
+/// tower service, and yields the JSON body of the response.
+pub(crate) type Transport = Arc<dyn Fn(reqwest::Request, String) -> TransportFuture + Send + Sync>;
+
+/// Sends a request with the transport of the instance if any, otherwise with `client`, and
+/// deserializes the response body into `T`.
+///
+/// The API methods of `DingTalk` and `OrgApp` go through this function.
+pub(crate) async fn dispatch<T: DeserializeOwned>(
+    client: &reqwest::Client,
+    transport: Option<&Transport>,
+    request: reqwest::Request,
+    context: &str,
+) -> Result<T, DingTalkError> {
+    match transport {
+        None => execute_and_parse(client, request, context).await,
+        Some(transport) => {
+            let url = redact_url(request.url());
+            let body = transport(request, context.to_string()).await?;
+            parse_body(body, context, &url)
+        }
+    }
+}
+
+/// Sends a request and deserializes the response body into `T`.
+///
+/// Unsuccessful HTTP statuses become [DingTalkError::HttpStatus] with the given context. Bodies
+/// with a non-zero `errcode`, as returned by `oapi.dingtalk.com` with HTTP 200, become
+/// [DingTalkError::Api] or [DingTalkError::IpNotWhitelisted].
+#[cfg(feature = "stream")]
+pub(crate) async fn send_and_parse<T: DeserializeOwned>(
+    request: reqwest::RequestBuilder,
+    context: &str,
+) -> Result<T, DingTalkError> {
+    let (client, request) = request.build_split();
+    execute_and_parse(&client, request?, context).await
+}
+
+/// Like `send_and_parse`, for a request built already.
+///
+/// Each call runs in a `dingtalk.request` span recording the method, the endpoint path, the HTTP
+/// status, the `errcode` and the duration. Query credentials are redacted from every URL.
+pub(crate) async fn execute_and_parse<T: DeserializeOwned>(
+    client: &reqwest::Client,
+    request: reqwest::Request,
+    context: &str,
+) -> Result<T, DingTalkError> {
+    let span = info_span!(
+        "dingtalk.request",
+        method = %request.method(),
+        endpoint = %request.url().path(),
+        status = field::Empty,
+        errcode = field::Empty,
+        duration_ms = field::Empty,
+    );
+    let path = request.url().path().to_string();
+    let started = Instant::now();
+
+    let result = execute(client, request, context)
+        .instrument(span.clone())
+        .await;
+
+    let elapsed = started.elapsed();
+    span.record("duration_ms", elapsed.as_millis() as u64);
+    telemetry::record_request(&path, &result, elapsed);
+    if let Some(errcode) = result.as_ref().err().and_then(DingTalkError::errcode) {
+        span.record("errcode", errcode);
+    }
+    debug!(parent: &span, "{} request completed", if result.is_ok() { "successful" } else { "failed" });
+
+    result
+}
+
+async fn execute<T: DeserializeOwned>(
+    client: &reqwest::Client,
+    request: reqwest::Request,
+    context: &str,
+) -> Result<T, DingTalkError> {
+    let url = redact_url(request.url());
+
+    #[cfg(feature = "sandbox")]
+    if request.url().scheme() == crate::sandbox::SCHEME {
+        let mut request = request;
+        apply_deadline(&mut request, context)?;
+        let body = crate::sandbox::respond(request.url())?;
+        return parse_body(body, context, &url);
+    }
+
+    let response = send_request(client, request, context).await?;
+    let status = response.status();
+
+    /// The error body of `api.dingtalk.com`.
+    #[derive(Serialize, Deserialize, Debug)]
+    struct ApiError {
+        code: Option<String>,
+        message: Option<String>,
+        requestid: Option<String>,
+    }
+
+    if !status.is_success() {
+        let body: Option<ApiError> = response.json().await.ok();
+        error!("{}: {} {:?} ({})", context, status, body, url);
+        let body = body.unwrap_or(ApiError {
+            code: None,
+            message: None,
+            requestid: None,
+        });
+        return Err(DingTalkError::HttpStatus {
+            status,
+            context: context.to_string(),
+            url,
+            code: body.code,
+            message: body.message,
+            request_id: body.requestid,
+        });
+    }
+
+    let body = response.json::<serde_json::Value>().await?;
+    parse_body(body, context, &url)
+}
+
+/// Sends a request within the deadline of the task and returns the response whatever its status,
+/// for probes reading only the status and headers.
+///
+/// Like [execute_and_parse], the call runs in a `dingtalk.request` span.
+pub(crate) async fn execute_raw(
+    client: &reqwest::Client,
+    request: reqwest::Request,
+    context: &str,
+) -> Result<reqwest::Response, DingTalkError> {
+    let span = info_span!(
+        "dingtalk.request",
+        method = %request.method(),
+        endpoint = %request.url().path(),
+        status = field::Empty,
+    );
+
+    send_request(client, request, context)
+        .instrument(span)
+        .await
+}
+
+/// Limits the timeout of a request to the deadline of the task.
+fn apply_deadline(request: &mut reqwest::Request, context: &str) -> Result<(), DingTalkError> {
+    if let Some(budget) = deadline::remaining() {
+        if budget.is_zero() {
+            return Err(DingTalkError::DeadlineExceeded(context.to_string()));
+        }
+        let timeout = request
+            .timeout()
+            .map_or(budget, |timeout| (*timeout).min(budget));
+        *request.timeout_mut() = Some(timeout);
+    }
+
+    Ok(())
+}
+
+/// Sends a request within the deadline of the task, recording the HTTP status in the current
+/// span.
+async fn send_request(
+    client: &reqwest::Client,
+    mut request: reqwest::Request,
+    context: &str,
+) -> Result<reqwest::Response, DingTalkError> {
+    apply_deadline(&mut request, context)?;
+
+    let response = match client.execute(request).await {
+        Ok(response) => response,
+        Err(e)
+            if e.is_timeout() && matches!(deadline::remaining(), Some(left) if left.is_zero()) =>
+        {
+            return Err(DingTalkError::DeadlineExceeded(context.to_string()))
+        }
+        Err(e) => return Err(e.into()),
+    };
+    Span::current().record("status", response.status().as_u16());
+
+    Ok(response)
+}
+
+/// Turns bodies with a non-zero `errcode` into errors and deserializes the others into `T`.
+pub(crate) fn parse_body<T: DeserializeOwned>(
+    body: serde_json::Value,
+    context: &str,
+    url: &str,
+) -> Result<T, DingTalkError> {
+    #[derive(Serialize, Deserialize, Debug)]
+    struct Envelope {
+        #[serde(default)]
+        errcode: i64,
+        #[serde(default)]
+        errmsg: String,
+        #[serde(default)]
+        request_id: Option<String>,
+    }
+
+    if body.is_object() {
+        let envelope: Envelope = serde_json::from_value(body.clone())?;
+        if envelope.errcode != 0 {
+            error!(
+                "{}: {} {} ({})",
+                context, envelope.errcode, envelope.errmsg, url
+            );
+            return Err(DingTalkError::from_errcode(
+                envelope.errcode,
+                envelope.errmsg,
+                envelope.request_id,
+            )
+            .with_url(url));
+        }
+    }
+
+    decode(&body, url)
+}
+
+/// The keys of response fields holding personal data, masked in [DingTalkError::Decode].
+const PERSONAL_FIELDS: &[&str] = &[
+    "avatar",
+    "avatarUrl",
+    "email",
+    "mobile",
+    "name",
+    "nick",
+    "org_email",
+    "remark",
+    "stateCode",
+    "state_code",
+    "telephone",
+    "username",
+    "work_place",
+];
+/// The keys of the roster values masked in [DingTalkError::Decode], which hold personal data
+/// of any field, e.g. ID card numbers of `sys02-certNo`.
+const ROSTER_VALUE_FIELDS: &[&str] = &["value", "label"];
+/// How many characters of a body are kept in [DingTalkError::Decode].
+const MAX_CAPTURED_BODY: usize = 1024;
 
-        if !response.status().is_success() {
-            return Err(format!("Failed to get access token: {}", response.status()).into());
+/// Deserializes a response body, capturing the redacted body when it does not match `T`.
+pub(crate) fn decode<T: DeserializeOwned>(
+    body: &serde_json::Value,
+    url: &str,
+) -> Result<T, DingTalkError> {
+    T::deserialize(body).map_err(|source| {
+        let body = capture_body(body);
+        error!(
+            "failed to decode response of {}: {} (body: {})",
+            url, source, body
+        );
+        DingTalkError::Decode {
+            source,
+            url: url.to_string(),
+            body,
         }
+    })
+}
 
-        #[derive(Serialize, Deserialize, Debug)]
-        struct AccessToken {
-            #[serde(rename = "accessToken")]
-            pub access_token: String,
-            #[serde(rename = "refreshToken")]
-            pub refresh_token: String,
-            #[serde(rename = "corpId")]
-            pub corp_id: String,
-            #[serde(rename = "expireIn")]
-            pub expire_in: i32,
+/// Formats a body for errors, masking personal data and keeping the first characters.
+fn capture_body(body: &serde_json::Value) -> String {
+    fn redact_fields(value: &mut serde_json::Value, personal: &[&str]) {
+        match value {
+            serde_json::Value::Object(fields) => {
+                for (key, value) in fields.iter_mut() {
+                    if personal.contains(&key.as_str()) && !value.is_null() {
+                        *value = "***".into();
+                    } else if key == "field_value_list" {
+                        redact_fields(value, ROSTER_VALUE_FIELDS);
+                    } else {
+                        redact_fields(value, personal);
+                    }
+                }
+            }
+            serde_json::Value::Array(items) => items
+                .iter_mut()
+                .for_each(|item| redact_fields(item, personal)),
+            _ => {}
         }
-        let at = response.json::<AccessToken>().await?;
+    }
 
-        let mut rdb = self.rdb.get().await.unwrap();
-        cmd("SET")
-            .arg(&self.appid)
-            .arg(serde_json::to_string(&at)?)
-            .query_async::<()>(&mut rdb)
+    let mut body = body.clone();
+    redact_fields(&mut body, PERSONAL_FIELDS);
+    let body = body.to_string();
+    match body.char_indices().nth(MAX_CAPTURED_BODY) {
+        Some((end, _)) => format!("{}...", &body[..end]),
+        None => body,
+    }
+}
+
+/// Formats a mobile number for logs, keeping only the first three and last four digits.
+pub(crate) fn redact_mobile(mobile: &str) -> String {
+    let chars: Vec<char> = mobile.chars().collect();
+    if chars.len() <= 7 {
+        return "***".to_string();
+    }
+
+    let head: String = chars[..3].iter().collect();
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("{}****{}", head, tail)
+}
+
+/// Formats a request URL for errors and logs, hiding credentials passed in the query.
+fn redact_url(url: &Url) -> String {
+    let mut url = url.clone();
+    let secrets = ["access_token", "appsecret", "sign", "suite_access_token"];
+    if url
+        .query_pairs()
+        .any(|(key, _)| secrets.contains(&key.as_ref()))
+    {
+        let pairs: Vec<(String, String)> = url
+            .query_pairs()
+            .map(|(key, value)| {
+                let value = if secrets.contains(&key.as_ref()) {
+                    "***".to_string()
+                } else {
+                    value.into_owned()
+                };
+                (key.into_owned(), value)
+            })
+            .collect();
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+
+    url.to_string()
+}
+
+/// The user access token as returned by DingTalk and kept in the token store.
+#[derive(Serialize, Deserialize, Debug)]
+struct UserAccessToken {
+    #[serde(rename = "accessToken")]
+    access_token: String,
+    #[serde(rename = "refreshToken")]
+    refresh_token: String,
+    #[serde(rename = "corpId", default)]
+    corp_id: String,
+    #[serde(rename = "expireIn")]
+    expire_in: i64,
+    /// Millisecond timestamp of the expiry, missing in entries stored by older versions.
+    #[serde(rename = "expiresAt", default)]
+    expires_at: Option<i64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Endpoints, MemoryStore};
+    use std::sync::Arc;
+
+    #[test]
+    fn redirect_urls_use_the_login_endpoint() {
+        let dt = DingTalk::with_store(
+            "app".to_string(),
+            "secret".to_string(),
+            Arc::new(MemoryStore::new()),
+        );
+        let url = dt.get_redirect_url(
+            "https://example.com/callback".to_string(),
+            Some("state".to_string()),
+        );
+        assert_eq!(
+            url,
+            "https://login.dingtalk.com/oauth2/auth?redirect_uri=https%3A%2F%2Fexample.com%2Fcallback\
+             &response_type=code&client_id=app&scope=openid+corpid&state=state&prompt=consent"
+        );
+
+        let dt = dt.with_endpoints(Endpoints::with_base("http://localhost:8080/"));
+        let url = dt.get_redirect_url("https://example.com/callback".to_string(), None);
+        assert!(url.starts_with("http://localhost:8080/oauth2/auth?redirect_uri="));
+    }
+
+    /// Stores a user access token expiring in `expires_in` milliseconds.
+    async fn store_user_token(dt: &DingTalk, expires_in: i64) {
+        let at = UserAccessToken {
+            access_token: "stale".to_string(),
+            refresh_token: "refresh-1".to_string(),
+            corp_id: "corp".to_string(),
+            expire_in: 7200,
+            expires_at: Some(dt.clock.now_millis() + expires_in),
+        };
+        dt.store
+            .set(&dt.token_key(), &serde_json::to_string(&at).unwrap(), None)
             .await
             .unwrap();
+    }
 
-        Ok(at.corp_id) // 企业corpId
+    fn respond_user_token(mock: &crate::mock::Mock) {
+        mock.respond(
+            "v1.0/oauth2/userAccessToken",
+            serde_json::json!({
+                "accessToken": "fresh",
+                "refreshToken": "refresh-2",
+                "expireIn": 7200,
+            }),
+        );
     }
 
-    /// Get the access token for the application.
-    ///
-    /// The access token is stored in Redis by calling [set_app_access_token].
-    ///
-    /// # Returns
-    ///
-    /// A Result containing the access token as a string if the access token exists, otherwise an error string.
-    pub async fn get_app_access_token(&self) -> Result<String, Box<dyn std::error::Error>> {
-        let mut rdb = self.rdb.get().await.unwrap();
-        let value: Option<String> = cmd("GET")
-            .arg(&self.appid)
-            .query_async(&mut rdb)
-            .await
-            .unwrap_or(None);
-
-        #[derive(Serialize, Deserialize, Debug)]
-        struct AccessToken {
-            #[serde(rename = "accessToken")]
-            pub access_token: String,
-            #[serde(rename = "refreshToken")]
-            pub refresh_token: String,
-            #[serde(rename = "corpId")]
-            pub corp_id: String,
-            #[serde(rename = "expireIn")]
-            pub expire_in: i32,
+    #[tokio::test]
+    async fn expiring_user_tokens_are_refreshed() {
+        let mock = crate::mock::Mock::new();
+        respond_user_token(&mock);
+        let dt = mock.dingtalk();
+        // Within the default margin of five minutes.
+        store_user_token(&dt, 60_000).await;
+
+        assert_eq!(dt.get_app_access_token().await.unwrap(), "fresh");
+        let bodies = mock.bodies("v1.0/oauth2/userAccessToken");
+        assert_eq!(bodies.len(), 1);
+        assert_eq!(bodies[0]["grantType"], "refresh_token");
+        assert_eq!(bodies[0]["refreshToken"], "refresh-1");
+
+        let stored = dt.stored_user_access_token().await.unwrap();
+        assert_eq!(stored.refresh_token, "refresh-2");
+        assert!(!dt.is_expiring(&stored));
+    }
+
+    #[tokio::test]
+    async fn fresh_user_tokens_are_returned_unchanged() {
+        let mock = crate::mock::Mock::new();
+        let dt = mock.dingtalk();
+        store_user_token(&dt, 3_600_000).await;
+
+        assert_eq!(dt.get_app_access_token().await.unwrap(), "stale");
+        assert!(mock.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn legacy_user_tokens_are_refreshed_once() {
+        let mock = crate::mock::Mock::new();
+        respond_user_token(&mock);
+        let dt = mock.dingtalk();
+        // As stored by versions without `expiresAt`.
+        let legacy =
+            r#"{"accessToken":"stale","refreshToken":"refresh-1","corpId":"corp","expireIn":7200}"#;
+        dt.store.set(&dt.token_key(), legacy, None).await.unwrap();
+
+        assert_eq!(dt.get_app_access_token().await.unwrap(), "fresh");
+        assert_eq!(dt.get_app_access_token().await.unwrap(), "fresh");
+        let bodies = mock.bodies("v1.0/oauth2/userAccessToken");
+        assert_eq!(bodies.len(), 1);
+        assert_eq!(bodies[0]["refreshToken"], "refresh-1");
+    }
+
+    #[tokio::test]
+    async fn concurrent_user_token_refreshes_are_coalesced() {
+        let mock = crate::mock::Mock::new();
+        respond_user_token(&mock);
+        mock.delay(std::time::Duration::from_millis(20));
+        let dt = mock.dingtalk();
+        store_user_token(&dt, 0).await;
+
+        let tokens = futures::future::join_all((0..5).map(|_| dt.get_app_access_token())).await;
+        for token in tokens {
+            assert_eq!(token.unwrap(), "fresh");
         }
-        if let Some(bytes) = value {
-            let value: AccessToken = serde_json::from_str(&bytes).unwrap();
-            return Ok(value.access_token);
+        assert_eq!(mock.bodies("v1.0/oauth2/userAccessToken").len(), 1);
+
+        // A forced refresh sends the rotated refresh token.
+        dt.refresh_app_access_token().await.unwrap();
+        let bodies = mock.bodies("v1.0/oauth2/userAccessToken");
+        assert_eq!(bodies.len(), 2);
+        assert_eq!(bodies[1]["refreshToken"], "refresh-2");
+    }
+
+    #[test]
+    fn credentials_are_redacted() {
+        let url = Url::parse(
+            "https://oapi.dingtalk.com/robot/send?access_token=secret&timestamp=1&sign=abc",
+        )
+        .unwrap();
+        assert_eq!(
+            redact_url(&url),
+            "https://oapi.dingtalk.com/robot/send?access_token=***&timestamp=1&sign=***"
+        );
+
+        assert_eq!(redact_mobile("13812345678"), "138****5678");
+        assert_eq!(redact_mobile("1234"), "***");
+    }
+
+    #[test]
+    fn decode_errors_capture_the_redacted_body() {
+        #[derive(Deserialize, Debug)]
+        struct Profile {
+            #[allow(dead_code)]
+            userid: String,
         }
 
-        Err("Failed to get access token".into())
+        let body = serde_json::json!({ "userid": 1, "mobile": "13812345678", "dept": [1] });
+        match decode::<Profile>(&body, "https://oapi.dingtalk.com/topapi/v2/user/get") {
+            Err(DingTalkError::Decode { body, url, .. }) => {
+                assert_eq!(body, r#"{"dept":[1],"mobile":"***","userid":1}"#);
+                assert_eq!(url, "https://oapi.dingtalk.com/topapi/v2/user/get");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn captured_roster_bodies_mask_the_field_values() {
+        let body = serde_json::json!([{
+            "userid": "zhangsan",
+            "field_data_list": [{
+                "field_code": "sys02-certNo",
+                "field_name": "证件号码",
+                "field_value_list": [{ "value": "110101199001011234", "label": "110101199001011234", "item_index": 0 }],
+            }],
+        }]);
+
+        let captured: serde_json::Value = serde_json::from_str(&capture_body(&body)).unwrap();
+        assert_eq!(
+            captured[0]["field_data_list"][0],
+            serde_json::json!({
+                "field_code": "sys02-certNo",
+                "field_name": "证件号码",
+                "field_value_list": [{ "value": "***", "label": "***", "item_index": 0 }],
+            })
+        );
     }
 }
@@ -0,0 +1,259 @@
+//! The signing and encryption primitives of DingTalk, as pure functions.
+//!
+//! The clients of the crate use them internally; they are public for custom transports, e.g. a
+//! callback handler in another web framework or a robot webhook called without [crate::Robot].
+//!
+//! * [robot_sign] - the `sign` of robot webhooks and of messages pushed to robots.
+//! * [msg_signature], [encrypt_message] and [decrypt_message] - the encryption of the HTTP
+//!   event callbacks, see [crate::CallbackCrypto].
+//! * [jsapi_sign] - the signature of `dd.config`.
+
+use crate::error::DingTalkError;
+use aes::Aes256;
+use base64::alphabet;
+use base64::engine::general_purpose::{GeneralPurpose, GeneralPurposeConfig, STANDARD};
+use base64::Engine;
+use cbc::cipher::{block_padding::NoPadding, BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use hmac::{Hmac, Mac};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+
+/// DingTalk pads the plaintext with PKCS#7 to a multiple of 32 bytes, not of the AES block size.
+const PADDING_BLOCK: usize = 32;
+/// The length of the random prefix of callback plaintexts.
+pub const RANDOM_LEN: usize = 16;
+
+/// The `aes_key` is random, so the unused bits of its last character are not always zero.
+const AES_KEY_ENGINE: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_allow_trailing_bits(true),
+);
+
+fn sha1_hex(plain: &str) -> String {
+    Sha1::digest(plain.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Signs a robot webhook request.
+///
+/// [Documents](https://open.dingtalk.com/document/robots/customize-robot-security-settings)
+///
+/// DingTalk signs the messages it pushes to robots the same way, with the app secret.
+///
+/// # Arguments
+///
+/// * `secret` - The signing secret of the robot (`SEC...`), or the app secret.
+/// * `timestamp` - The Unix time in milliseconds.
+///
+/// # Returns
+///
+/// The base64 HMAC-SHA256 of `"{timestamp}\n{secret}"` keyed with the secret, not URL-encoded.
+pub fn robot_sign(secret: &str, timestamp: i64) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(format!("{}\n{}", timestamp, secret).as_bytes());
+    STANDARD.encode(mac.finalize().into_bytes())
+}
+
+/// Returns the `msg_signature` of an encrypted callback message: the sha1 hex digest of the
+/// token, the timestamp, the nonce and the ciphertext, sorted and concatenated.
+///
+/// [回调加解密](https://open.dingtalk.com/document/orgapp/callback-encryption-and-decryption)
+pub fn msg_signature(token: &str, timestamp: &str, nonce: &str, encrypt: &str) -> String {
+    let mut parts = [token, timestamp, nonce, encrypt];
+    parts.sort_unstable();
+    sha1_hex(&parts.concat())
+}
+
+/// Compares two signatures in time independent of where they differ, so that a forged signature
+/// cannot be guessed byte by byte.
+pub(crate) fn signatures_match(expected: &str, actual: &str) -> bool {
+    let (expected, actual) = (expected.as_bytes(), actual.as_bytes());
+    expected.len() == actual.len()
+        && expected
+            .iter()
+            .zip(actual)
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Decodes the 43 characters long `aes_key` of a callback into the AES key.
+///
+/// # Errors
+///
+/// Returns [DingTalkError::Config] if `aes_key` is not 43 characters of base64.
+pub fn decode_aes_key(aes_key: &str) -> Result<[u8; 32], DingTalkError> {
+    let decoded = AES_KEY_ENGINE
+        .decode(format!("{}=", aes_key))
+        .map_err(|e| DingTalkError::Config(format!("invalid callback aes_key: {}", e)))?;
+    let mut key = [0u8; 32];
+    if decoded.len() != key.len() {
+        return Err(DingTalkError::Config(
+            "invalid callback aes_key: expected 43 characters".to_string(),
+        ));
+    }
+    key.copy_from_slice(&decoded);
+    Ok(key)
+}
+
+/// The IV is the first 16 bytes of the key.
+fn iv(key: &[u8; 32]) -> [u8; 16] {
+    let mut iv = [0u8; 16];
+    iv.copy_from_slice(&key[..16]);
+    iv
+}
+
+/// Encrypts a callback message: AES-256-CBC of the random prefix, the big-endian length of the
+/// message, the message and the owner key.
+///
+/// # Arguments
+///
+/// * `key` - The key of [decode_aes_key].
+/// * `random` - Random bytes; they only need to be fixed in tests.
+/// * `message` - The plaintext, e.g. `success`.
+/// * `owner_key` - The corp ID, app key or suite key the message is encrypted for.
+///
+/// # Returns
+///
+/// The base64 ciphertext, the `encrypt` field of the message.
+pub fn encrypt_message(
+    key: &[u8; 32],
+    random: &[u8; RANDOM_LEN],
+    message: &str,
+    owner_key: &str,
+) -> Result<String, DingTalkError> {
+    let mut buffer = Vec::new();
+    buffer.extend_from_slice(random);
+    buffer.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    buffer.extend_from_slice(message.as_bytes());
+    buffer.extend_from_slice(owner_key.as_bytes());
+    let pad = PADDING_BLOCK - buffer.len() % PADDING_BLOCK;
+    buffer.extend(std::iter::repeat(pad as u8).take(pad));
+
+    let len = buffer.len();
+    cbc::Encryptor::<Aes256>::new(key.into(), &iv(key).into())
+        .encrypt_padded_mut::<NoPadding>(&mut buffer, len)
+        .map_err(|_| DingTalkError::Callback("failed to encrypt".to_string()))?;
+
+    Ok(STANDARD.encode(&buffer))
+}
+
+/// Decrypts the `encrypt` field of a callback message, without checking its signature.
+///
+/// # Returns
+///
+/// The plaintext and the owner key it was encrypted for.
+///
+/// # Errors
+///
+/// Returns [DingTalkError::Callback] if the ciphertext is malformed.
+pub fn decrypt_message(key: &[u8; 32], encrypt: &str) -> Result<(String, String), DingTalkError> {
+    let invalid = |reason: &str| DingTalkError::Callback(format!("invalid ciphertext: {}", reason));
+
+    let mut buffer = STANDARD
+        .decode(encrypt)
+        .map_err(|_| invalid("not base64"))?;
+    let plain = cbc::Decryptor::<Aes256>::new(key.into(), &iv(key).into())
+        .decrypt_padded_mut::<NoPadding>(&mut buffer)
+        .map_err(|_| invalid("not a multiple of the block size"))?;
+
+    let pad = *plain.last().ok_or_else(|| invalid("empty"))? as usize;
+    if pad == 0 || pad > PADDING_BLOCK || pad > plain.len() {
+        return Err(invalid("bad padding"));
+    }
+    let plain = &plain[..plain.len() - pad];
+
+    if plain.len() < RANDOM_LEN + 4 {
+        return Err(invalid("too short"));
+    }
+    let mut len = [0u8; 4];
+    len.copy_from_slice(&plain[RANDOM_LEN..RANDOM_LEN + 4]);
+    let len = u32::from_be_bytes(len) as usize;
+    let rest = &plain[RANDOM_LEN + 4..];
+    if len > rest.len() {
+        return Err(invalid("bad message length"));
+    }
+
+    let (message, owner_key) = rest.split_at(len);
+    let message =
+        String::from_utf8(message.to_vec()).map_err(|_| invalid("message is not UTF-8"))?;
+    let owner_key =
+        String::from_utf8(owner_key.to_vec()).map_err(|_| invalid("owner key is not UTF-8"))?;
+
+    Ok((message, owner_key))
+}
+
+/// Signs a page for `dd.config` with a JSAPI ticket.
+///
+/// [JSAPI鉴权](https://open.dingtalk.com/document/orgapp/jsapi-authentication)
+///
+/// # Arguments
+///
+/// * `ticket` - The JSAPI ticket of [crate::OrgApp::get_jsapi_ticket].
+/// * `url` - The URL of the page calling `dd.config`; the fragment is not signed.
+/// * `nonce` - A random string, passed to `dd.config` as `nonceStr`.
+/// * `timestamp` - The current Unix time in seconds, passed to `dd.config` as `timeStamp`.
+///
+/// # Returns
+///
+/// The lowercase hex sha1 digest of the sorted parameters.
+pub fn jsapi_sign(ticket: &str, url: &str, nonce: &str, timestamp: i64) -> String {
+    let url = url.split('#').next().unwrap_or(url);
+    sha1_hex(&format!(
+        "jsapi_ticket={}&noncestr={}&timestamp={}&url={}",
+        ticket, nonce, timestamp, url
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AES_KEY: &str = "o1w0aum42yaptlz8alnhwikjd3jenzt9cb9wmzptgus";
+
+    #[test]
+    fn robot_signatures_match_the_reference() {
+        assert_eq!(
+            robot_sign("SEC0123456789abcdef", 1700000000000),
+            "TSZbRFUuvaSQaRKUpF970OPCb2/LcQAP3wOvwZIzBZk="
+        );
+    }
+
+    #[test]
+    fn signatures_match_only_when_equal() {
+        let signature = msg_signature("token", "1700000000000", "nonce", "encrypt");
+        assert!(signatures_match(&signature, &signature.clone()));
+        assert!(!signatures_match(&signature, &signature[1..]));
+        assert!(!signatures_match(&signature, &signature.to_uppercase()));
+        assert!(!signatures_match(&signature, ""));
+    }
+
+    #[test]
+    fn callback_messages_match_the_reference() {
+        let key = decode_aes_key(AES_KEY).unwrap();
+        let encrypt = encrypt_message(&key, b"0123456789abcdef", "success", "corp").unwrap();
+        assert_eq!(encrypt, "WSbGdl4cHPt1ALTmzUYV7mBHQNWIWNtOCCowqG9IwGU=");
+        assert_eq!(
+            msg_signature("token", "1700000000000", "nonce", &encrypt),
+            "3f36c334515dcdaa47585b827304bc737580a593"
+        );
+
+        let (message, owner_key) = decrypt_message(&key, &encrypt).unwrap();
+        assert_eq!((message.as_str(), owner_key.as_str()), ("success", "corp"));
+
+        assert!(decode_aes_key("short").is_err());
+    }
+
+    #[test]
+    fn jsapi_signatures_ignore_the_fragment() {
+        let signature = jsapi_sign(
+            "ticket",
+            "https://example.com/app?a=1#/home",
+            "nonce",
+            1700000000,
+        );
+        assert_eq!(signature, "886f544a5810dd610bca7032fb4d176bcb9d0f04");
+    }
+}
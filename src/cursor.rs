@@ -0,0 +1,89 @@
+use crate::error::DingTalkError;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// An opaque position in a paged listing, for resuming long-running syncs after a restart.
+///
+/// Store the string of [Cursor::as_str] (or the serialized cursor) and pass it back through
+/// [Cursor::parse] to continue where the previous run stopped. A cursor is only valid for the
+/// listing, and its filters, it was returned by.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(transparent)]
+pub struct Cursor(String);
+
+impl Cursor {
+    pub(crate) fn new(listing: &str, position: i64) -> Self {
+        Cursor(URL_SAFE_NO_PAD.encode(format!("{}\n{}", listing, position)))
+    }
+
+    /// Restores a cursor from its string form.
+    ///
+    /// # Errors
+    ///
+    /// Returns [DingTalkError::Config] if the string is not a cursor.
+    pub fn parse(value: &str) -> Result<Self, DingTalkError> {
+        let cursor = Cursor(value.to_string());
+        cursor.decode()?;
+        Ok(cursor)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    fn decode(&self) -> Result<(String, i64), DingTalkError> {
+        let invalid = || DingTalkError::Config(format!("invalid cursor {}", self.0));
+
+        let bytes = URL_SAFE_NO_PAD.decode(&self.0).map_err(|_| invalid())?;
+        let value = String::from_utf8(bytes).map_err(|_| invalid())?;
+        let (listing, position) = value.rsplit_once('\n').ok_or_else(invalid)?;
+        let position = position.parse().map_err(|_| invalid())?;
+
+        Ok((listing.to_string(), position))
+    }
+
+    /// Returns the position of the cursor in the given listing.
+    ///
+    /// # Errors
+    ///
+    /// Returns [DingTalkError::Config] if the cursor belongs to another listing.
+    pub(crate) fn position(&self, listing: &str) -> Result<i64, DingTalkError> {
+        let (origin, position) = self.decode()?;
+        if origin != listing {
+            return Err(DingTalkError::Config(format!(
+                "cursor of {} used for {}",
+                origin, listing
+            )));
+        }
+
+        Ok(position)
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A page of user IDs and the cursor of the next page, `None` after the last page.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct UserIdPage {
+    pub user_ids: Vec<String>,
+    pub next: Option<Cursor>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursors_round_trip_within_their_listing() {
+        let cursor = Cursor::parse(Cursor::new("on_job:2,3", 100).as_str()).unwrap();
+
+        assert_eq!(cursor.position("on_job:2,3").unwrap(), 100);
+        assert!(cursor.position("off_job").is_err());
+        assert!(Cursor::parse("not a cursor").is_err());
+    }
+}
@@ -0,0 +1,64 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+tokio::task_local! {
+    static DEADLINE: Instant;
+}
+
+/// Runs a logical operation, e.g. [OrgApp::get_userinfo](crate::OrgApp::get_userinfo) which makes
+/// two HTTP calls, within an overall deadline.
+///
+/// Every request sent by the operation is given the remaining budget as its timeout, and requests
+/// which would start after the deadline are not sent. Both fail with
+/// [DingTalkError::DeadlineExceeded](crate::DingTalkError::DeadlineExceeded). A nested call can
+/// only shorten the deadline of the enclosing one.
+///
+/// # Example
+///
+/// ```ignore
+/// let budget = Instant::now() + Duration::from_millis(800);
+/// let user = with_deadline(budget, app.get_userinfo(code)).await?;
+/// ```
+pub async fn with_deadline<F: Future>(deadline: Instant, operation: F) -> F::Output {
+    let deadline = match current() {
+        Some(outer) => outer.min(deadline),
+        None => deadline,
+    };
+    DEADLINE.scope(deadline, operation).await
+}
+
+/// Like [with_deadline], with a budget starting now.
+pub async fn with_timeout<F: Future>(budget: Duration, operation: F) -> F::Output {
+    with_deadline(Instant::now() + budget, operation).await
+}
+
+/// Returns the deadline of the running operation, if any.
+fn current() -> Option<Instant> {
+    DEADLINE.try_with(|deadline| *deadline).ok()
+}
+
+/// Returns the budget left to the running operation: `None` without deadline, zero once it has
+/// passed.
+pub(crate) fn remaining() -> Option<Duration> {
+    current().map(|deadline| deadline.saturating_duration_since(Instant::now()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn nested_deadlines_only_shorten_the_budget() {
+        assert_eq!(remaining(), None);
+
+        let left = with_timeout(Duration::from_secs(1), async {
+            with_timeout(Duration::from_secs(60), async { remaining() }).await
+        })
+        .await
+        .unwrap();
+        assert!(left <= Duration::from_secs(1));
+
+        let left = with_deadline(Instant::now(), async { remaining() }).await;
+        assert_eq!(left, Some(Duration::ZERO));
+    }
+}
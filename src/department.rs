@@ -0,0 +1,1095 @@
+use crate::catalog::oapi_endpoint;
+use crate::organization::{
+    Department, EmployeeUser, LeaderInDepartment, OrgApp, UserGetProfileResponse,
+};
+use crate::pacing::Pacer;
+use crate::{cache::ProfileKind, coalesce::EndpointClass, error::DingTalkError};
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+use tracing::info;
+
+/// How many department details [OrgApp::get_departments] requests at the same time.
+const MAX_CONCURRENT_REQUESTS: usize = 8;
+/// The ID of the root department of every organization.
+pub(crate) const ROOT_DEPT_ID: i64 = 1;
+
+/// A department as listed by `topapi/v2/department/listsub`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DeptInfo {
+    pub dept_id: i64,
+    pub name: String,
+    #[serde(default)]
+    pub parent_id: i64,
+    #[serde(default)]
+    pub create_dept_group: bool,
+    #[serde(default)]
+    pub auto_add_user: bool,
+    #[serde(default)]
+    pub order: Option<i64>,
+}
+
+/// The details of a department as returned by `topapi/v2/department/get`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DeptDetail {
+    pub dept_id: i64,
+    pub name: String,
+    #[serde(default)]
+    pub parent_id: i64,
+    #[serde(default)]
+    pub create_dept_group: bool,
+    #[serde(default)]
+    pub auto_add_user: bool,
+    #[serde(default)]
+    pub order: Option<i64>,
+    #[serde(default)]
+    pub source_identifier: Option<String>,
+    #[serde(default)]
+    pub brief: Option<String>,
+    /// The user ID of the owner of the department group chat.
+    #[serde(default)]
+    pub org_dept_owner: Option<String>,
+    /// The user IDs of the managers (主管) of the department.
+    #[serde(default)]
+    pub dept_manager_userid_list: Vec<String>,
+    /// The chat ID of the department group, if `create_dept_group` is set.
+    #[serde(default)]
+    pub dept_group_chat_id: Option<String>,
+    /// Whether the members of sub-departments are in the department group too.
+    #[serde(default)]
+    pub group_contain_sub_dept: bool,
+    /// Whether the department is hidden from the contacts of the organization.
+    #[serde(default)]
+    pub hide_dept: bool,
+    /// Whether the members only see the contacts of the permitted users and departments, see
+    /// `outer_permit_users` and `outer_permit_depts`.
+    #[serde(default)]
+    pub outer_dept: bool,
+    /// Whether the members of an `outer_dept` only see themselves.
+    #[serde(default)]
+    pub outer_dept_only_self: bool,
+    #[serde(default)]
+    pub outer_permit_users: Vec<String>,
+    #[serde(default)]
+    pub outer_permit_depts: Vec<i64>,
+    /// The extension attributes of the department, a JSON object as a string.
+    #[serde(rename = "extention", default)]
+    pub extension: Option<String>,
+}
+
+impl DeptDetail {
+    /// Returns who approves for the department: its managers. Departments without a manager
+    /// have no approvers; the owner of the department group (`org_dept_owner`) is not one.
+    pub fn approvers(&self) -> Vec<&str> {
+        self.dept_manager_userid_list
+            .iter()
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+/// DingTalk expects the ID lists of the department write APIs as a comma separated string.
+fn comma_separated<T: Display, S: Serializer>(
+    values: &Option<Vec<T>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match values {
+        Some(values) => serializer.serialize_str(
+            &values
+                .iter()
+                .map(|value| value.to_string())
+                .collect::<Vec<String>>()
+                .join(","),
+        ),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Parameters of [OrgApp::create_department], built from the required name and parent:
+///
+/// ```
+/// use async_dingtalk::CreateDepartmentRequest;
+///
+/// let request = CreateDepartmentRequest::new("研发部", 1)
+///     .order(10)
+///     .hide_dept(true)
+///     .source_identifier("rd");
+/// ```
+///
+/// [Documents](https://open.dingtalk.com/document/orgapp/create-a-department-v2)
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CreateDepartmentRequest {
+    pub name: String,
+    pub parent_id: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub create_dept_group: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hide_dept: Option<bool>,
+    /// The users who still see a hidden department.
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "comma_separated"
+    )]
+    pub user_permits: Option<Vec<String>>,
+    /// The departments whose members still see a hidden department.
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "comma_separated"
+    )]
+    pub dept_permits: Option<Vec<i64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outer_dept: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outer_dept_only_self: Option<bool>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "comma_separated"
+    )]
+    pub outer_permit_users: Option<Vec<String>>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "comma_separated"
+    )]
+    pub outer_permit_depts: Option<Vec<i64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_identifier: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub brief: Option<String>,
+    /// A key making retries of [OrgApp::create_department] safe: a request with a key already
+    /// used within 24 hours returns the department created then instead of creating another one.
+    #[serde(skip)]
+    pub idempotency_key: Option<String>,
+}
+
+impl CreateDepartmentRequest {
+    pub fn new(name: impl Into<String>, parent_id: i64) -> Self {
+        CreateDepartmentRequest {
+            name: name.into(),
+            parent_id,
+            ..Default::default()
+        }
+    }
+
+    /// Sets the sort order among the sibling departments, smaller first.
+    pub fn order(mut self, order: i64) -> Self {
+        self.order = Some(order);
+        self
+    }
+
+    /// Creates a department group chat as well.
+    pub fn create_dept_group(mut self, create_dept_group: bool) -> Self {
+        self.create_dept_group = Some(create_dept_group);
+        self
+    }
+
+    /// Hides the department from the contacts, except for the given users and departments.
+    pub fn hide_dept(mut self, hide_dept: bool) -> Self {
+        self.hide_dept = Some(hide_dept);
+        self
+    }
+
+    pub fn user_permits(mut self, user_ids: Vec<String>) -> Self {
+        self.user_permits = Some(user_ids);
+        self
+    }
+
+    pub fn dept_permits(mut self, dept_ids: Vec<i64>) -> Self {
+        self.dept_permits = Some(dept_ids);
+        self
+    }
+
+    /// Limits the contacts the members see to the given users and departments.
+    pub fn outer_permissions(mut self, user_ids: Vec<String>, dept_ids: Vec<i64>) -> Self {
+        self.outer_dept = Some(true);
+        self.outer_permit_users = Some(user_ids);
+        self.outer_permit_depts = Some(dept_ids);
+        self
+    }
+
+    /// Limits the contacts the members see to themselves.
+    pub fn outer_dept_only_self(mut self, only_self: bool) -> Self {
+        self.outer_dept_only_self = Some(only_self);
+        self
+    }
+
+    /// Sets the ID of the department in the source system, e.g. the HR system.
+    pub fn source_identifier(mut self, source_identifier: impl Into<String>) -> Self {
+        self.source_identifier = Some(source_identifier.into());
+        self
+    }
+
+    pub fn brief(mut self, brief: impl Into<String>) -> Self {
+        self.brief = Some(brief.into());
+        self
+    }
+
+    pub fn idempotency_key(mut self, key: impl Into<String>) -> Self {
+        self.idempotency_key = Some(key.into());
+        self
+    }
+}
+
+/// Parameters of [OrgApp::update_department]. Only fields that are set are sent to DingTalk:
+///
+/// ```
+/// use async_dingtalk::UpdateDepartmentRequest;
+///
+/// // Moves the department below department 2 and renames it.
+/// let request = UpdateDepartmentRequest::new()
+///     .name("平台研发部")
+///     .parent_id(2);
+/// ```
+///
+/// [Documents](https://open.dingtalk.com/document/orgapp/update-a-department-v2)
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct UpdateDepartmentRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Moves the department below another department.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hide_dept: Option<bool>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "comma_separated"
+    )]
+    pub user_permits: Option<Vec<String>>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "comma_separated"
+    )]
+    pub dept_permits: Option<Vec<i64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outer_dept: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outer_dept_only_self: Option<bool>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "comma_separated"
+    )]
+    pub outer_permit_users: Option<Vec<String>>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "comma_separated"
+    )]
+    pub outer_permit_depts: Option<Vec<i64>>,
+    /// Replaces the managers (主管) of the department.
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "comma_separated"
+    )]
+    pub dept_manager_userid_list: Option<Vec<String>>,
+    /// The user ID of the owner of the department group chat.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub org_dept_owner: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub create_dept_group: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_contain_sub_dept: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_identifier: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub brief: Option<String>,
+}
+
+impl UpdateDepartmentRequest {
+    pub fn new() -> Self {
+        UpdateDepartmentRequest::default()
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Moves the department below another department.
+    pub fn parent_id(mut self, parent_id: i64) -> Self {
+        self.parent_id = Some(parent_id);
+        self
+    }
+
+    /// Sets the sort order among the sibling departments, smaller first.
+    pub fn order(mut self, order: i64) -> Self {
+        self.order = Some(order);
+        self
+    }
+
+    /// Hides the department from the contacts, except for the given users and departments.
+    pub fn hide_dept(mut self, hide_dept: bool) -> Self {
+        self.hide_dept = Some(hide_dept);
+        self
+    }
+
+    pub fn user_permits(mut self, user_ids: Vec<String>) -> Self {
+        self.user_permits = Some(user_ids);
+        self
+    }
+
+    pub fn dept_permits(mut self, dept_ids: Vec<i64>) -> Self {
+        self.dept_permits = Some(dept_ids);
+        self
+    }
+
+    /// Limits the contacts the members see to the given users and departments.
+    pub fn outer_permissions(mut self, user_ids: Vec<String>, dept_ids: Vec<i64>) -> Self {
+        self.outer_dept = Some(true);
+        self.outer_permit_users = Some(user_ids);
+        self.outer_permit_depts = Some(dept_ids);
+        self
+    }
+
+    /// Limits the contacts the members see to themselves.
+    pub fn outer_dept_only_self(mut self, only_self: bool) -> Self {
+        self.outer_dept_only_self = Some(only_self);
+        self
+    }
+
+    /// Replaces the managers (主管) of the department.
+    pub fn dept_managers(mut self, user_ids: Vec<String>) -> Self {
+        self.dept_manager_userid_list = Some(user_ids);
+        self
+    }
+
+    /// Sets the owner of the department group chat.
+    pub fn org_dept_owner(mut self, user_id: impl Into<String>) -> Self {
+        self.org_dept_owner = Some(user_id.into());
+        self
+    }
+
+    /// Creates a department group chat.
+    pub fn create_dept_group(mut self, create_dept_group: bool) -> Self {
+        self.create_dept_group = Some(create_dept_group);
+        self
+    }
+
+    /// Includes the members of the sub-departments in the department group chat.
+    pub fn group_contain_sub_dept(mut self, contain_sub_dept: bool) -> Self {
+        self.group_contain_sub_dept = Some(contain_sub_dept);
+        self
+    }
+
+    /// Sets the ID of the department in the source system, e.g. the HR system.
+    pub fn source_identifier(mut self, source_identifier: impl Into<String>) -> Self {
+        self.source_identifier = Some(source_identifier.into());
+        self
+    }
+
+    pub fn brief(mut self, brief: impl Into<String>) -> Self {
+        self.brief = Some(brief.into());
+        self
+    }
+}
+
+/// What is left in a department, see [OrgApp::check_department_deletion].
+///
+/// DingTalk refuses to delete departments which still have members or sub-departments.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct DepartmentContents {
+    pub dept_id: i64,
+    /// The IDs of the direct sub-departments.
+    pub sub_dept_ids: Vec<i64>,
+    /// The user IDs of the direct members.
+    pub user_ids: Vec<String>,
+}
+
+impl DepartmentContents {
+    /// Returns whether the department can be deleted.
+    pub fn is_empty(&self) -> bool {
+        self.sub_dept_ids.is_empty() && self.user_ids.is_empty()
+    }
+}
+
+/// A department of a user together with the user's position in it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DepartmentMembership {
+    pub dept_id: i64,
+    /// The sort order of the user within the department, if DingTalk returned one.
+    pub order: Option<i64>,
+    pub is_leader: bool,
+}
+
+impl EmployeeUser {
+    /// Returns `dept_id_list`, `dept_order_list` and `leader_in_dept` combined into one entry
+    /// per department, in the order of `dept_id_list`, as deserialized.
+    pub fn memberships(&self) -> &[DepartmentMembership] {
+        &self.memberships
+    }
+}
+
+impl UserGetProfileResponse {
+    /// Returns `dept_id_list`, `dept_order_list` and `leader_in_dept` combined into one entry
+    /// per department, in the order of `dept_id_list`, as deserialized.
+    pub fn memberships(&self) -> &[DepartmentMembership] {
+        &self.memberships
+    }
+}
+
+// The users derive their implementations with `#[serde(remote = "Self")]` and combine the
+// departments once they are deserialized.
+impl Serialize for EmployeeUser {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        EmployeeUser::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for EmployeeUser {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut user = EmployeeUser::deserialize(deserializer)?;
+        user.memberships = memberships(
+            &user.dept_id_list,
+            &user.dept_order_list,
+            user.leader_in_dept.as_deref().unwrap_or_default(),
+        );
+        Ok(user)
+    }
+}
+
+impl Serialize for UserGetProfileResponse {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        UserGetProfileResponse::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for UserGetProfileResponse {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut user = UserGetProfileResponse::deserialize(deserializer)?;
+        user.memberships = memberships(
+            &user.dept_id_list,
+            &user.dept_order_list,
+            &user.leader_in_dept,
+        );
+        Ok(user)
+    }
+}
+
+fn memberships(
+    dept_ids: &[i32],
+    orders: &[Department],
+    leaders: &[LeaderInDepartment],
+) -> Vec<DepartmentMembership> {
+    dept_ids
+        .iter()
+        .map(|&dept_id| DepartmentMembership {
+            dept_id: dept_id.into(),
+            order: orders
+                .iter()
+                .find(|order| order.id == dept_id)
+                .map(|order| order.sort_id),
+            is_leader: leaders
+                .iter()
+                .any(|leader| leader.id == dept_id && leader.leader),
+        })
+        .collect()
+}
+
+impl OrgApp {
+    /// Lists the direct sub-departments of a department.
+    ///
+    /// [获取部门列表](https://open.dingtalk.com/document/orgapp/obtain-the-department-list-v2)
+    ///
+    /// # Arguments
+    ///
+    /// * `parent_id` - The ID of the parent department, `1` for the root department.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the sub-departments if successful, otherwise a `DingTalkError`.
+    pub async fn list_departments(&self, parent_id: i64) -> Result<Vec<DeptInfo>, DingTalkError> {
+        let params = serde_json::json!({ "dept_id": parent_id, "language": self.language });
+        self.post_oapi("topapi/v2/department/listsub", &params)
+            .await
+    }
+
+    oapi_endpoint! {
+        /// Lists the IDs of the direct sub-departments of a department.
+        ///
+        /// [获取子部门ID列表](https://open.dingtalk.com/document/orgapp/obtain-a-sub-department-id-list-v2)
+        pub fn list_sub_department_ids(dept_id: i64) -> Vec<i64>
+            = "topapi/v2/department/listsubid" => dept_id_list;
+    }
+
+    oapi_endpoint! {
+        /// Lists the department path of a department: its ID followed by the IDs of its
+        /// ancestors, ending with the root department `1`.
+        ///
+        /// [获取指定部门的所有父部门列表](https://open.dingtalk.com/document/orgapp/query-the-list-of-all-parent-departments-of-a-department)
+        pub fn get_parent_departments_by_dept(dept_id: i64) -> Vec<i64>
+            = "topapi/v2/department/listparentbydept" => parent_id_list;
+    }
+
+    /// Lists the department paths of a user, one for each department of the user.
+    ///
+    /// [获取指定用户的所有父部门列表](https://open.dingtalk.com/document/orgapp/queries-the-list-of-all-parent-departments-of-a-user)
+    ///
+    /// # Arguments
+    ///
+    /// * `userid` - The user ID of the user.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the paths, each starting with a department of the user and ending
+    /// with the root department `1`.
+    pub async fn get_parent_departments_by_user(
+        &self,
+        userid: String,
+    ) -> Result<Vec<Vec<i64>>, DingTalkError> {
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Parents {
+            parent_dept_id_list: Vec<i64>,
+        }
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Response {
+            parent_list: Vec<Parents>,
+        }
+
+        let params = serde_json::json!({ "userid": userid });
+        let res: Response = self
+            .post_oapi("topapi/v2/department/listparentbyuser", &params)
+            .await?;
+
+        Ok(res
+            .parent_list
+            .into_iter()
+            .map(|parents| parents.parent_dept_id_list)
+            .collect())
+    }
+
+    /// Streams every department of the organization below the root department, breadth first,
+    /// listing the sub-departments of each department as the stream is consumed.
+    ///
+    /// [获取部门列表](https://open.dingtalk.com/document/orgapp/obtain-the-department-list-v2)
+    ///
+    /// Waits the [pace](OrgApp::with_pace) between requests and retries throttled requests. The
+    /// root department itself is not yielded.
+    pub fn walk_department_tree(&self) -> impl Stream<Item = Result<DeptInfo, DingTalkError>> + '_ {
+        let start = (
+            VecDeque::from(vec![ROOT_DEPT_ID]),
+            VecDeque::<DeptInfo>::new(),
+            Pacer::new(self.pace),
+        );
+        stream::try_unfold(
+            start,
+            move |(mut parents, mut pending, mut pacer)| async move {
+                loop {
+                    if let Some(dept) = pending.pop_front() {
+                        parents.push_back(dept.dept_id);
+                        return Ok(Some((dept, (parents, pending, pacer))));
+                    }
+                    let parent_id = match parents.pop_front() {
+                        Some(parent_id) => parent_id,
+                        None => return Ok::<_, DingTalkError>(None),
+                    };
+                    pending.extend(pacer.run(|| self.list_departments(parent_id)).await?);
+                }
+            },
+        )
+    }
+
+    /// Creates a department.
+    ///
+    /// [创建部门](https://open.dingtalk.com/document/orgapp/create-a-department-v2)
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The department to create.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the ID of the new department if successful, otherwise an error.
+    ///
+    /// With an [idempotency key](CreateDepartmentRequest::idempotency_key), a retry after an
+    /// unanswered request looks for a sub-department of the same name before creating it again.
+    pub async fn create_department(
+        &self,
+        request: CreateDepartmentRequest,
+    ) -> Result<i64, DingTalkError> {
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Response {
+            dept_id: i64,
+        }
+
+        let dept_id = self
+            .run_idempotent(
+                "create_department",
+                request.idempotency_key.as_deref(),
+                || async {
+                    let siblings = self.list_departments(request.parent_id).await?;
+                    Ok(siblings
+                        .into_iter()
+                        .find(|dept| dept.name == request.name)
+                        .map(|dept| dept.dept_id))
+                },
+                || async {
+                    let res: Response = self
+                        .post_oapi("topapi/v2/department/create", &request)
+                        .await?;
+                    Ok(res.dept_id)
+                },
+            )
+            .await?;
+
+        info!("create_department: {}", dept_id);
+
+        Ok(dept_id)
+    }
+
+    /// Updates the given fields of a department, e.g. to rename or move it.
+    ///
+    /// [更新部门](https://open.dingtalk.com/document/orgapp/update-a-department-v2)
+    ///
+    /// # Arguments
+    ///
+    /// * `dept_id` - The ID of the department to update.
+    /// * `request` - The fields to update; unset fields are left untouched.
+    ///
+    /// # Returns
+    ///
+    /// An empty `Result` if successful, otherwise an error.
+    pub async fn update_department(
+        &self,
+        dept_id: i64,
+        request: UpdateDepartmentRequest,
+    ) -> Result<(), DingTalkError> {
+        #[derive(Serialize, Debug)]
+        struct Params {
+            dept_id: i64,
+            language: String,
+            #[serde(flatten)]
+            request: UpdateDepartmentRequest,
+        }
+        let params = Params {
+            dept_id,
+            language: self.language.clone(),
+            request,
+        };
+        self.post_oapi::<serde_json::Value>("topapi/v2/department/update", &params)
+            .await?;
+
+        if let Some(cache) = &self.profile_cache {
+            cache
+                .discard(
+                    &self.key_prefix,
+                    ProfileKind::Department,
+                    &self.corp_id,
+                    &dept_id.to_string(),
+                )
+                .await;
+        }
+        info!("update_department: {}", dept_id);
+
+        Ok(())
+    }
+
+    /// Lists the sub-departments and members left in a department before deleting it.
+    ///
+    /// [获取子部门ID列表](https://open.dingtalk.com/document/orgapp/obtain-a-sub-department-id-list-v2)
+    /// [获取部门用户userid列表](https://open.dingtalk.com/document/orgapp/query-the-list-of-department-userids)
+    ///
+    /// # Arguments
+    ///
+    /// * `dept_id` - The ID of the department.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `DepartmentContents` if successful, otherwise a `DingTalkError`.
+    pub async fn check_department_deletion(
+        &self,
+        dept_id: i64,
+    ) -> Result<DepartmentContents, DingTalkError> {
+        let (sub_dept_ids, user_ids) = futures::try_join!(
+            self.list_sub_department_ids(dept_id),
+            self.list_department_user_ids(dept_id)
+        )?;
+
+        Ok(DepartmentContents {
+            dept_id,
+            sub_dept_ids,
+            user_ids,
+        })
+    }
+
+    /// Deletes a department.
+    ///
+    /// [删除部门](https://open.dingtalk.com/document/orgapp/delete-a-department-v2)
+    ///
+    /// The department must be empty, which [OrgApp::check_department_deletion] tells beforehand.
+    ///
+    /// # Arguments
+    ///
+    /// * `dept_id` - The ID of the department to delete.
+    ///
+    /// # Returns
+    ///
+    /// An empty `Result` if successful, otherwise an error.
+    pub async fn delete_department(&self, dept_id: i64) -> Result<(), DingTalkError> {
+        let params = serde_json::json!({ "dept_id": dept_id });
+        self.post_oapi::<serde_json::Value>("topapi/v2/department/delete", &params)
+            .await?;
+
+        if let Some(cache) = &self.profile_cache {
+            cache
+                .discard(
+                    &self.key_prefix,
+                    ProfileKind::Department,
+                    &self.corp_id,
+                    &dept_id.to_string(),
+                )
+                .await;
+        }
+        info!("delete_department: {}", dept_id);
+
+        Ok(())
+    }
+
+    /// Retrieves the details of several departments.
+    ///
+    /// [获取部门详情](https://open.dingtalk.com/document/orgapp/query-department-details0-v2)
+    ///
+    /// Up to 8 departments are requested at the same time. Duplicate IDs are fetched once.
+    ///
+    /// # Arguments
+    ///
+    /// * `dept_ids` - The IDs of the departments, e.g. the `dept_id_list` of a user.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the departments keyed by ID if successful, otherwise the first
+    /// `DingTalkError` encountered.
+    pub async fn get_departments(
+        &self,
+        dept_ids: &[i64],
+    ) -> Result<HashMap<i64, DeptDetail>, DingTalkError> {
+        let mut unique = dept_ids.to_vec();
+        unique.sort_unstable();
+        unique.dedup();
+
+        stream::iter(unique)
+            .map(|dept_id| async move {
+                self.get_department(dept_id)
+                    .await
+                    .map(|dept| (dept_id, dept))
+            })
+            .buffer_unordered(MAX_CONCURRENT_REQUESTS)
+            .try_collect()
+            .await
+    }
+
+    /// Retrieves the details of a department, including its managers, its visibility settings
+    /// and its extension attributes.
+    ///
+    /// [获取部门详情](https://open.dingtalk.com/document/orgapp/query-department-details0-v2)
+    ///
+    /// Departments in the profile cache are not requested again.
+    ///
+    /// # Arguments
+    ///
+    /// * `dept_id` - The ID of the department, `1` for the root department.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `DeptDetail` if successful, otherwise a `DingTalkError`.
+    pub async fn get_department(&self, dept_id: i64) -> Result<DeptDetail, DingTalkError> {
+        let id = dept_id.to_string();
+        if let Some(cache) = &self.profile_cache {
+            if let Some(dept) = cache
+                .get(
+                    &self.key_prefix,
+                    ProfileKind::Department,
+                    &self.corp_id,
+                    &id,
+                )
+                .await
+            {
+                return Ok(dept);
+            }
+        }
+
+        let key = format!("{}:topapi/v2/department/get:{}", self.corp_id, dept_id);
+        let dept: DeptDetail = self
+            .coalescer
+            .run(EndpointClass::Department, key, || async {
+                let params = serde_json::json!({ "dept_id": dept_id, "language": self.language });
+                self.post_oapi("topapi/v2/department/get", &params).await
+            })
+            .await?;
+
+        if let Some(cache) = &self.profile_cache {
+            cache
+                .set(
+                    &self.key_prefix,
+                    ProfileKind::Department,
+                    &self.corp_id,
+                    &id,
+                    &dept,
+                )
+                .await;
+        }
+
+        Ok(dept)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{FailingStore, Mock};
+    use crate::ProfileCache;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    #[test]
+    fn create_requests_send_id_lists_as_strings() {
+        let request = CreateDepartmentRequest::new("研发部", 1)
+            .order(10)
+            .outer_permissions(vec!["zhangsan".to_string(), "lisi".to_string()], vec![2, 3])
+            .idempotency_key("rd");
+
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            serde_json::json!({
+                "name": "研发部",
+                "parent_id": 1,
+                "order": 10,
+                "outer_dept": true,
+                "outer_permit_users": "zhangsan,lisi",
+                "outer_permit_depts": "2,3",
+            })
+        );
+    }
+
+    #[test]
+    fn approvers_are_the_managers() {
+        let mut dept = crate::fixtures::dept_detail();
+        assert_eq!(dept.approvers(), vec!["manager4220"]);
+
+        dept.dept_manager_userid_list.clear();
+        assert!(dept.org_dept_owner.is_some());
+        assert!(dept.approvers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn deletion_checks_list_sub_departments_and_members() {
+        let mock = Mock::new();
+        mock.respond(
+            "topapi/v2/department/listsubid",
+            json!({ "errcode": 0, "result": { "dept_id_list": [] } }),
+        );
+        mock.respond(
+            "topapi/user/listid",
+            json!({ "errcode": 0, "result": { "userid_list": ["zhangsan"] } }),
+        );
+        let app = mock.app().await;
+
+        let contents = app.check_department_deletion(2).await.unwrap();
+        app.delete_department(2).await.unwrap();
+
+        assert_eq!(contents.user_ids, vec!["zhangsan"]);
+        assert!(!contents.is_empty());
+        assert_eq!(
+            mock.bodies("topapi/v2/department/listsubid"),
+            vec![json!({ "dept_id": 2 })]
+        );
+        assert_eq!(
+            mock.bodies("topapi/user/listid"),
+            vec![json!({ "dept_id": 2 })]
+        );
+        assert_eq!(
+            mock.bodies("topapi/v2/department/delete"),
+            vec![json!({ "dept_id": 2 })]
+        );
+    }
+
+    #[tokio::test]
+    async fn parent_chains_are_requested_by_department_and_user() {
+        let mock = Mock::new();
+        mock.respond(
+            "topapi/v2/department/listparentbydept",
+            json!({ "errcode": 0, "result": { "parent_id_list": [2, 1] } }),
+        );
+        mock.respond(
+            "topapi/v2/department/listparentbyuser",
+            json!({ "errcode": 0, "result": { "parent_list": [{ "parent_dept_id_list": [2, 1] }] } }),
+        );
+        let app = mock.app().await;
+
+        assert_eq!(
+            app.get_parent_departments_by_dept(2).await.unwrap(),
+            vec![2, 1]
+        );
+        assert_eq!(
+            app.get_parent_departments_by_user("zhangsan".to_string())
+                .await
+                .unwrap(),
+            vec![vec![2, 1]]
+        );
+        assert_eq!(
+            mock.bodies("topapi/v2/department/listparentbydept"),
+            vec![json!({ "dept_id": 2 })]
+        );
+        assert_eq!(
+            mock.bodies("topapi/v2/department/listparentbyuser"),
+            vec![json!({ "userid": "zhangsan" })]
+        );
+    }
+
+    #[test]
+    fn memberships_are_combined_when_deserializing() {
+        let user = crate::fixtures::employee_user();
+        assert_eq!(
+            user.memberships(),
+            [
+                DepartmentMembership {
+                    dept_id: 1,
+                    order: Some(176294576350761500),
+                    is_leader: false,
+                },
+                DepartmentMembership {
+                    dept_id: 500123456,
+                    order: Some(176294576350761512),
+                    is_leader: true,
+                },
+            ]
+        );
+
+        let profile = crate::fixtures::user_get_profile();
+        assert_eq!(
+            profile.memberships(),
+            [DepartmentMembership {
+                dept_id: 1,
+                order: Some(176294576350761500),
+                is_leader: true,
+            }]
+        );
+    }
+
+    #[test]
+    fn list_entries_have_memberships_without_orders_or_leaders() {
+        let user: EmployeeUser = serde_json::from_value(json!({
+            "userid": "lisi",
+            "name": "李四",
+            "dept_id_list": [2],
+        }))
+        .unwrap();
+        assert_eq!(
+            user.memberships(),
+            [DepartmentMembership {
+                dept_id: 2,
+                order: None,
+                is_leader: false,
+            }]
+        );
+
+        let value = serde_json::to_value(&user).unwrap();
+        assert!(value.get("memberships").is_none());
+    }
+
+    #[tokio::test]
+    async fn department_updates_succeed_when_the_cache_fails() {
+        let mock = Mock::new();
+        let cache = Arc::new(ProfileCache::new(Arc::new(FailingStore)));
+        let app = mock.app().await.with_profile_cache(cache);
+
+        let request = UpdateDepartmentRequest::new().parent_id(2);
+        app.update_department(3, request).await.unwrap();
+
+        assert_eq!(mock.bodies("topapi/v2/department/update").len(), 1);
+    }
+
+    #[test]
+    fn department_updates_only_send_the_fields_set() {
+        let request = UpdateDepartmentRequest::new()
+            .name("平台研发部")
+            .dept_managers(vec!["zhangsan".to_string(), "lisi".to_string()])
+            .outer_permissions(vec!["wangwu".to_string()], vec![2, 3]);
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            json!({
+                "name": "平台研发部",
+                "dept_manager_userid_list": "zhangsan,lisi",
+                "outer_dept": true,
+                "outer_permit_users": "wangwu",
+                "outer_permit_depts": "2,3",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn department_deletions_succeed_when_the_cache_fails() {
+        let mock = Mock::new();
+        let cache = Arc::new(ProfileCache::new(Arc::new(FailingStore)));
+        let app = mock.app().await.with_profile_cache(cache);
+
+        app.delete_department(3).await.unwrap();
+
+        assert_eq!(
+            mock.bodies("topapi/v2/department/delete"),
+            vec![json!({ "dept_id": 3 })]
+        );
+    }
+
+    #[tokio::test]
+    async fn concurrent_department_reads_are_coalesced() {
+        let mock = Mock::new();
+        mock.respond(
+            "topapi/v2/department/get",
+            json!({ "errcode": 0, "result": { "dept_id": 3, "name": "研发部", "parent_id": 1 } }),
+        );
+        mock.delay(std::time::Duration::from_millis(20));
+        let app = mock.app().await;
+
+        let (a, b) = futures::join!(app.get_department(3), app.get_department(3));
+        assert_eq!(a.unwrap(), b.unwrap());
+        assert_eq!(mock.bodies("topapi/v2/department/get").len(), 1);
+
+        app.coalescer.set_enabled(EndpointClass::Department, false);
+        let (a, b) = futures::join!(app.get_department(3), app.get_department(3));
+        assert!(a.is_ok() && b.is_ok());
+        assert_eq!(mock.bodies("topapi/v2/department/get").len(), 3);
+    }
+
+    #[tokio::test]
+    async fn the_tree_is_walked_breadth_first() {
+        let mock = Mock::new();
+        let path = "topapi/v2/department/listsub";
+        let children = |ids: &[(i64, i64)]| {
+            let depts: Vec<_> = ids
+                .iter()
+                .map(|(dept_id, parent_id)| {
+                    json!({ "dept_id": dept_id, "name": dept_id.to_string(), "parent_id": parent_id })
+                })
+                .collect();
+            json!({ "errcode": 0, "result": depts })
+        };
+        // 1 -> 2 -> 4 -> 5 and 1 -> 3, answered in the order the departments are listed.
+        mock.respond(path, children(&[(2, 1), (3, 1)]));
+        mock.respond(path, children(&[(4, 2)]));
+        mock.respond(path, children(&[]));
+        mock.respond(path, children(&[(5, 4)]));
+        mock.respond(path, children(&[]));
+        let app = mock.app().await;
+
+        let tree: Vec<(i64, i64)> = app
+            .walk_department_tree()
+            .map_ok(|dept| (dept.dept_id, dept.parent_id))
+            .try_collect()
+            .await
+            .unwrap();
+
+        assert_eq!(tree, vec![(2, 1), (3, 1), (4, 2), (5, 4)]);
+        let listed: Vec<_> = mock
+            .bodies(path)
+            .iter()
+            .map(|body| body["dept_id"].clone())
+            .collect();
+        assert_eq!(
+            listed,
+            vec![json!(1), json!(2), json!(3), json!(4), json!(5)]
+        );
+    }
+}
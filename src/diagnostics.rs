@@ -0,0 +1,403 @@
+use crate::core::{dispatch, execute_raw};
+use crate::{error::DingTalkError, organization::OrgApp, DingTalk};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::{warn, Instrument};
+
+/// The outcome of a single diagnostic check.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum CheckStatus {
+    Passed,
+    Failed(String),
+    /// The check could not run because a check it depends on failed.
+    Skipped,
+}
+
+impl CheckStatus {
+    pub fn is_passed(&self) -> bool {
+        *self == CheckStatus::Passed
+    }
+}
+
+/// The result of [OrgApp::diagnose].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Diagnosis {
+    /// Whether `api.dingtalk.com` can be reached.
+    pub api_reachable: CheckStatus,
+    /// Whether `oapi.dingtalk.com` can be reached.
+    pub oapi_reachable: CheckStatus,
+    /// Whether an access token can be obtained with the app credentials.
+    pub credentials: CheckStatus,
+    /// Whether the server IP is accepted by the app's IP allowlist.
+    pub ip_allowlist: CheckStatus,
+    /// The server IP as seen by DingTalk, when reported by an allowlist rejection.
+    pub client_ip: Option<String>,
+}
+
+impl Diagnosis {
+    pub fn is_healthy(&self) -> bool {
+        self.api_reachable.is_passed()
+            && self.oapi_reachable.is_passed()
+            && self.credentials.is_passed()
+            && self.ip_allowlist.is_passed()
+    }
+}
+
+impl OrgApp {
+    /// Runs connectivity and authorization checks against DingTalk.
+    ///
+    /// This is meant to explain why calls fail, e.g. at service startup: it checks that both API
+    /// gateways are reachable, that the app credentials yield an access token, and that the
+    /// server IP is in the app's allowlist.
+    ///
+    /// # Returns
+    ///
+    /// A `Diagnosis` with the outcome of every check. Failures are reported in the diagnosis
+    /// rather than as an error.
+    pub async fn diagnose(&self) -> Diagnosis {
+        let api_reachable = self.check_reachable(&self.endpoints.api).await;
+        let oapi_reachable = self.check_reachable(&self.endpoints.oapi).await;
+
+        let credentials = if api_reachable.is_passed() {
+            match self.get_access_token().await {
+                Ok(_) => CheckStatus::Passed,
+                Err(e) => CheckStatus::Failed(e.to_string()),
+            }
+        } else {
+            CheckStatus::Skipped
+        };
+
+        let mut client_ip = None;
+        let ip_allowlist = if credentials.is_passed() && oapi_reachable.is_passed() {
+            let params = serde_json::json!({ "only_active": false });
+            match self
+                .post_oapi::<serde_json::Value>("topapi/user/count", &params)
+                .await
+            {
+                Ok(_) => CheckStatus::Passed,
+                Err(DingTalkError::IpNotWhitelisted { client_ip: ip, .. }) => {
+                    let message = format!("Server IP {:?} is not in the allowlist", ip);
+                    client_ip = ip;
+                    CheckStatus::Failed(message)
+                }
+                // Other API errors, e.g. missing permissions, mean the IP was accepted.
+                Err(DingTalkError::Api { .. }) => CheckStatus::Passed,
+                Err(e) => CheckStatus::Failed(e.to_string()),
+            }
+        } else {
+            CheckStatus::Skipped
+        };
+
+        let diagnosis = Diagnosis {
+            api_reachable,
+            oapi_reachable,
+            credentials,
+            ip_allowlist,
+            client_ip,
+        };
+        if !diagnosis.is_healthy() {
+            warn!("diagnose: {:?}", diagnosis);
+        }
+
+        diagnosis
+    }
+
+    async fn check_reachable(&self, url: &str) -> CheckStatus {
+        let request = match self.client.get(url).build() {
+            Ok(request) => request,
+            Err(e) => return CheckStatus::Failed(e.to_string()),
+        };
+
+        // Any HTTP response, even an error status or a body which is not JSON, proves the gateway
+        // is reachable.
+        let result = dispatch::<serde_json::Value>(
+            &self.client,
+            self.transport.as_ref(),
+            request,
+            "Failed to reach DingTalk",
+        )
+        .instrument(self.span())
+        .await;
+        match result {
+            Ok(_)
+            | Err(DingTalkError::HttpStatus { .. })
+            | Err(DingTalkError::Decode { .. })
+            | Err(DingTalkError::Deserialize(_))
+            | Err(DingTalkError::Api { .. })
+            | Err(DingTalkError::IpNotWhitelisted { .. }) => CheckStatus::Passed,
+            Err(e) => CheckStatus::Failed(e.to_string()),
+        }
+    }
+}
+
+/// Clock drifts above this break the timestamps of signed requests.
+const MAX_CLOCK_SKEW_SECONDS: i64 = 60;
+
+/// The result of [DingTalk::health_check].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HealthReport {
+    /// Whether the token store can be read.
+    pub token_store: CheckStatus,
+    /// Whether the app credentials yield an access token.
+    pub credentials: CheckStatus,
+    /// Whether the local clock agrees with DingTalk within a minute.
+    pub clock: CheckStatus,
+    /// The corrected local clock minus the DingTalk server clock, in seconds.
+    pub clock_skew_seconds: Option<i64>,
+}
+
+impl HealthReport {
+    pub fn is_healthy(&self) -> bool {
+        self.token_store.is_passed() && self.credentials.is_passed() && self.clock.is_passed()
+    }
+}
+
+impl DingTalk {
+    /// Verifies that the client is ready to serve requests.
+    ///
+    /// The check reads from the token store, obtains an app access token with the app
+    /// credentials and compares the local clock with the `Date` header returned by DingTalk. It
+    /// is suitable for service startup and readiness probes.
+    ///
+    /// # Returns
+    ///
+    /// A `HealthReport` with the outcome of every check. Failures are reported in the report
+    /// rather than as an error.
+    pub async fn health_check(&self) -> HealthReport {
+        let token_store = match self.store.get(&self.token_key()).await {
+            Ok(_) => CheckStatus::Passed,
+            Err(e) => CheckStatus::Failed(e.to_string()),
+        };
+
+        let mut params = HashMap::new();
+        params.insert("appKey", self.appid.clone());
+        params.insert("appSecret", self.app_secret.clone());
+
+        let request = self
+            .client
+            .post(self.endpoints.api("v1.0/oauth2/accessToken"))
+            .json(&params);
+        let credentials = match self
+            .send_and_parse::<serde_json::Value>(request, "Failed to get app access token")
+            .await
+        {
+            Ok(_) => CheckStatus::Passed,
+            Err(e) => CheckStatus::Failed(e.to_string()),
+        };
+
+        let (clock, clock_skew_seconds) = match self.check_clock_skew(false).await {
+            Ok(skew) => {
+                let skew = skew / 1000;
+                let clock = if skew.abs() > MAX_CLOCK_SKEW_SECONDS {
+                    CheckStatus::Failed(format!(
+                        "Local clock is {}s off the DingTalk server clock, see Clock::calibrate",
+                        skew
+                    ))
+                } else {
+                    CheckStatus::Passed
+                };
+                (clock, Some(skew))
+            }
+            Err(e) => (CheckStatus::Failed(e.to_string()), None),
+        };
+
+        let report = HealthReport {
+            token_store,
+            credentials,
+            clock,
+            clock_skew_seconds,
+        };
+        if !report.is_healthy() {
+            warn!("health_check: {:?}", report);
+        }
+
+        report
+    }
+}
+
+impl DingTalk {
+    /// Measures the skew between the client clock and the DingTalk server clock.
+    ///
+    /// # Arguments
+    ///
+    /// * `calibrate` - Whether to correct [DingTalk::clock] with the measured skew.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the skew in milliseconds before any correction, positive when the
+    /// local clock is ahead.
+    pub async fn check_clock_skew(&self, calibrate: bool) -> Result<i64, DingTalkError> {
+        let request = self.client.head(&self.endpoints.api).build()?;
+        let response = execute_raw(&self.client, request, "Failed to reach DingTalk").await?;
+
+        let skew = match self.clock.skew_millis(response.headers()) {
+            Some(skew) => skew,
+            None => {
+                return Err(DingTalkError::Other(
+                    "Missing Date header in DingTalk response".to_string(),
+                ))
+            }
+        };
+        if calibrate {
+            self.clock.calibrate(response.headers());
+        }
+
+        Ok(skew)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{FailingStore, Mock};
+    use crate::{Endpoints, MemoryStore, TokenStore};
+    use serde_json::json;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn rejected_ips_fail_with_the_client_ip() {
+        let mock = Mock::new();
+        mock.respond(
+            "topapi/user/count",
+            json!({
+                "errcode": 60020,
+                "errmsg": "访问ip不在白名单之中,request ip=47.98.123.45 appKey(app)",
+            }),
+        );
+        let app = mock.app().await;
+
+        let diagnosis = app.diagnose().await;
+
+        assert_eq!(diagnosis.api_reachable, CheckStatus::Passed);
+        assert_eq!(diagnosis.credentials, CheckStatus::Passed);
+        assert!(matches!(diagnosis.ip_allowlist, CheckStatus::Failed(_)));
+        assert_eq!(diagnosis.client_ip.as_deref(), Some("47.98.123.45"));
+        assert!(!diagnosis.is_healthy());
+    }
+
+    #[tokio::test]
+    async fn other_api_errors_pass_the_allowlist() {
+        let mock = Mock::new();
+        mock.respond(
+            "topapi/user/count",
+            json!({ "errcode": 60011, "errmsg": "没有调用该接口的权限" }),
+        );
+        let app = mock.app().await;
+
+        let diagnosis = app.diagnose().await;
+
+        assert_eq!(diagnosis.ip_allowlist, CheckStatus::Passed);
+        assert_eq!(diagnosis.client_ip, None);
+        assert!(diagnosis.is_healthy());
+    }
+
+    #[tokio::test]
+    async fn unreachable_gateways_skip_the_later_checks() {
+        let mock = Mock::new();
+        // Both gateways are served from the root path of their host.
+        mock.unreachable("");
+        let app = mock.app().await;
+
+        let diagnosis = app.diagnose().await;
+
+        assert!(matches!(diagnosis.api_reachable, CheckStatus::Failed(_)));
+        assert!(matches!(diagnosis.oapi_reachable, CheckStatus::Failed(_)));
+        assert_eq!(diagnosis.credentials, CheckStatus::Skipped);
+        assert_eq!(diagnosis.ip_allowlist, CheckStatus::Skipped);
+        assert!(mock.bodies("topapi/user/count").is_empty());
+    }
+
+    /// Serves every request with an empty response dated now, for the clock check, and returns
+    /// the base URL.
+    fn serve_date() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request);
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nDate: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    chrono::Utc::now().to_rfc2822()
+                );
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        base
+    }
+
+    fn healthy_mock() -> Arc<Mock> {
+        let mock = Mock::new();
+        mock.respond(
+            "v1.0/oauth2/accessToken",
+            json!({ "accessToken": "token", "expireIn": 7200 }),
+        );
+        mock
+    }
+
+    fn dingtalk(mock: &Arc<Mock>, store: Arc<dyn TokenStore>) -> DingTalk {
+        let mut dt = DingTalk::with_store("app".to_string(), "secret".to_string(), store)
+            .with_endpoints(Endpoints::with_base(serve_date()));
+        dt.transport = Some(mock.transport());
+        dt
+    }
+
+    #[tokio::test]
+    async fn healthy_clients_pass_every_check() {
+        let mock = healthy_mock();
+        let dt = dingtalk(&mock, Arc::new(MemoryStore::new()));
+
+        let report = dt.health_check().await;
+
+        assert!(report.is_healthy(), "{:?}", report);
+        assert!(report.clock_skew_seconds.unwrap().abs() <= 1);
+        assert_eq!(
+            mock.bodies("v1.0/oauth2/accessToken"),
+            vec![json!({ "appKey": "app", "appSecret": "secret" })]
+        );
+    }
+
+    #[tokio::test]
+    async fn failing_stores_fail_the_store_check() {
+        let mock = healthy_mock();
+        let dt = dingtalk(&mock, Arc::new(FailingStore));
+
+        let report = dt.health_check().await;
+
+        assert!(matches!(report.token_store, CheckStatus::Failed(_)));
+        assert_eq!(report.credentials, CheckStatus::Passed);
+        assert!(!report.is_healthy());
+    }
+
+    #[tokio::test]
+    async fn rejected_credentials_fail_the_credentials_check() {
+        let mock = Mock::new();
+        mock.respond(
+            "v1.0/oauth2/accessToken",
+            json!({ "errcode": 40089, "errmsg": "不合法的corpid或corpsecret" }),
+        );
+        let dt = dingtalk(&mock, Arc::new(MemoryStore::new()));
+
+        let report = dt.health_check().await;
+
+        assert_eq!(report.token_store, CheckStatus::Passed);
+        assert!(matches!(report.credentials, CheckStatus::Failed(_)));
+        assert!(!report.is_healthy());
+    }
+
+    #[tokio::test]
+    async fn clocks_skewed_by_more_than_a_minute_fail() {
+        let mock = healthy_mock();
+        let dt = dingtalk(&mock, Arc::new(MemoryStore::new()));
+        dt.clock.set_offset_millis(90_000);
+
+        let report = dt.health_check().await;
+
+        let skew = report.clock_skew_seconds.unwrap();
+        assert!((89..=91).contains(&skew), "{}", skew);
+        assert!(matches!(report.clock, CheckStatus::Failed(message) if message.contains("off")));
+        assert_eq!(report.credentials, CheckStatus::Passed);
+    }
+}
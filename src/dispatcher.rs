@@ -0,0 +1,193 @@
+use crate::error::DingTalkError;
+use crate::event::{EmploymentChange, Event, HrmStatusEvent};
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tracing::debug;
+
+/// How many handlers run at the same time unless configured otherwise.
+const DEFAULT_CONCURRENCY_LIMIT: usize = 16;
+
+type Handler = Arc<dyn Fn(Event) -> BoxFuture<'static, Result<(), DingTalkError>> + Send + Sync>;
+type ErrorHook = Arc<dyn Fn(&str, &DingTalkError) + Send + Sync>;
+
+/// Routes events to the handlers registered for their `EventType`.
+///
+/// The dispatcher does not depend on how events arrive; [EventDispatcher::into_handler] plugs it
+/// into the callback router of the `axum` feature or the client of the `stream` feature:
+///
+/// ```
+/// use async_dingtalk::{Event, EventDispatcher};
+///
+/// let handler = EventDispatcher::new()
+///     .on("user_add_org", |event: Event| async move {
+///         println!("{:?}", event);
+///         Ok(())
+///     })
+///     .on_any(|_| async { Ok(()) })
+///     .on_error(|event_type, e| eprintln!("{} failed: {}", event_type, e))
+///     .into_handler();
+/// ```
+pub struct EventDispatcher {
+    handlers: HashMap<String, Handler>,
+    fallback: Option<Handler>,
+    error_hook: Option<ErrorHook>,
+    permits: Arc<Semaphore>,
+}
+
+impl Default for EventDispatcher {
+    fn default() -> Self {
+        EventDispatcher::new()
+    }
+}
+
+impl EventDispatcher {
+    pub fn new() -> Self {
+        EventDispatcher {
+            handlers: HashMap::new(),
+            fallback: None,
+            error_hook: None,
+            permits: Arc::new(Semaphore::new(DEFAULT_CONCURRENCY_LIMIT)),
+        }
+    }
+
+    /// Handles the events of the given type, e.g. `user_add_org`, replacing any handler
+    /// registered for it before.
+    pub fn on<F, Fut>(mut self, event_type: &str, handler: F) -> Self
+    where
+        F: Fn(Event) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), DingTalkError>> + Send + 'static,
+    {
+        self.handlers.insert(event_type.to_string(), boxed(handler));
+        self
+    }
+
+    /// Handles the employment status changes of smart work (入职, 离职 and 转正), replacing the
+    /// handlers of their event types.
+    pub fn on_employment_change<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn(EmploymentChange, HrmStatusEvent) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), DingTalkError>> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        for event_type in ["hrm_user_entry", "hrm_user_dimission", "hrm_user_regular"] {
+            let handler = handler.clone();
+            self = self.on(event_type, move |event: Event| {
+                let handler = handler.clone();
+                async move {
+                    match event.employment_change() {
+                        Some((change, payload)) => handler(change, payload.clone()).await,
+                        None => Ok(()),
+                    }
+                }
+            });
+        }
+        self
+    }
+
+    /// Handles the events of every type without a handler of its own.
+    pub fn on_any<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn(Event) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), DingTalkError>> + Send + 'static,
+    {
+        self.fallback = Some(boxed(handler));
+        self
+    }
+
+    /// Calls `hook` with the event type and the error whenever a handler fails, e.g. to report
+    /// the failure; the error is still returned to the caller of [EventDispatcher::dispatch].
+    pub fn on_error<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&str, &DingTalkError) + Send + Sync + 'static,
+    {
+        self.error_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Sets how many handlers may run at the same time, 16 by default. Further events wait
+    /// for a running handler to finish.
+    pub fn with_concurrency_limit(mut self, limit: usize) -> Self {
+        self.permits = Arc::new(Semaphore::new(limit.max(1)));
+        self
+    }
+
+    /// Runs the handler of the event, or the fallback handler. Events without either are
+    /// ignored.
+    pub async fn dispatch(&self, event: Event) -> Result<(), DingTalkError> {
+        let event_type = event.event_type().to_string();
+        let handler = match self.handlers.get(&event_type).or(self.fallback.as_ref()) {
+            Some(handler) => handler.clone(),
+            None => {
+                debug!(event_type = %event_type, "no handler for event");
+                return Ok(());
+            }
+        };
+
+        let _permit = self
+            .permits
+            .acquire()
+            .await
+            .map_err(|e| DingTalkError::Other(e.to_string()))?;
+
+        let result = handler(event).await;
+        if let (Err(e), Some(hook)) = (&result, &self.error_hook) {
+            hook(&event_type, e);
+        }
+        result
+    }
+
+    /// Returns a handler dispatching every event, for [crate::DingTalk::stream_client] or the
+    /// `callback_router` of the `axum` feature.
+    pub fn into_handler(
+        self,
+    ) -> impl Fn(Event) -> BoxFuture<'static, Result<(), DingTalkError>> + Clone + Send + Sync + 'static
+    {
+        let dispatcher = Arc::new(self);
+        move |event| {
+            let dispatcher = dispatcher.clone();
+            Box::pin(async move { dispatcher.dispatch(event).await })
+        }
+    }
+}
+
+fn boxed<F, Fut>(handler: F) -> Handler
+where
+    F: Fn(Event) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<(), DingTalkError>> + Send + 'static,
+{
+    Arc::new(move |event| Box::pin(handler(event)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[tokio::test]
+    async fn events_go_to_their_handler_or_the_fallback() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let failures = Arc::new(Mutex::new(Vec::new()));
+
+        let (typed, any, hook) = (seen.clone(), seen.clone(), failures.clone());
+        let dispatcher = EventDispatcher::new()
+            .on("check_url", move |_| {
+                typed.lock().unwrap().push("check_url");
+                async { Err(DingTalkError::Other("boom".to_string())) }
+            })
+            .on_any(move |_| {
+                any.lock().unwrap().push("any");
+                async { Ok(()) }
+            })
+            .on_error(move |event_type, _| hook.lock().unwrap().push(event_type.to_string()));
+
+        assert!(dispatcher.dispatch(Event::CheckUrl).await.is_err());
+        let unknown = Event::Unknown(serde_json::json!({ "EventType": "custom" }));
+        assert!(dispatcher.dispatch(unknown).await.is_ok());
+
+        assert_eq!(*seen.lock().unwrap(), vec!["check_url", "any"]);
+        assert_eq!(*failures.lock().unwrap(), vec!["check_url".to_string()]);
+    }
+}
@@ -0,0 +1,440 @@
+use crate::store::StoreError;
+use std::net::IpAddr;
+
+/// `errcode` returned when the calling server IP is not in the app's allowlist.
+const IP_NOT_WHITELISTED: i64 = 60020;
+const INVALID_TOKEN: i64 = 40014;
+const PERMISSION_DENIED: i64 = 60011;
+const USER_NOT_FOUND: i64 = 60121;
+const THROTTLED: i64 = 90018;
+const INVALID_DEPARTMENT: i64 = 33012;
+
+/// The category of a DingTalk `errcode`, see [DingTalkError::kind].
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ApiErrorKind {
+    /// The access token was rejected (不合法的access_token), e.g. after the app secret was reset.
+    /// Cached tokens of the app should be discarded.
+    InvalidToken,
+    /// The app lacks the permission for the API or the scope of the data (没有调用该接口的权限).
+    /// Permissions are granted in the developer console.
+    PermissionDenied,
+    /// The user does not exist in the organization (找不到该用户).
+    UserNotFound,
+    /// The app exceeded the call frequency limit of DingTalk (调用频率超限). The call can be
+    /// retried after a short backoff.
+    Throttled,
+    /// The department ID is invalid or the department does not exist (无效的部门ID).
+    InvalidDepartment,
+    /// The server IP is not in the allowlist of the app, see [DingTalkError::IpNotWhitelisted].
+    IpNotWhitelisted,
+    /// Any other `errcode`.
+    Other,
+}
+
+impl ApiErrorKind {
+    /// Returns the category of the given `errcode`.
+    pub fn from_errcode(errcode: i64) -> Self {
+        match errcode {
+            INVALID_TOKEN => ApiErrorKind::InvalidToken,
+            PERMISSION_DENIED => ApiErrorKind::PermissionDenied,
+            USER_NOT_FOUND => ApiErrorKind::UserNotFound,
+            THROTTLED => ApiErrorKind::Throttled,
+            INVALID_DEPARTMENT => ApiErrorKind::InvalidDepartment,
+            IP_NOT_WHITELISTED => ApiErrorKind::IpNotWhitelisted,
+            _ => ApiErrorKind::Other,
+        }
+    }
+
+    /// Returns what to do about an error of this kind, as shown by [DingTalkError::Api].
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            ApiErrorKind::InvalidToken => {
+                Some("discard the cached access token and fetch a new one")
+            }
+            ApiErrorKind::PermissionDenied => Some("grant the permission in the developer console"),
+            ApiErrorKind::UserNotFound => {
+                Some("check the user ID, the user may have left the organization")
+            }
+            ApiErrorKind::Throttled => Some("retry after backoff"),
+            ApiErrorKind::InvalidDepartment => {
+                Some("check the department ID, the department may have been deleted")
+            }
+            ApiErrorKind::IpNotWhitelisted => {
+                Some("add the server IP to the allowlist in the developer console")
+            }
+            ApiErrorKind::Other => None,
+        }
+    }
+}
+
+/// The error type of the client.
+#[derive(Debug, thiserror::Error)]
+pub enum DingTalkError {
+    /// The client is misconfigured, e.g. with an invalid Redis URL.
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+
+    /// The HTTP request could not be sent or its response could not be read.
+    #[error("HTTP request failed: {0}")]
+    Http(#[from] reqwest::Error),
+
+    /// DingTalk answered with an unsuccessful HTTP status.
+    ///
+    /// `api.dingtalk.com` explains such responses with a `code`, `message` and `requestid` in the
+    /// body, which are kept when present. `url` is the called URL without the access token.
+    #[error(
+        "{context}: {status} {}{} (url: {url}, request_id: {})",
+        .code.as_deref().unwrap_or_default(),
+        .message.as_deref().map(|m| format!(" {}", m)).unwrap_or_default(),
+        .request_id.as_deref().unwrap_or_default()
+    )]
+    HttpStatus {
+        status: reqwest::StatusCode,
+        context: String,
+        url: String,
+        code: Option<String>,
+        message: Option<String>,
+        request_id: Option<String>,
+    },
+
+    /// The response body does not match the expected model.
+    #[error("Failed to deserialize response: {0}")]
+    Deserialize(#[from] serde_json::Error),
+
+    /// A response body of DingTalk does not match the expected model.
+    ///
+    /// `body` is the start of the body with personal data such as names and mobile numbers
+    /// masked, to diagnose schema changes from logs.
+    #[error("Failed to decode response of {url}: {source} (body: {body})")]
+    Decode {
+        #[source]
+        source: serde_json::Error,
+        url: String,
+        body: String,
+    },
+
+    /// DingTalk reported a non-zero `errcode`.
+    ///
+    /// The message includes the [hint](ApiErrorKind::hint) of the [kind](DingTalkError::kind).
+    /// Quote `request_id` when contacting DingTalk support. `url` is the called URL without the
+    /// access token.
+    #[error(
+        "DingTalk API error {errcode}: {errmsg}{} (url: {}, request_id: {})",
+        ApiErrorKind::from_errcode(*.errcode)
+            .hint()
+            .map(|hint| format!("; {}", hint))
+            .unwrap_or_default(),
+        .url.as_deref().unwrap_or_default(),
+        .request_id.as_deref().unwrap_or_default()
+    )]
+    Api {
+        errcode: i64,
+        errmsg: String,
+        request_id: Option<String>,
+        url: Option<String>,
+    },
+
+    /// The server IP is not in the allowlist of the app (访问ip不在白名单之中).
+    ///
+    /// `client_ip` is the IP DingTalk saw the request coming from, as parsed from `errmsg`, and
+    /// is the address to add to the allowlist in the developer console.
+    #[error(
+        "Server IP {} is not in the DingTalk allowlist (url: {}, request_id: {})",
+        .client_ip.as_deref().unwrap_or("unknown"),
+        .url.as_deref().unwrap_or_default(),
+        .request_id.as_deref().unwrap_or_default()
+    )]
+    IpNotWhitelisted {
+        client_ip: Option<String>,
+        request_id: Option<String>,
+        url: Option<String>,
+    },
+
+    /// The token store failed.
+    #[error("Token store error: {0}")]
+    Cache(#[source] StoreError),
+
+    /// No access token is available, e.g. before [crate::DingTalk::set_app_access_token] ran.
+    #[error("Access token missing: {0}")]
+    TokenMissing(String),
+
+    /// A message template could not be rendered.
+    #[error("Invalid template: {0}")]
+    Template(String),
+
+    /// The deadline of [crate::with_deadline] passed before or while sending the request of the
+    /// given context.
+    #[error("Deadline exceeded: {0}")]
+    DeadlineExceeded(String),
+
+    /// A callback request could not be verified or decrypted by [crate::CallbackCrypto].
+    #[error("Invalid callback: {0}")]
+    Callback(String),
+
+    /// The Stream Mode connection failed or was lost.
+    #[error("Stream mode error: {0}")]
+    Stream(String),
+
+    /// Every robot of a [crate::RobotPool] is muted or throttled.
+    #[error("No robot available in pool")]
+    NoRobotAvailable,
+
+    /// Another call with the same idempotency key is still running, so this one was not sent.
+    /// Retry once it has completed to get its result.
+    #[error("Operation already in progress: {0}")]
+    InProgress(String),
+
+    /// Several employees share a job number, so it does not identify one of them, see
+    /// [crate::JobNumberIndex::get].
+    #[error("Job number {job_number} is shared by the users {}", .user_ids.join(", "))]
+    AmbiguousJobNumber {
+        job_number: String,
+        user_ids: Vec<String>,
+    },
+
+    #[error("{0}")]
+    Other(String),
+}
+
+impl DingTalkError {
+    /// Creates the error for a non-zero `errcode` of a response.
+    pub(crate) fn from_errcode(errcode: i64, errmsg: String, request_id: Option<String>) -> Self {
+        if errcode == IP_NOT_WHITELISTED {
+            return DingTalkError::IpNotWhitelisted {
+                client_ip: parse_client_ip(&errmsg),
+                request_id,
+                url: None,
+            };
+        }
+
+        DingTalkError::Api {
+            errcode,
+            errmsg,
+            request_id,
+            url: None,
+        }
+    }
+
+    /// Records the called URL on API errors.
+    pub(crate) fn with_url(mut self, called: &str) -> Self {
+        match &mut self {
+            DingTalkError::Api { url, .. } | DingTalkError::IpNotWhitelisted { url, .. } => {
+                *url = Some(called.to_string())
+            }
+            DingTalkError::HttpStatus { url, .. } => *url = called.to_string(),
+            _ => {}
+        }
+        self
+    }
+
+    /// Returns the DingTalk `request_id` of API errors, to quote when contacting DingTalk support.
+    pub fn request_id(&self) -> Option<&str> {
+        match self {
+            DingTalkError::Api { request_id, .. }
+            | DingTalkError::IpNotWhitelisted { request_id, .. }
+            | DingTalkError::HttpStatus { request_id, .. } => request_id.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Returns the called URL of API errors, without the access token.
+    pub fn url(&self) -> Option<&str> {
+        match self {
+            DingTalkError::Api { url, .. } | DingTalkError::IpNotWhitelisted { url, .. } => {
+                url.as_deref()
+            }
+            DingTalkError::HttpStatus { url, .. } | DingTalkError::Decode { url, .. } => Some(url),
+            _ => None,
+        }
+    }
+
+    /// Returns the DingTalk `errcode` of API errors.
+    pub fn errcode(&self) -> Option<i64> {
+        match self {
+            DingTalkError::Api { errcode, .. } => Some(*errcode),
+            DingTalkError::IpNotWhitelisted { .. } => Some(IP_NOT_WHITELISTED),
+            _ => None,
+        }
+    }
+
+    /// Returns the category of the `errcode` of API errors, e.g. to retry throttled calls:
+    ///
+    /// ```
+    /// use async_dingtalk::{ApiErrorKind, DingTalkError};
+    ///
+    /// fn is_retryable(error: &DingTalkError) -> bool {
+    ///     error.kind() == Some(ApiErrorKind::Throttled)
+    /// }
+    /// ```
+    pub fn kind(&self) -> Option<ApiErrorKind> {
+        self.errcode().map(ApiErrorKind::from_errcode)
+    }
+
+    /// Copies the error for sharing it between callers, e.g. of a coalesced call.
+    ///
+    /// Variants wrapping non-clonable errors are turned into [DingTalkError::Other] with the same
+    /// message.
+    pub(crate) fn duplicate(&self) -> Self {
+        match self {
+            DingTalkError::Config(message) => DingTalkError::Config(message.clone()),
+            DingTalkError::HttpStatus {
+                status,
+                context,
+                url,
+                code,
+                message,
+                request_id,
+            } => DingTalkError::HttpStatus {
+                status: *status,
+                context: context.clone(),
+                url: url.clone(),
+                code: code.clone(),
+                message: message.clone(),
+                request_id: request_id.clone(),
+            },
+            DingTalkError::Api {
+                errcode,
+                errmsg,
+                request_id,
+                url,
+            } => DingTalkError::Api {
+                errcode: *errcode,
+                errmsg: errmsg.clone(),
+                request_id: request_id.clone(),
+                url: url.clone(),
+            },
+            DingTalkError::IpNotWhitelisted {
+                client_ip,
+                request_id,
+                url,
+            } => DingTalkError::IpNotWhitelisted {
+                client_ip: client_ip.clone(),
+                request_id: request_id.clone(),
+                url: url.clone(),
+            },
+            DingTalkError::TokenMissing(message) => DingTalkError::TokenMissing(message.clone()),
+            DingTalkError::Template(message) => DingTalkError::Template(message.clone()),
+            DingTalkError::DeadlineExceeded(context) => {
+                DingTalkError::DeadlineExceeded(context.clone())
+            }
+            DingTalkError::Callback(message) => DingTalkError::Callback(message.clone()),
+            DingTalkError::Stream(message) => DingTalkError::Stream(message.clone()),
+            DingTalkError::NoRobotAvailable => DingTalkError::NoRobotAvailable,
+            DingTalkError::InProgress(key) => DingTalkError::InProgress(key.clone()),
+            DingTalkError::AmbiguousJobNumber {
+                job_number,
+                user_ids,
+            } => DingTalkError::AmbiguousJobNumber {
+                job_number: job_number.clone(),
+                user_ids: user_ids.clone(),
+            },
+            e => DingTalkError::Other(e.to_string()),
+        }
+    }
+}
+
+/// Extracts the client IP from messages like `访问ip不在白名单之中,请参考FAQ：...,request ip=1.2.3.4 appKey(...)`.
+fn parse_client_ip(errmsg: &str) -> Option<String> {
+    let candidates = errmsg.split(|c: char| !(c.is_ascii_hexdigit() || c == '.' || c == ':'));
+    candidates
+        .filter(|candidate| candidate.contains('.') || candidate.matches(':').count() > 1)
+        .find_map(|candidate| candidate.parse::<IpAddr>().ok())
+        .map(|ip| ip.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errcodes_map_to_their_kind() {
+        let cases = [
+            (40014, ApiErrorKind::InvalidToken),
+            (60011, ApiErrorKind::PermissionDenied),
+            (60121, ApiErrorKind::UserNotFound),
+            (90018, ApiErrorKind::Throttled),
+            (33012, ApiErrorKind::InvalidDepartment),
+            (60020, ApiErrorKind::IpNotWhitelisted),
+            (40035, ApiErrorKind::Other),
+        ];
+
+        for (errcode, kind) in cases {
+            let error = DingTalkError::from_errcode(errcode, "errmsg".to_string(), None);
+            assert_eq!(error.kind(), Some(kind), "errcode {}", errcode);
+            assert_eq!(error.errcode(), Some(errcode));
+        }
+        assert_eq!(DingTalkError::Other("boom".to_string()).kind(), None);
+    }
+
+    #[test]
+    fn specific_errcodes_stay_api_errors() {
+        let error = DingTalkError::from_errcode(90018, "调用频率超限".to_string(), None);
+        assert!(matches!(error, DingTalkError::Api { errcode: 90018, .. }));
+    }
+
+    #[test]
+    fn api_errors_explain_what_to_do() {
+        let error = DingTalkError::from_errcode(
+            60011,
+            "没有调用该接口的权限".to_string(),
+            Some("req-1".to_string()),
+        );
+        assert_eq!(
+            error.to_string(),
+            "DingTalk API error 60011: 没有调用该接口的权限; grant the permission in the developer \
+             console (url: , request_id: req-1)"
+        );
+
+        let error = DingTalkError::from_errcode(90018, "调用频率超限".to_string(), None);
+        assert!(error
+            .to_string()
+            .contains("调用频率超限; retry after backoff"));
+
+        let error = DingTalkError::from_errcode(40035, "不合法的参数".to_string(), None);
+        assert!(error
+            .to_string()
+            .starts_with("DingTalk API error 40035: 不合法的参数 (url"));
+    }
+
+    #[test]
+    fn client_ips_are_parsed_from_errmsg() {
+        let errmsg = "访问ip不在白名单之中,请参考FAQ：https://open.dingtalk.com/document/orgapp/\
+                      the-server-ip-is-not-in-the-whitelist,request ip=47.98.123.45 \
+                      appKey(dingabcdef123456)";
+        assert_eq!(parse_client_ip(errmsg).as_deref(), Some("47.98.123.45"));
+
+        let error = DingTalkError::from_errcode(60020, errmsg.to_string(), None);
+        assert!(matches!(
+            error,
+            DingTalkError::IpNotWhitelisted { client_ip: Some(ref ip), .. } if ip == "47.98.123.45"
+        ));
+
+        assert_eq!(
+            parse_client_ip("访问ip不在白名单之中,request ip=240e:3a1:4c2::1f appKey(dingabc)")
+                .as_deref(),
+            Some("240e:3a1:4c2::1f")
+        );
+    }
+
+    #[test]
+    fn messages_without_ip_have_no_client_ip() {
+        assert_eq!(parse_client_ip("访问ip不在白名单之中"), None);
+        assert_eq!(
+            parse_client_ip(
+                "访问ip不在白名单之中,请参考FAQ：https://open.dingtalk.com/document/faq/v1.0"
+            ),
+            None
+        );
+
+        let error = DingTalkError::from_errcode(60020, "访问ip不在白名单之中".to_string(), None);
+        assert!(matches!(
+            error,
+            DingTalkError::IpNotWhitelisted {
+                client_ip: None,
+                ..
+            }
+        ));
+        assert!(error.to_string().starts_with("Server IP unknown"));
+    }
+}
@@ -0,0 +1,374 @@
+//! Typed payloads of the events pushed to HTTP callbacks, see [Event].
+
+use crate::{callback::CallbackCrypto, error::DingTalkError};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+/// An event decrypted from a callback request, tagged by its `EventType`.
+///
+/// [事件订阅总览](https://open.dingtalk.com/document/orgapp/event-overview)
+///
+/// Events without a model here, e.g. added by DingTalk later, are kept as
+/// [Event::Unknown] with their whole JSON.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// Sent once when the callback URL is registered; acknowledge it like any event.
+    CheckUrl,
+    /// The `suite_ticket` of an ISV suite, see [crate::DingTalk::set_suite_ticket].
+    SuiteTicket(SuiteTicketEvent),
+    /// A corp authorized an ISV suite, see [crate::DingTalk::onboard_corp].
+    TmpAuthCode(TmpAuthCodeEvent),
+    UserAddOrg(UserEvent),
+    UserModifyOrg(UserEvent),
+    UserLeaveOrg(UserEvent),
+    UserActiveOrg(UserEvent),
+    OrgDeptCreate(DeptEvent),
+    OrgDeptModify(DeptEvent),
+    OrgDeptRemove(DeptEvent),
+    /// An approval instance started or finished.
+    BpmsInstanceChange(BpmsInstanceEvent),
+    /// An approval task started, finished or was cancelled.
+    BpmsTaskChange(BpmsTaskEvent),
+    ChatAddMember(ChatMemberEvent),
+    ChatRemoveMember(ChatMemberEvent),
+    /// Employees joined (入职) in smart work (智能人事).
+    HrmUserEntry(HrmStatusEvent),
+    /// Employees left (离职) in smart work.
+    HrmUserDimission(HrmStatusEvent),
+    /// Employees passed their probation (转正) in smart work.
+    HrmUserRegular(HrmStatusEvent),
+    Unknown(Value),
+}
+
+/// The employment status changes of smart work, see [Event::employment_change].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EmploymentChange {
+    /// 入职
+    Entry,
+    /// 离职
+    Dimission,
+    /// 转正
+    Regular,
+}
+
+/// The `suite_ticket` event.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct SuiteTicketEvent {
+    pub suite_key: String,
+    pub suite_ticket: String,
+    #[serde(default, deserialize_with = "timestamp")]
+    pub time_stamp: i64,
+}
+
+/// The `tmp_auth_code` event.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct TmpAuthCodeEvent {
+    pub suite_key: String,
+    /// The temporary code of [crate::DingTalk::get_permanent_code].
+    pub auth_code: String,
+    #[serde(default)]
+    pub auth_corp_id: Option<String>,
+    #[serde(default, deserialize_with = "timestamp")]
+    pub time_stamp: i64,
+}
+
+/// The address book events about users, e.g. `user_add_org`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct UserEvent {
+    #[serde(default)]
+    pub corp_id: Option<String>,
+    #[serde(rename = "UserId", default)]
+    pub user_ids: Vec<String>,
+    #[serde(default, deserialize_with = "timestamp")]
+    pub time_stamp: i64,
+}
+
+/// The address book events about departments, e.g. `org_dept_create`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct DeptEvent {
+    #[serde(default)]
+    pub corp_id: Option<String>,
+    #[serde(rename = "DeptId", default)]
+    pub dept_ids: Vec<i64>,
+    #[serde(default, deserialize_with = "timestamp")]
+    pub time_stamp: i64,
+}
+
+/// The `bpms_instance_change` event.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct BpmsInstanceEvent {
+    pub process_instance_id: String,
+    #[serde(default)]
+    pub process_code: Option<String>,
+    #[serde(default)]
+    pub corp_id: Option<String>,
+    #[serde(default)]
+    pub title: Option<String>,
+    /// `start` or `finish`.
+    #[serde(rename = "type")]
+    pub change: String,
+    /// `agree` or `refuse` when finished.
+    #[serde(default)]
+    pub result: Option<String>,
+    /// The user ID of the initiator.
+    #[serde(default)]
+    pub staff_id: Option<String>,
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default, deserialize_with = "timestamp")]
+    pub create_time: i64,
+    #[serde(default, deserialize_with = "timestamp")]
+    pub finish_time: i64,
+}
+
+/// The `bpms_task_change` event.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct BpmsTaskEvent {
+    pub process_instance_id: String,
+    #[serde(default)]
+    pub process_code: Option<String>,
+    #[serde(default)]
+    pub corp_id: Option<String>,
+    #[serde(default)]
+    pub title: Option<String>,
+    /// `start`, `finish` or `cancel`.
+    #[serde(rename = "type")]
+    pub change: String,
+    /// `agree`, `refuse` or `redirect` when finished.
+    #[serde(default)]
+    pub result: Option<String>,
+    /// The user ID of the approver of the task.
+    #[serde(default)]
+    pub staff_id: Option<String>,
+    #[serde(default)]
+    pub remark: Option<String>,
+    #[serde(default, deserialize_with = "timestamp")]
+    pub create_time: i64,
+    #[serde(default, deserialize_with = "timestamp")]
+    pub finish_time: i64,
+}
+
+/// The group chat events about members, e.g. `chat_add_member`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct ChatMemberEvent {
+    pub chat_id: String,
+    #[serde(default)]
+    pub corp_id: Option<String>,
+    /// The user ID of who changed the members.
+    #[serde(default)]
+    pub operator: Option<String>,
+    #[serde(rename = "UserId", default)]
+    pub user_ids: Vec<String>,
+    #[serde(default, deserialize_with = "timestamp")]
+    pub time_stamp: i64,
+}
+
+/// The smart work events about the employment status of employees, e.g. `hrm_user_entry`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct HrmStatusEvent {
+    #[serde(default)]
+    pub corp_id: Option<String>,
+    #[serde(rename = "UserId", default)]
+    pub user_ids: Vec<String>,
+    /// The user ID of who made the change in DingTalk.
+    #[serde(default)]
+    pub operator: Option<String>,
+    #[serde(default, deserialize_with = "timestamp")]
+    pub time_stamp: i64,
+}
+
+impl Event {
+    /// Returns the employment status change of the smart work events.
+    pub fn employment_change(&self) -> Option<(EmploymentChange, &HrmStatusEvent)> {
+        match self {
+            Event::HrmUserEntry(e) => Some((EmploymentChange::Entry, e)),
+            Event::HrmUserDimission(e) => Some((EmploymentChange::Dimission, e)),
+            Event::HrmUserRegular(e) => Some((EmploymentChange::Regular, e)),
+            _ => None,
+        }
+    }
+
+    /// Returns the `EventType` of the event, e.g. `user_add_org`.
+    pub fn event_type(&self) -> &str {
+        match self {
+            Event::CheckUrl => "check_url",
+            Event::SuiteTicket(_) => "suite_ticket",
+            Event::TmpAuthCode(_) => "tmp_auth_code",
+            Event::UserAddOrg(_) => "user_add_org",
+            Event::UserModifyOrg(_) => "user_modify_org",
+            Event::UserLeaveOrg(_) => "user_leave_org",
+            Event::UserActiveOrg(_) => "user_active_org",
+            Event::OrgDeptCreate(_) => "org_dept_create",
+            Event::OrgDeptModify(_) => "org_dept_modify",
+            Event::OrgDeptRemove(_) => "org_dept_remove",
+            Event::BpmsInstanceChange(_) => "bpms_instance_change",
+            Event::BpmsTaskChange(_) => "bpms_task_change",
+            Event::ChatAddMember(_) => "chat_add_member",
+            Event::ChatRemoveMember(_) => "chat_remove_member",
+            Event::HrmUserEntry(_) => "hrm_user_entry",
+            Event::HrmUserDimission(_) => "hrm_user_dimission",
+            Event::HrmUserRegular(_) => "hrm_user_regular",
+            Event::Unknown(value) => value
+                .get("EventType")
+                .and_then(Value::as_str)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Builds the event of a decrypted callback payload.
+    ///
+    /// # Errors
+    ///
+    /// Returns [DingTalkError::Deserialize] if the payload is not JSON or a known event does not
+    /// match its model.
+    pub fn from_value(value: Value) -> Result<Event, DingTalkError> {
+        let event_type = value
+            .get("EventType")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+
+        let event = match event_type.as_str() {
+            "check_url" => Event::CheckUrl,
+            "suite_ticket" => Event::SuiteTicket(serde_json::from_value(value)?),
+            "tmp_auth_code" => Event::TmpAuthCode(serde_json::from_value(value)?),
+            "user_add_org" => Event::UserAddOrg(serde_json::from_value(value)?),
+            "user_modify_org" => Event::UserModifyOrg(serde_json::from_value(value)?),
+            "user_leave_org" => Event::UserLeaveOrg(serde_json::from_value(value)?),
+            "user_active_org" => Event::UserActiveOrg(serde_json::from_value(value)?),
+            "org_dept_create" => Event::OrgDeptCreate(serde_json::from_value(value)?),
+            "org_dept_modify" => Event::OrgDeptModify(serde_json::from_value(value)?),
+            "org_dept_remove" => Event::OrgDeptRemove(serde_json::from_value(value)?),
+            "bpms_instance_change" => Event::BpmsInstanceChange(serde_json::from_value(value)?),
+            "bpms_task_change" => Event::BpmsTaskChange(serde_json::from_value(value)?),
+            "chat_add_member" => Event::ChatAddMember(serde_json::from_value(value)?),
+            "chat_remove_member" => Event::ChatRemoveMember(serde_json::from_value(value)?),
+            "hrm_user_entry" => Event::HrmUserEntry(serde_json::from_value(value)?),
+            "hrm_user_dimission" => Event::HrmUserDimission(serde_json::from_value(value)?),
+            "hrm_user_regular" => Event::HrmUserRegular(serde_json::from_value(value)?),
+            _ => Event::Unknown(value),
+        };
+
+        Ok(event)
+    }
+}
+
+impl<'de> Deserialize<'de> for Event {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        Event::from_value(value).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for Event {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let payload = match self {
+            Event::CheckUrl => Ok(Value::Object(Default::default())),
+            Event::SuiteTicket(e) => serde_json::to_value(e),
+            Event::TmpAuthCode(e) => serde_json::to_value(e),
+            Event::UserAddOrg(e)
+            | Event::UserModifyOrg(e)
+            | Event::UserLeaveOrg(e)
+            | Event::UserActiveOrg(e) => serde_json::to_value(e),
+            Event::OrgDeptCreate(e) | Event::OrgDeptModify(e) | Event::OrgDeptRemove(e) => {
+                serde_json::to_value(e)
+            }
+            Event::BpmsInstanceChange(e) => serde_json::to_value(e),
+            Event::BpmsTaskChange(e) => serde_json::to_value(e),
+            Event::ChatAddMember(e) | Event::ChatRemoveMember(e) => serde_json::to_value(e),
+            Event::HrmUserEntry(e) | Event::HrmUserDimission(e) | Event::HrmUserRegular(e) => {
+                serde_json::to_value(e)
+            }
+            Event::Unknown(value) => return value.serialize(serializer),
+        };
+
+        let mut payload = payload.map_err(serde::ser::Error::custom)?;
+        if let Value::Object(fields) = &mut payload {
+            fields.insert("EventType".to_string(), self.event_type().into());
+        }
+        payload.serialize(serializer)
+    }
+}
+
+impl CallbackCrypto {
+    /// Verifies and decrypts a callback request into its [Event], see [CallbackCrypto::decrypt].
+    pub fn decrypt_event(
+        &self,
+        msg_signature: &str,
+        timestamp: &str,
+        nonce: &str,
+        encrypt: &str,
+    ) -> Result<Event, DingTalkError> {
+        let plain = self.decrypt(msg_signature, timestamp, nonce, encrypt)?;
+        Event::from_value(serde_json::from_str(&plain)?)
+    }
+}
+
+/// Reads timestamps sent as numbers or as strings of digits, which differ between events.
+fn timestamp<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+    match Value::deserialize(deserializer)? {
+        Value::Number(n) => n
+            .as_i64()
+            .ok_or_else(|| serde::de::Error::custom("timestamp out of range")),
+        Value::String(s) => s.parse().map_err(serde::de::Error::custom),
+        Value::Null => Ok(0),
+        other => Err(serde::de::Error::custom(format!(
+            "invalid timestamp: {}",
+            other
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_are_parsed_by_type() {
+        let event: Event = serde_json::from_str(
+            r#"{"EventType":"user_add_org","TimeStamp":"1700000000000","UserId":["zhangsan"],"CorpId":"ding123"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            event,
+            Event::UserAddOrg(UserEvent {
+                corp_id: Some("ding123".to_string()),
+                user_ids: vec!["zhangsan".to_string()],
+                time_stamp: 1700000000000,
+            })
+        );
+
+        let event: Event =
+            serde_json::from_str(r#"{"EventType":"attendance_check_record","DataList":[]}"#)
+                .unwrap();
+        assert!(matches!(event, Event::Unknown(_)));
+        assert_eq!(
+            serde_json::from_value::<Event>(serde_json::to_value(&event).unwrap()).unwrap(),
+            event
+        );
+        assert_eq!(event.event_type(), "attendance_check_record");
+    }
+
+    #[test]
+    fn employment_changes_are_typed() {
+        let event: Event = serde_json::from_str(
+            r#"{"EventType":"hrm_user_regular","TimeStamp":1700000000000,"UserId":["zhangsan"]}"#,
+        )
+        .unwrap();
+        let (change, payload) = event.employment_change().unwrap();
+        assert_eq!(change, EmploymentChange::Regular);
+        assert_eq!(payload.user_ids, vec!["zhangsan".to_string()]);
+        assert_eq!(
+            serde_json::from_value::<Event>(serde_json::to_value(&event).unwrap()).unwrap(),
+            event
+        );
+    }
+}
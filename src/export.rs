@@ -0,0 +1,443 @@
+use crate::pacing::paginate;
+use crate::{
+    department::{DeptInfo, ROOT_DEPT_ID},
+    error::DingTalkError,
+    organization::{OrgApp, Role},
+};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+use tracing::{info, warn};
+
+/// How many departments the users are exported of at the same time.
+const USER_CONCURRENCY: usize = 4;
+
+/// How long a checkpoint is kept after the last progress.
+const CHECKPOINT_TTL: Duration = Duration::from_secs(7 * 24 * 3600);
+
+/// The channels an [Exporter] sends the exported objects to. Objects of kinds without a channel
+/// are not exported.
+#[derive(Default)]
+pub struct ExportSinks {
+    pub departments: Option<Sender<DeptInfo>>,
+    /// Users as returned by `topapi/v2/user/list`.
+    pub users: Option<Sender<serde_json::Value>>,
+    pub roles: Option<Sender<Role>>,
+}
+
+/// The numbers of objects sent by [Exporter::run].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExportSummary {
+    pub departments: usize,
+    pub users: usize,
+    pub roles: usize,
+    /// The users left out because DingTalk listed them without `userid`.
+    #[serde(default)]
+    pub skipped_users: usize,
+}
+
+/// The progress of an export, kept in the token store so that a crashed export can resume.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct Checkpoint {
+    departments_done: bool,
+    roles_done: bool,
+    dept_ids: Vec<i64>,
+    users_done: BTreeSet<i64>,
+}
+
+/// Exports the users, departments and roles of an organization into channels, e.g. for nightly
+/// warehouse loads.
+///
+/// Departments and roles are exported concurrently, users of several departments at a time once
+/// the departments are known. With [Exporter::with_checkpoint], the progress is saved in the token
+/// store after every department, and a new run with the same key skips what a crashed run
+/// completed. Objects are delivered at least once: after a resume, or for users in several
+/// departments of different runs, the same object may be sent again.
+pub struct Exporter<'a> {
+    app: &'a OrgApp,
+    checkpoint_key: Option<String>,
+}
+
+impl OrgApp {
+    /// Returns an [Exporter] for this organization.
+    pub fn exporter(&self) -> Exporter<'_> {
+        Exporter {
+            app: self,
+            checkpoint_key: None,
+        }
+    }
+}
+
+impl<'a> Exporter<'a> {
+    /// Saves the progress under the given name, so that a run after a crash resumes.
+    pub fn with_checkpoint(mut self, name: &str) -> Self {
+        self.checkpoint_key = Some(format!(
+            "{}export:{}:{}",
+            self.app.key_prefix, self.app.corp_id, name
+        ));
+        self
+    }
+
+    /// Runs the export until everything is sent.
+    ///
+    /// [获取部门列表](https://open.dingtalk.com/document/orgapp/obtain-the-department-list-v2)
+    /// [获取部门用户详情](https://open.dingtalk.com/document/orgapp/queries-the-complete-information-of-a-department-user)
+    /// [获取角色列表](https://open.dingtalk.com/document/orgapp/obtains-a-list-of-enterprise-roles)
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the numbers of sent objects. The checkpoint is removed on success.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first `DingTalkError` of any request, or [DingTalkError::Other] if a channel
+    /// was closed. The checkpoint keeps the progress made until then.
+    pub async fn run(&self, sinks: ExportSinks) -> Result<ExportSummary, DingTalkError> {
+        let checkpoint = Mutex::new(self.load_checkpoint().await?);
+
+        let roles = self.export_roles(&checkpoint, sinks.roles.as_ref());
+        let tree = self.export_tree(
+            &checkpoint,
+            sinks.departments.as_ref(),
+            sinks.users.as_ref(),
+        );
+        let (roles, (departments, (users, skipped_users))) = futures::try_join!(roles, tree)?;
+
+        if let Some(key) = &self.checkpoint_key {
+            self.app
+                .store
+                .delete(key)
+                .await
+                .map_err(DingTalkError::Cache)?;
+        }
+
+        let summary = ExportSummary {
+            departments,
+            users,
+            roles,
+            skipped_users,
+        };
+        info!("export of {} finished: {:?}", self.app.corp_id, summary);
+
+        Ok(summary)
+    }
+
+    async fn export_roles(
+        &self,
+        checkpoint: &Mutex<Checkpoint>,
+        sink: Option<&Sender<Role>>,
+    ) -> Result<usize, DingTalkError> {
+        let sink = match sink {
+            Some(sink) if !checkpoint.lock().unwrap().roles_done => sink,
+            _ => return Ok(0),
+        };
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct RoleEntry {
+            id: i32,
+            name: String,
+        }
+        #[derive(Serialize, Deserialize, Debug)]
+        struct RoleGroup {
+            name: String,
+            #[serde(default)]
+            roles: Vec<RoleEntry>,
+        }
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Response {
+            #[serde(rename = "hasMore", default)]
+            has_more: bool,
+            #[serde(default)]
+            list: Vec<RoleGroup>,
+        }
+
+        let roles = paginate(self.app.pace, 0, |offset| async move {
+            let params = serde_json::json!({ "offset": offset, "size": 200 });
+            let page: Response = self.app.post_oapi("topapi/role/list", &params).await?;
+
+            let fetched = page.list.len() as i64;
+            let next = (page.has_more && fetched > 0).then(|| offset + fetched);
+            let roles = page
+                .list
+                .into_iter()
+                .flat_map(|group| {
+                    let group_name = group.name;
+                    group.roles.into_iter().map(move |role| Role {
+                        id: role.id,
+                        name: role.name,
+                        group_name: group_name.clone(),
+                    })
+                })
+                .collect();
+            Ok((roles, next))
+        });
+        futures::pin_mut!(roles);
+
+        let mut sent = 0;
+        while let Some(role) = roles.try_next().await? {
+            send(sink, role).await?;
+            sent += 1;
+        }
+
+        checkpoint.lock().unwrap().roles_done = true;
+        self.save_checkpoint(checkpoint).await?;
+
+        Ok(sent)
+    }
+
+    async fn export_tree(
+        &self,
+        checkpoint: &Mutex<Checkpoint>,
+        departments: Option<&Sender<DeptInfo>>,
+        users: Option<&Sender<serde_json::Value>>,
+    ) -> Result<(usize, (usize, usize)), DingTalkError> {
+        let mut sent_departments = 0;
+        if !checkpoint.lock().unwrap().departments_done {
+            let mut dept_ids = vec![ROOT_DEPT_ID];
+            let tree = self.app.walk_department_tree();
+            futures::pin_mut!(tree);
+            while let Some(dept) = tree.try_next().await? {
+                dept_ids.push(dept.dept_id);
+                if let Some(sink) = departments {
+                    send(sink, dept).await?;
+                    sent_departments += 1;
+                }
+            }
+
+            {
+                let mut checkpoint = checkpoint.lock().unwrap();
+                checkpoint.dept_ids = dept_ids;
+                checkpoint.departments_done = true;
+            }
+            self.save_checkpoint(checkpoint).await?;
+        }
+
+        let sink = match users {
+            Some(sink) => sink,
+            None => return Ok((sent_departments, (0, 0))),
+        };
+
+        let remaining: Vec<i64> = {
+            let checkpoint = checkpoint.lock().unwrap();
+            checkpoint
+                .dept_ids
+                .iter()
+                .filter(|dept_id| !checkpoint.users_done.contains(dept_id))
+                .copied()
+                .collect()
+        };
+        let seen = Mutex::new(HashSet::new());
+
+        let sent_users: Vec<(usize, usize)> = stream::iter(remaining)
+            .map(|dept_id| {
+                let seen = &seen;
+                async move {
+                    let sent = self.export_department_users(dept_id, seen, sink).await?;
+                    checkpoint.lock().unwrap().users_done.insert(dept_id);
+                    self.save_checkpoint(checkpoint).await?;
+                    Ok::<_, DingTalkError>(sent)
+                }
+            })
+            .buffer_unordered(USER_CONCURRENCY)
+            .try_collect()
+            .await?;
+
+        let (sent, skipped) = sent_users
+            .into_iter()
+            .fold((0, 0), |(sent, skipped), (s, k)| (sent + s, skipped + k));
+        Ok((sent_departments, (sent, skipped)))
+    }
+
+    /// Sends the users of a department not sent before, returning how many were sent and how
+    /// many were skipped for lack of a `userid`.
+    async fn export_department_users(
+        &self,
+        dept_id: i64,
+        seen: &Mutex<HashSet<String>>,
+        sink: &Sender<serde_json::Value>,
+    ) -> Result<(usize, usize), DingTalkError> {
+        let (mut sent, mut skipped) = (0, 0);
+        let users = self.app.department_users::<serde_json::Value>(dept_id);
+        futures::pin_mut!(users);
+        while let Some(user) = users.try_next().await? {
+            let user_id = match user["userid"].as_str() {
+                Some(user_id) if !user_id.is_empty() => user_id.to_string(),
+                _ => {
+                    warn!(dept_id, "skipping exported user without userid");
+                    skipped += 1;
+                    continue;
+                }
+            };
+            if seen.lock().unwrap().insert(user_id) {
+                send(sink, user).await?;
+                sent += 1;
+            }
+        }
+
+        Ok((sent, skipped))
+    }
+
+    async fn load_checkpoint(&self) -> Result<Checkpoint, DingTalkError> {
+        let key = match &self.checkpoint_key {
+            Some(key) => key,
+            None => return Ok(Checkpoint::default()),
+        };
+
+        match self
+            .app
+            .store
+            .get(key)
+            .await
+            .map_err(DingTalkError::Cache)?
+        {
+            Some(value) => {
+                info!("resuming export from checkpoint {}", key);
+                Ok(serde_json::from_str(&value)?)
+            }
+            None => Ok(Checkpoint::default()),
+        }
+    }
+
+    async fn save_checkpoint(&self, checkpoint: &Mutex<Checkpoint>) -> Result<(), DingTalkError> {
+        let key = match &self.checkpoint_key {
+            Some(key) => key,
+            None => return Ok(()),
+        };
+
+        let value = serde_json::to_string(&*checkpoint.lock().unwrap())?;
+        self.app
+            .store
+            .set(key, &value, Some(CHECKPOINT_TTL))
+            .await
+            .map_err(DingTalkError::Cache)
+    }
+}
+
+async fn send<T>(sink: &Sender<T>, value: T) -> Result<(), DingTalkError> {
+    sink.send(value)
+        .await
+        .map_err(|_| DingTalkError::Other("export channel closed".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::Mock;
+    use serde_json::json;
+    use tokio::sync::mpsc::{channel, Receiver};
+
+    fn drain<T>(mut receiver: Receiver<T>) -> Vec<T> {
+        let mut items = Vec::new();
+        while let Ok(item) = receiver.try_recv() {
+            items.push(item);
+        }
+        items
+    }
+
+    async fn mock_app() -> (std::sync::Arc<Mock>, OrgApp) {
+        let mock = Mock::new();
+        let listsub = "topapi/v2/department/listsub";
+        mock.respond(
+            listsub,
+            json!({ "errcode": 0, "result": [{ "dept_id": 2, "name": "研发部", "parent_id": 1 }] }),
+        );
+        mock.respond(listsub, json!({ "errcode": 0, "result": [] }));
+        mock.respond(
+            "topapi/role/list",
+            json!({ "errcode": 0, "result": { "hasMore": false, "list": [
+                { "name": "默认", "roles": [{ "id": 1, "name": "负责人" }] },
+            ] } }),
+        );
+        let users = "topapi/v2/user/list";
+        mock.respond(
+            users,
+            json!({ "errcode": 0, "result": { "has_more": false, "list": [
+                { "userid": "zhangsan", "name": "张三" },
+                { "name": "无名" },
+            ] } }),
+        );
+        mock.respond(
+            users,
+            json!({ "errcode": 0, "result": { "has_more": false, "list": [
+                { "userid": "zhangsan", "name": "张三" },
+                { "userid": "lisi", "name": "李四" },
+            ] } }),
+        );
+        let app = mock.app().await;
+        (mock, app)
+    }
+
+    #[tokio::test]
+    async fn users_are_sent_once_and_skipped_without_userid() {
+        let (_, app) = mock_app().await;
+        let (departments, departments_rx) = channel(16);
+        let (users, users_rx) = channel(16);
+        let (roles, roles_rx) = channel(16);
+
+        let summary = app
+            .exporter()
+            .run(ExportSinks {
+                departments: Some(departments),
+                users: Some(users),
+                roles: Some(roles),
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(
+            summary,
+            ExportSummary {
+                departments: 1,
+                users: 2,
+                roles: 1,
+                skipped_users: 1,
+            }
+        );
+        assert_eq!(drain(departments_rx)[0].dept_id, 2);
+        let mut user_ids: Vec<String> = drain(users_rx)
+            .iter()
+            .map(|user| user["userid"].as_str().unwrap().to_string())
+            .collect();
+        user_ids.sort();
+        assert_eq!(user_ids, vec!["lisi", "zhangsan"]);
+        assert_eq!(drain(roles_rx)[0].group_name, "默认");
+    }
+
+    #[tokio::test]
+    async fn runs_resume_from_the_checkpoint() {
+        let (mock, app) = mock_app().await;
+        let exporter = app.exporter().with_checkpoint("nightly");
+        let key = exporter.checkpoint_key.clone().unwrap();
+        let checkpoint = Checkpoint {
+            departments_done: true,
+            roles_done: true,
+            dept_ids: vec![1, 2],
+            users_done: [1].into_iter().collect(),
+        };
+        app.store
+            .set(&key, &serde_json::to_string(&checkpoint).unwrap(), None)
+            .await
+            .unwrap();
+        let (users, users_rx) = channel(16);
+
+        let summary = exporter
+            .run(ExportSinks {
+                users: Some(users),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(summary.users, 1);
+        assert_eq!(drain(users_rx).len(), 1);
+        assert_eq!(
+            mock.bodies("topapi/v2/user/list"),
+            vec![json!({ "dept_id": 2, "cursor": 0, "size": 100, "language": "zh_CN" })]
+        );
+        assert!(mock.bodies("topapi/v2/department/listsub").is_empty());
+        assert_eq!(app.store.get(&key).await.unwrap(), None);
+    }
+}
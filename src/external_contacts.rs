@@ -0,0 +1,290 @@
+use crate::{error::DingTalkError, organization::OrgApp};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+/// An external contact of the organization address book, e.g. a customer shared with sales.
+///
+/// `name`, `follower_user_id`, `label_ids`, `state_code` and `mobile` are required to create a
+/// contact.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExternalContact {
+    /// Empty when creating a contact.
+    #[serde(alias = "userid", default, skip_serializing_if = "String::is_empty")]
+    pub user_id: String,
+    pub name: String,
+    /// The user ID of the employee in charge of the contact.
+    #[serde(default)]
+    pub follower_user_id: String,
+    #[serde(default)]
+    pub label_ids: Vec<i64>,
+    /// The country calling code of `mobile`, e.g. `86`.
+    #[serde(default)]
+    pub state_code: String,
+    #[serde(default)]
+    pub mobile: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub company_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remark: Option<String>,
+    /// The departments the contact is shared with.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub share_dept_ids: Vec<i64>,
+    /// The users the contact is shared with.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub share_user_ids: Vec<String>,
+}
+
+/// A group of the labels of external contacts, see
+/// [OrgApp::list_external_contact_label_groups].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ExternalContactLabelGroup {
+    pub name: String,
+    #[serde(default)]
+    pub color: i64,
+    #[serde(default)]
+    pub labels: Vec<ExternalContactLabel>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ExternalContactLabel {
+    pub id: i64,
+    pub name: String,
+}
+
+impl OrgApp {
+    /// Lists the external contacts of the organization.
+    ///
+    /// [获取外部联系人列表](https://open.dingtalk.com/document/orgapp/obtain-the-external-contact-list)
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - The offset of the page, `0` for the first page.
+    /// * `size` - The page size, at most 100.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the contacts of the page; a page shorter than `size` is the last.
+    pub async fn list_external_contacts(
+        &self,
+        offset: i64,
+        size: i64,
+    ) -> Result<Vec<ExternalContact>, DingTalkError> {
+        let params = serde_json::json!({ "offset": offset, "size": size });
+        self.post_oapi("topapi/extcontact/list", &params).await
+    }
+
+    /// Gets an external contact.
+    ///
+    /// [获取外部联系人详情](https://open.dingtalk.com/document/orgapp/obtain-external-contact-details)
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID of the contact.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the contact if successful, otherwise an error.
+    pub async fn get_external_contact(
+        &self,
+        user_id: String,
+    ) -> Result<ExternalContact, DingTalkError> {
+        let params = serde_json::json!({ "user_id": user_id });
+        self.post_oapi("topapi/extcontact/get", &params).await
+    }
+
+    /// Creates an external contact.
+    ///
+    /// [添加外部联系人](https://open.dingtalk.com/document/orgapp/add-an-external-contact)
+    ///
+    /// # Arguments
+    ///
+    /// * `contact` - The contact; its `user_id` is ignored.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the user ID of the new contact if successful, otherwise an error.
+    pub async fn create_external_contact(
+        &self,
+        contact: &ExternalContact,
+    ) -> Result<String, DingTalkError> {
+        let contact = ExternalContact {
+            user_id: String::new(),
+            ..contact.clone()
+        };
+        let params = serde_json::json!({ "contact": contact });
+        let user_id: String = self.post_oapi("topapi/extcontact/create", &params).await?;
+        info!("create_external_contact: {}", user_id);
+        Ok(user_id)
+    }
+
+    /// Updates an external contact, replacing all its fields.
+    ///
+    /// [更新外部联系人](https://open.dingtalk.com/document/orgapp/update-external-contacts)
+    ///
+    /// # Arguments
+    ///
+    /// * `contact` - The contact, identified by its `user_id`.
+    ///
+    /// # Returns
+    ///
+    /// An empty `Result` if successful, otherwise an error.
+    pub async fn update_external_contact(
+        &self,
+        contact: &ExternalContact,
+    ) -> Result<(), DingTalkError> {
+        if contact.user_id.is_empty() {
+            return Err(DingTalkError::Config(
+                "external contact to update has no user_id".to_string(),
+            ));
+        }
+        let params = serde_json::json!({ "contact": contact });
+        self.post_oapi::<serde_json::Value>("topapi/extcontact/update", &params)
+            .await?;
+        info!("update_external_contact: {}", contact.user_id);
+        Ok(())
+    }
+
+    /// Deletes an external contact.
+    ///
+    /// [删除外部联系人](https://open.dingtalk.com/document/orgapp/delete-an-external-contact)
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID of the contact.
+    ///
+    /// # Returns
+    ///
+    /// An empty `Result` if successful, otherwise an error.
+    pub async fn delete_external_contact(&self, user_id: String) -> Result<(), DingTalkError> {
+        let params = serde_json::json!({ "user_id": user_id });
+        self.post_oapi::<serde_json::Value>("topapi/extcontact/delete", &params)
+            .await?;
+        info!("delete_external_contact: {}", user_id);
+        Ok(())
+    }
+
+    /// Lists the label groups of external contacts, e.g. to resolve their `label_ids`.
+    ///
+    /// [获取外部联系人标签列表](https://open.dingtalk.com/document/orgapp/obtains-the-external-contact-label-list)
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - The offset of the page, `0` for the first page.
+    /// * `size` - The page size, at most 100.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the label groups of the page; a page shorter than `size` is the last.
+    pub async fn list_external_contact_label_groups(
+        &self,
+        offset: i64,
+        size: i64,
+    ) -> Result<Vec<ExternalContactLabelGroup>, DingTalkError> {
+        let params = serde_json::json!({ "offset": offset, "size": size });
+        self.post_oapi("topapi/extcontact/listlabelgroups", &params)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::Mock;
+    use serde_json::json;
+
+    fn contact() -> ExternalContact {
+        ExternalContact {
+            user_id: "ext-1".to_string(),
+            name: "王五".to_string(),
+            follower_user_id: "zhangsan".to_string(),
+            label_ids: vec![1001],
+            state_code: "86".to_string(),
+            mobile: "13800000000".to_string(),
+            company_name: Some("示例科技".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn created_contacts_are_sent_without_user_id() {
+        let mock = Mock::new();
+        let path = "topapi/extcontact/create";
+        mock.respond(path, json!({ "errcode": 0, "result": "ext-2" }));
+        let app = mock.app().await;
+
+        let user_id = app.create_external_contact(&contact()).await.unwrap();
+
+        assert_eq!(user_id, "ext-2");
+        assert_eq!(
+            mock.bodies(path),
+            vec![json!({ "contact": {
+                "name": "王五",
+                "follower_user_id": "zhangsan",
+                "label_ids": [1001],
+                "state_code": "86",
+                "mobile": "13800000000",
+                "company_name": "示例科技",
+            } })]
+        );
+    }
+
+    #[tokio::test]
+    async fn contacts_are_looked_up_and_deleted_by_user_id() {
+        let mock = Mock::new();
+        mock.respond(
+            "topapi/extcontact/get",
+            json!({ "errcode": 0, "result": { "userid": "ext-1", "name": "王五" } }),
+        );
+        let app = mock.app().await;
+
+        let contact = app.get_external_contact("ext-1".to_string()).await.unwrap();
+        app.delete_external_contact("ext-1".to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(contact.user_id, "ext-1");
+        assert_eq!(
+            mock.bodies("topapi/extcontact/get"),
+            vec![json!({ "user_id": "ext-1" })]
+        );
+        assert_eq!(
+            mock.bodies("topapi/extcontact/delete"),
+            vec![json!({ "user_id": "ext-1" })]
+        );
+    }
+
+    #[tokio::test]
+    async fn updated_contacts_are_identified_by_user_id() {
+        let mock = Mock::new();
+        let app = mock.app().await;
+
+        app.update_external_contact(&contact()).await.unwrap();
+
+        assert_eq!(
+            mock.bodies("topapi/extcontact/update"),
+            vec![json!({ "contact": {
+                "user_id": "ext-1",
+                "name": "王五",
+                "follower_user_id": "zhangsan",
+                "label_ids": [1001],
+                "state_code": "86",
+                "mobile": "13800000000",
+                "company_name": "示例科技",
+            } })]
+        );
+        let anonymous = ExternalContact {
+            user_id: String::new(),
+            ..contact()
+        };
+        assert!(matches!(
+            app.update_external_contact(&anonymous).await,
+            Err(DingTalkError::Config(_))
+        ));
+    }
+}
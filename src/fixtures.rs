@@ -0,0 +1,143 @@
+//! Sanitized samples of real DingTalk responses, for writing deserialization tests without calling
+//! the API.
+//!
+//! Each sample is the object a method deserializes, i.e. the `result` of the response envelope
+//! where there is one. The constants hold the raw JSON; the functions of the same name load it.
+//!
+//! ```
+//! let user = async_dingtalk::fixtures::employee_user();
+//! assert_eq!(user.user_id, "zhangsan");
+//! ```
+
+use crate::{
+    AttendanceColumn, AttendancePage, ColumnValues, DeptDetail, DeptInfo, EmployeeUser, LeaveTimes,
+    Organization, PageResult, UserGetByCodeResponse, UserGetProfileResponse, UserInfo,
+};
+use serde::de::DeserializeOwned;
+
+pub const CONTACT_USER: &str = include_str!("../fixtures/contact_user.json");
+pub const ORGANIZATION: &str = include_str!("../fixtures/organization.json");
+pub const USER_GET_BY_CODE: &str = include_str!("../fixtures/user_get_by_code.json");
+pub const USER_GET_PROFILE: &str = include_str!("../fixtures/user_get_profile.json");
+pub const EMPLOYEE_USER: &str = include_str!("../fixtures/employee_user.json");
+pub const EMPLOYEE_USER_LIST: &str = include_str!("../fixtures/employee_user_list.json");
+pub const PAGE_RESULT: &str = include_str!("../fixtures/page_result.json");
+pub const DEPT_INFO: &str = include_str!("../fixtures/dept_info.json");
+pub const DEPT_DETAIL: &str = include_str!("../fixtures/dept_detail.json");
+pub const ATTENDANCE_PAGE: &str = include_str!("../fixtures/attendance_page.json");
+pub const ATTENDANCE_COLUMN: &str = include_str!("../fixtures/attendance_column.json");
+pub const COLUMN_VALUES: &str = include_str!("../fixtures/column_values.json");
+pub const LEAVE_TIMES: &str = include_str!("../fixtures/leave_times.json");
+
+/// Deserializes a sample.
+///
+/// # Panics
+///
+/// Panics if the JSON does not match `T`.
+pub fn load<T: DeserializeOwned>(json: &str) -> T {
+    match serde_json::from_str(json) {
+        Ok(value) => value,
+        Err(e) => panic!("invalid fixture: {}", e),
+    }
+}
+
+pub fn contact_user() -> UserInfo {
+    load(CONTACT_USER)
+}
+
+pub fn organization() -> Organization {
+    load(ORGANIZATION)
+}
+
+pub fn user_get_by_code() -> UserGetByCodeResponse {
+    load(USER_GET_BY_CODE)
+}
+
+pub fn user_get_profile() -> UserGetProfileResponse {
+    load(USER_GET_PROFILE)
+}
+
+pub fn employee_user() -> EmployeeUser {
+    load(EMPLOYEE_USER)
+}
+
+pub fn employee_user_list() -> PageResult<EmployeeUser> {
+    load(EMPLOYEE_USER_LIST)
+}
+
+pub fn page_result() -> PageResult {
+    load(PAGE_RESULT)
+}
+
+pub fn dept_info() -> DeptInfo {
+    load(DEPT_INFO)
+}
+
+pub fn dept_detail() -> DeptDetail {
+    load(DEPT_DETAIL)
+}
+
+pub fn attendance_page() -> AttendancePage {
+    load(ATTENDANCE_PAGE)
+}
+
+pub fn attendance_column() -> AttendanceColumn {
+    load(ATTENDANCE_COLUMN)
+}
+
+pub fn column_values() -> ColumnValues {
+    load(COLUMN_VALUES)
+}
+
+pub fn leave_times() -> LeaveTimes {
+    load(LEAVE_TIMES)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Mobile;
+    use serde::Serialize;
+
+    /// Serializing a sample and deserializing it again must not lose anything.
+    fn assert_round_trip<T: Serialize + DeserializeOwned>(json: &str) {
+        let first = serde_json::to_value(load::<T>(json)).unwrap();
+        let second = serde_json::to_value(serde_json::from_value::<T>(first.clone()).unwrap());
+
+        assert_eq!(first, second.unwrap());
+    }
+
+    #[test]
+    fn fixtures_round_trip() {
+        assert_round_trip::<UserInfo>(CONTACT_USER);
+        assert_round_trip::<Organization>(ORGANIZATION);
+        assert_round_trip::<UserGetByCodeResponse>(USER_GET_BY_CODE);
+        assert_round_trip::<UserGetProfileResponse>(USER_GET_PROFILE);
+        assert_round_trip::<EmployeeUser>(EMPLOYEE_USER);
+        assert_round_trip::<PageResult<EmployeeUser>>(EMPLOYEE_USER_LIST);
+        assert_round_trip::<PageResult>(PAGE_RESULT);
+        assert_round_trip::<DeptInfo>(DEPT_INFO);
+        assert_round_trip::<DeptDetail>(DEPT_DETAIL);
+        assert_round_trip::<AttendancePage>(ATTENDANCE_PAGE);
+        assert_round_trip::<AttendanceColumn>(ATTENDANCE_COLUMN);
+        assert_round_trip::<ColumnValues>(COLUMN_VALUES);
+        assert_round_trip::<LeaveTimes>(LEAVE_TIMES);
+    }
+
+    #[test]
+    fn list_entries_may_omit_fields() {
+        let page = employee_user_list();
+        assert_eq!(page.next_offset(), None);
+
+        let full = &page.data[0];
+        assert!(full.boss);
+        assert_eq!(full.leader_in_dept, None);
+        assert!(full.dept_order_list.is_empty());
+
+        let sparse = &page.data[1];
+        assert_eq!(sparse.user_id, "lisi");
+        assert!(!sparse.boss && !sparse.admin && !sparse.hide_mobile);
+        assert_eq!(sparse.mobile, Mobile::Hidden);
+        assert!(sparse.job_number.is_empty());
+    }
+}
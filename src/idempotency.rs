@@ -0,0 +1,238 @@
+use crate::{error::DingTalkError, organization::OrgApp};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::future::Future;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// How long the outcome of an idempotent operation is remembered.
+const IDEMPOTENCY_TTL: Duration = Duration::from_secs(24 * 3600);
+/// How long a call holds the claim of its key, longer than any request takes. The claim of a
+/// crashed process expires after this time.
+const CLAIM_TTL: Duration = Duration::from_secs(300);
+
+/// The state of an idempotent operation kept in the token store.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "state", content = "value", rename_all = "snake_case")]
+enum Record {
+    /// The request was sent, but its outcome is unknown, e.g. after a timeout.
+    Pending,
+    /// The request succeeded with the given value.
+    Done(serde_json::Value),
+}
+
+impl OrgApp {
+    fn idempotency_key(&self, operation: &str, key: &str) -> String {
+        format!(
+            "{}idempotency:{}:{}:{}:{}",
+            self.key_prefix, self.appid, self.corp_id, operation, key
+        )
+    }
+
+    /// Runs a create operation at most once per caller-supplied key.
+    ///
+    /// A completed operation returns its remembered value. If an earlier attempt was sent without
+    /// an answer, `recover` is asked for the object it might have created before sending again.
+    /// Operations failing with an `errcode` are known not to have happened and are forgotten.
+    ///
+    /// Concurrent calls with the same key are serialized by claiming the key with
+    /// [TokenStore::set_if_absent](crate::TokenStore::set_if_absent): only the call holding the
+    /// claim sends the request, the others fail with [DingTalkError::InProgress].
+    pub(crate) async fn run_idempotent<T, R, RFut, F, FFut>(
+        &self,
+        operation: &str,
+        key: Option<&str>,
+        recover: R,
+        run: F,
+    ) -> Result<T, DingTalkError>
+    where
+        T: Serialize + DeserializeOwned,
+        R: FnOnce() -> RFut,
+        RFut: Future<Output = Result<Option<T>, DingTalkError>>,
+        F: FnOnce() -> FFut,
+        FFut: Future<Output = Result<T, DingTalkError>>,
+    {
+        let key = match key {
+            Some(key) => self.idempotency_key(operation, key),
+            None => return run().await,
+        };
+
+        let claim = format!("{}:claim", key);
+        let claimed = self
+            .store
+            .set_if_absent(&claim, "", Some(CLAIM_TTL))
+            .await
+            .map_err(DingTalkError::Cache)?;
+        if !claimed {
+            // The call holding the claim may have completed since.
+            if let Some(Record::Done(value)) = self.read_record(operation, &key).await? {
+                info!("{}: replaying {}", operation, key);
+                return Ok(serde_json::from_value(value)?);
+            }
+            return Err(DingTalkError::InProgress(key));
+        }
+
+        let result = self.run_claimed(operation, &key, recover, run).await;
+        if let Err(e) = self.store.delete(&claim).await {
+            warn!("{}: failed to release {}: {}", operation, claim, e);
+        }
+
+        result
+    }
+
+    /// Runs the operation once the key is claimed.
+    async fn run_claimed<T, R, RFut, F, FFut>(
+        &self,
+        operation: &str,
+        key: &str,
+        recover: R,
+        run: F,
+    ) -> Result<T, DingTalkError>
+    where
+        T: Serialize + DeserializeOwned,
+        R: FnOnce() -> RFut,
+        RFut: Future<Output = Result<Option<T>, DingTalkError>>,
+        F: FnOnce() -> FFut,
+        FFut: Future<Output = Result<T, DingTalkError>>,
+    {
+        match self.read_record(operation, key).await? {
+            Some(Record::Done(value)) => {
+                info!("{}: replaying {}", operation, key);
+                return Ok(serde_json::from_value(value)?);
+            }
+            Some(Record::Pending) => {
+                if let Some(value) = recover().await? {
+                    info!("{}: recovered {}", operation, key);
+                    self.save_record(key, &Record::Done(serde_json::to_value(&value)?))
+                        .await?;
+                    return Ok(value);
+                }
+            }
+            None => {}
+        }
+
+        self.save_record(key, &Record::Pending).await?;
+        match run().await {
+            Ok(value) => {
+                self.save_record(key, &Record::Done(serde_json::to_value(&value)?))
+                    .await?;
+                Ok(value)
+            }
+            Err(e) => {
+                if e.errcode().is_some() {
+                    self.store.delete(key).await.map_err(DingTalkError::Cache)?;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Reads the record of a key, ignoring unreadable ones.
+    async fn read_record(
+        &self,
+        operation: &str,
+        key: &str,
+    ) -> Result<Option<Record>, DingTalkError> {
+        let record = self.store.get(key).await.map_err(DingTalkError::Cache)?;
+        match record.map(|record| serde_json::from_str::<Record>(&record)) {
+            Some(Ok(record)) => Ok(Some(record)),
+            Some(Err(e)) => {
+                warn!("{}: ignoring unreadable record {}: {}", operation, key, e);
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn save_record(&self, key: &str, record: &Record) -> Result<(), DingTalkError> {
+        self.store
+            .set(key, &serde_json::to_string(record)?, Some(IDEMPOTENCY_TTL))
+            .await
+            .map_err(DingTalkError::Cache)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{MemoryStore, TokenStore};
+    use std::sync::Arc;
+
+    fn org_app() -> OrgApp {
+        let store: Arc<dyn TokenStore> = Arc::new(MemoryStore::new());
+        OrgApp::new(
+            "app".to_string(),
+            "secret".to_string(),
+            "corp".to_string(),
+            store,
+        )
+    }
+
+    #[tokio::test]
+    async fn completed_operations_are_replayed() {
+        let app = org_app();
+        let first: String = app
+            .run_idempotent(
+                "create",
+                Some("key"),
+                || async { Ok(None) },
+                || async { Ok("user-1".to_string()) },
+            )
+            .await
+            .unwrap();
+        let second: String = app
+            .run_idempotent(
+                "create",
+                Some("key"),
+                || async { Ok(None) },
+                || async { Ok("user-2".to_string()) },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(first, "user-1");
+        assert_eq!(second, "user-1");
+    }
+
+    #[tokio::test]
+    async fn pending_operations_are_recovered() {
+        let app = org_app();
+        let key = app.idempotency_key("create", "key");
+        app.save_record(&key, &Record::Pending).await.unwrap();
+
+        let user_id: String = app
+            .run_idempotent(
+                "create",
+                Some("key"),
+                || async { Ok(Some("user-1".to_string())) },
+                || async { Ok("user-2".to_string()) },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(user_id, "user-1");
+    }
+
+    #[tokio::test]
+    async fn concurrent_retries_send_one_create() {
+        let mock = crate::mock::Mock::new();
+        mock.respond(
+            "topapi/v2/user/create",
+            serde_json::json!({ "errcode": 0, "result": { "userid": "zhaoliu" } }),
+        );
+        mock.delay(Duration::from_millis(20));
+        let app = mock.app().await;
+        let request =
+            || crate::CreateUserRequest::new("赵六", "13900000000").idempotency_key("hr-6");
+
+        let (a, b) = futures::join!(app.create_user(request()), app.create_user(request()));
+
+        assert_eq!(mock.bodies("topapi/v2/user/create").len(), 1);
+        let (ok, in_progress) = if a.is_ok() { (a, b) } else { (b, a) };
+        assert_eq!(ok.unwrap(), "zhaoliu");
+        assert!(matches!(in_progress, Err(DingTalkError::InProgress(_))));
+
+        // Once completed, retries replay the result.
+        assert_eq!(app.create_user(request()).await.unwrap(), "zhaoliu");
+        assert_eq!(mock.bodies("topapi/v2/user/create").len(), 1);
+    }
+}
@@ -0,0 +1,390 @@
+use crate::cache::ProfileKind;
+use crate::organization::OrgApp;
+use crate::{department::ROOT_DEPT_ID, error::DingTalkError};
+use futures::future;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::{debug, warn};
+
+/// How many departments are listed at the same time.
+const MAX_CONCURRENT_REQUESTS: usize = 8;
+/// The profile cache ID of the index, which is one entry per organization.
+const CACHE_ID: &str = "all";
+
+/// The user IDs of the employees of an organization keyed by job number, see
+/// [OrgApp::get_job_number_index].
+///
+/// Build it once and pass it to [OrgApp::upsert_user_with_index] to sync many users without
+/// listing the organization again for every one of them.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct JobNumberIndex {
+    /// The user IDs of every job number, several when a job number is shared.
+    user_ids: HashMap<String, Vec<String>>,
+}
+
+impl JobNumberIndex {
+    /// Returns the user ID of the employee with the job number.
+    ///
+    /// # Errors
+    ///
+    /// Returns [DingTalkError::AmbiguousJobNumber] if several employees share the job number,
+    /// as picking one of them would act on the wrong person.
+    pub fn get(&self, job_number: &str) -> Result<Option<&str>, DingTalkError> {
+        match self.user_ids.get(job_number).map(Vec::as_slice) {
+            None | Some([]) => Ok(None),
+            Some([user_id]) => Ok(Some(user_id)),
+            Some(user_ids) => Err(DingTalkError::AmbiguousJobNumber {
+                job_number: job_number.to_string(),
+                user_ids: user_ids.to_vec(),
+            }),
+        }
+    }
+
+    /// Returns the number of job numbers.
+    pub fn len(&self) -> usize {
+        self.user_ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.user_ids.is_empty()
+    }
+
+    /// Iterates over the job numbers and the user IDs of the employees sharing them.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[String])> {
+        self.user_ids
+            .iter()
+            .map(|(job_number, user_ids)| (job_number.as_str(), user_ids.as_slice()))
+    }
+
+    /// Records the job number of an employee, replacing its previous one. An empty job number
+    /// only removes the previous one.
+    pub fn assign(&mut self, user_id: &str, job_number: &str) {
+        self.remove_user(user_id);
+        if !job_number.is_empty() {
+            self.insert(job_number.to_string(), user_id.to_string());
+        }
+    }
+
+    /// Removes an employee, e.g. after it was deleted.
+    pub fn remove_user(&mut self, user_id: &str) {
+        self.user_ids.retain(|_, user_ids| {
+            user_ids.retain(|id| id != user_id);
+            !user_ids.is_empty()
+        });
+    }
+
+    fn insert(&mut self, job_number: String, user_id: String) {
+        let user_ids = self.user_ids.entry(job_number).or_default();
+        // Members of several departments are listed once per department.
+        if !user_ids.contains(&user_id) {
+            user_ids.push(user_id);
+        }
+    }
+}
+
+impl OrgApp {
+    /// Indexes the employees of the organization by job number.
+    ///
+    /// DingTalk has no endpoint to look users up by job number, so the members of every
+    /// department are listed. The index is kept in the profile cache as
+    /// [ProfileKind::JobNumbers] when one is configured.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the index; employees without a job number are left out. A job
+    /// number shared by several employees is logged, and [looking it up](JobNumberIndex::get)
+    /// fails.
+    pub async fn get_job_number_index(&self) -> Result<JobNumberIndex, DingTalkError> {
+        if let Some(cache) = &self.profile_cache {
+            if let Some(index) = cache
+                .get(
+                    &self.key_prefix,
+                    ProfileKind::JobNumbers,
+                    &self.corp_id,
+                    CACHE_ID,
+                )
+                .await
+            {
+                return Ok(index);
+            }
+        }
+
+        let mut dept_ids = vec![ROOT_DEPT_ID];
+        let tree: Vec<i64> = self
+            .walk_department_tree()
+            .map_ok(|dept| dept.dept_id)
+            .try_collect()
+            .await?;
+        dept_ids.extend(tree);
+
+        let pages: Vec<Vec<(String, String)>> = stream::iter(dept_ids)
+            .map(|dept_id| self.list_job_numbers(dept_id))
+            .buffer_unordered(MAX_CONCURRENT_REQUESTS)
+            .try_collect()
+            .await?;
+        let mut index = JobNumberIndex::default();
+        for (job_number, user_id) in pages.into_iter().flatten() {
+            index.insert(job_number, user_id);
+        }
+        for (job_number, user_ids) in index.iter().filter(|(_, user_ids)| user_ids.len() > 1) {
+            warn!(
+                job_number = %job_number,
+                user_ids = ?user_ids,
+                "job number shared by several employees"
+            );
+        }
+        debug!(employees = index.len(), "indexed job numbers");
+
+        if let Some(cache) = &self.profile_cache {
+            cache
+                .set(
+                    &self.key_prefix,
+                    ProfileKind::JobNumbers,
+                    &self.corp_id,
+                    CACHE_ID,
+                    &index,
+                )
+                .await;
+        }
+
+        Ok(index)
+    }
+
+    /// Applies a change of employees to the cached job number index, if any, so that a batch of
+    /// writes does not list the organization again after each one. Failures are logged, as the
+    /// change has been made already.
+    ///
+    /// Concurrent writers may overwrite each other's changes, which the TTL of
+    /// [ProfileKind::JobNumbers] bounds.
+    pub(crate) async fn update_job_number_index(&self, change: impl FnOnce(&mut JobNumberIndex)) {
+        if let Some(cache) = &self.profile_cache {
+            let index: Option<JobNumberIndex> = cache
+                .get(
+                    &self.key_prefix,
+                    ProfileKind::JobNumbers,
+                    &self.corp_id,
+                    CACHE_ID,
+                )
+                .await;
+            if let Some(mut index) = index {
+                change(&mut index);
+                cache
+                    .set(
+                        &self.key_prefix,
+                        ProfileKind::JobNumbers,
+                        &self.corp_id,
+                        CACHE_ID,
+                        &index,
+                    )
+                    .await;
+            }
+        }
+    }
+
+    /// Resolves job numbers, e.g. of a payroll system, to user IDs with
+    /// [OrgApp::get_job_number_index].
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the user IDs keyed by job number; unknown job numbers are left out.
+    ///
+    /// # Errors
+    ///
+    /// Returns [DingTalkError::AmbiguousJobNumber] if a job number is shared by several
+    /// employees.
+    pub async fn resolve_job_numbers(
+        &self,
+        job_numbers: &[String],
+    ) -> Result<HashMap<String, String>, DingTalkError> {
+        let index = self.get_job_number_index().await?;
+        let mut user_ids = HashMap::new();
+        for job_number in job_numbers {
+            if let Some(user_id) = index.get(job_number)? {
+                user_ids.insert(job_number.clone(), user_id.to_string());
+            }
+        }
+        Ok(user_ids)
+    }
+
+    /// Lists the job numbers and user IDs of the direct members of a department.
+    async fn list_job_numbers(&self, dept_id: i64) -> Result<Vec<(String, String)>, DingTalkError> {
+        #[derive(Serialize, Deserialize, Debug)]
+        struct User {
+            userid: String,
+            #[serde(default)]
+            job_number: String,
+        }
+
+        let entries = self
+            .department_users::<User>(dept_id)
+            .try_filter(|user| future::ready(!user.job_number.is_empty()))
+            .map_ok(|user| (user.job_number, user.userid))
+            .try_collect()
+            .await?;
+
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mock::{FailingStore, Mock};
+    use crate::{
+        CreateUserRequest, DingTalkError, MemoryStore, ProfileCache, UpdateUserRequest,
+        UpsertOutcome,
+    };
+    use serde_json::json;
+    use std::sync::Arc;
+
+    async fn mock_app() -> (Arc<Mock>, crate::OrgApp) {
+        let mock = Mock::new();
+        mock.respond(
+            "topapi/v2/department/listsub",
+            json!({ "errcode": 0, "result": [] }),
+        );
+        mock.respond(
+            "topapi/v2/user/list",
+            json!({ "errcode": 0, "result": { "has_more": false, "list": [
+                { "userid": "zhangsan", "name": "张三", "job_number": "4" },
+                { "userid": "lisi", "name": "李四", "job_number": "4" },
+                { "userid": "wangwu", "name": "王五", "job_number": "5" },
+                { "userid": "sunqi", "name": "孙七" },
+            ] } }),
+        );
+        mock.respond(
+            "topapi/v2/user/getbymobile",
+            json!({ "errcode": 60121, "errmsg": "找不到该用户" }),
+        );
+        mock.respond(
+            "topapi/v2/user/create",
+            json!({ "errcode": 0, "result": { "userid": "zhaoliu" } }),
+        );
+        let cache = Arc::new(ProfileCache::new(Arc::new(MemoryStore::new())));
+        let app = mock.app().await.with_profile_cache(cache);
+        (mock, app)
+    }
+
+    #[tokio::test]
+    async fn shared_job_numbers_are_ambiguous() {
+        let (_, app) = mock_app().await;
+
+        let index = app.get_job_number_index().await.unwrap();
+
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.get("5").unwrap(), Some("wangwu"));
+        assert_eq!(index.get("404").unwrap(), None);
+        match index.get("4") {
+            Err(DingTalkError::AmbiguousJobNumber {
+                job_number,
+                user_ids,
+            }) => {
+                assert_eq!(job_number, "4");
+                assert_eq!(user_ids, vec!["zhangsan", "lisi"]);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(app.resolve_job_numbers(&["4".to_string()]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn upserts_find_users_by_job_number() {
+        let (mock, app) = mock_app().await;
+
+        let profile = CreateUserRequest::new("王五", "13800000000").job_number("5");
+        let outcome = app.upsert_user(profile).await.unwrap();
+
+        assert_eq!(outcome, UpsertOutcome::Updated("wangwu".to_string()));
+        assert_eq!(
+            mock.bodies("topapi/v2/user/update")[0]["userid"],
+            json!("wangwu")
+        );
+    }
+
+    #[tokio::test]
+    async fn upserts_of_shared_job_numbers_change_nobody() {
+        let (mock, app) = mock_app().await;
+
+        let profile = CreateUserRequest::new("张三", "13800000000").job_number("4");
+        let result = app.upsert_user(profile).await;
+
+        assert!(matches!(
+            result,
+            Err(DingTalkError::AmbiguousJobNumber { .. })
+        ));
+        assert!(mock.bodies("topapi/v2/user/update").is_empty());
+        assert!(mock.bodies("topapi/v2/user/create").is_empty());
+    }
+
+    #[tokio::test]
+    async fn user_changes_update_the_cached_index() {
+        let (mock, app) = mock_app().await;
+        let lists = || mock.bodies("topapi/v2/user/list").len();
+
+        app.get_job_number_index().await.unwrap();
+        app.get_job_number_index().await.unwrap();
+        assert_eq!(lists(), 1);
+
+        app.create_user(CreateUserRequest::new("赵六", "13900000000").job_number("9"))
+            .await
+            .unwrap();
+        let index = app.get_job_number_index().await.unwrap();
+        assert_eq!(index.get("9").unwrap(), Some("zhaoliu"));
+
+        app.update_user(
+            "zhaoliu".to_string(),
+            UpdateUserRequest::new().job_number("10"),
+        )
+        .await
+        .unwrap();
+        let index = app.get_job_number_index().await.unwrap();
+        assert_eq!(index.get("9").unwrap(), None);
+        assert_eq!(index.get("10").unwrap(), Some("zhaoliu"));
+
+        app.delete_user("wangwu".to_string()).await.unwrap();
+        let index = app.get_job_number_index().await.unwrap();
+        assert_eq!(index.get("5").unwrap(), None);
+        assert_eq!(lists(), 1);
+    }
+
+    #[tokio::test]
+    async fn batches_share_one_index() {
+        let (mock, app) = mock_app().await;
+        // Without a working cache, only the passed index avoids listing the organization again.
+        let app = app.with_profile_cache(Arc::new(ProfileCache::new(Arc::new(FailingStore))));
+
+        let mut index = app.get_job_number_index().await.unwrap();
+        let created = app
+            .upsert_user_with_index(
+                CreateUserRequest::new("赵六", "13900000000").job_number("9"),
+                &mut index,
+            )
+            .await
+            .unwrap();
+        let updated = app
+            .upsert_user_with_index(
+                CreateUserRequest::new("赵六", "13900000001").job_number("9"),
+                &mut index,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(created, UpsertOutcome::Created("zhaoliu".to_string()));
+        assert_eq!(updated, UpsertOutcome::Updated("zhaoliu".to_string()));
+        assert_eq!(mock.bodies("topapi/v2/user/list").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn failing_caches_do_not_fail_the_write() {
+        let (mock, app) = mock_app().await;
+        let app = app.with_profile_cache(Arc::new(ProfileCache::new(Arc::new(FailingStore))));
+
+        let user_id = app
+            .create_user(CreateUserRequest::new("赵六", "13900000000"))
+            .await
+            .unwrap();
+
+        assert_eq!(user_id, "zhaoliu");
+        assert_eq!(mock.bodies("topapi/v2/user/create").len(), 1);
+    }
+}
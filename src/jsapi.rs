@@ -0,0 +1,87 @@
+use crate::store::get_cached;
+use crate::{crypto::jsapi_sign, error::DingTalkError, organization::OrgApp, telemetry};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+/// The parameters of `dd.config` in H5 micro-apps, see [OrgApp::jsapi_config].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct JsapiConfig {
+    pub corp_id: String,
+    pub time_stamp: i64,
+    pub nonce_str: String,
+    pub signature: String,
+}
+
+impl OrgApp {
+    /// Returns the token store key of the JSAPI ticket: `{prefix}jsapi_ticket:{appid}:{corp_id}`.
+    fn jsapi_ticket_key(&self) -> String {
+        format!(
+            "{}jsapi_ticket:{}:{}",
+            self.key_prefix, self.appid, self.corp_id
+        )
+    }
+
+    /// Get the JSAPI ticket of the organization, cached like the access token.
+    ///
+    /// [获取jsapi_ticket](https://open.dingtalk.com/document/orgapp/obtain-jsapi_ticket)
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the ticket if successful, otherwise a `DingTalkError`.
+    pub async fn get_jsapi_ticket(&self) -> Result<String, DingTalkError> {
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Response {
+            ticket: String,
+            expires_in: i64,
+        }
+
+        let key = self.jsapi_ticket_key();
+        let value = get_cached(self.store.as_ref(), &key).await;
+
+        telemetry::record_token_cache("jsapi", value.is_some());
+        if let Some(ticket) = value {
+            return Ok(ticket);
+        }
+
+        let result: Response = self.get_oapi_body("get_jsapi_ticket", &[]).await?;
+        debug!(expires_in = result.expires_in, "fetched jsapi ticket");
+
+        if let Some(ttl) = self.token_config.cache_ttl(result.expires_in) {
+            self.store
+                .set(&key, &result.ticket, Some(ttl))
+                .await
+                .map_err(DingTalkError::Cache)?;
+        }
+
+        Ok(result.ticket)
+    }
+
+    /// Signs a page with the JSAPI ticket of the organization, see [jsapi_sign].
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The URL of the page calling `dd.config`.
+    /// * `nonce` - A random string.
+    /// * `timestamp` - The current Unix time in seconds.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `JsapiConfig` to pass to `dd.config` together with the agent ID
+    /// and the list of JSAPIs, otherwise a `DingTalkError`.
+    pub async fn jsapi_config(
+        &self,
+        url: &str,
+        nonce: &str,
+        timestamp: i64,
+    ) -> Result<JsapiConfig, DingTalkError> {
+        let ticket = self.get_jsapi_ticket().await?;
+
+        Ok(JsapiConfig {
+            corp_id: self.corp_id.clone(),
+            time_stamp: timestamp,
+            nonce_str: nonce.to_string(),
+            signature: jsapi_sign(&ticket, url, nonce, timestamp),
+        })
+    }
+}
@@ -1,41 +1,247 @@
-use deadpool_redis::{Config, Pool, Runtime};
-
-use std::env;
+use builder::DEFAULT_LANGUAGE;
+use config::DEFAULT_KEY_PREFIX;
 use std::sync::Arc;
 
+mod attendance;
+mod builder;
+mod cache;
+mod callback;
+pub mod catalog;
+mod clock;
+mod coalesce;
+mod config;
 mod contact;
 mod core;
+pub mod crypto;
+mod cursor;
+mod deadline;
+mod department;
+mod diagnostics;
+mod dispatcher;
+mod error;
+mod event;
+mod export;
+mod external_contacts;
+#[cfg(any(test, feature = "fixtures"))]
+pub mod fixtures;
+mod idempotency;
+mod job_number;
+mod jsapi;
+mod migrate;
+#[cfg(test)]
+mod mock;
+mod notification;
+mod offboarding;
 mod organization;
+mod pacing;
+mod recipient;
+mod robot;
+mod role;
+mod roster;
+#[cfg(feature = "axum")]
+mod router;
+#[cfg(feature = "sandbox")]
+pub mod sandbox;
+#[cfg(feature = "tower")]
+mod service;
+mod snapshot;
+mod store;
+#[cfg(feature = "stream")]
+mod stream;
+mod suite;
+mod summary;
+mod telemetry;
+mod template;
+mod unionid;
+mod usage;
+mod user;
+mod welcome;
+
+pub use attendance::{
+    Anomaly, AnomalyKind, AttendanceColumn, AttendancePage, AttendanceReport, AttendanceResult,
+    CheckType, ChunkFailure, ColumnValue, ColumnValues, LeaveTimes, TimeResult,
+};
+pub use builder::DingTalkBuilder;
+pub use cache::{ProfileCache, ProfileKind};
+pub use callback::{
+    CallbackCrypto, CallbackRegistration, EncryptedMessage, FailedCallback, FailedCallbacks,
+};
+pub use clock::Clock;
+pub use coalesce::{Coalescer, EndpointClass};
+pub use config::{Endpoints, LogDetail, TokenConfig};
+pub use contact::{AccountKind, ContactBatch, UserInfo};
+pub use crypto::jsapi_sign;
+pub use cursor::{Cursor, UserIdPage};
+pub use deadline::{with_deadline, with_timeout};
+pub use department::{
+    CreateDepartmentRequest, DepartmentContents, DepartmentMembership, DeptDetail, DeptInfo,
+    UpdateDepartmentRequest,
+};
+pub use diagnostics::{CheckStatus, Diagnosis, HealthReport};
+pub use dispatcher::EventDispatcher;
+pub use error::{ApiErrorKind, DingTalkError};
+pub use event::{
+    BpmsInstanceEvent, BpmsTaskEvent, ChatMemberEvent, DeptEvent, EmploymentChange, Event,
+    HrmStatusEvent, SuiteTicketEvent, TmpAuthCodeEvent, UserEvent,
+};
+pub use export::{ExportSinks, ExportSummary, Exporter};
+pub use external_contacts::{ExternalContact, ExternalContactLabel, ExternalContactLabelGroup};
+pub use job_number::JobNumberIndex;
+pub use jsapi::JsapiConfig;
+pub use notification::{WorkActionCard, WorkMessage, WorkNotification};
+pub use offboarding::{OffboardAction, OffboardOptions};
+pub use organization::{
+    Department, EmployeeCounts, EmployeeUser, LeaderInDepartment, Mobile, OrgApp, Organization,
+    PageResult, Role, TokenFlow, UserGetByCodeResponse, UserGetProfileResponse,
+};
+pub use recipient::Recipient;
+pub use robot::{
+    ActionCardContent, At, LinkContent, MarkdownContent, Robot, RobotMessage, RobotPool,
+    RobotStatus, TextContent,
+};
+pub use roster::{
+    Anniversary, Dismissal, EmployeeRoster, PreEntryEmployee, ProbationEnd, RosterField,
+    RosterFieldDefinition, RosterFieldGroup, RosterFieldValue, RosterGroupDefinition, RosterUpdate,
+};
+#[cfg(feature = "axum")]
+pub use router::{callback_router, CallbackRequest};
+#[cfg(feature = "tower")]
+pub use service::{DingTalkRequest, DingTalkService};
+pub use snapshot::{diff_snapshots, Changed, Changes, MobileChange, OrgSnapshot, SnapshotDiff};
+#[cfg(feature = "redis-cache")]
+pub use store::RedisStore;
+pub use store::{MemoryStore, StoreError, TokenStore};
+#[cfg(feature = "stream")]
+pub use stream::{BotMessage, BotText, StreamClient};
+pub use suite::{AuthAgent, AuthCorpInfo, AuthInfo, PermanentCode};
+pub use summary::{EmployeeField, EmployeeSummary};
+pub use template::{escape_markdown, MessageTemplate};
+pub use usage::{CorpUsage, Usage, UsageTracker};
+pub use user::{
+    Admin, CreateUserRequest, ExtensionAttrs, SimpleUser, UnionIdUser, UpdateUserRequest,
+    UpsertOutcome,
+};
+pub use welcome::{OnboardingTodo, WelcomeAutomation, WelcomeOutcome};
 
 pub struct DingTalk {
     pub appid: String,
     pub app_secret: String,
     pub client: reqwest::Client,
-    pub rdb: Arc<Pool>,
+    pub store: Arc<dyn TokenStore>,
+    pub clock: Arc<Clock>,
+    pub coalescer: Arc<Coalescer>,
+    /// Counts the requests of this instance and its `OrgApp`s, see [DingTalk::usage].
+    pub usage: Arc<UsageTracker>,
+    pub profile_cache: Option<Arc<ProfileCache>>,
+    pub token_config: TokenConfig,
+    /// Prepended to the keys of the token store, `dingtalk:token:` by default.
+    pub key_prefix: String,
+    /// The language of returned names and titles, `zh_CN` or `en_US`.
+    pub language: String,
+    /// The base URLs of the DingTalk APIs.
+    pub endpoints: Endpoints,
+    /// How much of the read user data is logged, only IDs by default.
+    pub log_detail: LogDetail,
+    /// Sends the requests instead of `client`, see `DingTalk::with_service`.
+    pub(crate) transport: Option<core::Transport>,
 }
 
 impl DingTalk {
     /// Creates a new instance of DingTalk.
     ///
+    /// Tokens are cached in the Redis server given by the `REDIS_URL` environment variable, or in
+    /// the memory of the process without the feature `redis-cache`.
+    ///
     /// # Arguments
     ///
     /// * `appid` - The app ID issued by DingTalk.
     /// * `app_secret` - The app secret issued by DingTalk.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the Redis pool cannot be created, see [DingTalk::try_new].
     pub fn new(appid: String, app_secret: String) -> Self {
-        let cfg =
-            env::var("REDIS_URL").unwrap_or_else(|_| "redis://:@127.0.0.1:6379/1".to_string());
+        match DingTalk::try_new(appid, app_secret) {
+            Ok(dt) => dt,
+            Err(e) => panic!("{}", e),
+        }
+    }
 
-        let pool_config = Config::from_url(cfg);
-        let pool = match pool_config.create_pool(Some(Runtime::Tokio1)) {
-            Ok(pool) => pool,
-            Err(e) => panic!("Failed to create Redis pool: {}", e),
-        };
+    /// Creates a new instance of DingTalk, failing instead of panicking on misconfiguration.
+    ///
+    /// # Arguments
+    ///
+    /// * `appid` - The app ID issued by DingTalk.
+    /// * `app_secret` - The app secret issued by DingTalk.
+    ///
+    /// # Errors
+    ///
+    /// Returns [DingTalkError::Config] if `REDIS_URL` is not a valid Redis URL or the Redis pool
+    /// cannot be created.
+    pub fn try_new(appid: String, app_secret: String) -> Result<Self, DingTalkError> {
+        DingTalk::builder().credentials(appid, app_secret).build()
+    }
 
+    /// Creates a new instance of DingTalk caching tokens in the given store.
+    ///
+    /// # Arguments
+    ///
+    /// * `appid` - The app ID issued by DingTalk.
+    /// * `app_secret` - The app secret issued by DingTalk.
+    /// * `store` - The store for access tokens, e.g. a [MemoryStore] when Redis is not available.
+    pub fn with_store(appid: String, app_secret: String, store: Arc<dyn TokenStore>) -> Self {
         DingTalk {
             appid,
             app_secret,
             client: reqwest::Client::new(),
-            rdb: Arc::new(pool),
+            store,
+            clock: Arc::new(Clock::new()),
+            coalescer: Arc::new(Coalescer::new()),
+            usage: Arc::new(UsageTracker::new()),
+            profile_cache: None,
+            token_config: TokenConfig::default(),
+            key_prefix: DEFAULT_KEY_PREFIX.to_string(),
+            language: DEFAULT_LANGUAGE.to_string(),
+            endpoints: Endpoints::default(),
+            log_detail: LogDetail::default(),
+            transport: None,
         }
     }
+
+    /// Sends requests with the given client, also for the `OrgApp`s created from this instance.
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Sends requests to the given endpoints, also for the `OrgApp`s created from this instance.
+    pub fn with_endpoints(mut self, endpoints: Endpoints) -> Self {
+        self.endpoints = endpoints;
+        self
+    }
+
+    /// Caches read profiles in the given cache, also for the `OrgApp`s created from this instance.
+    pub fn with_profile_cache(mut self, cache: Arc<ProfileCache>) -> Self {
+        self.profile_cache = Some(cache);
+        self
+    }
+
+    /// Sets how much of the read user data is logged, also for the `OrgApp`s created from this
+    /// instance.
+    pub fn with_log_detail(mut self, log_detail: LogDetail) -> Self {
+        self.log_detail = log_detail;
+        self
+    }
+
+    /// Returns the requests sent so far by this instance and the `OrgApp`s created from it, per
+    /// corp and endpoint.
+    pub fn usage(&self) -> Usage {
+        self.usage.snapshot()
+    }
+
+    /// Sets how tokens are cached, also for the `OrgApp`s created from this instance.
+    pub fn with_token_config(mut self, token_config: TokenConfig) -> Self {
+        self.token_config = token_config;
+        self
+    }
 }
@@ -0,0 +1,223 @@
+use crate::{error::DingTalkError, DingTalk};
+use tracing::info;
+
+impl DingTalk {
+    /// Copies tokens cached under the key formats of earlier versions to the current keys.
+    ///
+    /// Run at startup during a rolling deploy, so that the new instances reuse the tokens of the
+    /// old ones instead of all requesting new tokens at once. Legacy entries are kept for the old
+    /// instances still running and expire by themselves; current entries are never overwritten.
+    ///
+    /// Organization tokens used to be cached under the bare corp ID, shared by all apps of the
+    /// corp. Only migrate them if this app is the only one caching tokens for these corps.
+    ///
+    /// # Arguments
+    ///
+    /// * `corp_ids` - The corps whose organization access tokens should be migrated.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the number of migrated entries if successful, otherwise a
+    /// `DingTalkError`.
+    pub async fn migrate_cache_keys(&self, corp_ids: &[String]) -> Result<usize, DingTalkError> {
+        // Newest format first: `{prefix}{appid}:{corp_id}` and `{prefix}{appid}` without token
+        // type, `{appid}:{corp_id}` without prefix, then the bare IDs.
+        // User tokens are stored without expiry, organization tokens must keep theirs.
+        let mut moves = vec![(
+            vec![
+                format!("{}{}", self.key_prefix, self.appid),
+                self.appid.clone(),
+            ],
+            self.token_key(),
+            false,
+        )];
+        for corp_id in corp_ids {
+            let app = self.set_corp_id(corp_id.clone());
+            moves.push((
+                vec![
+                    format!("{}{}:{}", self.key_prefix, self.appid, corp_id),
+                    format!("{}:{}", self.appid, corp_id),
+                    corp_id.clone(),
+                ],
+                app.token_key(),
+                true,
+            ));
+        }
+
+        let mut migrated = 0;
+        for (legacy_keys, key, expiring) in moves {
+            if self.migrate_cache_key(&legacy_keys, &key, expiring).await? {
+                migrated += 1;
+            }
+        }
+
+        Ok(migrated)
+    }
+
+    /// Copies the first of `legacy_keys` holding a value to `key`, keeping its expiry.
+    ///
+    /// If `expiring` is set, entries whose expiry the store cannot tell are not copied.
+    async fn migrate_cache_key(
+        &self,
+        legacy_keys: &[String],
+        key: &str,
+        expiring: bool,
+    ) -> Result<bool, DingTalkError> {
+        if self
+            .store
+            .get(key)
+            .await
+            .map_err(DingTalkError::Cache)?
+            .is_some()
+        {
+            return Ok(false);
+        }
+
+        for legacy_key in legacy_keys.iter().filter(|legacy_key| *legacy_key != key) {
+            let value = match self
+                .store
+                .get(legacy_key)
+                .await
+                .map_err(DingTalkError::Cache)?
+            {
+                Some(value) => value,
+                None => continue,
+            };
+            let ttl = self
+                .store
+                .ttl(legacy_key)
+                .await
+                .map_err(DingTalkError::Cache)?;
+            if expiring && ttl.is_none() {
+                continue;
+            }
+
+            self.store
+                .set(key, &value, ttl)
+                .await
+                .map_err(DingTalkError::Cache)?;
+            info!("migrated cache key {} to {}", legacy_key, key);
+
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemoryStore, TokenStore};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    const CORP_KEY: &str = "dingtalk:token:corp:app:corp";
+    const USER_KEY: &str = "dingtalk:token:user:app";
+    const TTL: Duration = Duration::from_secs(600);
+
+    fn dingtalk() -> (Arc<MemoryStore>, DingTalk) {
+        let store = Arc::new(MemoryStore::new());
+        let dt = DingTalk::with_store("app".to_string(), "secret".to_string(), store.clone());
+        (store, dt)
+    }
+
+    async fn migrate(dt: &DingTalk) -> usize {
+        dt.migrate_cache_keys(&["corp".to_string()]).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn every_legacy_format_is_migrated() {
+        for legacy_key in ["dingtalk:token:app:corp", "app:corp", "corp"] {
+            let (store, dt) = dingtalk();
+            store
+                .set(legacy_key, "corp-token", Some(TTL))
+                .await
+                .unwrap();
+
+            assert_eq!(migrate(&dt).await, 1, "{}", legacy_key);
+            assert_eq!(
+                store.get(CORP_KEY).await.unwrap().as_deref(),
+                Some("corp-token")
+            );
+            assert_eq!(
+                store.get(legacy_key).await.unwrap().as_deref(),
+                Some("corp-token")
+            );
+        }
+
+        for legacy_key in ["dingtalk:token:app", "app"] {
+            let (store, dt) = dingtalk();
+            store.set(legacy_key, "user-token", None).await.unwrap();
+
+            assert_eq!(migrate(&dt).await, 1, "{}", legacy_key);
+            assert_eq!(
+                store.get(USER_KEY).await.unwrap().as_deref(),
+                Some("user-token")
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn newer_formats_win() {
+        let (store, dt) = dingtalk();
+        store.set("corp", "bare", Some(TTL)).await.unwrap();
+        store
+            .set("app:corp", "unprefixed", Some(TTL))
+            .await
+            .unwrap();
+
+        assert_eq!(migrate(&dt).await, 1);
+        assert_eq!(
+            store.get(CORP_KEY).await.unwrap().as_deref(),
+            Some("unprefixed")
+        );
+    }
+
+    #[tokio::test]
+    async fn current_entries_are_never_overwritten() {
+        let (store, dt) = dingtalk();
+        store.set(CORP_KEY, "current", Some(TTL)).await.unwrap();
+        store.set(USER_KEY, "current", None).await.unwrap();
+        store.set("corp", "legacy", Some(TTL)).await.unwrap();
+        store.set("app", "legacy", None).await.unwrap();
+
+        assert_eq!(migrate(&dt).await, 0);
+        assert_eq!(
+            store.get(CORP_KEY).await.unwrap().as_deref(),
+            Some("current")
+        );
+        assert_eq!(
+            store.get(USER_KEY).await.unwrap().as_deref(),
+            Some("current")
+        );
+    }
+
+    #[tokio::test]
+    async fn expiries_are_kept() {
+        let (store, dt) = dingtalk();
+        store.set("corp", "corp-token", Some(TTL)).await.unwrap();
+
+        assert_eq!(migrate(&dt).await, 1);
+        let ttl = store.ttl(CORP_KEY).await.unwrap().unwrap();
+        assert!(
+            ttl <= TTL && ttl > TTL - Duration::from_secs(10),
+            "{:?}",
+            ttl
+        );
+    }
+
+    #[tokio::test]
+    async fn organization_tokens_of_unknown_expiry_are_skipped() {
+        let (store, dt) = dingtalk();
+        store.set("app:corp", "no-expiry", None).await.unwrap();
+
+        assert_eq!(migrate(&dt).await, 0);
+        assert_eq!(store.get(CORP_KEY).await.unwrap(), None);
+
+        // An older entry with a known expiry is used instead.
+        store.set("corp", "bare", Some(TTL)).await.unwrap();
+        assert_eq!(migrate(&dt).await, 1);
+        assert_eq!(store.get(CORP_KEY).await.unwrap().as_deref(), Some("bare"));
+    }
+}
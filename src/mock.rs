@@ -0,0 +1,172 @@
+//! A transport answering requests with canned bodies and recording them, for asserting the
+//! requests the API methods build.
+
+use crate::core::Transport;
+use crate::store::StoreError;
+use crate::{DingTalk, DingTalkError, MemoryStore, OrgApp, TokenStore};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A [TokenStore] failing every operation, like an unreachable Redis server.
+pub(crate) struct FailingStore;
+
+#[async_trait]
+impl TokenStore for FailingStore {
+    async fn get(&self, _key: &str) -> Result<Option<String>, StoreError> {
+        Err("connection refused".into())
+    }
+
+    async fn set(
+        &self,
+        _key: &str,
+        _value: &str,
+        _ttl: Option<Duration>,
+    ) -> Result<(), StoreError> {
+        Err("connection refused".into())
+    }
+
+    async fn set_if_absent(
+        &self,
+        _key: &str,
+        _value: &str,
+        _ttl: Option<Duration>,
+    ) -> Result<bool, StoreError> {
+        Err("connection refused".into())
+    }
+
+    async fn delete(&self, _key: &str) -> Result<(), StoreError> {
+        Err("connection refused".into())
+    }
+}
+
+/// A request seen by a [Mock].
+#[derive(Debug, Clone)]
+pub(crate) struct Recorded {
+    pub method: reqwest::Method,
+    /// The path without the leading slash, e.g. `topapi/v2/user/get`.
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    /// The JSON body, `Null` without body.
+    pub body: Value,
+}
+
+#[derive(Default)]
+pub(crate) struct Mock {
+    requests: Mutex<Vec<Recorded>>,
+    responses: Mutex<HashMap<String, VecDeque<Value>>>,
+    unreachable: Mutex<HashSet<String>>,
+    delay: Mutex<Option<Duration>>,
+}
+
+impl Mock {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Mock::default())
+    }
+
+    /// Answers the next request to `path` with `body`. Queued bodies are used in order, the last
+    /// one for all further requests.
+    pub fn respond(&self, path: &str, body: Value) {
+        self.responses
+            .lock()
+            .unwrap()
+            .entry(path.to_string())
+            .or_default()
+            .push_back(body);
+    }
+
+    /// Fails the requests to `path` like a refused connection, without an HTTP response.
+    pub fn unreachable(&self, path: &str) {
+        self.unreachable.lock().unwrap().insert(path.to_string());
+    }
+
+    /// Answers every request after `delay`, e.g. to overlap concurrent calls.
+    pub fn delay(&self, delay: Duration) {
+        *self.delay.lock().unwrap() = Some(delay);
+    }
+
+    pub fn requests(&self) -> Vec<Recorded> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Returns the bodies of the requests to `path`.
+    pub fn bodies(&self, path: &str) -> Vec<Value> {
+        self.requests()
+            .into_iter()
+            .filter(|request| request.path == path)
+            .map(|request| request.body)
+            .collect()
+    }
+
+    fn answer(&self, path: &str) -> Value {
+        let mut responses = self.responses.lock().unwrap();
+        match responses.get_mut(path) {
+            Some(queue) if queue.len() > 1 => queue.pop_front().unwrap(),
+            Some(queue) => queue.front().cloned().unwrap_or(Value::Null),
+            None => json!({ "errcode": 0, "errmsg": "ok" }),
+        }
+    }
+
+    pub fn transport(self: &Arc<Self>) -> Transport {
+        let mock = self.clone();
+        Arc::new(move |request: reqwest::Request, _context| {
+            let path = request.url().path().trim_start_matches('/').to_string();
+            let body = request
+                .body()
+                .and_then(|body| body.as_bytes())
+                .and_then(|bytes| serde_json::from_slice(bytes).ok())
+                .unwrap_or(Value::Null);
+            mock.requests.lock().unwrap().push(Recorded {
+                method: request.method().clone(),
+                path: path.clone(),
+                query: request.url().query_pairs().into_owned().collect(),
+                body,
+            });
+
+            let answer = if mock.unreachable.lock().unwrap().contains(&path) {
+                // An invalid URL is the one way to obtain a `reqwest::Error` without a network.
+                let error = reqwest::Client::new().get("http://").build().unwrap_err();
+                Err(DingTalkError::Http(error))
+            } else {
+                Ok(mock.answer(&path))
+            };
+            let delay = *mock.delay.lock().unwrap();
+            Box::pin(async move {
+                if let Some(delay) = delay {
+                    tokio::time::sleep(delay).await;
+                }
+                answer
+            })
+        })
+    }
+
+    /// Returns an organization sending its requests to this mock, with an access token in its
+    /// store.
+    pub async fn app(self: &Arc<Self>) -> OrgApp {
+        let mut app = OrgApp::new(
+            "app".to_string(),
+            "secret".to_string(),
+            "corp".to_string(),
+            Arc::new(MemoryStore::new()),
+        );
+        app.transport = Some(self.transport());
+        app.store
+            .set(&app.token_key(), "token", None)
+            .await
+            .unwrap();
+        app
+    }
+
+    /// Returns an instance sending its requests to this mock, with an empty store.
+    pub fn dingtalk(self: &Arc<Self>) -> DingTalk {
+        let mut dt = DingTalk::with_store(
+            "app".to_string(),
+            "secret".to_string(),
+            Arc::new(MemoryStore::new()),
+        );
+        dt.transport = Some(self.transport());
+        dt
+    }
+}
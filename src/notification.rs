@@ -0,0 +1,384 @@
+use crate::robot::{LinkContent, MarkdownContent, RobotMessage, TextContent};
+use crate::{error::DingTalkError, organization::OrgApp, recipient::Recipient};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use tracing::info;
+
+/// `asyncsend_v2` accepts at most 100 users per send.
+const MAX_USERS: usize = 100;
+/// `asyncsend_v2` accepts at most 20 departments per send.
+const MAX_DEPARTMENTS: usize = 20;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WorkActionCard {
+    pub title: String,
+    pub markdown: String,
+    pub single_title: String,
+    pub single_url: String,
+}
+
+/// The message of a work notification.
+///
+/// [消息类型](https://open.dingtalk.com/document/orgapp/message-types-and-data-format)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "msgtype")]
+pub enum WorkMessage {
+    #[serde(rename = "text")]
+    Text { text: TextContent },
+    #[serde(rename = "markdown")]
+    Markdown { markdown: MarkdownContent },
+    #[serde(rename = "link")]
+    Link { link: LinkContent },
+    #[serde(rename = "action_card")]
+    ActionCard { action_card: WorkActionCard },
+}
+
+impl WorkMessage {
+    pub fn text(content: String) -> Self {
+        WorkMessage::Text {
+            text: TextContent { content },
+        }
+    }
+
+    pub fn markdown(title: String, text: String) -> Self {
+        WorkMessage::Markdown {
+            markdown: MarkdownContent { title, text },
+        }
+    }
+}
+
+/// Sends a robot message, e.g. rendered from a [crate::MessageTemplate], as a work notification.
+/// Mentions (`at`) and the button orientation of action cards are dropped.
+impl From<RobotMessage> for WorkMessage {
+    fn from(message: RobotMessage) -> Self {
+        match message {
+            RobotMessage::Text { text, .. } => WorkMessage::Text { text },
+            RobotMessage::Markdown { markdown, .. } => WorkMessage::Markdown { markdown },
+            RobotMessage::Link { link } => WorkMessage::Link { link },
+            RobotMessage::ActionCard { action_card } => WorkMessage::ActionCard {
+                action_card: WorkActionCard {
+                    title: action_card.title,
+                    markdown: action_card.text,
+                    single_title: action_card.single_title,
+                    single_url: action_card.single_url,
+                },
+            },
+        }
+    }
+}
+
+/// A work notification sent by an app to the members of the organization.
+///
+/// Exactly one way of targeting must be used: users (`userid_list` and `recipients`),
+/// `dept_id_list` or `to_all_user`. The constructors set one of them;
+/// [WorkNotification::validate] checks notifications assembled by hand.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WorkNotification {
+    pub agent_id: i64,
+    #[serde(default)]
+    pub userid_list: Vec<String>,
+    /// Resolved into further users when sending, see [OrgApp::resolve_recipients].
+    #[serde(default)]
+    pub recipients: Vec<Recipient>,
+    #[serde(default)]
+    pub dept_id_list: Vec<i64>,
+    #[serde(default)]
+    pub to_all_user: bool,
+    pub msg: WorkMessage,
+}
+
+impl WorkNotification {
+    /// Creates a notification to the given users.
+    pub fn to_users(agent_id: i64, user_ids: Vec<String>, msg: WorkMessage) -> Self {
+        WorkNotification {
+            agent_id,
+            userid_list: user_ids,
+            recipients: Vec::new(),
+            dept_id_list: Vec::new(),
+            to_all_user: false,
+            msg,
+        }
+    }
+
+    /// Creates a notification to the users of the given recipients, e.g. the members of a role.
+    /// Departments are expanded into their direct members, unlike with
+    /// [WorkNotification::to_departments].
+    pub fn to_recipients(agent_id: i64, recipients: Vec<Recipient>, msg: WorkMessage) -> Self {
+        WorkNotification {
+            agent_id,
+            userid_list: Vec::new(),
+            recipients,
+            dept_id_list: Vec::new(),
+            to_all_user: false,
+            msg,
+        }
+    }
+
+    /// Creates a notification to all members of the given departments, including their
+    /// sub-departments.
+    pub fn to_departments(agent_id: i64, dept_ids: Vec<i64>, msg: WorkMessage) -> Self {
+        WorkNotification {
+            agent_id,
+            userid_list: Vec::new(),
+            recipients: Vec::new(),
+            dept_id_list: dept_ids,
+            to_all_user: false,
+            msg,
+        }
+    }
+
+    /// Creates a notification to every member of the organization.
+    pub fn to_all_users(agent_id: i64, msg: WorkMessage) -> Self {
+        WorkNotification {
+            agent_id,
+            userid_list: Vec::new(),
+            recipients: Vec::new(),
+            dept_id_list: Vec::new(),
+            to_all_user: true,
+            msg,
+        }
+    }
+
+    /// Checks that exactly one way of targeting is used and within the limits of DingTalk.
+    ///
+    /// # Errors
+    ///
+    /// Returns [DingTalkError::Config] describing the problem.
+    pub fn validate(&self) -> Result<(), DingTalkError> {
+        let modes = [
+            !self.userid_list.is_empty() || !self.recipients.is_empty(),
+            !self.dept_id_list.is_empty(),
+            self.to_all_user,
+        ];
+        match modes.iter().filter(|set| **set).count() {
+            0 => {
+                return Err(DingTalkError::Config(
+                    "work notification has no recipients".to_string(),
+                ))
+            }
+            1 => {}
+            _ => {
+                return Err(DingTalkError::Config(
+                    "work notification must use only one of users, dept_id_list and to_all_user"
+                        .to_string(),
+                ))
+            }
+        }
+
+        if self.userid_list.len() > MAX_USERS {
+            return Err(DingTalkError::Config(format!(
+                "work notification has {} users, at most {} are allowed",
+                self.userid_list.len(),
+                MAX_USERS
+            )));
+        }
+        if self.dept_id_list.len() > MAX_DEPARTMENTS {
+            return Err(DingTalkError::Config(format!(
+                "work notification has {} departments, at most {} are allowed",
+                self.dept_id_list.len(),
+                MAX_DEPARTMENTS
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl OrgApp {
+    /// Sends a work notification.
+    ///
+    /// Once its `recipients` are resolved, a notification to more users than one send accepts
+    /// is split into several sends of at most 100 users.
+    ///
+    /// [发送工作通知](https://open.dingtalk.com/document/orgapp/asynchronous-sending-of-enterprise-session-messages)
+    ///
+    /// # Arguments
+    ///
+    /// * `notification` - The notification to send.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the task IDs of the asynchronous sends, one per batch of users.
+    ///
+    /// # Errors
+    ///
+    /// Returns [DingTalkError::Config] without calling DingTalk if the notification is invalid,
+    /// see [WorkNotification::validate]. If a later batch fails, the earlier ones have been sent.
+    pub async fn send_work_notification(
+        &self,
+        notification: &WorkNotification,
+    ) -> Result<Vec<i64>, DingTalkError> {
+        notification.validate()?;
+
+        let mut user_ids = notification.userid_list.clone();
+        if !notification.recipients.is_empty() {
+            let mut seen: HashSet<String> = user_ids.iter().cloned().collect();
+            for user_id in self.resolve_recipients(&notification.recipients).await? {
+                if seen.insert(user_id.clone()) {
+                    user_ids.push(user_id);
+                }
+            }
+        }
+
+        let params = serde_json::json!({
+            "agent_id": notification.agent_id,
+            "msg": notification.msg,
+        });
+        if user_ids.is_empty() {
+            return Ok(vec![self.send_batch(notification, params).await?]);
+        }
+
+        let mut task_ids = Vec::new();
+        for batch in user_ids.chunks(MAX_USERS) {
+            let mut params = params.clone();
+            params["userid_list"] = batch.join(",").into();
+            task_ids.push(self.send_batch(notification, params).await?);
+        }
+        Ok(task_ids)
+    }
+
+    async fn send_batch(
+        &self,
+        notification: &WorkNotification,
+        mut params: serde_json::Value,
+    ) -> Result<i64, DingTalkError> {
+        if !notification.dept_id_list.is_empty() {
+            params["dept_id_list"] = notification
+                .dept_id_list
+                .iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<String>>()
+                .join(",")
+                .into();
+        }
+        if notification.to_all_user {
+            params["to_all_user"] = true.into();
+        }
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Response {
+            task_id: i64,
+        }
+
+        let res: Response = self
+            .post_oapi_body("topapi/message/corpconversation/asyncsend_v2", &params)
+            .await?;
+        info!("send_work_notification task_id: {}", res.task_id);
+
+        Ok(res.task_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::Mock;
+    use serde_json::json;
+
+    fn message() -> WorkMessage {
+        WorkMessage::text("hello".to_string())
+    }
+
+    fn users(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("user{}", i)).collect()
+    }
+
+    fn assert_rejected(notification: WorkNotification, reason: &str) {
+        match notification.validate() {
+            Err(DingTalkError::Config(message)) => assert!(
+                message.contains(reason),
+                "{:?} does not mention {:?}",
+                message,
+                reason
+            ),
+            other => panic!("expected a config error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn notifications_need_recipients() {
+        assert_rejected(
+            WorkNotification::to_users(1, Vec::new(), message()),
+            "no recipients",
+        );
+        assert_rejected(
+            WorkNotification::to_departments(1, Vec::new(), message()),
+            "no recipients",
+        );
+    }
+
+    #[test]
+    fn notifications_use_one_way_of_targeting() {
+        let mut notification = WorkNotification::to_departments(1, vec![1], message());
+        notification.to_all_user = true;
+        assert_rejected(notification, "only one of");
+
+        let mut notification = WorkNotification::to_users(1, users(1), message());
+        notification.dept_id_list = vec![1];
+        assert_rejected(notification, "only one of");
+
+        // Recipients are users, so they may be combined with user IDs but nothing else.
+        let mut notification =
+            WorkNotification::to_recipients(1, vec![Recipient::Role(1)], message());
+        notification.userid_list = users(1);
+        assert!(notification.validate().is_ok());
+        notification.to_all_user = true;
+        assert_rejected(notification, "only one of");
+    }
+
+    #[test]
+    fn notifications_have_at_most_100_users() {
+        assert!(WorkNotification::to_users(1, users(MAX_USERS), message())
+            .validate()
+            .is_ok());
+        assert_rejected(
+            WorkNotification::to_users(1, users(MAX_USERS + 1), message()),
+            "101 users",
+        );
+    }
+
+    #[test]
+    fn notifications_have_at_most_20_departments() {
+        let dept_ids = |count: i64| (1..=count).collect::<Vec<_>>();
+        assert!(WorkNotification::to_departments(1, dept_ids(20), message())
+            .validate()
+            .is_ok());
+        assert_rejected(
+            WorkNotification::to_departments(1, dept_ids(21), message()),
+            "21 departments",
+        );
+    }
+
+    #[tokio::test]
+    async fn resolved_recipients_are_sent_in_batches() {
+        let mock = Mock::new();
+        mock.respond(
+            "topapi/user/listid",
+            json!({ "errcode": 0, "result": { "userid_list": users(250) } }),
+        );
+        mock.respond(
+            "topapi/message/corpconversation/asyncsend_v2",
+            json!({ "errcode": 0, "task_id": 7 }),
+        );
+        let app = mock.app().await;
+
+        let notification =
+            WorkNotification::to_recipients(1, vec![Recipient::Department(2)], message());
+        let task_ids = app.send_work_notification(&notification).await.unwrap();
+
+        assert_eq!(task_ids, vec![7, 7, 7]);
+        let batches: Vec<usize> = mock
+            .bodies("topapi/message/corpconversation/asyncsend_v2")
+            .iter()
+            .map(|body| body["userid_list"].as_str().unwrap().split(',').count())
+            .collect();
+        assert_eq!(batches, vec![100, 100, 50]);
+    }
+
+    #[test]
+    fn notifications_to_all_users_are_valid() {
+        assert!(WorkNotification::to_all_users(1, message())
+            .validate()
+            .is_ok());
+    }
+}
@@ -0,0 +1,369 @@
+use crate::organization::OrgApp;
+use crate::{error::DingTalkError, user::UpdateUserRequest};
+use futures::{future, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use tracing::info;
+
+/// How [OrgApp::offboard_users] cleans up after the users before deleting them.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct OffboardOptions {
+    /// Removes the users from their roles.
+    #[serde(default)]
+    pub remove_roles: bool,
+    /// The group chats to remove the users from.
+    #[serde(default)]
+    pub chat_ids: Vec<String>,
+    /// The user ID of the new manager of the users reporting to an offboarded user. Reports are
+    /// left untouched without it.
+    ///
+    /// Only the direct members of the departments of the offboarded user are searched; reports
+    /// in other departments, including sub-departments, keep their manager.
+    #[serde(default)]
+    pub new_manager: Option<String>,
+    /// Only plans the actions, without changing anything.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// A step of [OrgApp::offboard_users], in the order they are taken.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum OffboardAction {
+    RemoveRoles {
+        user_id: String,
+        role_ids: Vec<i64>,
+    },
+    LeaveChat {
+        user_id: String,
+        chat_id: String,
+    },
+    /// Sets the manager of a user reporting to an offboarded user.
+    ReassignManager {
+        user_id: String,
+        manager_user_id: String,
+    },
+    DeleteUser {
+        user_id: String,
+    },
+}
+
+impl OrgApp {
+    /// Offboards users: removes them from their roles and the given group chats, hands their
+    /// reports over to a new manager and deletes their accounts.
+    ///
+    /// [批量删除员工角色](https://open.dingtalk.com/document/orgapp/delete-a-role-from-multiple-users)
+    /// [修改群会话](https://open.dingtalk.com/document/orgapp/modify-a-group-session)
+    /// [更新用户信息](https://open.dingtalk.com/document/orgapp/user-information-update)
+    /// [删除用户](https://open.dingtalk.com/document/orgapp/delete-a-user)
+    ///
+    /// All actions are planned before the first one is taken. Reports are looked up among the
+    /// direct members of the departments of the offboarded users only, see
+    /// [OffboardOptions::new_manager].
+    ///
+    /// # Arguments
+    ///
+    /// * `user_ids` - The user IDs of the users to offboard.
+    /// * `options` - What to clean up, and whether to stop after planning.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the planned actions with [OffboardOptions::dry_run], otherwise the
+    /// actions taken. Actions taken before an error are not undone.
+    ///
+    /// # Errors
+    ///
+    /// Returns [DingTalkError::Config] if a user is the owner of the organization, or if the new
+    /// manager is offboarded as well.
+    pub async fn offboard_users(
+        &self,
+        user_ids: &[String],
+        options: &OffboardOptions,
+    ) -> Result<Vec<OffboardAction>, DingTalkError> {
+        let offboarded: HashSet<&str> = user_ids.iter().map(String::as_str).collect();
+        if let Some(manager) = &options.new_manager {
+            if offboarded.contains(manager.as_str()) {
+                return Err(DingTalkError::Config(format!(
+                    "new manager {} is offboarded as well",
+                    manager
+                )));
+            }
+        }
+
+        let actions = self
+            .plan_offboarding(user_ids, &offboarded, options)
+            .await?;
+        if options.dry_run {
+            return Ok(actions);
+        }
+
+        for action in &actions {
+            self.take_offboard_action(action).await?;
+        }
+        info!(
+            users = user_ids.len(),
+            actions = actions.len(),
+            "offboarded users"
+        );
+
+        Ok(actions)
+    }
+
+    async fn plan_offboarding(
+        &self,
+        user_ids: &[String],
+        offboarded: &HashSet<&str>,
+        options: &OffboardOptions,
+    ) -> Result<Vec<OffboardAction>, DingTalkError> {
+        let mut actions = Vec::new();
+        let mut reassigned = HashSet::new();
+        let mut deletions = Vec::new();
+
+        for user_id in user_ids {
+            let user = self.get_employee_userinfo(user_id.clone()).await?;
+            if user.boss {
+                return Err(DingTalkError::Config(format!(
+                    "user {} owns the organization and cannot be offboarded",
+                    user_id
+                )));
+            }
+
+            let role_ids: Vec<i64> = user
+                .role_list
+                .iter()
+                .flatten()
+                .map(|role| role.id.into())
+                .collect();
+            if options.remove_roles && !role_ids.is_empty() {
+                actions.push(OffboardAction::RemoveRoles {
+                    user_id: user_id.clone(),
+                    role_ids,
+                });
+            }
+
+            for chat_id in &options.chat_ids {
+                actions.push(OffboardAction::LeaveChat {
+                    user_id: user_id.clone(),
+                    chat_id: chat_id.clone(),
+                });
+            }
+
+            if let Some(manager) = &options.new_manager {
+                for dept_id in &user.dept_id_list {
+                    for report in self.list_reports(*dept_id as i64, user_id).await? {
+                        if !offboarded.contains(report.as_str())
+                            && reassigned.insert(report.clone())
+                        {
+                            actions.push(OffboardAction::ReassignManager {
+                                user_id: report,
+                                manager_user_id: manager.clone(),
+                            });
+                        }
+                    }
+                }
+            }
+
+            deletions.push(OffboardAction::DeleteUser {
+                user_id: user_id.clone(),
+            });
+        }
+
+        actions.extend(deletions);
+        Ok(actions)
+    }
+
+    /// Lists the direct members of a department whose manager is `manager_user_id`; the
+    /// sub-departments are not searched.
+    async fn list_reports(
+        &self,
+        dept_id: i64,
+        manager_user_id: &str,
+    ) -> Result<Vec<String>, DingTalkError> {
+        #[derive(Serialize, Deserialize, Debug)]
+        struct User {
+            userid: String,
+            #[serde(default)]
+            manager_userid: Option<String>,
+        }
+
+        let reports = self
+            .department_users::<User>(dept_id)
+            .try_filter(|user| {
+                future::ready(user.manager_userid.as_deref() == Some(manager_user_id))
+            })
+            .map_ok(|user| user.userid)
+            .try_collect()
+            .await?;
+
+        Ok(reports)
+    }
+
+    async fn take_offboard_action(&self, action: &OffboardAction) -> Result<(), DingTalkError> {
+        match action {
+            OffboardAction::RemoveRoles { user_id, role_ids } => {
+                self.remove_roles_from_users(role_ids, std::slice::from_ref(user_id))
+                    .await?
+            }
+            OffboardAction::LeaveChat { user_id, chat_id } => {
+                let params = serde_json::json!({ "chatid": chat_id, "del_useridlist": [user_id] });
+                self.post_oapi_body::<serde_json::Value>("chat/update", &params)
+                    .await?;
+            }
+            OffboardAction::ReassignManager {
+                user_id,
+                manager_user_id,
+            } => {
+                let request = UpdateUserRequest {
+                    manager_userid: Some(manager_user_id.clone()),
+                    ..Default::default()
+                };
+                self.update_user(user_id.clone(), request).await?;
+            }
+            OffboardAction::DeleteUser { user_id } => self.delete_user(user_id.clone()).await?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::Mock;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    const WRITES: [&str; 4] = [
+        "topapi/role/removerolesforemps",
+        "chat/update",
+        "topapi/v2/user/update",
+        "topapi/v2/user/delete",
+    ];
+
+    async fn mock_app(boss: bool) -> (Arc<Mock>, OrgApp) {
+        let mock = Mock::new();
+        mock.respond(
+            "topapi/v2/user/get",
+            json!({ "errcode": 0, "result": {
+                "userid": "zhangsan",
+                "name": "张三",
+                "dept_id_list": [2],
+                "boss": boss,
+                "role_list": [{ "id": 1, "name": "主管", "group_name": "默认" }],
+            } }),
+        );
+        mock.respond(
+            "topapi/v2/user/list",
+            json!({ "errcode": 0, "result": { "has_more": false, "list": [
+                { "userid": "lisi", "name": "李四", "manager_userid": "zhangsan" },
+                { "userid": "wangwu", "name": "王五", "manager_userid": "zhaoliu" },
+            ] } }),
+        );
+        let app = mock.app().await;
+        (mock, app)
+    }
+
+    fn options() -> OffboardOptions {
+        OffboardOptions {
+            remove_roles: true,
+            chat_ids: vec!["chat".to_string()],
+            new_manager: Some("zhaoliu".to_string()),
+            dry_run: false,
+        }
+    }
+
+    fn writes(mock: &Mock) -> Vec<String> {
+        mock.requests()
+            .into_iter()
+            .map(|request| request.path)
+            .filter(|path| WRITES.contains(&path.as_str()))
+            .collect()
+    }
+
+    fn planned() -> Vec<OffboardAction> {
+        vec![
+            OffboardAction::RemoveRoles {
+                user_id: "zhangsan".to_string(),
+                role_ids: vec![1],
+            },
+            OffboardAction::LeaveChat {
+                user_id: "zhangsan".to_string(),
+                chat_id: "chat".to_string(),
+            },
+            OffboardAction::ReassignManager {
+                user_id: "lisi".to_string(),
+                manager_user_id: "zhaoliu".to_string(),
+            },
+            OffboardAction::DeleteUser {
+                user_id: "zhangsan".to_string(),
+            },
+        ]
+    }
+
+    #[tokio::test]
+    async fn owners_are_not_offboarded() {
+        let (mock, app) = mock_app(true).await;
+
+        let err = app
+            .offboard_users(&["zhangsan".to_string()], &options())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, DingTalkError::Config(message) if message.contains("owns")));
+        assert!(writes(&mock).is_empty());
+    }
+
+    #[tokio::test]
+    async fn new_managers_are_not_offboarded() {
+        let (mock, app) = mock_app(false).await;
+
+        let user_ids = ["zhangsan".to_string(), "zhaoliu".to_string()];
+        let err = app.offboard_users(&user_ids, &options()).await.unwrap_err();
+
+        assert!(matches!(err, DingTalkError::Config(message) if message.contains("zhaoliu")));
+        assert!(mock.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn dry_runs_only_plan() {
+        let (mock, app) = mock_app(false).await;
+        let options = OffboardOptions {
+            dry_run: true,
+            ..options()
+        };
+
+        let actions = app
+            .offboard_users(&["zhangsan".to_string()], &options)
+            .await
+            .unwrap();
+
+        assert_eq!(actions, planned());
+        assert!(writes(&mock).is_empty());
+    }
+
+    #[tokio::test]
+    async fn actions_are_taken_in_order() {
+        let (mock, app) = mock_app(false).await;
+
+        let actions = app
+            .offboard_users(&["zhangsan".to_string()], &options())
+            .await
+            .unwrap();
+
+        assert_eq!(actions, planned());
+        assert_eq!(writes(&mock), WRITES);
+        assert_eq!(
+            mock.bodies("topapi/role/removerolesforemps")[0],
+            json!({ "roleIds": "1", "userIds": "zhangsan" })
+        );
+        assert_eq!(
+            mock.bodies("chat/update")[0],
+            json!({ "chatid": "chat", "del_useridlist": ["zhangsan"] })
+        );
+        let update = &mock.bodies("topapi/v2/user/update")[0];
+        assert_eq!(update["userid"], "lisi");
+        assert_eq!(update["manager_userid"], "zhaoliu");
+        assert_eq!(
+            mock.bodies("topapi/v2/user/delete")[0],
+            json!({ "userid": "zhangsan" })
+        );
+    }
+}
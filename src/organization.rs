@@ -1,14 +1,30 @@
-use crate::{contact::UserInfo, DingTalk};
-use deadpool_redis::redis::cmd;
-use deadpool_redis::Pool;
-
-use log::{error, info, warn};
+use crate::builder::DEFAULT_LANGUAGE;
+use crate::cache::{ProfileCache, ProfileKind};
+use crate::coalesce::{Coalescer, EndpointClass};
+use crate::config::{log_user, Endpoints, LogDetail, TokenConfig, DEFAULT_KEY_PREFIX};
+use crate::core::{dispatch, Transport};
+use crate::cursor::{Cursor, UserIdPage};
+use crate::pacing::paginate;
+use crate::usage::{CorpUsage, UsageTracker};
+use crate::{
+    contact::UserInfo,
+    department::DepartmentMembership,
+    error::{ApiErrorKind, DingTalkError},
+    store::{get_cached, TokenStore},
+    telemetry, DingTalk,
+};
+
+use chrono::{DateTime, Utc};
+use futures::stream::Stream;
+use futures::TryFutureExt;
 use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info_span, warn, Instrument, Span};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Organization {
     #[serde(rename = "licenseUrl")]
     pub license_url: String,
@@ -40,7 +56,8 @@ impl DingTalk {
     ///
     /// This method creates a new instance of `OrgApp` with the given corporate ID and the same
     /// configuration as the current `DingTalk` instance. This is useful for accessing the DingTalk
-    /// API of a specific organization.
+    /// API of a specific organization. The `OrgApp` shares the HTTP client, and so the connection
+    /// pool, of this instance.
     ///
     /// # Arguments
     ///
@@ -50,12 +67,23 @@ impl DingTalk {
     ///
     /// A new `OrgApp` instance with the given corporate ID and configuration.
     pub fn set_corp_id(&self, corp_id: String) -> OrgApp {
-        OrgApp::new(
+        let mut app = OrgApp::new(
             self.appid.clone(),
             self.app_secret.clone(),
             corp_id,
-            self.rdb.clone(),
-        )
+            self.store.clone(),
+        );
+        app.coalescer = self.coalescer.clone();
+        app.usage = self.usage.clone();
+        app.profile_cache = self.profile_cache.clone();
+        app.token_config = self.token_config;
+        app.client = self.client.clone();
+        app.key_prefix = self.key_prefix.clone();
+        app.language = self.language.clone();
+        app.endpoints = self.endpoints.clone();
+        app.log_detail = self.log_detail;
+        app.transport = self.transport.clone();
+        app
     }
 }
 
@@ -74,7 +102,7 @@ pub struct UserGetByCodeResponse {
     pub user_id: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Department {
     #[serde(rename = "dept_id")]
     pub id: i32,
@@ -82,21 +110,24 @@ pub struct Department {
     pub sort_id: i64,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct LeaderInDepartment {
     #[serde(rename = "dept_id")]
     pub id: i32,
     pub leader: bool,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Role {
     pub id: i32,
     pub name: String,
     pub group_name: String,
 }
 
+/// Deserialized with its [memberships](UserGetProfileResponse::memberships), see
+/// `department.rs`.
 #[derive(Serialize, Deserialize, Debug)]
+#[serde(remote = "Self")]
 pub struct UserGetProfileResponse {
     pub active: bool,
     pub admin: bool,
@@ -132,82 +163,397 @@ pub struct UserGetProfileResponse {
     #[serde(rename = "userid")]
     pub user_id: String,
     pub work_place: String,
+    #[serde(skip)]
+    pub(crate) memberships: Vec<DepartmentMembership>,
+}
+
+/// How an [OrgApp] obtains its organization access token.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TokenFlow {
+    /// `POST api.dingtalk.com/v1.0/oauth2/{corpId}/token` with the client credentials.
+    #[default]
+    OAuth2,
+    /// `GET oapi.dingtalk.com/gettoken?appkey=&appsecret=`, the legacy flow of internal
+    /// enterprise apps.
+    ///
+    /// [获取企业内部应用的access_token](https://open.dingtalk.com/document/orgapp/obtain-orgapp-token)
+    Internal,
+    /// `POST api.dingtalk.com/v1.0/oauth2/corpAccessToken` with the suite key, secret and the
+    /// ticket stored by [DingTalk::set_suite_ticket], for third-party apps of ISVs.
+    ///
+    /// [获取第三方应用授权企业的accessToken](https://open.dingtalk.com/document/isvapp/obtain-the-access_token-of-the-authorized-enterprise)
+    Suite,
 }
 
 pub struct OrgApp {
-    appid: String,
-    app_secret: String,
-    corp_id: String,
-    client: reqwest::Client,
-    rdb: Arc<Pool>,
+    pub(crate) appid: String,
+    pub(crate) app_secret: String,
+    pub(crate) corp_id: String,
+    pub(crate) client: reqwest::Client,
+    pub(crate) store: Arc<dyn TokenStore>,
+    pub(crate) coalescer: Arc<Coalescer>,
+    pub(crate) usage: Arc<UsageTracker>,
+    pub(crate) profile_cache: Option<Arc<ProfileCache>>,
+    pub(crate) token_config: TokenConfig,
+    pub(crate) key_prefix: String,
+    pub(crate) language: String,
+    pub(crate) endpoints: Endpoints,
+    pub(crate) token_flow: TokenFlow,
+    pub(crate) log_detail: LogDetail,
+    pub(crate) pace: Option<Duration>,
+    pub(crate) transport: Option<Transport>,
 }
 
 impl OrgApp {
-    pub fn new(appid: String, app_secret: String, corp_id: String, rdb: Arc<Pool>) -> OrgApp {
+    pub fn new(
+        appid: String,
+        app_secret: String,
+        corp_id: String,
+        store: Arc<dyn TokenStore>,
+    ) -> OrgApp {
         OrgApp {
             appid,
             app_secret,
             corp_id,
-            rdb,
+            store,
             client: reqwest::Client::new(),
+            coalescer: Arc::new(Coalescer::new()),
+            usage: Arc::new(UsageTracker::new()),
+            profile_cache: None,
+            token_config: TokenConfig::default(),
+            key_prefix: DEFAULT_KEY_PREFIX.to_string(),
+            language: DEFAULT_LANGUAGE.to_string(),
+            endpoints: Endpoints::default(),
+            token_flow: TokenFlow::default(),
+            log_detail: LogDetail::default(),
+            pace: None,
+            transport: None,
         }
     }
 
-    async fn get_access_token(&self) -> Result<String, Box<dyn std::error::Error>> {
-        #[derive(Serialize, Deserialize, Debug)]
-        struct AccessToken {
-            access_token: String,
-            #[serde(rename = "expires_in")]
-            expire_in: i32,
+    /// Sends requests with the given client, e.g. the one of a [DingTalk] instance.
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Sends requests to the given endpoints instead of the public DingTalk APIs.
+    pub fn with_endpoints(mut self, endpoints: Endpoints) -> Self {
+        self.endpoints = endpoints;
+        self
+    }
+
+    /// Caches read profiles in the given cache.
+    pub fn with_profile_cache(mut self, cache: Arc<ProfileCache>) -> Self {
+        self.profile_cache = Some(cache);
+        self
+    }
+
+    /// Sets how the organization access token is cached.
+    pub fn with_token_config(mut self, token_config: TokenConfig) -> Self {
+        self.token_config = token_config;
+        self
+    }
+
+    /// Sets how much of the read user data is logged, only IDs by default.
+    pub fn with_log_detail(mut self, log_detail: LogDetail) -> Self {
+        self.log_detail = log_detail;
+        self
+    }
+
+    /// Waits `pace` between the requests of listings and batch operations, e.g. the pages of
+    /// [OrgApp::stream_on_job_employees] or the departments of [OrgApp::walk_department_tree], to
+    /// keep full crawls of large organizations under the per-minute quotas of DingTalk. Throttled
+    /// requests of these operations are retried with an exponential backoff.
+    pub fn with_pace(mut self, pace: Duration) -> Self {
+        self.pace = Some(pace);
+        self
+    }
+
+    /// Obtains the organization access token with the given flow, e.g. [TokenFlow::Internal]
+    /// for internal enterprise apps.
+    pub fn with_token_flow(mut self, token_flow: TokenFlow) -> Self {
+        self.token_flow = token_flow;
+        self
+    }
+
+    /// Removes a cached employee profile, e.g. after updating the user.
+    pub async fn invalidate_employee(&self, user_id: &str) -> Result<(), DingTalkError> {
+        match &self.profile_cache {
+            Some(cache) => {
+                cache
+                    .invalidate(
+                        &self.key_prefix,
+                        ProfileKind::Employee,
+                        &self.corp_id,
+                        user_id,
+                    )
+                    .await
+            }
+            None => Ok(()),
         }
+    }
+
+    /// Returns the token store key of the organization access token.
+    ///
+    /// Tokens are issued per app, so the key contains the app ID as well as the corp ID to keep
+    /// apps installed in the same corp from reading each other's token:
+    /// `{prefix}corp:{appid}:{corp_id}`.
+    pub(crate) fn token_key(&self) -> String {
+        format!("{}corp:{}:{}", self.key_prefix, self.appid, self.corp_id)
+    }
 
-        let mut rdb = self.rdb.get().await.unwrap();
-        let value: Option<String> = cmd("GET")
-            .arg(&self.corp_id)
-            .query_async(&mut rdb)
+    /// Returns the corp ID of the organization, e.g. to route or log by tenant.
+    pub fn corp_id(&self) -> &str {
+        &self.corp_id
+    }
+
+    /// Returns the app key, or the suite key of [TokenFlow::Suite].
+    pub fn appid(&self) -> &str {
+        &self.appid
+    }
+
+    /// Returns when the cached access token of the organization is dropped from the token store,
+    /// the [TokenConfig::expiry_margin] before DingTalk expires it.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the expiry, or `None` if no token is cached or the store cannot
+    /// tell, see [TokenStore::ttl].
+    pub async fn token_expires_at(&self) -> Result<Option<DateTime<Utc>>, DingTalkError> {
+        let ttl = self
+            .store
+            .ttl(&self.token_key())
             .await
-            .unwrap_or(None);
+            .map_err(DingTalkError::Cache)?;
+
+        Ok(ttl
+            .and_then(|ttl| chrono::Duration::from_std(ttl).ok())
+            .map(|ttl| Utc::now() + ttl))
+    }
+
+    /// Returns the requests sent so far for this organization.
+    pub fn usage(&self) -> CorpUsage {
+        self.usage
+            .snapshot()
+            .corps
+            .remove(&self.corp_id)
+            .unwrap_or_default()
+    }
 
+    /// Sends a request of this organization, counting it in the [UsageTracker] and running it in
+    /// the span of the organization.
+    pub(crate) async fn send_and_parse<T: DeserializeOwned>(
+        &self,
+        request: reqwest::RequestBuilder,
+        context: &str,
+    ) -> Result<T, DingTalkError> {
+        let (client, request) = request.build_split();
+        let request = request?;
+        self.usage.record(&self.corp_id, request.url().path());
+
+        dispatch(&client, self.transport.as_ref(), request, context)
+            .instrument(self.span())
+            .await
+    }
+
+    /// Returns the span the requests of this organization run in, recording its corp ID.
+    pub(crate) fn span(&self) -> Span {
+        info_span!("dingtalk.org", corp_id = %self.corp_id)
+    }
+
+    pub(crate) async fn get_access_token(&self) -> Result<String, DingTalkError> {
+        let key = self.token_key();
+        let value = get_cached(self.store.as_ref(), &key).await;
+
+        telemetry::record_token_cache("corp", value.is_some());
         if let Some(bytes) = value {
             return Ok(bytes);
         }
 
-        let mut params = HashMap::new();
-        params.insert("client_id", self.appid.clone());
-        params.insert("client_secret", self.app_secret.clone());
-        params.insert("grant_type", "client_credentials".to_string());
+        // Concurrent calls missing the cache share a single refresh.
+        self.coalescer
+            .run(EndpointClass::Token, key, || self.fetch_access_token())
+            .await
+    }
 
-        let response = self
-            .client
-            .post(format!(
-                "https://api.dingtalk.com/v1.0/oauth2/{}/token",
-                self.corp_id
-            ))
-            .json(&params)
-            .send()
+    /// Fetches a new access token of the [TokenFlow] and caches it.
+    async fn fetch_access_token(&self) -> Result<String, DingTalkError> {
+        #[derive(Serialize, Deserialize, Debug)]
+        struct AccessToken {
+            #[serde(alias = "accessToken")]
+            access_token: String,
+            #[serde(rename = "expires_in", alias = "expireIn")]
+            expire_in: i64,
+        }
+
+        let request = match self.token_flow {
+            TokenFlow::OAuth2 => {
+                let mut params = HashMap::new();
+                params.insert("client_id", self.appid.clone());
+                params.insert("client_secret", self.app_secret.clone());
+                params.insert("grant_type", "client_credentials".to_string());
+
+                self.client
+                    .post(
+                        self.endpoints
+                            .api(&format!("v1.0/oauth2/{}/token", self.corp_id)),
+                    )
+                    .json(&params)
+            }
+            TokenFlow::Internal => self.client.get(self.endpoints.oapi("gettoken")).query(&[
+                ("appkey", self.appid.as_str()),
+                ("appsecret", self.app_secret.as_str()),
+            ]),
+            TokenFlow::Suite => {
+                let params = serde_json::json!({
+                    "suiteKey": self.appid,
+                    "suiteSecret": self.app_secret,
+                    "authCorpId": self.corp_id,
+                    "suiteTicket": self.suite_ticket().await?,
+                });
+
+                self.client
+                    .post(self.endpoints.api("v1.0/oauth2/corpAccessToken"))
+                    .json(&params)
+            }
+        };
+        let result: AccessToken = self
+            .send_and_parse(request, "Failed to get organization access token")
             .await?;
+        debug!(
+            expires_in = result.expire_in,
+            "fetched organization access token"
+        );
+
+        if let Some(ttl) = self.token_config.cache_ttl(result.expire_in) {
+            self.store
+                .set(&self.token_key(), &result.access_token, Some(ttl))
+                .await
+                .map_err(DingTalkError::Cache)?;
+        }
 
-        if !response.status().is_success() {
-            return Err(format!(
-                "Failed to get organization access token: {}",
-                response.status()
-            )
-            .into());
+        Ok(result.access_token)
+    }
+
+    /// Sends a POST request to an `oapi.dingtalk.com` endpoint and unwraps the response envelope.
+    ///
+    /// The access token is passed in the query parameters. Responses with a non-zero `errcode` are
+    /// turned into errors, otherwise the `result` field is deserialized into `T`.
+    pub(crate) async fn post_oapi<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        params: &impl Serialize,
+    ) -> Result<T, DingTalkError> {
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Response {
+            #[serde(default)]
+            result: Option<serde_json::Value>,
         }
+        let res: Response = self.post_oapi_body(path, params).await?;
 
-        let result = response.json::<AccessToken>().await?;
-        warn!("fetch_org_access_token result: {:#?}", result);
+        crate::core::decode(
+            &res.result.unwrap_or(serde_json::Value::Null),
+            &self.endpoints.oapi(path),
+        )
+    }
 
-        let mut rdb = self.rdb.get().await.unwrap();
-        cmd("SETEX")
-            .arg(&self.corp_id)
-            .arg(7200)
-            .arg(&result.access_token)
-            .query_async::<()>(&mut rdb)
-            .await
-            .unwrap();
+    /// Like [OrgApp::post_oapi], but deserializes the whole response body into `T`, for the
+    /// endpoints which do not wrap their data into a `result` field.
+    pub(crate) async fn post_oapi_body<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        params: &impl Serialize,
+    ) -> Result<T, DingTalkError> {
+        let at = self.get_access_token().await?;
 
-        Ok(result.access_token)
+        let request = self
+            .client
+            .post(format!("{}?access_token={}", self.endpoints.oapi(path), at))
+            .json(params);
+
+        self.send_oapi(request, path).await
+    }
+
+    /// Like [OrgApp::post_oapi_body], for the endpoints which are called with GET and take their
+    /// parameters in the query.
+    pub(crate) async fn get_oapi_body<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<T, DingTalkError> {
+        let at = self.get_access_token().await?;
+
+        let request = self
+            .client
+            .get(self.endpoints.oapi(path))
+            .query(&[("access_token", at.as_str())])
+            .query(query);
+
+        self.send_oapi(request, path).await
+    }
+
+    /// Calls an endpoint which the crate does not wrap yet, with the cached access token.
+    ///
+    /// Paths of the new gateway, starting with a version such as `v1.0/`, are sent to
+    /// `api.dingtalk.com` with the `x-acs-dingtalk-access-token` header; the others to
+    /// `oapi.dingtalk.com` with the `access_token` query parameter. Unsuccessful statuses and
+    /// non-zero `errcode`s are turned into errors like for the wrapped endpoints.
+    ///
+    /// # Arguments
+    ///
+    /// * `method` - The HTTP method.
+    /// * `path` - The path of the endpoint, e.g. `topapi/v2/user/get` or `v1.0/contact/users/me`.
+    /// * `query` - The query parameters.
+    /// * `body` - The JSON body, if any.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the whole response body deserialized into `T`.
+    pub async fn request<T: DeserializeOwned>(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        query: &[(&str, &str)],
+        body: Option<&serde_json::Value>,
+    ) -> Result<T, DingTalkError> {
+        let path = path.trim_start_matches('/');
+        let at = self.get_access_token().await?;
+
+        let mut request = if is_versioned(path) {
+            self.client
+                .request(method, self.endpoints.api(path))
+                .header("x-acs-dingtalk-access-token", at)
+        } else {
+            self.client
+                .request(method, self.endpoints.oapi(path))
+                .query(&[("access_token", at.as_str())])
+        };
+        request = request.query(query);
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+
+        self.send_oapi(request, path).await
+    }
+
+    async fn send_oapi<T: DeserializeOwned>(
+        &self,
+        request: reqwest::RequestBuilder,
+        path: &str,
+    ) -> Result<T, DingTalkError> {
+        let result = self
+            .send_and_parse(request, &format!("Failed to response {}", path))
+            .await;
+
+        // A rejected token is useless until it expires, so the next call fetches a new one.
+        if result.as_ref().err().and_then(DingTalkError::kind) == Some(ApiErrorKind::InvalidToken) {
+            if let Err(e) = self.store.delete(&self.token_key()).await {
+                warn!("failed to discard the rejected access token: {}", e);
+            }
+        }
+
+        result
     }
 
     /// Retrieves the organization information associated with the provided corporate ID.
@@ -224,8 +570,42 @@ impl OrgApp {
     /// # Returns
     ///
     /// A `Result` containing an `Organization` struct with the organization details if successful,
-    /// otherwise an error string.
-    pub async fn get_organization(&self) -> Result<Organization, Box<dyn std::error::Error>> {
+    /// otherwise a `DingTalkError`.
+    pub async fn get_organization(&self) -> Result<Organization, DingTalkError> {
+        self.get_target_organization(&self.corp_id).await
+    }
+
+    /// Retrieves the authentication information of another organization, e.g. for an ISV app
+    /// checking the auth level of a prospective customer before enabling features.
+    ///
+    /// [Documents](https://open.dingtalk.com/document/orgapp/obtain-enterprise-authentication-information)
+    ///
+    /// # Arguments
+    ///
+    /// * `target_corp_id` - The corp ID of the organization to query.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `Organization` details if successful, otherwise a `DingTalkError`.
+    pub async fn get_target_organization(
+        &self,
+        target_corp_id: &str,
+    ) -> Result<Organization, DingTalkError> {
+        let key = format!(
+            "{}:v1.0/contact/organizations/authInfos:{}",
+            self.corp_id, target_corp_id
+        );
+        self.coalescer
+            .run(EndpointClass::Organization, key, || {
+                self.fetch_organization(target_corp_id)
+            })
+            .await
+    }
+
+    async fn fetch_organization(
+        &self,
+        target_corp_id: &str,
+    ) -> Result<Organization, DingTalkError> {
         let mut headers = HeaderMap::new();
         match self.get_access_token().await {
             Ok(at) => {
@@ -238,17 +618,15 @@ impl OrgApp {
         };
 
         let url: String = format!(
-            "https://api.dingtalk.com/v1.0/contact/organizations/authInfos?targetCorpId={}",
-            self.corp_id
+            "{}?targetCorpId={}",
+            self.endpoints.api("v1.0/contact/organizations/authInfos"),
+            target_corp_id
         );
-        let response = self.client.get(&url).headers(headers).send().await?;
-
-        if !response.status().is_success() {
-            return Err(format!("Failed to get organization: {}", response.status()).into());
-        }
-
-        let result = response.json::<Organization>().await?;
-        info!("get_organization: {:?}", result);
+        let request = self.client.get(&url).headers(headers);
+        let result: Organization = self
+            .send_and_parse(request, "Failed to get organization")
+            .await?;
+        debug!(org_name = %result.name, "fetched organization");
 
         Ok(result)
     }
@@ -271,45 +649,20 @@ impl OrgApp {
     /// # Errors
     ///
     /// Returns an error if the response status is not successful, or if the request fails.
-    async fn get_user_id(&self, code: String) -> Result<String, Box<dyn std::error::Error>> {
-        let token = match self.get_access_token().await {
-            Ok(value) => value,
-            Err(e) => return Err(e),
-        };
-
+    async fn get_user_id(&self, code: String) -> Result<String, DingTalkError> {
         let mut params = HashMap::new();
         params.insert("code", code);
 
-        let response = self
-            .client
-            .post(format!(
-                "https://oapi.dingtalk.com/topapi/v2/user/getuserinfo?access_token={}",
-                token
-            ))
-            .json(&params)
-            .send()
+        let user: UserGetByCodeResponse = self
+            .post_oapi("topapi/v2/user/getuserinfo", &params)
             .await?;
 
-        if !response.status().is_success() {
-            return Err(format!("Failed to response user info: {}", response.status()).into());
-        }
-
-        #[derive(Serialize, Deserialize, Debug)]
-        struct Response {
-            errcode: i32,
-            errmsg: String,
-            result: UserGetByCodeResponse,
-            request_id: Option<String>,
-        }
-        let user = match response.json::<Response>().await {
-            Ok(value) => value.result,
-            Err(e) => {
-                error!("response get_user info {:?}", e);
-                return Err(e.into());
-            }
-        };
-
-        info!("get_org_user_id {:?}", &user);
+        log_user!(
+            self.log_detail,
+            user_id = user.user_id,
+            &user,
+            "resolved user by code"
+        );
 
         Ok(user.user_id)
     }
@@ -333,51 +686,20 @@ impl OrgApp {
     ///
     /// # Errors
     ///
-    pub async fn get_userinfo(&self, code: String) -> Result<UserInfo, Box<dyn std::error::Error>> {
+    pub async fn get_userinfo(&self, code: String) -> Result<UserInfo, DingTalkError> {
         let mut params = HashMap::new();
         match self.get_user_id(code.clone()).await {
             Ok(id) => params.insert("userid", id),
             Err(e) => return Err(e),
         };
 
-        let at = match self.get_access_token().await {
-            Ok(at) => at,
-            Err(e) => return Err(e),
-        };
-
-        let response = self
-            .client
-            .post(format!(
-                "https://oapi.dingtalk.com/topapi/v2/user/get?access_token={}",
-                at
-            ))
-            .json(&params)
-            .send()
-            .await?;
-
-        if !response.status().is_success() {
-            return Err(format!(
-                "Failed to response get org user info: {}",
-                response.status()
-            )
-            .into());
-        }
-
-        #[derive(Serialize, Deserialize, Debug)]
-        struct Response {
-            errcode: i32,
-            errmsg: String,
-            result: UserGetProfileResponse,
-            request_id: Option<String>,
-        }
-        let profile = match response.json::<Response>().await {
-            Ok(res) => res.result,
-            Err(e) => {
-                error!("response get org user info {:?}", e);
-                return Err(e.into());
-            }
-        };
-        info!("get org user info {:?}", &profile);
+        let profile: UserGetProfileResponse = self.post_oapi("topapi/v2/user/get", &params).await?;
+        log_user!(
+            self.log_detail,
+            user_id = profile.user_id,
+            &profile,
+            "fetched user profile"
+        );
 
         let profile: UserInfo = UserInfo {
             email: profile.org_email.clone(),
@@ -392,60 +714,48 @@ impl OrgApp {
         Ok(profile)
     }
 
-    /// Retrieves the total number of employees in the organization.
+    /// Retrieves the number of employees in the organization.
     ///
     /// [获取员工人数](https://open.dingtalk.com/document/orgapp/obtain-the-number-of-employees-v2)
     ///
-    /// If `only_active` is `Some(true)`, only active employees are counted.
-    ///
     /// # Arguments
     ///
-    /// * `only_active` - An optional boolean indicating whether to only count active employees.
+    /// * `only_active` - `Some(true)` to count only employees who have activated DingTalk,
+    ///   `Some(false)` or `None` to count all employees including the inactive ones.
     ///
     /// # Returns
     ///
-    /// A `Result` containing the total number of employees if successful, otherwise an error string.
+    /// A `Result` containing the number of employees if successful, otherwise a `DingTalkError`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [DingTalkError::Api] if DingTalk reports a non-zero `errcode`.
     pub async fn get_employee_count(
         &self,
         only_active: Option<bool>,
-    ) -> Result<i32, Box<dyn std::error::Error>> {
-        let mut params = HashMap::new();
-        params.insert("only_active", only_active.unwrap_or(false));
-
-        let at = match self.get_access_token().await {
-            Ok(at) => at,
-            Err(e) => return Err(e),
-        };
-
-        let response = self
-            .client
-            .post(format!(
-                "https://oapi.dingtalk.com/topapi/user/count?access_token={}",
-                at
-            ))
-            .json(&params)
-            .send()
-            .await?;
-
-        if !response.status().is_success() {
-            return Err(format!(
-                "Failed to response get employee count: {}",
-                response.status()
-            )
-            .into());
-        }
+    ) -> Result<i32, DingTalkError> {
+        let params = serde_json::json!({ "only_active": only_active.unwrap_or(false) });
+        let res: CountUserResponse = self.post_oapi("topapi/user/count", &params).await?;
 
-        #[derive(Serialize, Deserialize, Debug)]
-        struct Response {
-            errcode: i32,
-            errmsg: String,
-            result: CountUserResponse,
-            request_id: Option<String>,
-        }
+        Ok(res.count)
+    }
 
-        let res = response.json::<Response>().await?;
+    /// Retrieves both the number of active employees and the total number of employees.
+    ///
+    /// [获取员工人数](https://open.dingtalk.com/document/orgapp/obtain-the-number-of-employees-v2)
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the [EmployeeCounts] if successful, otherwise a `DingTalkError`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [DingTalkError::Api] if DingTalk reports a non-zero `errcode` for either count.
+    pub async fn get_employee_counts(&self) -> Result<EmployeeCounts, DingTalkError> {
+        let active = self.get_employee_count(Some(true)).await?;
+        let total = self.get_employee_count(Some(false)).await?;
 
-        Ok(res.result.count)
+        Ok(EmployeeCounts { active, total })
     }
 
     /// Query employees on job.
@@ -465,46 +775,19 @@ impl OrgApp {
         &self,
         status: String,
         offset: i32,
-    ) -> Result<PageResult, Box<dyn std::error::Error>> {
+    ) -> Result<PageResult, DingTalkError> {
         let mut params: HashMap<&str, String> = HashMap::new();
         params.insert("status_list", status);
         params.insert("offset", format!("{}", offset));
         params.insert("size", "50".to_string());
 
-        let at = match self.get_access_token().await {
-            Ok(at) => at,
-            Err(e) => return Err(e),
-        };
-
-        let response = self
-            .client
-            .post(format!(
-                "https://oapi.dingtalk.com/topapi/smartwork/hrm/employee/queryonjob?access_token={}",
-                at
-            ))
-            .json(&params)
-            .send()
+        let mut page: PageResult = self
+            .post_oapi("topapi/smartwork/hrm/employee/queryonjob", &params)
             .await?;
+        // The endpoint only reports the offset of the next page.
+        page.has_more = page.next_cursor.is_some();
 
-        if !response.status().is_success() {
-            return Err(format!(
-                "Failed to response get employee count: {}",
-                response.status()
-            )
-            .into());
-        }
-
-        #[derive(Serialize, Deserialize, Debug)]
-        struct Response {
-            errcode: i32,
-            errmsg: String,
-            result: PageResult,
-            request_id: Option<String>,
-        }
-
-        let res = response.json::<Response>().await?;
-
-        Ok(res.result)
+        Ok(page)
     }
 
     /// Retrieves a list of employees who are no longer on the job.
@@ -512,7 +795,8 @@ impl OrgApp {
     /// [获取离职员工列表](https://open.dingtalk.com/document/orgapp/obtain-the-list-of-employees-who-have-left)
     ///
     /// The results are paginated, with the `offset` parameter specifying the starting
-    /// index of the page. The `nextToken` parameter is used to fetch the next page.
+    /// index of the page. The `next_cursor` of the page is the `offset` of the next page, `None`
+    /// after the last page.
     ///
     /// # Arguments
     ///
@@ -526,10 +810,7 @@ impl OrgApp {
     /// # Errors
     ///
     /// Returns an error if the response status is not successful, or if the request fails.
-    pub async fn query_off_job_employees(
-        &self,
-        offset: i64,
-    ) -> Result<PageResult, Box<dyn std::error::Error>> {
+    pub async fn query_off_job_employees(&self, offset: i64) -> Result<PageResult, DingTalkError> {
         let mut headers = HeaderMap::new();
         match self.get_access_token().await {
             Ok(at) => headers.insert(
@@ -540,15 +821,12 @@ impl OrgApp {
         };
 
         let url: String = format!(
-            "https://api.dingtalk.com/v1.0/hrm/employees/dismissions?nextToken={}&maxResults=50",
+            "{}?nextToken={}&maxResults=50",
+            self.endpoints.api("v1.0/hrm/employees/dismissions"),
             offset
         );
-        info!("query_off_job_employees: {}", url);
 
-        let response = self.client.get(&url).headers(headers).send().await?;
-        if !response.status().is_success() {
-            return Err(format!("Failed to get user info: {}", response.status()).into());
-        }
+        let request = self.client.get(&url).headers(headers);
 
         #[derive(Serialize, Deserialize, Debug)]
         struct Response {
@@ -559,17 +837,138 @@ impl OrgApp {
             #[serde(rename = "userIdList")]
             data: Vec<String>,
         }
-        let result = response.json::<Response>().await?;
-        info!("query_off_job_employees: {:?}", &result);
+        let result: Response = self
+            .send_and_parse(request, "Failed to query off job employees")
+            .await?;
+        debug!(count = result.data.len(), "queried off job employees");
 
         let reply = PageResult {
             data: result.data,
-            next_cursor: Some(result.next_cursor),
+            has_more: result.has_more,
+            next_cursor: if result.has_more {
+                Some(result.next_cursor)
+            } else {
+                None
+            },
+            total: None,
         };
 
         Ok(reply)
     }
 
+    /// Retrieves a page of on-job employees, resuming at a cursor of an earlier page.
+    ///
+    /// [获取在职员工列表](https://open.dingtalk.com/document/orgapp/intelligent-personnel-query-the-list-of-on-the-job-employees-of-the)
+    ///
+    /// # Arguments
+    ///
+    /// * `status` - The statuses of the employees, as for [OrgApp::query_on_job_employees].
+    /// * `cursor` - The cursor of the previous page, or `None` for the first page.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the user IDs and the cursor of the next page.
+    ///
+    /// # Errors
+    ///
+    /// Returns [DingTalkError::Config] if the cursor belongs to another listing or status, or
+    /// points past the offsets DingTalk accepts.
+    pub async fn list_on_job_employees(
+        &self,
+        status: String,
+        cursor: Option<&Cursor>,
+    ) -> Result<UserIdPage, DingTalkError> {
+        let listing = format!("on_job:{}", status);
+        let offset = match cursor {
+            Some(cursor) => cursor.position(&listing)?,
+            None => 0,
+        };
+
+        let offset = i32::try_from(offset).map_err(|_| {
+            DingTalkError::Config(format!("cursor position {} is out of range", offset))
+        })?;
+        let page = self.query_on_job_employees(status, offset).await?;
+
+        Ok(UserIdPage {
+            user_ids: page.data,
+            next: page
+                .next_cursor
+                .map(|position| Cursor::new(&listing, position)),
+        })
+    }
+
+    /// Retrieves a page of employees who left, resuming at a cursor of an earlier page.
+    ///
+    /// [获取离职员工列表](https://open.dingtalk.com/document/orgapp/obtain-the-list-of-employees-who-have-left)
+    ///
+    /// # Arguments
+    ///
+    /// * `cursor` - The cursor of the previous page, or `None` for the first page.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the user IDs and the cursor of the next page.
+    ///
+    /// # Errors
+    ///
+    /// Returns [DingTalkError::Config] if the cursor belongs to another listing.
+    pub async fn list_off_job_employees(
+        &self,
+        cursor: Option<&Cursor>,
+    ) -> Result<UserIdPage, DingTalkError> {
+        let offset = match cursor {
+            Some(cursor) => cursor.position("off_job")?,
+            None => 0,
+        };
+
+        let page = self.query_off_job_employees(offset).await?;
+
+        Ok(UserIdPage {
+            user_ids: page.data,
+            next: page
+                .next_cursor
+                .map(|position| Cursor::new("off_job", position)),
+        })
+    }
+
+    /// Streams the user IDs of the on-job employees, requesting the pages as they are consumed.
+    ///
+    /// [获取在职员工列表](https://open.dingtalk.com/document/orgapp/intelligent-personnel-query-the-list-of-on-the-job-employees-of-the)
+    ///
+    /// # Arguments
+    ///
+    /// * `status` - The statuses of the employees, as for [OrgApp::query_on_job_employees].
+    ///
+    /// # Returns
+    ///
+    /// A stream of the user IDs; it ends after the last page or with the first error. Pages
+    /// are requested at the [pace](OrgApp::with_pace) of the organization, and again after a
+    /// while when DingTalk throttles them.
+    pub fn stream_on_job_employees(
+        &self,
+        status: String,
+    ) -> impl Stream<Item = Result<String, DingTalkError>> + '_ {
+        user_id_stream(self.pace, move |cursor| {
+            let status = status.clone();
+            async move { self.list_on_job_employees(status, cursor.as_ref()).await }
+        })
+    }
+
+    /// Streams the user IDs of the employees who left, requesting the pages as they are consumed.
+    ///
+    /// [获取离职员工列表](https://open.dingtalk.com/document/orgapp/obtain-the-list-of-employees-who-have-left)
+    ///
+    /// # Returns
+    ///
+    /// A stream of the user IDs, paced like [OrgApp::stream_on_job_employees].
+    pub fn stream_off_job_employees(
+        &self,
+    ) -> impl Stream<Item = Result<String, DingTalkError>> + '_ {
+        user_id_stream(self.pace, move |cursor| async move {
+            self.list_off_job_employees(cursor.as_ref()).await
+        })
+    }
+
     /// Retrieves detailed profile information of an employee using their user ID.
     ///
     /// [查询用户详情](https://open.dingtalk.com/document/orgapp/query-user-details)
@@ -594,83 +993,205 @@ impl OrgApp {
     pub async fn get_employee_userinfo(
         &self,
         user_id: String,
-    ) -> Result<EmployeeUser, Box<dyn std::error::Error>> {
-        let mut params: HashMap<&str, String> = HashMap::new();
-        params.insert("language", "zh_CN".to_string());
-        params.insert("userid", user_id);
-
-        let at = match self.get_access_token().await {
-            Ok(at) => at,
-            Err(e) => return Err(e),
-        };
+    ) -> Result<EmployeeUser, DingTalkError> {
+        if let Some(cache) = &self.profile_cache {
+            if let Some(user) = cache
+                .get(
+                    &self.key_prefix,
+                    ProfileKind::Employee,
+                    &self.corp_id,
+                    &user_id,
+                )
+                .await
+            {
+                return Ok(user);
+            }
+        }
 
-        let response = self
-            .client
-            .post(format!(
-                "https://oapi.dingtalk.com/topapi/v2/user/get?access_token={}",
-                at
-            ))
-            .json(&params)
-            .send()
+        let key = format!("{}:topapi/v2/user/get:{}", self.corp_id, user_id);
+        let user: EmployeeUser = self
+            .coalescer
+            .run(EndpointClass::User, key, || {
+                self.fetch_employee_userinfo(user_id)
+            })
             .await?;
 
-        if !response.status().is_success() {
-            return Err(format!(
-                "Failed to response get employee count: {}",
-                response.status()
-            )
-            .into());
+        if let Some(cache) = &self.profile_cache {
+            cache
+                .set(
+                    &self.key_prefix,
+                    ProfileKind::Employee,
+                    &self.corp_id,
+                    &user.user_id,
+                    &user,
+                )
+                .await;
         }
 
-        #[derive(Serialize, Deserialize, Debug)]
-        struct Response {
-            errcode: i32,
-            errmsg: String,
-            result: EmployeeUser,
-            request_id: Option<String>,
-        }
+        Ok(user)
+    }
 
-        let result = match response.json::<Response>().await {
-            Ok(res) => res.result,
-            Err(e) => {
-                error!("Failed to get user info: {}", e);
-                return Err(e.into());
-            }
-        };
+    async fn fetch_employee_userinfo(
+        &self,
+        user_id: String,
+    ) -> Result<EmployeeUser, DingTalkError> {
+        let mut params: HashMap<&str, String> = HashMap::new();
+        params.insert("language", self.language.clone());
+        params.insert("userid", user_id);
 
-        Ok(result)
+        let user: EmployeeUser = self.post_oapi("topapi/v2/user/get", &params).await?;
+        log_user!(
+            self.log_detail,
+            user_id = user.user_id,
+            &user,
+            "fetched employee"
+        );
+
+        Ok(user)
     }
 }
 
+/// Whether a path belongs to the new gateway, e.g. `v1.0/contact/users/me`.
+fn is_versioned(path: &str) -> bool {
+    let version = path.split('/').next().unwrap_or_default();
+    version.len() > 1
+        && version.starts_with('v')
+        && version[1..].chars().all(|c| c.is_ascii_digit() || c == '.')
+}
+
+/// Follows the cursors of a paged listing of user IDs until its last page.
+///
+/// Waits `pace` between pages, and retries throttled pages with an exponential backoff.
+fn user_id_stream<F, Fut>(
+    pace: Option<Duration>,
+    mut fetch: F,
+) -> impl Stream<Item = Result<String, DingTalkError>>
+where
+    F: FnMut(Option<Cursor>) -> Fut,
+    Fut: std::future::Future<Output = Result<UserIdPage, DingTalkError>>,
+{
+    // The cursor of the first page is `None`.
+    paginate(pace, None, move |cursor| {
+        fetch(cursor).map_ok(|page| (page.user_ids, page.next.map(Some)))
+    })
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct CountUserResponse {
     count: i32,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct PageResult {
-    #[serde(rename = "data_list")]
-    pub data: Vec<String>,
+/// The employee counts of an organization.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmployeeCounts {
+    /// Employees who have activated DingTalk.
+    pub active: i32,
+    /// All employees, including the inactive ones.
+    pub total: i32,
+}
+
+/// A page of a paginated listing, e.g. the user IDs of [OrgApp::query_on_job_employees].
+///
+/// Deserializes the page shapes of the different endpoints: the items of `data_list` or `list`,
+/// and `has_more`/`next_cursor` in snake or camel case.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct PageResult<T = String> {
+    #[serde(rename = "data_list", alias = "list", default = "Vec::new")]
+    pub data: Vec<T>,
+    #[serde(alias = "hasMore", default)]
+    pub has_more: bool,
+    /// The offset or cursor of the next page.
+    #[serde(alias = "nextCursor", default)]
     pub next_cursor: Option<i64>,
+    /// The number of items of the whole listing, when the endpoint reports it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<i64>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl<T> PageResult<T> {
+    /// Returns the offset or cursor of the next page, `None` after the last page.
+    pub fn next_offset(&self) -> Option<i64> {
+        match self.next_cursor {
+            Some(next) if self.has_more => Some(next),
+            _ => None,
+        }
+    }
+}
+
+/// The mobile number of an employee.
+///
+/// When the employee set `hide_mobile`, DingTalk returns an empty or masked number such as
+/// `138****5678`, which must not overwrite a number known from before. Hidden numbers serialize
+/// as an empty string.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Mobile {
+    #[default]
+    Hidden,
+    Visible(String),
+}
+
+impl Mobile {
+    /// Returns the number unless it is hidden.
+    pub fn as_visible(&self) -> Option<&str> {
+        match self {
+            Mobile::Hidden => None,
+            Mobile::Visible(mobile) => Some(mobile),
+        }
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        matches!(self, Mobile::Hidden)
+    }
+}
+
+impl From<String> for Mobile {
+    fn from(mobile: String) -> Self {
+        if mobile.trim().is_empty() || mobile.contains('*') {
+            Mobile::Hidden
+        } else {
+            Mobile::Visible(mobile)
+        }
+    }
+}
+
+impl Serialize for Mobile {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_visible().unwrap_or_default())
+    }
+}
+
+impl<'de> Deserialize<'de> for Mobile {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Option::<String>::deserialize(deserializer)?
+            .map(Mobile::from)
+            .unwrap_or_default())
+    }
+}
+
+/// Deserialized with its [memberships](EmployeeUser::memberships), see `department.rs`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(remote = "Self")]
 pub struct EmployeeUser {
-    #[serde(rename = "unionid")]
+    #[serde(rename = "unionid", default)]
     pub union_id: String,
     #[serde(rename = "userid")]
     pub user_id: String,
     #[serde(rename = "name")]
     pub username: String,
-    #[serde(rename = "avatar")]
+    #[serde(rename = "avatar", default)]
     pub profile_url: String,
+    #[serde(default)]
     pub state_code: String,
 
     #[serde(default)]
     pub manager_userid: Option<String>,
 
-    pub mobile: String,
+    #[serde(default)]
+    pub mobile: Mobile,
+    #[serde(default)]
     pub hide_mobile: bool,
+    #[serde(default)]
     pub telephone: String,
 
     #[serde(default)]
@@ -681,14 +1202,20 @@ pub struct EmployeeUser {
 
     #[serde(default)]
     pub email: Option<String>,
+    #[serde(default)]
     pub work_place: String,
+    #[serde(default)]
     pub remark: String,
+    #[serde(default)]
     pub exclusive_account: bool,
 
     #[serde(default)]
     pub org_email: Option<String>,
 
+    #[serde(default)]
     pub dept_id_list: Vec<i32>,
+    /// Empty in the entries of [OrgApp::list_department_users].
+    #[serde(default)]
     pub dept_order_list: Vec<Department>,
 
     #[serde(default)]
@@ -697,15 +1224,225 @@ pub struct EmployeeUser {
     #[serde(default)]
     pub hired_date: Option<u64>,
 
+    #[serde(default)]
     pub active: bool,
+    #[serde(default)]
     pub real_authed: bool,
+    #[serde(default)]
     pub senior: bool,
+    #[serde(default)]
     pub admin: bool,
+    #[serde(default)]
     pub boss: bool,
+    /// `None` in the entries of [OrgApp::list_department_users].
+    #[serde(default)]
     pub leader_in_dept: Option<Vec<LeaderInDepartment>>,
 
     #[serde(default)]
     pub role_list: Option<Vec<Role>>,
     #[serde(default)]
     pub union_emp_ext: HashMap<String, String>,
+    #[serde(skip)]
+    pub(crate) memberships: Vec<DepartmentMembership>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use futures::TryStreamExt;
+
+    fn org_app(appid: &str, corp_id: &str, store: Arc<dyn TokenStore>) -> OrgApp {
+        OrgApp::new(
+            appid.to_string(),
+            "secret".to_string(),
+            corp_id.to_string(),
+            store,
+        )
+    }
+
+    #[test]
+    fn token_keys_are_scoped_by_app_and_corp() {
+        let store: Arc<dyn TokenStore> = Arc::new(MemoryStore::new());
+        let a = org_app("app-a", "corp", store.clone());
+        let b = org_app("app-b", "corp", store.clone());
+        let c = org_app("app-a", "other-corp", store);
+
+        assert_eq!(a.token_key(), "dingtalk:token:corp:app-a:corp");
+        assert_ne!(a.token_key(), b.token_key());
+        assert_ne!(a.token_key(), c.token_key());
+    }
+
+    #[tokio::test]
+    async fn apps_in_same_corp_read_their_own_tokens() {
+        let store: Arc<dyn TokenStore> = Arc::new(MemoryStore::new());
+        let a = org_app("app-a", "corp", store.clone());
+        let b = org_app("app-b", "corp", store.clone());
+
+        store.set(&a.token_key(), "token-a", None).await.unwrap();
+        store.set(&b.token_key(), "token-b", None).await.unwrap();
+
+        assert_eq!(a.get_access_token().await.unwrap(), "token-a");
+        assert_eq!(b.get_access_token().await.unwrap(), "token-b");
+    }
+
+    #[tokio::test]
+    async fn token_expiry_comes_from_the_store() {
+        let store: Arc<dyn TokenStore> = Arc::new(MemoryStore::new());
+        let app = org_app("app", "corp", store.clone());
+        assert_eq!(app.token_expires_at().await.unwrap(), None);
+
+        let ttl = std::time::Duration::from_secs(3600);
+        store
+            .set(&app.token_key(), "token", Some(ttl))
+            .await
+            .unwrap();
+        let expires_at = app.token_expires_at().await.unwrap().unwrap();
+        let left = expires_at - Utc::now();
+        assert!(left > chrono::Duration::minutes(59) && left <= chrono::Duration::minutes(60));
+        assert_eq!((app.corp_id(), app.appid()), ("corp", "app"));
+    }
+
+    #[tokio::test]
+    async fn rejected_tokens_are_discarded() {
+        let mock = crate::mock::Mock::new();
+        mock.respond(
+            "topapi/user/count",
+            serde_json::json!({ "errcode": 40014, "errmsg": "不合法的access_token" }),
+        );
+        let app = mock.app().await;
+
+        let error = app.get_employee_count(None).await.unwrap_err();
+        assert_eq!(error.kind(), Some(ApiErrorKind::InvalidToken));
+        let request = &mock.requests()[0];
+        assert_eq!(request.method, reqwest::Method::POST);
+        assert!(request
+            .query
+            .contains(&("access_token".to_string(), "token".to_string())));
+        assert_eq!(
+            mock.bodies("topapi/user/count"),
+            vec![serde_json::json!({ "only_active": false })]
+        );
+        assert_eq!(app.store.get(&app.token_key()).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn concurrent_token_refreshes_are_coalesced() {
+        let mock = crate::mock::Mock::new();
+        mock.respond(
+            "v1.0/oauth2/corp/token",
+            serde_json::json!({ "access_token": "fresh", "expires_in": 7200 }),
+        );
+        mock.delay(std::time::Duration::from_millis(20));
+        let app = mock.app().await;
+        app.store.delete(&app.token_key()).await.unwrap();
+
+        let (a, b, c) = futures::join!(
+            app.get_access_token(),
+            app.get_access_token(),
+            app.get_access_token()
+        );
+        assert_eq!(
+            (a.unwrap(), b.unwrap(), c.unwrap()),
+            ("fresh".into(), "fresh".into(), "fresh".into())
+        );
+        assert_eq!(mock.requests().len(), 1);
+        assert_eq!(app.get_access_token().await.unwrap(), "fresh");
+        assert_eq!(mock.requests().len(), 1);
+    }
+
+    #[test]
+    fn versioned_paths_go_to_the_new_gateway() {
+        assert!(is_versioned("v1.0/contact/users/me"));
+        assert!(is_versioned("v2.0/calendar/users"));
+        assert!(!is_versioned("topapi/v2/user/get"));
+        assert!(!is_versioned("gettoken"));
+    }
+
+    #[tokio::test]
+    async fn user_id_streams_follow_the_cursors() {
+        let user_ids: Vec<String> = user_id_stream(None, |cursor: Option<Cursor>| async move {
+            let position = match cursor {
+                Some(cursor) => cursor.position("test")?,
+                None => 0,
+            };
+            Ok(UserIdPage {
+                user_ids: vec![format!("user{}", position)],
+                next: (position < 2).then(|| Cursor::new("test", position + 1)),
+            })
+        })
+        .try_collect()
+        .await
+        .unwrap();
+
+        assert_eq!(user_ids, vec!["user0", "user1", "user2"]);
+    }
+
+    #[tokio::test]
+    async fn off_job_pages_have_a_cursor_until_the_last_page() {
+        let mock = crate::mock::Mock::new();
+        let path = "v1.0/hrm/employees/dismissions";
+        mock.respond(
+            path,
+            serde_json::json!({ "nextToken": 50, "hasMore": true, "userIdList": ["wangwu"] }),
+        );
+        mock.respond(
+            path,
+            serde_json::json!({ "nextToken": 0, "hasMore": false, "userIdList": ["zhaoliu"] }),
+        );
+        let app = mock.app().await;
+
+        let first = app.query_off_job_employees(0).await.unwrap();
+        let last = app.query_off_job_employees(50).await.unwrap();
+
+        assert_eq!(first.next_cursor, Some(50));
+        assert_eq!(first.next_offset(), Some(50));
+        assert_eq!(last.data, vec!["zhaoliu"]);
+        assert_eq!(last.next_cursor, None);
+        let tokens: Vec<String> = mock
+            .requests()
+            .into_iter()
+            .flat_map(|request| request.query)
+            .filter(|(key, _)| key == "nextToken")
+            .map(|(_, value)| value)
+            .collect();
+        assert_eq!(tokens, vec!["0", "50"]);
+    }
+
+    #[tokio::test]
+    async fn cursors_past_the_offset_range_are_rejected() {
+        let mock = crate::mock::Mock::new();
+        let app = mock.app().await;
+
+        let status = "2,3".to_string();
+        let cursor = Cursor::new(&format!("on_job:{}", status), i64::from(i32::MAX) + 1);
+        let result = app.list_on_job_employees(status, Some(&cursor)).await;
+
+        assert!(matches!(result, Err(DingTalkError::Config(_))));
+        assert!(mock.requests().is_empty());
+    }
+
+    #[test]
+    fn pages_deserialize_the_shapes_of_all_endpoints() {
+        let snake: PageResult = serde_json::from_value(serde_json::json!({
+            "data_list": ["zhangsan"],
+            "has_more": true,
+            "next_cursor": 1,
+        }))
+        .unwrap();
+        let camel: PageResult = serde_json::from_value(serde_json::json!({
+            "list": ["zhangsan"],
+            "hasMore": true,
+            "nextCursor": 1,
+        }))
+        .unwrap();
+
+        assert_eq!(snake, camel);
+        assert_eq!(snake.data, vec!["zhangsan"]);
+        assert_eq!(snake.next_offset(), Some(1));
+
+        let empty: PageResult = serde_json::from_value(serde_json::json!({})).unwrap();
+        assert!(empty.data.is_empty());
+        assert_eq!(empty.next_offset(), None);
+    }
 }
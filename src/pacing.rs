@@ -0,0 +1,179 @@
+use crate::error::{ApiErrorKind, DingTalkError};
+use crate::organization::PageResult;
+use futures::stream::{self, Stream, TryStreamExt};
+use std::future::Future;
+use std::time::Duration;
+use tracing::warn;
+
+/// How often a throttled request is sent again.
+const MAX_THROTTLED_RETRIES: u32 = 3;
+/// The first wait before sending a throttled request again, doubled on every retry.
+const MIN_THROTTLED_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Paces a sequence of requests, e.g. the pages of a listing: waits `pace` before every request
+/// but the first, and retries throttled requests with [retry_throttled].
+pub(crate) struct Pacer {
+    pace: Option<Duration>,
+    first: bool,
+}
+
+impl Pacer {
+    pub(crate) fn new(pace: Option<Duration>) -> Self {
+        Pacer { pace, first: true }
+    }
+
+    /// Sends the next request of the sequence with `fetch`.
+    pub(crate) async fn run<T, F, Fut>(&mut self, fetch: F) -> Result<T, DingTalkError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, DingTalkError>>,
+    {
+        if let (Some(pace), false) = (self.pace, self.first) {
+            tokio::time::sleep(pace).await;
+        }
+        self.first = false;
+
+        retry_throttled(self.pace, fetch).await
+    }
+}
+
+/// Runs `fetch` again while DingTalk throttles it, waiting the larger of `pace` and a second
+/// before the first retry and doubling the wait on every further retry.
+pub(crate) async fn retry_throttled<T, F, Fut>(
+    pace: Option<Duration>,
+    mut fetch: F,
+) -> Result<T, DingTalkError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, DingTalkError>>,
+{
+    let mut backoff = pace.unwrap_or_default().max(MIN_THROTTLED_BACKOFF);
+    let mut retries = 0;
+    loop {
+        match fetch().await {
+            Err(e)
+                if e.kind() == Some(ApiErrorKind::Throttled) && retries < MAX_THROTTLED_RETRIES =>
+            {
+                warn!(backoff_ms = backoff.as_millis() as u64, "request throttled");
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                retries += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Streams the items of a paged listing, requesting the pages with a [Pacer] as the stream is
+/// consumed.
+///
+/// `fetch` requests the page at a cursor and returns its items and the cursor of the next page,
+/// `None` after the last page.
+pub(crate) fn paginate<C, T, F, Fut>(
+    pace: Option<Duration>,
+    start: C,
+    fetch: F,
+) -> impl Stream<Item = Result<T, DingTalkError>>
+where
+    C: Clone,
+    F: FnMut(C) -> Fut,
+    Fut: Future<Output = Result<(Vec<T>, Option<C>), DingTalkError>>,
+{
+    // The state is `None` once the last page was fetched.
+    let state = Some((fetch, start, Pacer::new(pace)));
+    stream::try_unfold(state, |state| async move {
+        let (mut fetch, cursor, mut pacer) = match state {
+            Some(state) => state,
+            None => return Ok(None),
+        };
+
+        let (items, next) = pacer.run(|| fetch(cursor.clone())).await?;
+        let state = next.map(|next| (fetch, next, pacer));
+        Ok::<_, DingTalkError>(Some((items, state)))
+    })
+    .map_ok(|items| stream::iter(items.into_iter().map(Ok)))
+    .try_flatten()
+}
+
+/// Splits a page of a listing paged by [PageResult::next_offset] for [paginate].
+pub(crate) fn offset_page<T>(page: PageResult<T>) -> (Vec<T>, Option<i64>) {
+    let next = page.next_offset();
+    (page.data, next)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use tokio::time::Instant;
+
+    fn throttled() -> DingTalkError {
+        DingTalkError::from_errcode(90018, "调用频率超限".to_string(), None)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pages_are_paced() {
+        let started = Instant::now();
+        let requested = std::sync::Mutex::new(Vec::new());
+        let items: Vec<i64> = paginate(Some(Duration::from_millis(200)), 0, |cursor: i64| {
+            requested.lock().unwrap().push(started.elapsed());
+            async move { Ok((vec![cursor], (cursor < 2).then(|| cursor + 1))) }
+        })
+        .try_collect()
+        .await
+        .unwrap();
+
+        assert_eq!(items, vec![0, 1, 2]);
+        assert_eq!(
+            *requested.lock().unwrap(),
+            vec![
+                Duration::ZERO,
+                Duration::from_millis(200),
+                Duration::from_millis(400)
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn throttled_requests_back_off() {
+        let started = Instant::now();
+        let calls = AtomicU32::new(0);
+        let result = retry_throttled(None, || {
+            let call = calls.fetch_add(1, Ordering::SeqCst);
+            async move {
+                match call {
+                    0 | 1 => Err(throttled()),
+                    _ => Ok(call),
+                }
+            }
+        })
+        .await;
+
+        assert_eq!(result.unwrap(), 2);
+        // 1s before the first retry, 2s before the second.
+        assert_eq!(started.elapsed(), Duration::from_secs(3));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_give_up_and_keep_other_errors() {
+        let calls = AtomicU32::new(0);
+        let result: Result<(), _> = retry_throttled(Some(Duration::from_secs(2)), || {
+            calls.fetch_add(1, Ordering::SeqCst);
+            async { Err(throttled()) }
+        })
+        .await;
+        assert_eq!(result.unwrap_err().kind(), Some(ApiErrorKind::Throttled));
+        assert_eq!(calls.load(Ordering::SeqCst), MAX_THROTTLED_RETRIES + 1);
+
+        let calls = AtomicU32::new(0);
+        let started = Instant::now();
+        let result: Result<(), _> = retry_throttled(None, || {
+            calls.fetch_add(1, Ordering::SeqCst);
+            async { Err(DingTalkError::Other("boom".to_string())) }
+        })
+        .await;
+        assert!(matches!(result, Err(DingTalkError::Other(_))));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(started.elapsed(), Duration::ZERO);
+    }
+}
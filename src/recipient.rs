@@ -0,0 +1,282 @@
+use crate::catalog::oapi_endpoint;
+use crate::pacing::paginate;
+use crate::{error::DingTalkError, organization::OrgApp, organization::PageResult};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// How many IDs are converted at the same time.
+const MAX_CONCURRENT_REQUESTS: usize = 8;
+
+/// A specification of who should receive a message or todo.
+///
+/// Recipients may be given in whichever ID space the caller has at hand;
+/// [OrgApp::resolve_recipients] and [OrgApp::resolve_union_ids] convert them into the user IDs of
+/// the `oapi` endpoints or the union IDs of the `v1.0` endpoints.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Recipient {
+    /// Explicit user IDs.
+    Users(Vec<String>),
+    /// Explicit union IDs, e.g. from the login flow.
+    UnionIds(Vec<String>),
+    /// The direct members of a department.
+    Department(i64),
+    /// The members of a role.
+    Role(i64),
+    /// A group conversation, by its `open_conversation_id`.
+    ///
+    /// Conversations are addressed as a whole and cannot be expanded into users.
+    Conversation(String),
+}
+
+impl Recipient {
+    /// Returns the `open_conversation_id` of a conversation recipient.
+    pub fn open_conversation_id(&self) -> Option<&str> {
+        match self {
+            Recipient::Conversation(id) => Some(id),
+            _ => None,
+        }
+    }
+}
+
+impl OrgApp {
+    /// Expands recipient specifications into user IDs.
+    ///
+    /// [获取部门用户userid列表](https://open.dingtalk.com/document/orgapp/query-the-list-of-department-userids)
+    /// [获取指定角色的员工列表](https://open.dingtalk.com/document/orgapp/obtains-the-list-of-employees-of-a-specified-role)
+    ///
+    /// Union IDs are converted with [OrgApp::get_userid_by_unionid].
+    ///
+    /// # Arguments
+    ///
+    /// * `recipients` - The recipients to resolve.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the user IDs in order of first appearance, without duplicates.
+    ///
+    /// # Errors
+    ///
+    /// Returns [DingTalkError::Config] for [Recipient::Conversation].
+    pub async fn resolve_recipients(
+        &self,
+        recipients: &[Recipient],
+    ) -> Result<Vec<String>, DingTalkError> {
+        let mut seen = HashSet::new();
+        let mut user_ids = Vec::new();
+
+        for recipient in recipients {
+            let resolved = match recipient {
+                Recipient::Users(ids) => ids.clone(),
+                Recipient::UnionIds(ids) => {
+                    stream::iter(ids.iter().cloned())
+                        .map(|union_id| async move {
+                            let user = self.get_userid_by_unionid(union_id).await?;
+                            Ok::<String, DingTalkError>(user.user_id)
+                        })
+                        .buffered(MAX_CONCURRENT_REQUESTS)
+                        .try_collect()
+                        .await?
+                }
+                Recipient::Department(dept_id) => self.list_department_user_ids(*dept_id).await?,
+                Recipient::Role(role_id) => self.list_role_user_ids(*role_id).await?,
+                Recipient::Conversation(id) => {
+                    return Err(DingTalkError::Config(format!(
+                        "conversation {} cannot be resolved into users",
+                        id
+                    )))
+                }
+            };
+
+            for user_id in resolved {
+                if seen.insert(user_id.clone()) {
+                    user_ids.push(user_id);
+                }
+            }
+        }
+
+        Ok(user_ids)
+    }
+
+    /// Expands recipient specifications into union IDs, for the endpoints of `api.dingtalk.com`.
+    ///
+    /// Union IDs are passed through; the other recipients are resolved with
+    /// [OrgApp::resolve_recipients] and converted with the employee profiles.
+    ///
+    /// # Arguments
+    ///
+    /// * `recipients` - The recipients to resolve.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the union IDs in order of first appearance, without duplicates.
+    ///
+    /// # Errors
+    ///
+    /// Returns [DingTalkError::Config] for [Recipient::Conversation].
+    pub async fn resolve_union_ids(
+        &self,
+        recipients: &[Recipient],
+    ) -> Result<Vec<String>, DingTalkError> {
+        let mut seen = HashSet::new();
+        let mut union_ids = Vec::new();
+
+        for recipient in recipients {
+            let resolved = match recipient {
+                Recipient::UnionIds(ids) => ids.clone(),
+                _ => {
+                    let user_ids = self
+                        .resolve_recipients(std::slice::from_ref(recipient))
+                        .await?;
+                    stream::iter(user_ids)
+                        .map(|user_id| async move {
+                            let user = self.get_employee_userinfo(user_id).await?;
+                            Ok::<String, DingTalkError>(user.union_id)
+                        })
+                        .buffered(MAX_CONCURRENT_REQUESTS)
+                        .try_collect()
+                        .await?
+                }
+            };
+
+            for union_id in resolved {
+                if seen.insert(union_id.clone()) {
+                    union_ids.push(union_id);
+                }
+            }
+        }
+
+        Ok(union_ids)
+    }
+
+    oapi_endpoint! {
+        pub(crate) fn list_department_user_ids(dept_id: i64) -> Vec<String>
+            = "topapi/user/listid" => userid_list;
+    }
+
+    async fn list_role_user_ids(&self, role_id: i64) -> Result<Vec<String>, DingTalkError> {
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Member {
+            userid: String,
+        }
+
+        paginate(self.pace, 0, |offset| async move {
+            let params = serde_json::json!({ "role_id": role_id, "offset": offset, "size": 200 });
+            let page: PageResult<Member> =
+                self.post_oapi("topapi/role/simplelist", &params).await?;
+
+            let next = page
+                .has_more
+                .then(|| page.next_cursor.unwrap_or(offset + 200));
+            let user_ids = page.data.into_iter().map(|member| member.userid).collect();
+            Ok((user_ids, next))
+        })
+        .try_collect()
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mock::Mock, WorkMessage, WorkNotification};
+    use serde_json::json;
+
+    async fn mock_app() -> (std::sync::Arc<Mock>, OrgApp) {
+        let mock = Mock::new();
+        mock.respond(
+            "topapi/user/getbyunionid",
+            json!({ "errcode": 0, "result": { "contact_type": 0, "userid": "lisi" } }),
+        );
+        mock.respond(
+            "topapi/user/listid",
+            json!({ "errcode": 0, "result": { "userid_list": ["zhangsan", "wangwu"] } }),
+        );
+        mock.respond(
+            "topapi/role/simplelist",
+            json!({ "errcode": 0, "result": { "hasMore": false, "list": [{ "userid": "zhaoliu" }] } }),
+        );
+        mock.respond(
+            "topapi/v2/user/get",
+            json!({ "errcode": 0, "result": { "userid": "zhangsan", "name": "张三", "unionid": "union-zhangsan" } }),
+        );
+        mock.respond(
+            "topapi/message/corpconversation/asyncsend_v2",
+            json!({ "errcode": 0, "task_id": 7 }),
+        );
+        let app = mock.app().await;
+        (mock, app)
+    }
+
+    #[tokio::test]
+    async fn recipients_resolve_into_user_ids_once() {
+        let (mock, app) = mock_app().await;
+
+        let user_ids = app
+            .resolve_recipients(&[
+                Recipient::Users(vec!["zhangsan".to_string()]),
+                Recipient::UnionIds(vec!["union-lisi".to_string()]),
+                Recipient::Department(2),
+                Recipient::Role(3),
+            ])
+            .await
+            .unwrap();
+
+        assert_eq!(user_ids, vec!["zhangsan", "lisi", "wangwu", "zhaoliu"]);
+        assert_eq!(
+            mock.bodies("topapi/user/getbyunionid"),
+            vec![json!({ "unionid": "union-lisi" })]
+        );
+        assert_eq!(
+            mock.bodies("topapi/user/listid"),
+            vec![json!({ "dept_id": 2 })]
+        );
+        assert_eq!(
+            mock.bodies("topapi/role/simplelist"),
+            vec![json!({ "role_id": 3, "offset": 0, "size": 200 })]
+        );
+    }
+
+    #[tokio::test]
+    async fn recipients_resolve_into_union_ids() {
+        let (mock, app) = mock_app().await;
+
+        let union_ids = app
+            .resolve_union_ids(&[
+                Recipient::UnionIds(vec!["union-lisi".to_string()]),
+                Recipient::Users(vec!["zhangsan".to_string()]),
+            ])
+            .await
+            .unwrap();
+
+        assert_eq!(union_ids, vec!["union-lisi", "union-zhangsan"]);
+        assert!(mock.bodies("topapi/user/getbyunionid").is_empty());
+    }
+
+    #[tokio::test]
+    async fn conversations_cannot_be_resolved() {
+        let (_, app) = mock_app().await;
+        let conversation = Recipient::Conversation("cid".to_string());
+
+        assert_eq!(conversation.open_conversation_id(), Some("cid"));
+        let err = app.resolve_recipients(&[conversation]).await.unwrap_err();
+        assert!(matches!(err, DingTalkError::Config(_)));
+    }
+
+    #[tokio::test]
+    async fn work_notifications_send_to_the_resolved_recipients() {
+        let (mock, app) = mock_app().await;
+
+        let mut notification = WorkNotification::to_recipients(
+            1,
+            vec![Recipient::Department(2), Recipient::Role(3)],
+            WorkMessage::text("hello".to_string()),
+        );
+        notification.userid_list = vec!["wangwu".to_string()];
+        app.send_work_notification(&notification).await.unwrap();
+
+        let body = &mock.bodies("topapi/message/corpconversation/asyncsend_v2")[0];
+        assert_eq!(body["userid_list"], json!("wangwu,zhangsan,zhaoliu"));
+        assert!(body.get("dept_id_list").is_none());
+    }
+}
@@ -0,0 +1,513 @@
+use crate::core::{dispatch, Transport};
+use crate::{clock::Clock, config::Endpoints, crypto, error::DingTalkError, DingTalk};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+use url::{form_urlencoded, Url};
+
+/// DingTalk allows each robot to send at most 20 messages per minute.
+const MESSAGES_PER_MINUTE: usize = 20;
+/// Robots exceeding the limit are throttled by DingTalk for 10 minutes.
+const THROTTLE_PENALTY: Duration = Duration::from_secs(600);
+
+/// A custom group robot identified by its webhook access token.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Robot {
+    pub access_token: String,
+    /// The signing secret (`SEC...`), required when the robot uses the "加签" security setting.
+    #[serde(default)]
+    pub secret: Option<String>,
+}
+
+impl std::fmt::Debug for Robot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Robot")
+            .field("signed", &self.secret.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Robot {
+    pub fn new(access_token: String, secret: Option<String>) -> Self {
+        Robot {
+            access_token,
+            secret,
+        }
+    }
+
+    /// Builds the webhook URL, signing it with the robot secret when present.
+    ///
+    /// [Documents](https://open.dingtalk.com/document/robots/customize-robot-security-settings)
+    fn webhook_url(&self, endpoints: &Endpoints, clock: &Clock) -> Result<String, DingTalkError> {
+        let mut url = Url::parse(&endpoints.oapi("robot/send"))
+            .map_err(|e| DingTalkError::Config(e.to_string()))?;
+
+        let mut query = form_urlencoded::Serializer::new(String::new());
+        query.append_pair("access_token", &self.access_token);
+
+        if let Some(secret) = &self.secret {
+            let timestamp = clock.now_millis();
+            let sign = crypto::robot_sign(secret, timestamp);
+
+            query
+                .append_pair("timestamp", &timestamp.to_string())
+                .append_pair("sign", &sign);
+        }
+
+        url.set_query(Some(&query.finish()));
+
+        Ok(url.to_string())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct At {
+    #[serde(rename = "atMobiles", default, skip_serializing_if = "Vec::is_empty")]
+    pub at_mobiles: Vec<String>,
+    #[serde(rename = "atUserIds", default, skip_serializing_if = "Vec::is_empty")]
+    pub at_user_ids: Vec<String>,
+    #[serde(rename = "isAtAll", default)]
+    pub is_at_all: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TextContent {
+    pub content: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MarkdownContent {
+    pub title: String,
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LinkContent {
+    pub title: String,
+    pub text: String,
+    #[serde(rename = "messageUrl")]
+    pub message_url: String,
+    #[serde(rename = "picUrl", default)]
+    pub pic_url: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ActionCardContent {
+    pub title: String,
+    pub text: String,
+    #[serde(rename = "singleTitle")]
+    pub single_title: String,
+    #[serde(rename = "singleURL")]
+    pub single_url: String,
+    #[serde(rename = "btnOrientation", default)]
+    pub btn_orientation: String,
+}
+
+/// A message sent by a custom group robot.
+///
+/// [Documents](https://open.dingtalk.com/document/robots/custom-robot-access)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "msgtype")]
+pub enum RobotMessage {
+    #[serde(rename = "text")]
+    Text {
+        text: TextContent,
+        #[serde(default)]
+        at: At,
+    },
+    #[serde(rename = "markdown")]
+    Markdown {
+        markdown: MarkdownContent,
+        #[serde(default)]
+        at: At,
+    },
+    #[serde(rename = "link")]
+    Link { link: LinkContent },
+    #[serde(rename = "actionCard")]
+    ActionCard {
+        #[serde(rename = "actionCard")]
+        action_card: ActionCardContent,
+    },
+}
+
+impl RobotMessage {
+    pub fn text(content: String) -> Self {
+        RobotMessage::Text {
+            text: TextContent { content },
+            at: At::default(),
+        }
+    }
+
+    pub fn markdown(title: String, text: String) -> Self {
+        RobotMessage::Markdown {
+            markdown: MarkdownContent { title, text },
+            at: At::default(),
+        }
+    }
+}
+
+/// A point-in-time view of a robot in the pool.
+#[derive(Debug, Clone)]
+pub struct RobotStatus {
+    /// The position of the robot in the robots the pool was created with. The access token is
+    /// left out, as it is the credential of the webhook.
+    pub index: usize,
+    /// Messages sent through this robot during the last minute.
+    pub sent_last_minute: usize,
+    /// When set, the robot is throttled by DingTalk until this instant.
+    pub throttled_until: Option<Instant>,
+    /// Whether the robot was muted or disabled and is skipped until [RobotPool::unmute].
+    pub muted: bool,
+}
+
+#[derive(Default)]
+struct RobotState {
+    sent: VecDeque<Instant>,
+    throttled_until: Option<Instant>,
+    muted: bool,
+}
+
+struct RobotSlot {
+    robot: Robot,
+    state: Mutex<RobotState>,
+}
+
+impl RobotSlot {
+    /// Reserves a send on this robot if it is neither muted, throttled nor at its rate limit.
+    fn acquire(&self, now: Instant) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.muted {
+            return false;
+        }
+        if let Some(until) = state.throttled_until {
+            if until > now {
+                return false;
+            }
+            state.throttled_until = None;
+        }
+
+        while let Some(sent) = state.sent.front() {
+            if now.duration_since(*sent) < Duration::from_secs(60) {
+                break;
+            }
+            state.sent.pop_front();
+        }
+        if state.sent.len() >= MESSAGES_PER_MINUTE {
+            return false;
+        }
+
+        state.sent.push_back(now);
+        true
+    }
+}
+
+enum SendFailure {
+    Throttled,
+    Muted,
+    Other(DingTalkError),
+}
+
+/// A pool of webhook robots posting to the same group.
+///
+/// Sends are distributed round-robin across robots. Robots reaching the per-minute limit or
+/// throttled by DingTalk are skipped, and robots that were muted or disabled are failed over
+/// until they are explicitly unmuted.
+pub struct RobotPool {
+    robots: Vec<RobotSlot>,
+    cursor: AtomicUsize,
+    client: reqwest::Client,
+    clock: Arc<Clock>,
+    endpoints: Endpoints,
+    transport: Option<Transport>,
+}
+
+impl RobotPool {
+    pub fn new(robots: Vec<Robot>) -> RobotPool {
+        RobotPool::with_clock(robots, Arc::new(Clock::new()))
+    }
+
+    /// Creates a pool signing its requests with timestamps of the given clock.
+    pub fn with_clock(robots: Vec<Robot>, clock: Arc<Clock>) -> RobotPool {
+        RobotPool {
+            robots: robots
+                .into_iter()
+                .map(|robot| RobotSlot {
+                    robot,
+                    state: Mutex::new(RobotState::default()),
+                })
+                .collect(),
+            cursor: AtomicUsize::new(0),
+            client: reqwest::Client::new(),
+            clock,
+            endpoints: Endpoints::default(),
+            transport: None,
+        }
+    }
+
+    /// Sends messages to the given endpoints instead of `oapi.dingtalk.com`.
+    pub fn with_endpoints(mut self, endpoints: Endpoints) -> Self {
+        self.endpoints = endpoints;
+        self
+    }
+
+    /// Sends a message through the next available robot.
+    ///
+    /// [Documents](https://open.dingtalk.com/document/robots/custom-robot-access)
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The message to send.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is `Ok` once one robot accepted the message, otherwise an error if the
+    /// request fails or no robot in the pool is currently available.
+    pub async fn send(&self, message: &RobotMessage) -> Result<(), DingTalkError> {
+        let len = self.robots.len();
+        if len == 0 {
+            return Err(DingTalkError::NoRobotAvailable);
+        }
+
+        let start = self.cursor.fetch_add(1, Ordering::Relaxed);
+        for i in 0..len {
+            let index = (start + i) % len;
+            let slot = &self.robots[index];
+            if !slot.acquire(Instant::now()) {
+                continue;
+            }
+
+            match self.send_with(&slot.robot, message).await {
+                Ok(()) => return Ok(()),
+                Err(SendFailure::Throttled) => {
+                    warn!("robot #{} throttled, failing over", index);
+                    slot.state.lock().unwrap().throttled_until =
+                        Some(Instant::now() + THROTTLE_PENALTY);
+                }
+                Err(SendFailure::Muted) => {
+                    warn!("robot #{} muted, failing over", index);
+                    slot.state.lock().unwrap().muted = true;
+                }
+                Err(SendFailure::Other(e)) => return Err(e),
+            }
+        }
+
+        Err(DingTalkError::NoRobotAvailable)
+    }
+
+    async fn send_with(&self, robot: &Robot, message: &RobotMessage) -> Result<(), SendFailure> {
+        let url = robot
+            .webhook_url(&self.endpoints, &self.clock)
+            .map_err(SendFailure::Other)?;
+        let (client, request) = self.client.post(url).json(message).build_split();
+        let request = request.map_err(|e| SendFailure::Other(e.into()))?;
+        let result: Result<serde_json::Value, DingTalkError> = dispatch(
+            &client,
+            self.transport.as_ref(),
+            request,
+            "Failed to send robot message",
+        )
+        .await;
+        debug!(ok = result.is_ok(), "robot message sent");
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => match e.errcode() {
+                // 发送速度太快而限流
+                Some(410100) | Some(130101) => Err(SendFailure::Throttled),
+                // access_token 不存在 / 机器人已停用
+                Some(400101) | Some(400102) => Err(SendFailure::Muted),
+                _ => Err(SendFailure::Other(e)),
+            },
+        }
+    }
+
+    /// Returns the current throttling state of every robot in the pool.
+    pub fn statuses(&self) -> Vec<RobotStatus> {
+        let now = Instant::now();
+        self.robots
+            .iter()
+            .enumerate()
+            .map(|(index, slot)| {
+                let state = slot.state.lock().unwrap();
+                RobotStatus {
+                    index,
+                    sent_last_minute: state
+                        .sent
+                        .iter()
+                        .filter(|sent| now.duration_since(**sent) < Duration::from_secs(60))
+                        .count(),
+                    throttled_until: state.throttled_until.filter(|until| *until > now),
+                    muted: state.muted,
+                }
+            })
+            .collect()
+    }
+
+    /// Puts a muted robot back into rotation, e.g. after it was re-enabled in the group settings.
+    pub fn unmute(&self, access_token: &str) {
+        for slot in self
+            .robots
+            .iter()
+            .filter(|slot| slot.robot.access_token == access_token)
+        {
+            slot.state.lock().unwrap().muted = false;
+        }
+    }
+}
+
+impl DingTalk {
+    /// Creates a robot pool signing its requests with the clock of this instance.
+    pub fn robot_pool(&self, robots: Vec<Robot>) -> RobotPool {
+        let mut pool = RobotPool::with_clock(robots, self.clock.clone());
+        pool.client = self.client.clone();
+        pool.endpoints = self.endpoints.clone();
+        pool.transport = self.transport.clone();
+        pool
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::Mock;
+    use serde_json::json;
+
+    fn robots(tokens: &[&str]) -> Vec<Robot> {
+        tokens
+            .iter()
+            .map(|token| Robot::new(token.to_string(), None))
+            .collect()
+    }
+
+    #[test]
+    fn debug_output_redacts_the_credentials() {
+        let robot = Robot::new("token-123".to_string(), Some("SEC456".to_string()));
+        let debug = format!("{:?}", robot);
+        assert_eq!(debug, "Robot { signed: true, .. }");
+    }
+
+    /// Returns the access tokens of the robots the mock received messages from, in order.
+    fn senders(mock: &Mock) -> Vec<String> {
+        mock.requests()
+            .into_iter()
+            .filter_map(|request| {
+                request
+                    .query
+                    .into_iter()
+                    .find(|(key, _)| key == "access_token")
+                    .map(|(_, value)| value)
+            })
+            .collect()
+    }
+
+    fn message() -> RobotMessage {
+        RobotMessage::text("构建成功".to_string())
+    }
+
+    #[tokio::test]
+    async fn sends_go_round_robin() {
+        let mock = Mock::new();
+        let pool = mock.dingtalk().robot_pool(robots(&["a", "b", "c"]));
+
+        for _ in 0..4 {
+            pool.send(&message()).await.unwrap();
+        }
+
+        assert_eq!(senders(&mock), vec!["a", "b", "c", "a"]);
+        assert_eq!(mock.bodies("robot/send")[0]["text"]["content"], "构建成功");
+    }
+
+    #[test]
+    fn robots_send_twenty_messages_per_minute() {
+        let slot = RobotSlot {
+            robot: Robot::new("a".to_string(), None),
+            state: Mutex::new(RobotState::default()),
+        };
+        let now = Instant::now();
+
+        for _ in 0..MESSAGES_PER_MINUTE {
+            assert!(slot.acquire(now));
+        }
+        assert!(!slot.acquire(now + Duration::from_secs(59)));
+        assert!(slot.acquire(now + Duration::from_secs(60)));
+    }
+
+    #[tokio::test]
+    async fn throttled_robots_are_failed_over() {
+        for errcode in [410100, 130101] {
+            let mock = Mock::new();
+            mock.respond(
+                "robot/send",
+                json!({ "errcode": errcode, "errmsg": "send too fast" }),
+            );
+            mock.respond("robot/send", json!({ "errcode": 0, "errmsg": "ok" }));
+            let pool = mock.dingtalk().robot_pool(robots(&["a", "b"]));
+
+            pool.send(&message()).await.unwrap();
+            pool.send(&message()).await.unwrap();
+
+            assert_eq!(senders(&mock), vec!["a", "b", "b"]);
+            let statuses = pool.statuses();
+            assert!(statuses[0].throttled_until.is_some());
+            assert!(!statuses[0].muted);
+            assert_eq!(statuses[1].sent_last_minute, 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn muted_robots_are_skipped_until_unmuted() {
+        for errcode in [400101, 400102] {
+            let mock = Mock::new();
+            mock.respond(
+                "robot/send",
+                json!({ "errcode": errcode, "errmsg": "robot disabled" }),
+            );
+            mock.respond("robot/send", json!({ "errcode": 0, "errmsg": "ok" }));
+            let pool = mock.dingtalk().robot_pool(robots(&["a", "b"]));
+
+            pool.send(&message()).await.unwrap();
+            pool.send(&message()).await.unwrap();
+            assert_eq!(senders(&mock), vec!["a", "b", "b"]);
+            assert!(pool.statuses()[0].muted);
+
+            pool.unmute("a");
+            pool.send(&message()).await.unwrap();
+            assert_eq!(senders(&mock), vec!["a", "b", "b", "a"]);
+            assert!(!pool.statuses()[0].muted);
+        }
+    }
+
+    #[tokio::test]
+    async fn pools_without_available_robots_fail() {
+        let mock = Mock::new();
+        let empty = mock.dingtalk().robot_pool(Vec::new());
+        assert!(matches!(
+            empty.send(&message()).await,
+            Err(DingTalkError::NoRobotAvailable)
+        ));
+
+        mock.respond(
+            "robot/send",
+            json!({ "errcode": 400102, "errmsg": "robot disabled" }),
+        );
+        let pool = mock.dingtalk().robot_pool(robots(&["a", "b"]));
+        assert!(matches!(
+            pool.send(&message()).await,
+            Err(DingTalkError::NoRobotAvailable)
+        ));
+        assert_eq!(senders(&mock), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn statuses_leave_out_the_access_tokens() {
+        let pool = RobotPool::new(robots(&["secret-token"]));
+        let status = &pool.statuses()[0];
+
+        assert_eq!(status.index, 0);
+        assert!(!format!("{:?}", status).contains("secret-token"));
+    }
+}
@@ -0,0 +1,183 @@
+use crate::{error::DingTalkError, organization::OrgApp};
+use tracing::info;
+
+/// How many roles the role assignment endpoints accept per call.
+const MAX_ROLES_PER_CALL: usize = 20;
+/// How many users the role assignment endpoints accept per call.
+const MAX_USERS_PER_CALL: usize = 20;
+
+impl OrgApp {
+    /// Assigns roles to users.
+    ///
+    /// [批量设置角色](https://open.dingtalk.com/document/orgapp/add-role-information-to-employees-in-batches)
+    ///
+    /// The roles and the users are sent in batches of 20 each, the limit of DingTalk.
+    ///
+    /// # Arguments
+    ///
+    /// * `role_ids` - The IDs of the roles.
+    /// * `user_ids` - The user IDs of the users.
+    ///
+    /// # Returns
+    ///
+    /// An empty `Result` if successful, otherwise the first error. Batches before an error stay
+    /// assigned.
+    pub async fn add_roles_to_users(
+        &self,
+        role_ids: &[i64],
+        user_ids: &[String],
+    ) -> Result<(), DingTalkError> {
+        self.update_role_members("topapi/role/addrolesforemps", role_ids, user_ids)
+            .await?;
+        info!("add_roles_to_users: {:?} {}", role_ids, user_ids.len());
+        Ok(())
+    }
+
+    /// Removes roles from users.
+    ///
+    /// [批量删除员工角色](https://open.dingtalk.com/document/orgapp/delete-a-role-from-multiple-users)
+    ///
+    /// The roles and the users are sent in batches of 20 each, the limit of DingTalk.
+    ///
+    /// # Arguments
+    ///
+    /// * `role_ids` - The IDs of the roles.
+    /// * `user_ids` - The user IDs of the users.
+    ///
+    /// # Returns
+    ///
+    /// An empty `Result` if successful, otherwise the first error. Batches before an error stay
+    /// removed.
+    pub async fn remove_roles_from_users(
+        &self,
+        role_ids: &[i64],
+        user_ids: &[String],
+    ) -> Result<(), DingTalkError> {
+        self.update_role_members("topapi/role/removerolesforemps", role_ids, user_ids)
+            .await?;
+        info!("remove_roles_from_users: {:?} {}", role_ids, user_ids.len());
+        Ok(())
+    }
+
+    /// Sets the departments a user manages through a role, e.g. the scope of a sub-admin.
+    ///
+    /// [设定角色成员管理范围](https://open.dingtalk.com/document/orgapp/sets-the-management-scope-of-a-role-member)
+    ///
+    /// # Arguments
+    ///
+    /// * `userid` - The user ID of the member of the role.
+    /// * `role_id` - The ID of the role.
+    /// * `dept_ids` - The IDs of the managed departments; empty sets the scope to the whole
+    ///   organization.
+    ///
+    /// # Returns
+    ///
+    /// An empty `Result` if successful, otherwise an error.
+    pub async fn set_role_member_manage_scope(
+        &self,
+        userid: String,
+        role_id: i64,
+        dept_ids: &[i64],
+    ) -> Result<(), DingTalkError> {
+        let mut params = serde_json::json!({ "userid": userid, "role_id": role_id });
+        if !dept_ids.is_empty() {
+            let dept_ids: Vec<String> = dept_ids.iter().map(i64::to_string).collect();
+            params["dept_ids"] = dept_ids.join(",").into();
+        }
+        self.post_oapi::<serde_json::Value>("topapi/role/scope/update", &params)
+            .await?;
+        info!("set_role_member_manage_scope: {} {}", userid, role_id);
+        Ok(())
+    }
+
+    async fn update_role_members(
+        &self,
+        path: &str,
+        role_ids: &[i64],
+        user_ids: &[String],
+    ) -> Result<(), DingTalkError> {
+        for roles in role_ids.chunks(MAX_ROLES_PER_CALL) {
+            let roles: Vec<String> = roles.iter().map(i64::to_string).collect();
+            let roles = roles.join(",");
+
+            for users in user_ids.chunks(MAX_USERS_PER_CALL) {
+                let params = serde_json::json!({ "roleIds": roles, "userIds": users.join(",") });
+                self.post_oapi::<serde_json::Value>(path, &params).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mock::Mock;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn role_members_are_sent_in_batches() {
+        let mock = Mock::new();
+        let app = mock.app().await;
+
+        let user_ids: Vec<String> = (0..45).map(|i| format!("user{}", i)).collect();
+        app.add_roles_to_users(&[1, 2], &user_ids).await.unwrap();
+        app.remove_roles_from_users(&[3], &user_ids[..1])
+            .await
+            .unwrap();
+
+        let added = mock.bodies("topapi/role/addrolesforemps");
+        assert_eq!(added.len(), 3);
+        assert_eq!(
+            added[0],
+            json!({ "roleIds": "1,2", "userIds": user_ids[..20].join(",") })
+        );
+        assert_eq!(added[2]["userIds"], json!(user_ids[40..].join(",")));
+        assert_eq!(
+            mock.bodies("topapi/role/removerolesforemps"),
+            vec![json!({ "roleIds": "3", "userIds": "user0" })]
+        );
+    }
+
+    #[tokio::test]
+    async fn roles_are_sent_in_batches() {
+        let mock = Mock::new();
+        let app = mock.app().await;
+
+        let role_ids: Vec<i64> = (1..=25).collect();
+        let user_ids: Vec<String> = (0..21).map(|i| format!("user{}", i)).collect();
+        app.remove_roles_from_users(&role_ids, &user_ids)
+            .await
+            .unwrap();
+
+        let batches: Vec<(usize, usize)> = mock
+            .bodies("topapi/role/removerolesforemps")
+            .iter()
+            .map(|body| {
+                let count = |key: &str| body[key].as_str().unwrap().split(',').count();
+                (count("roleIds"), count("userIds"))
+            })
+            .collect();
+        assert_eq!(batches, vec![(20, 20), (20, 1), (5, 20), (5, 1)]);
+    }
+
+    #[tokio::test]
+    async fn manage_scopes_join_the_department_ids() {
+        let mock = Mock::new();
+        let app = mock.app().await;
+
+        app.set_role_member_manage_scope("user1".to_string(), 1, &[2, 3])
+            .await
+            .unwrap();
+        app.set_role_member_manage_scope("user1".to_string(), 1, &[])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            mock.bodies("topapi/role/scope/update"),
+            vec![
+                json!({ "userid": "user1", "role_id": 1, "dept_ids": "2,3" }),
+                json!({ "userid": "user1", "role_id": 1 }),
+            ]
+        );
+    }
+}
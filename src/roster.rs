@@ -0,0 +1,969 @@
+use crate::{error::DingTalkError, organization::OrgApp};
+use chrono::{Datelike, FixedOffset, Months, NaiveDate};
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+use tracing::info;
+
+/// The on-job statuses of [OrgApp::query_on_job_employees]: probation, regular, leaving, none.
+const ON_JOB_STATUSES: &str = "2,3,5,-1";
+const PROBATION_STATUS: &str = "2";
+/// The roster fields of [OrgApp::list_upcoming_anniversaries] and
+/// [OrgApp::list_probation_endings]: the name, the hire date, the length of the probation in
+/// months and the planned end of the probation (计划转正日期).
+const NAME_FIELD: &str = "sys00-name";
+const HIRED_FIELD: &str = "sys00-confirmJoinTime";
+const PROBATION_PERIOD_FIELD: &str = "sys01-probationPeriodType";
+const PLANNED_REGULAR_FIELD: &str = "sys01-planRegularTime";
+/// How many employees the roster endpoints accept per call.
+const MAX_ROSTER_USERS: usize = 100;
+
+/// The departure of an employee in the smart work roster (智能人事), see
+/// [OrgApp::delete_user_with_dismissal] and [OrgApp::dismiss_employee].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Dismissal {
+    pub last_work_day: NaiveDate,
+    /// A note on why the employee left.
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+/// The group of a roster field, the part of its code before the `-`, e.g. `sys02` of
+/// `sys02-birthTime`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RosterFieldGroup {
+    /// `sys00`, the name, department, job number and hire date.
+    Basic,
+    /// `sys01`, the position, rank and work place.
+    Position,
+    /// `sys02`, the ID card, birthday, address and other personal data.
+    Personal,
+    /// `sys05`, the employment contracts.
+    Contract,
+    /// Any other group, including the custom groups of the organization.
+    Other(String),
+}
+
+impl RosterFieldGroup {
+    pub fn from_group_id(group_id: &str) -> Self {
+        match group_id {
+            "sys00" => RosterFieldGroup::Basic,
+            "sys01" => RosterFieldGroup::Position,
+            "sys02" => RosterFieldGroup::Personal,
+            "sys05" => RosterFieldGroup::Contract,
+            other => RosterFieldGroup::Other(other.to_string()),
+        }
+    }
+
+    pub fn group_id(&self) -> &str {
+        match self {
+            RosterFieldGroup::Basic => "sys00",
+            RosterFieldGroup::Position => "sys01",
+            RosterFieldGroup::Personal => "sys02",
+            RosterFieldGroup::Contract => "sys05",
+            RosterFieldGroup::Other(group_id) => group_id,
+        }
+    }
+}
+
+/// A value of a roster field; fields of repeated groups, e.g. contracts, have one per entry.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RosterFieldValue {
+    #[serde(default)]
+    pub value: Option<String>,
+    /// The display text of the value, e.g. the option name of a select field.
+    #[serde(default)]
+    pub label: Option<String>,
+    /// The entry of a repeated group the value belongs to.
+    #[serde(default)]
+    pub item_index: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RosterField {
+    pub field_code: String,
+    #[serde(default)]
+    pub field_name: String,
+    #[serde(default)]
+    pub group_id: String,
+    #[serde(rename = "field_value_list", default)]
+    pub values: Vec<RosterFieldValue>,
+}
+
+impl RosterField {
+    pub fn group(&self) -> RosterFieldGroup {
+        RosterFieldGroup::from_group_id(&self.group_id)
+    }
+
+    /// Returns the value of the first entry.
+    pub fn value(&self) -> Option<&str> {
+        self.values.first()?.value.as_deref()
+    }
+}
+
+/// The roster (花名册) of an employee, see [OrgApp::get_employee_roster].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EmployeeRoster {
+    #[serde(rename = "userid")]
+    pub user_id: String,
+    #[serde(rename = "field_data_list", default)]
+    pub fields: Vec<RosterField>,
+}
+
+impl EmployeeRoster {
+    pub fn field(&self, field_code: &str) -> Option<&RosterField> {
+        self.fields
+            .iter()
+            .find(|field| field.field_code == field_code)
+    }
+
+    /// Returns the fields of a group, e.g. the contract fields.
+    pub fn fields_of(&self, group: &RosterFieldGroup) -> impl Iterator<Item = &RosterField> {
+        let group_id = group.group_id().to_string();
+        self.fields
+            .iter()
+            .filter(move |field| field.group_id == group_id)
+    }
+}
+
+/// The changes of [OrgApp::update_employee_roster], grouped like the roster.
+///
+/// ```
+/// use async_dingtalk::{RosterFieldGroup, RosterUpdate};
+///
+/// let update = RosterUpdate::new()
+///     .field(RosterFieldGroup::Personal, "sys02-birthTime", "1990-01-01")
+///     .field(RosterFieldGroup::Position, "sys01-positionLevel", "P6");
+/// assert!(!update.is_empty());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RosterUpdate {
+    /// The field codes and values by group ID, in the order the groups were first set.
+    groups: Vec<(String, Vec<(String, String)>)>,
+    /// The codes of fields set in a group other than the `sysNN` group of their code.
+    misplaced: Vec<String>,
+}
+
+impl RosterUpdate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets a field, replacing an earlier value of the same field.
+    ///
+    /// # Arguments
+    ///
+    /// * `group` - The group of the field; [OrgApp::update_employee_roster] fails if the code of
+    ///   a DingTalk field names another group, e.g. `sys01-positionLevel` set as
+    ///   [RosterFieldGroup::Personal].
+    /// * `field_code` - The code of the field, e.g. `sys02-birthTime`.
+    /// * `value` - The new value, as DingTalk formats it, e.g. `1990-01-01` for dates.
+    pub fn field(
+        mut self,
+        group: RosterFieldGroup,
+        field_code: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Self {
+        let (field_code, value) = (field_code.into(), value.into());
+        let group_id = group.group_id();
+        if matches!(sys_group_id(&field_code), Some(prefix) if prefix != group_id) {
+            self.misplaced.push(field_code.clone());
+        }
+        let index = match self.groups.iter().position(|(id, _)| id == group_id) {
+            Some(index) => index,
+            None => {
+                self.groups.push((group_id.to_string(), Vec::new()));
+                self.groups.len() - 1
+            }
+        };
+
+        let fields = &mut self.groups[index].1;
+        match fields.iter_mut().find(|(code, _)| *code == field_code) {
+            Some(field) => field.1 = value,
+            None => fields.push((field_code, value)),
+        }
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    fn to_groups(&self) -> serde_json::Value {
+        self.groups
+            .iter()
+            .map(|(group_id, fields)| {
+                let section: Vec<serde_json::Value> = fields
+                    .iter()
+                    .map(|(code, value)| serde_json::json!({ "field_code": code, "value": value }))
+                    .collect();
+                serde_json::json!({ "group_id": group_id, "sections": [{ "section": section }] })
+            })
+            .collect()
+    }
+}
+
+/// Returns the group ID of the code of a DingTalk field, e.g. `sys02` of `sys02-birthTime`.
+fn sys_group_id(field_code: &str) -> Option<&str> {
+    let (prefix, _) = field_code.split_once('-')?;
+    let digits = prefix.strip_prefix("sys")?;
+    if digits.len() == 2 && digits.bytes().all(|b| b.is_ascii_digit()) {
+        Some(prefix)
+    } else {
+        None
+    }
+}
+
+/// An accepted candidate to onboard, see [OrgApp::add_pre_entry_employee].
+///
+/// ```
+/// use async_dingtalk::PreEntryEmployee;
+/// use chrono::NaiveDate;
+///
+/// let joins_on = NaiveDate::from_ymd_opt(2024, 7, 1).unwrap();
+/// let candidate = PreEntryEmployee::new("张三", "13800000000", joins_on).operator("manager4220");
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PreEntryEmployee {
+    pub name: String,
+    pub mobile: String,
+    pub pre_entry_time: NaiveDate,
+    /// The user ID of the HR employee adding the candidate.
+    #[serde(default)]
+    pub op_userid: Option<String>,
+    /// Roster fields prefilled for the onboarding, by field code, e.g. `sys00-dept`.
+    #[serde(default)]
+    pub extend_info: BTreeMap<String, String>,
+}
+
+impl PreEntryEmployee {
+    pub fn new(
+        name: impl Into<String>,
+        mobile: impl Into<String>,
+        pre_entry_time: NaiveDate,
+    ) -> Self {
+        PreEntryEmployee {
+            name: name.into(),
+            mobile: mobile.into(),
+            pre_entry_time,
+            op_userid: None,
+            extend_info: BTreeMap::new(),
+        }
+    }
+
+    pub fn operator(mut self, op_userid: impl Into<String>) -> Self {
+        self.op_userid = Some(op_userid.into());
+        self
+    }
+
+    /// Prefills a roster field.
+    pub fn field(mut self, field_code: impl Into<String>, value: impl Into<String>) -> Self {
+        self.extend_info.insert(field_code.into(), value.into());
+        self
+    }
+}
+
+/// A group of roster fields as defined by the organization, see
+/// [OrgApp::list_hrm_field_definitions].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RosterGroupDefinition {
+    pub group_id: String,
+    #[serde(default)]
+    pub group_name: String,
+    /// The group holds a list of entries, e.g. contracts, with one section per entry.
+    #[serde(rename = "detail", default)]
+    pub repeated: bool,
+    #[serde(rename = "field_meta_info_list", default)]
+    pub fields: Vec<RosterFieldDefinition>,
+}
+
+impl RosterGroupDefinition {
+    pub fn group(&self) -> RosterFieldGroup {
+        RosterFieldGroup::from_group_id(&self.group_id)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RosterFieldDefinition {
+    pub field_code: String,
+    #[serde(default)]
+    pub field_name: String,
+    /// The form component of the field, e.g. `TextField`, `DDDateField` or `DDSelectField`.
+    #[serde(default)]
+    pub field_type: String,
+    /// Custom fields of the organization, as opposed to the `sys` fields of DingTalk.
+    #[serde(rename = "user_custom", default)]
+    pub custom: bool,
+    #[serde(default)]
+    pub editable_by_hr: bool,
+    #[serde(rename = "visible_by_emp", default)]
+    pub visible_to_employee: bool,
+}
+
+/// A work anniversary of an employee.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Anniversary {
+    pub user_id: String,
+    pub name: String,
+    pub hired_date: NaiveDate,
+    /// The day of the anniversary; Feb 29 hires celebrate on Feb 28 in common years.
+    pub date: NaiveDate,
+    /// The years of service completed on `date`.
+    pub years: i32,
+}
+
+/// The end of the probation of an employee.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ProbationEnd {
+    pub user_id: String,
+    pub name: String,
+    pub hired_date: NaiveDate,
+    pub ends_on: NaiveDate,
+}
+
+impl OrgApp {
+    /// Lists the work anniversaries of the on-job employees within the range, by date.
+    ///
+    /// Anniversaries are computed from the hire date (入职时间) of the roster; employees without
+    /// one are left out.
+    ///
+    /// # Arguments
+    ///
+    /// * `agent_id` - The agent ID of the app, see [OrgApp::get_employee_roster].
+    /// * `range` - The days to report, e.g. the next 30 days.
+    pub async fn list_upcoming_anniversaries(
+        &self,
+        agent_id: i64,
+        range: RangeInclusive<NaiveDate>,
+    ) -> Result<Vec<Anniversary>, DingTalkError> {
+        let rosters = self
+            .on_job_rosters(agent_id, ON_JOB_STATUSES, &[NAME_FIELD, HIRED_FIELD])
+            .await?;
+        Ok(anniversaries(&rosters, &range))
+    }
+
+    /// Lists the employees on probation (试用期) whose probation ends within the range, by date.
+    ///
+    /// The probation ends on the planned regularization date (计划转正日期) of the roster, or
+    /// after the probation period counted from the hire date when no date is planned. Employees
+    /// without a hire date in their roster are left out.
+    ///
+    /// # Arguments
+    ///
+    /// * `agent_id` - The agent ID of the app, see [OrgApp::get_employee_roster].
+    /// * `range` - The days to report.
+    pub async fn list_probation_endings(
+        &self,
+        agent_id: i64,
+        range: RangeInclusive<NaiveDate>,
+    ) -> Result<Vec<ProbationEnd>, DingTalkError> {
+        let fields = [
+            NAME_FIELD,
+            HIRED_FIELD,
+            PROBATION_PERIOD_FIELD,
+            PLANNED_REGULAR_FIELD,
+        ];
+        let rosters = self
+            .on_job_rosters(agent_id, PROBATION_STATUS, &fields)
+            .await?;
+        Ok(probation_endings(&rosters, &range))
+    }
+
+    /// Retrieves the rosters of employees from the smart work HR master data.
+    ///
+    /// [获取员工花名册字段信息](https://open.dingtalk.com/document/orgapp/intelligent-personnel-obtain-employee-roster-field-information)
+    ///
+    /// The employees are requested in batches of 100, the limit of DingTalk.
+    ///
+    /// # Arguments
+    ///
+    /// * `agent_id` - The agent ID of the app.
+    /// * `userids` - The user IDs of the employees.
+    /// * `field_filter_list` - The codes of the fields to return, e.g. `sys00-name`; empty for
+    ///   all fields.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the rosters if successful, otherwise the first error.
+    pub async fn get_employee_roster(
+        &self,
+        agent_id: i64,
+        userids: &[String],
+        field_filter_list: &[String],
+    ) -> Result<Vec<EmployeeRoster>, DingTalkError> {
+        let mut rosters = Vec::new();
+        for batch in userids.chunks(MAX_ROSTER_USERS) {
+            let mut params = serde_json::json!({
+                "agentid": agent_id,
+                "userid_list": batch.join(","),
+            });
+            if !field_filter_list.is_empty() {
+                params["field_filter_list"] = field_filter_list.join(",").into();
+            }
+            let page: Vec<EmployeeRoster> = self
+                .post_oapi("topapi/smartwork/hrm/employee/v2/list", &params)
+                .await?;
+            rosters.extend(page);
+        }
+        Ok(rosters)
+    }
+
+    /// Writes roster fields back to the smart work HR master data, e.g. corrections of an HR
+    /// system.
+    ///
+    /// [更新员工花名册信息](https://open.dingtalk.com/document/orgapp/intelligent-personnel-update-employee-file-information)
+    ///
+    /// # Arguments
+    ///
+    /// * `agent_id` - The agent ID of the app.
+    /// * `userid` - The user ID of the employee.
+    /// * `fields` - The fields to change; the others are kept.
+    ///
+    /// # Returns
+    ///
+    /// An empty `Result` if successful, otherwise an error.
+    ///
+    /// # Errors
+    ///
+    /// Returns [DingTalkError::Config] if `fields` is empty or sets a field in a group other than
+    /// the group of its code.
+    pub async fn update_employee_roster(
+        &self,
+        agent_id: i64,
+        userid: String,
+        fields: &RosterUpdate,
+    ) -> Result<(), DingTalkError> {
+        if fields.is_empty() {
+            return Err(DingTalkError::Config(
+                "roster update has no fields".to_string(),
+            ));
+        }
+        if !fields.misplaced.is_empty() {
+            return Err(DingTalkError::Config(format!(
+                "roster fields set outside the group of their code: {}",
+                fields.misplaced.join(", ")
+            )));
+        }
+
+        let params = serde_json::json!({
+            "agentid": agent_id,
+            "param": { "userid": userid, "groups": fields.to_groups() },
+        });
+        self.post_oapi::<serde_json::Value>("topapi/smartwork/hrm/employee/v2/update", &params)
+            .await?;
+        info!("update_employee_roster: {}", userid);
+        Ok(())
+    }
+
+    /// Adds an accepted candidate as a pending hire (待入职) to the smart work onboarding.
+    ///
+    /// [添加待入职员工](https://open.dingtalk.com/document/orgapp/intelligent-personnel-add-employees-to-be-hired)
+    ///
+    /// # Arguments
+    ///
+    /// * `employee` - The candidate.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the user ID of the pending hire if successful, otherwise an error.
+    pub async fn add_pre_entry_employee(
+        &self,
+        employee: &PreEntryEmployee,
+    ) -> Result<String, DingTalkError> {
+        let mut param = serde_json::json!({
+            "name": employee.name,
+            "mobile": employee.mobile,
+            "pre_entry_time": format!("{} 00:00:00", employee.pre_entry_time.format("%Y-%m-%d")),
+        });
+        if let Some(op_userid) = &employee.op_userid {
+            param["op_userid"] = op_userid.clone().into();
+        }
+        if !employee.extend_info.is_empty() {
+            param["extend_info"] = serde_json::to_string(&employee.extend_info)?.into();
+        }
+
+        let params = serde_json::json!({ "param": param });
+        let userid: String = self
+            .post_oapi("topapi/smartwork/hrm/employee/addpreentry", &params)
+            .await?;
+        info!("add_pre_entry_employee: {}", userid);
+        Ok(userid)
+    }
+
+    /// Lists the roster fields of the organization, including its custom fields, e.g. to find
+    /// the field codes of [OrgApp::get_employee_roster] and [OrgApp::update_employee_roster].
+    ///
+    /// [获取花名册元数据](https://open.dingtalk.com/document/orgapp/intelligent-personnel-obtain-roster-metadata)
+    ///
+    /// # Arguments
+    ///
+    /// * `agent_id` - The agent ID of the app.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the field groups if successful, otherwise an error.
+    pub async fn list_hrm_field_definitions(
+        &self,
+        agent_id: i64,
+    ) -> Result<Vec<RosterGroupDefinition>, DingTalkError> {
+        let params = serde_json::json!({ "agentid": agent_id });
+        self.post_oapi("topapi/smartwork/hrm/roster/meta/get", &params)
+            .await
+    }
+
+    /// Records the departure of an employee in the smart work roster, e.g. driven by an external
+    /// HR system. The user stays in the organization until deleted, see
+    /// [OrgApp::delete_user_with_dismissal].
+    ///
+    /// [办理离职](https://open.dingtalk.com/document/orgapp/intelligent-personnel-handle-employee-resignation)
+    ///
+    /// # Arguments
+    ///
+    /// * `userid` - The user ID of the employee.
+    /// * `last_work_day` - The last work day, in China.
+    /// * `reason` - A note on why the employee left.
+    ///
+    /// # Returns
+    ///
+    /// An empty `Result` if successful, otherwise an error.
+    pub async fn dismiss_employee(
+        &self,
+        userid: String,
+        last_work_day: NaiveDate,
+        reason: Option<String>,
+    ) -> Result<(), DingTalkError> {
+        let last_work_day = FixedOffset::east_opt(8 * 3600)
+            .and_then(|china| {
+                let start = last_work_day.and_hms_opt(0, 0, 0)?;
+                start.and_local_timezone(china).single()
+            })
+            .ok_or_else(|| DingTalkError::Config("invalid last work day".to_string()))?;
+
+        let mut params = serde_json::json!({
+            "userid": userid,
+            "last_work_day": last_work_day.timestamp_millis(),
+        });
+        if let Some(reason) = reason {
+            params["dismission_memo"] = reason.into();
+        }
+        self.post_oapi::<serde_json::Value>("topapi/smartwork/hrm/employee/dismission", &params)
+            .await?;
+        info!("dismiss_employee: {}", userid);
+
+        Ok(())
+    }
+
+    /// Retrieves the given roster fields of all on-job employees with the given statuses.
+    async fn on_job_rosters(
+        &self,
+        agent_id: i64,
+        status: &str,
+        fields: &[&str],
+    ) -> Result<Vec<EmployeeRoster>, DingTalkError> {
+        let user_ids: Vec<String> = self
+            .stream_on_job_employees(status.to_string())
+            .try_collect()
+            .await?;
+        let fields: Vec<String> = fields.iter().map(|code| code.to_string()).collect();
+
+        self.get_employee_roster(agent_id, &user_ids, &fields).await
+    }
+}
+
+/// Returns the name of the employee, empty if the roster has none.
+fn roster_name(roster: &EmployeeRoster) -> String {
+    roster
+        .field(NAME_FIELD)
+        .and_then(RosterField::value)
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn anniversaries(
+    rosters: &[EmployeeRoster],
+    range: &RangeInclusive<NaiveDate>,
+) -> Vec<Anniversary> {
+    let mut anniversaries: Vec<Anniversary> = rosters
+        .iter()
+        .filter_map(|roster| Some((roster, roster_date(roster, HIRED_FIELD)?)))
+        .flat_map(|(roster, hired)| {
+            (range.start().year()..=range.end().year()).filter_map(move |year| {
+                let date = NaiveDate::from_ymd_opt(year, hired.month(), hired.day())
+                    .or_else(|| NaiveDate::from_ymd_opt(year, 2, 28))?;
+                let years = year - hired.year();
+                if years < 1 || !range.contains(&date) {
+                    return None;
+                }
+                Some(Anniversary {
+                    user_id: roster.user_id.clone(),
+                    name: roster_name(roster),
+                    hired_date: hired,
+                    date,
+                    years,
+                })
+            })
+        })
+        .collect();
+
+    anniversaries.sort_by(|a, b| (a.date, &a.user_id).cmp(&(b.date, &b.user_id)));
+    anniversaries
+}
+
+/// Returns the day of a date field of the roster, e.g. `2023-03-01`.
+fn roster_date(roster: &EmployeeRoster, field_code: &str) -> Option<NaiveDate> {
+    let value = roster.field(field_code)?.value()?;
+    NaiveDate::parse_from_str(value.get(..10)?, "%Y-%m-%d").ok()
+}
+
+fn probation_endings(
+    rosters: &[EmployeeRoster],
+    range: &RangeInclusive<NaiveDate>,
+) -> Vec<ProbationEnd> {
+    let mut endings: Vec<ProbationEnd> = rosters
+        .iter()
+        .filter_map(|roster| {
+            let hired = roster_date(roster, HIRED_FIELD)?;
+            let ends_on = roster_date(roster, PLANNED_REGULAR_FIELD).or_else(|| {
+                let months = roster
+                    .field(PROBATION_PERIOD_FIELD)?
+                    .value()?
+                    .parse()
+                    .ok()?;
+                hired.checked_add_months(Months::new(months))
+            })?;
+            if !range.contains(&ends_on) {
+                return None;
+            }
+            Some(ProbationEnd {
+                user_id: roster.user_id.clone(),
+                name: roster_name(roster),
+                hired_date: hired,
+                ends_on,
+            })
+        })
+        .collect();
+
+    endings.sort_by(|a, b| (a.ends_on, &a.user_id).cmp(&(b.ends_on, &b.user_id)));
+    endings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::Mock;
+    use serde_json::json;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn roster(user_id: &str, fields: &[(&str, &str)]) -> serde_json::Value {
+        let fields: Vec<_> = fields
+            .iter()
+            .map(|(code, value)| {
+                json!({
+                    "field_code": code,
+                    "group_id": &code[..5],
+                    "field_value_list": [{ "value": value, "item_index": 0 }],
+                })
+            })
+            .collect();
+        json!({ "userid": user_id, "field_data_list": fields })
+    }
+
+    #[test]
+    fn anniversaries_are_dated_from_the_hire() {
+        let rosters: Vec<EmployeeRoster> = serde_json::from_value(json!([
+            roster("zhangsan", &[("sys00-confirmJoinTime", "2023-03-01")]),
+            roster("lisi", &[("sys00-confirmJoinTime", "2024-02-29 00:00:00")]),
+            roster("wangwu", &[]),
+        ]))
+        .unwrap();
+
+        let found = anniversaries(&rosters, &(date(2024, 1, 1)..=date(2025, 12, 31)));
+        let dates: Vec<(&str, NaiveDate, i32)> = found
+            .iter()
+            .map(|a| (a.user_id.as_str(), a.date, a.years))
+            .collect();
+        assert_eq!(
+            dates,
+            vec![
+                ("zhangsan", date(2024, 3, 1), 1),
+                ("lisi", date(2025, 2, 28), 1),
+                ("zhangsan", date(2025, 3, 1), 2),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn anniversaries_are_read_from_the_roster_in_batches() {
+        let mock = Mock::new();
+        let user_ids: Vec<String> = (0..150).map(|i| format!("user{}", i)).collect();
+        mock.respond(
+            "topapi/smartwork/hrm/employee/queryonjob",
+            json!({ "errcode": 0, "result": { "data_list": user_ids } }),
+        );
+        let path = "topapi/smartwork/hrm/employee/v2/list";
+        mock.respond(
+            path,
+            json!({ "errcode": 0, "result": [roster("user0", &[
+                ("sys00-name", "张三"),
+                ("sys00-confirmJoinTime", "2023-03-01"),
+            ])] }),
+        );
+        mock.respond(path, json!({ "errcode": 0, "result": [] }));
+        let app = mock.app().await;
+
+        let found = app
+            .list_upcoming_anniversaries(1, date(2024, 3, 1)..=date(2024, 3, 31))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            found,
+            vec![Anniversary {
+                user_id: "user0".to_string(),
+                name: "张三".to_string(),
+                hired_date: date(2023, 3, 1),
+                date: date(2024, 3, 1),
+                years: 1,
+            }]
+        );
+        let bodies = mock.bodies(path);
+        assert_eq!(bodies.len(), 2);
+        assert_eq!(bodies[0]["userid_list"], json!(user_ids[..100].join(",")));
+        assert_eq!(
+            bodies[0]["field_filter_list"],
+            json!("sys00-name,sys00-confirmJoinTime")
+        );
+        assert!(mock.bodies("topapi/v2/user/get").is_empty());
+    }
+
+    #[tokio::test]
+    async fn probation_ends_are_read_from_the_roster() {
+        let mock = Mock::new();
+        mock.respond(
+            "topapi/smartwork/hrm/employee/queryonjob",
+            json!({ "errcode": 0, "result": { "data_list": ["zhangsan", "lisi", "wangwu"] } }),
+        );
+        let path = "topapi/smartwork/hrm/employee/v2/list";
+        mock.respond(
+            path,
+            json!({ "errcode": 0, "result": [
+                roster("zhangsan", &[
+                    ("sys00-name", "张三"),
+                    ("sys00-confirmJoinTime", "2023-03-01"),
+                    ("sys01-probationPeriodType", "3"),
+                    ("sys01-planRegularTime", "2023-05-15"),
+                ]),
+                roster("lisi", &[
+                    ("sys00-name", "李四"),
+                    ("sys00-confirmJoinTime", "2023-03-01 00:00:00"),
+                    ("sys01-probationPeriodType", "3"),
+                ]),
+                roster("wangwu", &[("sys01-planRegularTime", "2023-05-20")]),
+            ] }),
+        );
+        let app = mock.app().await;
+
+        let endings = app
+            .list_probation_endings(1, date(2023, 5, 1)..=date(2023, 6, 30))
+            .await
+            .unwrap();
+
+        let ends: Vec<(&str, &str, NaiveDate)> = endings
+            .iter()
+            .map(|end| (end.user_id.as_str(), end.name.as_str(), end.ends_on))
+            .collect();
+        assert_eq!(
+            ends,
+            vec![
+                ("zhangsan", "张三", date(2023, 5, 15)),
+                ("lisi", "李四", date(2023, 6, 1)),
+            ]
+        );
+        assert_eq!(
+            mock.bodies(path)[0]["field_filter_list"],
+            json!(
+                "sys00-name,sys00-confirmJoinTime,sys01-probationPeriodType,sys01-planRegularTime"
+            )
+        );
+    }
+
+    #[test]
+    fn roster_updates_group_the_fields() {
+        let update = RosterUpdate::new()
+            .field(RosterFieldGroup::Personal, "sys02-birthTime", "1990-01-01")
+            .field(RosterFieldGroup::Position, "sys01-positionLevel", "P6")
+            .field(RosterFieldGroup::Personal, "sys02-birthTime", "1991-01-01");
+        assert_eq!(
+            update.to_groups(),
+            serde_json::json!([
+                {
+                    "group_id": "sys02",
+                    "sections": [{
+                        "section": [{ "field_code": "sys02-birthTime", "value": "1991-01-01" }],
+                    }],
+                },
+                {
+                    "group_id": "sys01",
+                    "sections": [{
+                        "section": [{ "field_code": "sys01-positionLevel", "value": "P6" }],
+                    }],
+                },
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn roster_requests_join_the_user_ids_of_each_batch() {
+        let mock = Mock::new();
+        let path = "topapi/smartwork/hrm/employee/v2/list";
+        mock.respond(path, json!({ "errcode": 0, "result": [] }));
+        let app = mock.app().await;
+
+        let user_ids: Vec<String> = (0..101).map(|i| format!("user{}", i)).collect();
+        let fields = vec!["sys00-name".to_string(), "sys02-birthTime".to_string()];
+        app.get_employee_roster(1, &user_ids, &fields)
+            .await
+            .unwrap();
+
+        let bodies = mock.bodies(path);
+        assert_eq!(bodies.len(), 2);
+        assert_eq!(bodies[0]["agentid"], json!(1));
+        assert_eq!(bodies[0]["userid_list"], json!(user_ids[..100].join(",")));
+        assert_eq!(
+            bodies[0]["field_filter_list"],
+            json!("sys00-name,sys02-birthTime")
+        );
+        assert_eq!(bodies[1]["userid_list"], json!("user100"));
+    }
+
+    #[tokio::test]
+    async fn roster_updates_send_the_groups_as_param() {
+        let mock = Mock::new();
+        let app = mock.app().await;
+
+        let update =
+            RosterUpdate::new().field(RosterFieldGroup::Position, "sys01-positionLevel", "P6");
+        app.update_employee_roster(1, "zhangsan".to_string(), &update)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            mock.bodies("topapi/smartwork/hrm/employee/v2/update"),
+            vec![json!({
+                "agentid": 1,
+                "param": { "userid": "zhangsan", "groups": update.to_groups() },
+            })]
+        );
+        assert!(app
+            .update_employee_roster(1, "zhangsan".to_string(), &RosterUpdate::new())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn pre_entry_employees_send_extend_info_as_a_json_string() {
+        let mock = Mock::new();
+        let path = "topapi/smartwork/hrm/employee/addpreentry";
+        mock.respond(path, json!({ "errcode": 0, "result": "pre-entry-1" }));
+        let app = mock.app().await;
+
+        let candidate = PreEntryEmployee::new("张三", "13800000000", date(2024, 7, 1))
+            .operator("manager4220")
+            .field("sys00-dept", "研发部");
+        let user_id = app.add_pre_entry_employee(&candidate).await.unwrap();
+
+        assert_eq!(user_id, "pre-entry-1");
+        assert_eq!(
+            mock.bodies(path),
+            vec![json!({ "param": {
+                "name": "张三",
+                "mobile": "13800000000",
+                "pre_entry_time": "2024-07-01 00:00:00",
+                "op_userid": "manager4220",
+                "extend_info": r#"{"sys00-dept":"研发部"}"#,
+            } })]
+        );
+    }
+
+    #[tokio::test]
+    async fn dismissals_send_the_last_work_day_in_china_and_the_memo() {
+        let mock = Mock::new();
+        let app = mock.app().await;
+
+        app.dismiss_employee(
+            "zhangsan".to_string(),
+            date(2024, 3, 31),
+            Some("个人原因".to_string()),
+        )
+        .await
+        .unwrap();
+        app.dismiss_employee("lisi".to_string(), date(2024, 3, 31), None)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            mock.bodies("topapi/smartwork/hrm/employee/dismission"),
+            vec![
+                json!({
+                    "userid": "zhangsan",
+                    "last_work_day": 1711814400000i64,
+                    "dismission_memo": "个人原因",
+                }),
+                json!({ "userid": "lisi", "last_work_day": 1711814400000i64 }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn field_definitions_are_requested_for_the_agent() {
+        let mock = Mock::new();
+        let path = "topapi/smartwork/hrm/roster/meta/get";
+        mock.respond(
+            path,
+            json!({ "errcode": 0, "result": [{
+                "group_id": "sys05",
+                "group_name": "合同信息",
+                "detail": true,
+                "field_meta_info_list": [{ "field_code": "sys05-contractType", "user_custom": false }],
+            }] }),
+        );
+        let app = mock.app().await;
+
+        let groups = app.list_hrm_field_definitions(1).await.unwrap();
+
+        assert_eq!(mock.bodies(path), vec![json!({ "agentid": 1 })]);
+        assert_eq!(groups[0].group(), RosterFieldGroup::Contract);
+        assert!(groups[0].repeated);
+        assert_eq!(groups[0].fields[0].field_code, "sys05-contractType");
+    }
+
+    #[tokio::test]
+    async fn roster_updates_reject_fields_outside_their_group() {
+        let mock = Mock::new();
+        let app = mock.app().await;
+
+        let update = RosterUpdate::new()
+            .field(RosterFieldGroup::Personal, "sys01-positionLevel", "P6")
+            .field(
+                RosterFieldGroup::Other("custom".to_string()),
+                "a1b2-c3d4",
+                "x",
+            );
+        let err = app
+            .update_employee_roster(1, "zhangsan".to_string(), &update)
+            .await
+            .unwrap_err();
+
+        match err {
+            DingTalkError::Config(message) => assert!(message.ends_with("sys01-positionLevel")),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(mock.requests().is_empty());
+        assert_eq!(sys_group_id("sys02-birthTime"), Some("sys02"));
+        assert_eq!(sys_group_id("system-x"), None);
+    }
+}
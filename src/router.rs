@@ -0,0 +1,221 @@
+//! Axum integration of the HTTP event callbacks, enabled with the `axum` feature.
+//!
+//! [callback_router] answers a callback URL on its own:
+//!
+//! ```no_run
+//! # fn app(crypto: async_dingtalk::CallbackCrypto) -> axum::Router {
+//! use async_dingtalk::{callback_router, Event};
+//!
+//! axum::Router::new().nest(
+//!     "/dingtalk/callback",
+//!     callback_router(crypto, |event: Event| async move {
+//!         println!("{}", event.event_type());
+//!         Ok(())
+//!     }),
+//! )
+//! # }
+//! ```
+//!
+//! Handlers of other routers can extract a [CallbackRequest] instead, from any state providing
+//! an `Arc<CallbackCrypto>`.
+
+use crate::{callback::CallbackCrypto, error::DingTalkError, event::Event};
+use axum::extract::{FromRef, FromRequest, Query, Request, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{async_trait, Json, Router};
+use serde::Deserialize;
+use std::future::Future;
+use std::sync::Arc;
+use tracing::warn;
+
+#[derive(Deserialize, Debug)]
+struct CallbackQuery {
+    #[serde(alias = "msg_signature")]
+    signature: String,
+    timestamp: String,
+    nonce: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct CallbackBody {
+    encrypt: String,
+}
+
+/// A verified and decrypted callback request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallbackRequest {
+    pub event: Event,
+    /// The `timestamp` of the query, to answer with.
+    pub timestamp: String,
+    /// The `nonce` of the query, to answer with.
+    pub nonce: String,
+}
+
+impl CallbackRequest {
+    /// Returns the response acknowledging the request, the encrypted `success`.
+    pub fn success(&self, crypto: &CallbackCrypto) -> Response {
+        match crypto.success_response(&self.timestamp, &self.nonce) {
+            Ok(message) => Json(message).into_response(),
+            Err(e) => rejection(e),
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequest<S> for CallbackRequest
+where
+    Arc<CallbackCrypto>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let crypto = Arc::<CallbackCrypto>::from_ref(state);
+        let Query(query) =
+            Query::<CallbackQuery>::try_from_uri(req.uri()).map_err(IntoResponse::into_response)?;
+        let Json(body) = Json::<CallbackBody>::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        let event = crypto
+            .decrypt_event(
+                &query.signature,
+                &query.timestamp,
+                &query.nonce,
+                &body.encrypt,
+            )
+            .map_err(rejection)?;
+
+        Ok(CallbackRequest {
+            event,
+            timestamp: query.timestamp,
+            nonce: query.nonce,
+        })
+    }
+}
+
+/// Answers forged or malformed callback requests with `403` or `400`.
+fn rejection(e: DingTalkError) -> Response {
+    warn!("rejected callback request: {}", e);
+    let status = match e {
+        DingTalkError::Callback(_) => StatusCode::FORBIDDEN,
+        _ => StatusCode::BAD_REQUEST,
+    };
+    (status, e.to_string()).into_response()
+}
+
+/// Returns a router answering callback requests at `/`.
+///
+/// Requests are verified and decrypted with `crypto`, then their [Event] is passed to
+/// `handler`. The encrypted `success` is returned once the handler succeeded; on errors the
+/// router answers `500`, so DingTalk pushes the event again.
+pub fn callback_router<F, Fut>(crypto: CallbackCrypto, handler: F) -> Router
+where
+    F: Fn(Event) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<(), DingTalkError>> + Send + 'static,
+{
+    let state = CallbackState {
+        crypto: Arc::new(crypto),
+        handler: Arc::new(handler),
+    };
+    Router::new()
+        .route("/", post(handle::<F, Fut>))
+        .with_state(state)
+}
+
+struct CallbackState<F> {
+    crypto: Arc<CallbackCrypto>,
+    handler: Arc<F>,
+}
+
+impl<F> Clone for CallbackState<F> {
+    fn clone(&self) -> Self {
+        CallbackState {
+            crypto: self.crypto.clone(),
+            handler: self.handler.clone(),
+        }
+    }
+}
+
+impl<F> FromRef<CallbackState<F>> for Arc<CallbackCrypto> {
+    fn from_ref(state: &CallbackState<F>) -> Self {
+        state.crypto.clone()
+    }
+}
+
+async fn handle<F, Fut>(State(state): State<CallbackState<F>>, request: CallbackRequest) -> Response
+where
+    F: Fn(Event) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<(), DingTalkError>> + Send + 'static,
+{
+    if let Err(e) = (state.handler)(request.event.clone()).await {
+        warn!("callback handler failed: {}", e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+    }
+    request.success(&state.crypto)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{to_bytes, Body};
+    use tower::ServiceExt;
+
+    const AES_KEY: &str = "o1w0aum42yaptlz8alnhwikjd3jenzt9cb9wmzptgus";
+
+    fn crypto() -> CallbackCrypto {
+        CallbackCrypto::new("token", AES_KEY, "corp").unwrap()
+    }
+
+    fn request(uri: &str, encrypt: &str) -> Request {
+        Request::post(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(format!(r#"{{"encrypt":"{}"}}"#, encrypt)))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn events_are_acknowledged_with_an_encrypted_success() {
+        let pushed = crypto()
+            .encrypt(r#"{"EventType":"check_url"}"#, "1700000000000", "nonce")
+            .unwrap();
+        let uri = format!(
+            "/?signature={}&timestamp=1700000000000&nonce=nonce",
+            pushed.msg_signature
+        );
+
+        let router = callback_router(crypto(), |event: Event| async move {
+            assert_eq!(event, Event::CheckUrl);
+            Ok(())
+        });
+        let response = router
+            .oneshot(request(&uri, &pushed.encrypt))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let message: crate::EncryptedMessage = serde_json::from_slice(&body).unwrap();
+        let plain = crypto()
+            .decrypt(
+                &message.msg_signature,
+                &message.timestamp,
+                &message.nonce,
+                &message.encrypt,
+            )
+            .unwrap();
+        assert_eq!(plain, "success");
+    }
+
+    #[tokio::test]
+    async fn forged_requests_are_rejected() {
+        let pushed = crypto().success_response("1700000000000", "nonce").unwrap();
+        let router = callback_router(crypto(), |_: Event| async { Ok(()) });
+
+        let uri = "/?signature=forged&timestamp=1700000000000&nonce=nonce";
+        let response = router.oneshot(request(uri, &pushed.encrypt)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,556 @@
+//! Deterministic fake responses for local development and integration tests without DingTalk
+//! credentials, enabled with the `sandbox` feature.
+//!
+//! Clients configured with [Endpoints::sandbox](crate::Endpoints::sandbox) never reach the
+//! network: every endpoint of the [catalog](crate::catalog) answers with a built-in sample based
+//! on the [fixtures](crate::fixtures). With [Endpoints::sandbox_dir](crate::Endpoints::sandbox_dir),
+//! a response body can be replaced by a JSON file in the fixture directory, looked up as
+//!
+//! 1. the request path, e.g. `v1.0/contact/users/union-id.json`, then
+//! 2. the catalog path with path parameters as `_`, e.g. `v1.0/contact/users/_.json`.
+//!
+//! Files hold the whole response body, so they can also simulate errors such as
+//! `{"errcode": 60121, "errmsg": "找不到该用户"}`.
+
+use crate::catalog::{Envelope, Host, CATALOG};
+use crate::{error::DingTalkError, fixtures};
+use percent_encoding::percent_decode_str;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use url::Url;
+
+/// The URL scheme of sandbox endpoints.
+pub(crate) const SCHEME: &str = "sandbox";
+
+/// Returns the base URL of sandbox endpoints reading overrides from `dir`.
+pub(crate) fn base_url(dir: Option<&std::path::Path>) -> String {
+    let mut url = Url::parse("sandbox://local").unwrap();
+    if let Some(dir) = dir {
+        url.set_path(&dir.to_string_lossy());
+    }
+    url.to_string()
+}
+
+/// Answers a request to a sandbox endpoint.
+pub(crate) fn respond(url: &Url) -> Result<Value, DingTalkError> {
+    let path = percent_decode_str(url.path()).decode_utf8_lossy();
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+    let (spec, dir) = CATALOG
+        .iter()
+        .filter(|spec| spec.host != Host::Login)
+        .filter_map(|spec| {
+            let pattern: Vec<&str> = spec.path.split('/').collect();
+            let start = segments.len().checked_sub(pattern.len())?;
+            let matches = pattern
+                .iter()
+                .zip(&segments[start..])
+                .all(|(expected, segment)| *expected == "{}" || expected == segment);
+            if matches {
+                Some((spec, &segments[..start]))
+            } else {
+                None
+            }
+        })
+        .max_by_key(|(spec, _)| spec.path.len())
+        .ok_or_else(|| DingTalkError::Config(format!("sandbox has no response for {}", path)))?;
+
+    if !dir.is_empty() {
+        let dir: PathBuf = std::iter::once("/").chain(dir.iter().copied()).collect();
+        let endpoint = &segments[segments.len() - spec.path.split('/').count()..];
+        let candidates = [endpoint.join("/"), spec.path.replace("{}", "_")];
+        for candidate in candidates.iter() {
+            let file = dir.join(format!("{}.json", candidate));
+            if let Ok(body) = std::fs::read_to_string(&file) {
+                return serde_json::from_str(&body).map_err(|e| {
+                    DingTalkError::Config(format!("invalid sandbox file {}: {}", file.display(), e))
+                });
+            }
+        }
+    }
+
+    let payload = builtin(spec.path)
+        .ok_or_else(|| DingTalkError::Config(format!("sandbox has no response for {}", path)))?;
+    Ok(match spec.envelope {
+        Envelope::Result => json!({ "errcode": 0, "errmsg": "ok", "result": payload }),
+        Envelope::Body => {
+            let mut body = json!({ "errcode": 0, "errmsg": "ok" });
+            if let (Some(body), Value::Object(payload)) = (body.as_object_mut(), payload) {
+                body.extend(payload);
+            }
+            body
+        }
+        Envelope::Plain => payload,
+    })
+}
+
+/// Returns the built-in payload of a catalog path. Paged endpoints answer with a single page and
+/// department listings with no sub-departments, so that walks terminate.
+fn builtin(path: &str) -> Option<Value> {
+    let sample = |json: &str| -> Value { fixtures::load(json) };
+
+    let payload = match path {
+        "v1.0/oauth2/userAccessToken" => json!({
+            "accessToken": "sandbox-user-token",
+            "refreshToken": "sandbox-refresh-token",
+            "expireIn": 7200,
+            "corpId": "sandbox-corp",
+        }),
+        "v1.0/oauth2/accessToken" => json!({
+            "accessToken": "sandbox-app-token",
+            "expireIn": 7200,
+        }),
+        "v1.0/oauth2/{}/token" => json!({
+            "access_token": "sandbox-corp-token",
+            "expires_in": 7200,
+        }),
+        "v1.0/oauth2/corpAccessToken" => json!({
+            "accessToken": "sandbox-corp-token",
+            "expireIn": 7200,
+        }),
+        "service/get_suite_token" => json!({
+            "suite_access_token": "sandbox-suite-token",
+            "expires_in": 7200,
+        }),
+        "service/get_permanent_code" => json!({
+            "permanent_code": "sandbox-permanent-code",
+            "auth_corp_info": { "corpid": "sandbox-corp", "corp_name": "沙箱企业" },
+        }),
+        "service/activate_suite" => json!({}),
+        "service/get_auth_info" => json!({
+            "auth_corp_info": { "corpid": "sandbox-corp", "corp_name": "沙箱企业" },
+            "auth_user_info": { "userId": "manager4220" },
+            "auth_info": { "agent": [{ "agentid": 1, "agent_name": "沙箱应用", "appid": 1 }] },
+        }),
+        "service/get_unactive_corp" => json!({ "corp_list": [] }),
+        "gettoken" => json!({
+            "access_token": "sandbox-corp-token",
+            "expires_in": 7200,
+        }),
+        "call_back/register_call_back" => json!({}),
+        "call_back/update_call_back" => json!({}),
+        "call_back/delete_call_back" => json!({}),
+        "call_back/get_call_back_failed_result" => json!({
+            "has_more": false,
+            "failed_list": [{
+                "call_back_tag": "user_add_org",
+                "event_time": 1700000000000i64,
+                "user_add_org": { "corpid": "sandbox-corp", "userid": ["zhangsan"] },
+            }],
+        }),
+        "v1.0/gateway/connections/open" => json!({
+            "endpoint": "wss://sandbox.invalid/connect",
+            "ticket": "sandbox-ticket",
+        }),
+        "get_jsapi_ticket" => json!({
+            "ticket": "sandbox-jsapi-ticket",
+            "expires_in": 7200,
+        }),
+        "v1.0/contact/users/{}" => sample(fixtures::CONTACT_USER),
+        "v1.0/contact/organizations/authInfos" => sample(fixtures::ORGANIZATION),
+        "v1.0/hrm/employees/dismissions" => json!({
+            "nextToken": 0,
+            "hasMore": false,
+            "userIdList": ["wangwu"],
+        }),
+        "topapi/v2/user/getuserinfo" => sample(fixtures::USER_GET_BY_CODE),
+        "topapi/v2/user/get" => sample(fixtures::EMPLOYEE_USER),
+        "topapi/user/count" => json!({ "count": 2 }),
+        "topapi/smartwork/hrm/employee/queryonjob" => {
+            json!({ "data_list": ["zhangsan", "manager4220"] })
+        }
+        "topapi/v2/user/create" => json!({ "userid": "sandbox-user" }),
+        "topapi/v2/user/update" => Value::Null,
+        "topapi/v2/user/delete" => Value::Null,
+        "topapi/smartwork/hrm/employee/v2/list" => json!([{
+            "userid": "zhangsan",
+            "field_data_list": [
+                {
+                    "field_code": "sys00-name",
+                    "field_name": "姓名",
+                    "group_id": "sys00",
+                    "field_value_list": [{ "value": "张三", "label": "张三", "item_index": 0 }],
+                },
+                {
+                    "field_code": "sys05-contractType",
+                    "field_name": "合同类型",
+                    "group_id": "sys05",
+                    "field_value_list": [
+                        { "value": "0", "label": "固定期限劳动合同", "item_index": 0 },
+                    ],
+                },
+            ],
+        }]),
+        "topapi/smartwork/hrm/employee/v2/update" => Value::Null,
+        "topapi/smartwork/hrm/employee/addpreentry" => json!("sandbox-pre-entry"),
+        "topapi/smartwork/hrm/roster/meta/get" => json!([{
+            "group_id": "sys05",
+            "group_name": "合同信息",
+            "detail": true,
+            "field_meta_info_list": [{
+                "field_code": "sys05-contractType",
+                "field_name": "合同类型",
+                "field_type": "DDSelectField",
+                "user_custom": false,
+                "editable_by_hr": true,
+                "visible_by_emp": true,
+            }],
+        }]),
+        "topapi/smartwork/hrm/employee/dismission" => Value::Null,
+        "topapi/user/getunionidbyopenid" => json!({ "unionid": "union-zhangsan" }),
+        "topapi/extcontact/list" => json!([{
+            "userid": "ext001",
+            "name": "王客户",
+            "follower_user_id": "zhangsan",
+            "label_ids": [1001],
+            "state_code": "86",
+            "mobile": "13700000000",
+            "company_name": "沙箱贸易",
+        }]),
+        "topapi/extcontact/get" => json!({
+            "userid": "ext001",
+            "name": "王客户",
+            "follower_user_id": "zhangsan",
+            "label_ids": [1001],
+            "state_code": "86",
+            "mobile": "13700000000",
+            "company_name": "沙箱贸易",
+        }),
+        "topapi/extcontact/create" => json!("ext002"),
+        "topapi/extcontact/update" => Value::Null,
+        "topapi/extcontact/delete" => Value::Null,
+        "topapi/extcontact/listlabelgroups" => json!([{
+            "name": "客户级别",
+            "color": -15220075,
+            "labels": [{ "id": 1001, "name": "重点客户" }],
+        }]),
+        "topapi/role/addrolesforemps" => Value::Null,
+        "topapi/role/removerolesforemps" => Value::Null,
+        "topapi/role/scope/update" => Value::Null,
+        "topapi/user/listadmin" => json!([
+            { "userid": "manager4220", "sys_level": 1 },
+            { "userid": "lisi", "sys_level": 2 },
+        ]),
+        "topapi/user/get_admin_scope" => json!({ "dept_ids": [500123456] }),
+        "topapi/inactive/user/v2/get" => json!({ "has_more": false, "list": ["wangwu"] }),
+        "topapi/user/listsimple" => json!({
+            "has_more": false,
+            "list": [{ "userid": "zhangsan", "name": "张三" }],
+        }),
+        "topapi/v2/user/getbymobile" => json!({ "userid": "zhangsan" }),
+        "topapi/v2/user/list" => sample(fixtures::EMPLOYEE_USER_LIST),
+        "topapi/v2/department/listsubid" => json!({ "dept_id_list": [] }),
+        "topapi/v2/department/listsub" => json!([]),
+        "chat/update" => json!({}),
+        "v1.0/todo/users/{}/tasks" => json!({ "id": "sandbox-todo", "subject": "入职培训" }),
+        "topapi/v2/department/create" => json!({ "dept_id": 500123457 }),
+        "topapi/v2/department/update" => Value::Null,
+        "topapi/v2/department/delete" => Value::Null,
+        "topapi/v2/department/listparentbydept" => json!({ "parent_id_list": [500123456, 1] }),
+        "topapi/v2/department/listparentbyuser" => json!({
+            "parent_list": [{ "parent_dept_id_list": [500123456, 1] }, { "parent_dept_id_list": [1] }],
+        }),
+        "topapi/v2/department/get" => sample(fixtures::DEPT_DETAIL),
+        "topapi/user/getbyunionid" => json!({ "contact_type": 0, "userid": "zhangsan" }),
+        "topapi/user/listid" => json!({ "userid_list": ["zhangsan", "manager4220"] }),
+        "topapi/role/simplelist" => json!({
+            "hasMore": false,
+            "list": [{ "userid": "manager4220", "name": "李四" }],
+        }),
+        "topapi/role/list" => json!({
+            "hasMore": false,
+            "list": [{
+                "groupId": 1,
+                "name": "默认",
+                "roles": [{ "id": 1, "name": "主管理员" }, { "id": 2, "name": "负责人" }],
+            }],
+        }),
+        "topapi/message/corpconversation/asyncsend_v2" => json!({ "task_id": 1 }),
+        "attendance/list" => sample(fixtures::ATTENDANCE_PAGE),
+        "topapi/attendance/getattcolumns" => json!({
+            "columns": [sample(fixtures::ATTENDANCE_COLUMN)],
+        }),
+        "topapi/attendance/getcolumnval" => {
+            let values = sample(fixtures::COLUMN_VALUES);
+            json!({
+                "column_vals": [{
+                    "column_vo": { "id": values["column_id"] },
+                    "column_vals": values["values"],
+                }],
+            })
+        }
+        "topapi/attendance/getleavetimebynames" => {
+            let leave = sample(fixtures::LEAVE_TIMES);
+            json!({
+                "columns": [{
+                    "columnvo": { "name": leave["name"] },
+                    "columnvals": leave["values"],
+                }],
+            })
+        }
+        "robot/send" => json!({}),
+        _ => return None,
+    };
+
+    Some(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        DingTalk, Dismissal, Endpoints, ExportSinks, ExternalContact, MemoryStore, OffboardAction,
+        OffboardOptions, OrgApp, PreEntryEmployee, RosterFieldGroup, RosterUpdate, TokenFlow,
+    };
+    use futures::TryStreamExt;
+    use std::sync::Arc;
+
+    #[test]
+    fn every_endpoint_has_a_response() {
+        for spec in CATALOG.iter().filter(|spec| spec.host != Host::Login) {
+            assert!(builtin(spec.path).is_some(), "{}", spec.path);
+        }
+    }
+
+    #[tokio::test]
+    async fn org_apps_run_against_the_sandbox() {
+        let app = OrgApp::new(
+            "app".to_string(),
+            "secret".to_string(),
+            "corp".to_string(),
+            Arc::new(MemoryStore::new()),
+        )
+        .with_endpoints(Endpoints::sandbox());
+
+        let user = app
+            .get_employee_userinfo("zhangsan".to_string())
+            .await
+            .unwrap();
+        assert_eq!(user.user_id, "zhangsan");
+        assert_eq!(app.get_employee_count(Some(true)).await.unwrap(), 2);
+        assert_eq!(app.usage().endpoints["topapi/user/count"], 1);
+        assert_eq!(
+            app.get_userinfo("code".to_string()).await.unwrap().username,
+            "张三"
+        );
+
+        let contents = app.check_department_deletion(1).await.unwrap();
+        assert!(contents.sub_dept_ids.is_empty());
+        assert!(!contents.is_empty());
+        app.delete_department(2).await.unwrap();
+        let page = app.list_department_users_simple(1, 0, 100).await.unwrap();
+        assert_eq!(page.data[0].name, "张三");
+        assert_eq!(page.next_offset(), None);
+        let page = app.list_department_users(1, 0, 100).await.unwrap();
+        assert_eq!(page.data[0].user_id, "zhangsan");
+        assert_eq!(
+            app.get_parent_departments_by_user("zhangsan".to_string())
+                .await
+                .unwrap(),
+            vec![vec![500123456, 1], vec![1]]
+        );
+        let dismissal = Dismissal {
+            last_work_day: chrono::NaiveDate::from_ymd_opt(2024, 3, 31).unwrap(),
+            reason: None,
+        };
+        app.delete_user_with_dismissal("wangwu".to_string(), &dismissal)
+            .await
+            .unwrap();
+        assert_eq!(app.usage().endpoints["topapi/v2/user/delete"], 1);
+        let user = app
+            .get_userid_by_unionid("union-id".to_string())
+            .await
+            .unwrap();
+        assert_eq!(user.account_kind(), crate::AccountKind::Internal);
+        let admins = app.list_admins().await.unwrap();
+        assert!(admins[0].is_primary());
+        assert_eq!(
+            app.get_admin_scope(admins[1].user_id.clone())
+                .await
+                .unwrap(),
+            vec![500123456]
+        );
+        let day = chrono::NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        let inactive = app
+            .list_inactive_users(day, &[], false, 0, 100)
+            .await
+            .unwrap();
+        assert_eq!(inactive.data, vec!["wangwu"]);
+        let resolved = app
+            .resolve_job_numbers(&["4".to_string(), "404".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved["4"], "zhangsan");
+        let user_ids: Vec<String> = (0..45).map(|i| format!("user{}", i)).collect();
+        app.add_roles_to_users(&[1, 2], &user_ids).await.unwrap();
+        assert_eq!(app.usage().endpoints["topapi/role/addrolesforemps"], 3);
+        app.set_role_member_manage_scope("user1".to_string(), 1, &[2, 3])
+            .await
+            .unwrap();
+        let contacts = app.list_external_contacts(0, 100).await.unwrap();
+        assert_eq!(contacts[0].follower_user_id, "zhangsan");
+        let contact = ExternalContact {
+            user_id: "ignored".to_string(),
+            ..contacts[0].clone()
+        };
+        assert_eq!(
+            app.create_external_contact(&contact).await.unwrap(),
+            "ext002"
+        );
+        app.update_external_contact(&contacts[0]).await.unwrap();
+        assert!(app
+            .update_external_contact(&ExternalContact::default())
+            .await
+            .is_err());
+        let groups = app
+            .list_external_contact_label_groups(0, 100)
+            .await
+            .unwrap();
+        assert_eq!(groups[0].labels[0].id, contacts[0].label_ids[0]);
+        let union_ids = app
+            .convert_open_ids(&["open1".to_string(), "open2".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(union_ids["open2"], "union-zhangsan");
+        let rosters = app
+            .get_employee_roster(1, &["zhangsan".to_string()], &[])
+            .await
+            .unwrap();
+        assert_eq!(
+            rosters[0].field("sys00-name").unwrap().value(),
+            Some("张三")
+        );
+        assert_eq!(rosters[0].fields_of(&RosterFieldGroup::Contract).count(), 1);
+        let update = RosterUpdate::new().field(RosterFieldGroup::Basic, "sys00-name", "张三");
+        app.update_employee_roster(1, "zhangsan".to_string(), &update)
+            .await
+            .unwrap();
+        let joins_on = chrono::NaiveDate::from_ymd_opt(2024, 7, 1).unwrap();
+        let candidate = PreEntryEmployee::new("李四", "13900000000", joins_on);
+        assert_eq!(
+            app.add_pre_entry_employee(&candidate).await.unwrap(),
+            "sandbox-pre-entry"
+        );
+        let groups = app.list_hrm_field_definitions(1).await.unwrap();
+        assert_eq!(groups[0].group(), RosterFieldGroup::Contract);
+        assert!(groups[0].repeated);
+        let tree: Vec<_> = app.walk_department_tree().try_collect().await.unwrap();
+        assert!(tree.is_empty());
+
+        let internal = app.with_token_flow(TokenFlow::Internal);
+        internal.store.delete(&internal.token_key()).await.unwrap();
+        assert_eq!(
+            internal.get_access_token().await.unwrap(),
+            "sandbox-corp-token"
+        );
+    }
+
+    #[tokio::test]
+    async fn app_level_calls_are_counted() {
+        let dt = DingTalk::builder()
+            .credentials("app", "secret")
+            .store(Arc::new(MemoryStore::new()))
+            .endpoints(Endpoints::sandbox())
+            .build()
+            .unwrap();
+
+        dt.set_app_access_token("code".to_string()).await.unwrap();
+        dt.get_contact_userinfo("me".to_string()).await.unwrap();
+
+        let usage = dt.usage();
+        assert_eq!(usage.app.total, 2);
+        assert_eq!(usage.app.endpoints["v1.0/oauth2/userAccessToken"], 1);
+        assert_eq!(usage.app.endpoints["v1.0/contact/users/{}"], 1);
+        assert!(usage.corps.is_empty());
+    }
+
+    #[tokio::test]
+    async fn exports_run_against_the_sandbox() {
+        let app = OrgApp::new(
+            "app".to_string(),
+            "secret".to_string(),
+            "corp".to_string(),
+            Arc::new(MemoryStore::new()),
+        )
+        .with_endpoints(Endpoints::sandbox());
+        let (users, mut users_rx) = tokio::sync::mpsc::channel(16);
+        let (roles, _roles_rx) = tokio::sync::mpsc::channel(16);
+
+        let summary = app
+            .exporter()
+            .with_checkpoint("sandbox")
+            .run(ExportSinks {
+                users: Some(users),
+                roles: Some(roles),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(summary.departments, 0);
+        assert_eq!(summary.users, 2);
+        assert_eq!(summary.roles, 2);
+        assert_eq!(summary.skipped_users, 0);
+        assert_eq!(users_rx.recv().await.unwrap()["userid"], "zhangsan");
+    }
+
+    #[tokio::test]
+    async fn offboarding_plans_before_acting() {
+        let app = OrgApp::new(
+            "app".to_string(),
+            "secret".to_string(),
+            "corp".to_string(),
+            Arc::new(MemoryStore::new()),
+        )
+        .with_endpoints(Endpoints::sandbox());
+
+        let options = OffboardOptions {
+            remove_roles: true,
+            new_manager: Some("lisi".to_string()),
+            dry_run: true,
+            ..Default::default()
+        };
+        let user_ids = vec!["manager4220".to_string()];
+        let actions = app.offboard_users(&user_ids, &options).await.unwrap();
+        assert_eq!(
+            actions,
+            vec![
+                OffboardAction::RemoveRoles {
+                    user_id: "manager4220".to_string(),
+                    role_ids: vec![1],
+                },
+                OffboardAction::ReassignManager {
+                    user_id: "zhangsan".to_string(),
+                    manager_user_id: "lisi".to_string(),
+                },
+                OffboardAction::DeleteUser {
+                    user_id: "manager4220".to_string(),
+                },
+            ]
+        );
+        assert!(!app.usage().endpoints.contains_key("topapi/v2/user/delete"));
+
+        let options = OffboardOptions {
+            dry_run: false,
+            ..options
+        };
+        app.offboard_users(&user_ids, &options).await.unwrap();
+        assert_eq!(app.usage().endpoints["topapi/v2/user/delete"], 1);
+    }
+
+    #[test]
+    fn files_in_the_fixture_directory_override_responses() {
+        let dir = std::env::temp_dir().join("async-dingtalk-sandbox");
+        std::fs::create_dir_all(dir.join("v1.0/contact/users")).unwrap();
+        std::fs::write(
+            dir.join("v1.0/contact/users/_.json"),
+            r#"{"errcode": 60121, "errmsg": "找不到该用户"}"#,
+        )
+        .unwrap();
+
+        let url = Endpoints::sandbox_dir(&dir).api("v1.0/contact/users/union-id");
+        let body = respond(&Url::parse(&url).unwrap()).unwrap();
+        assert_eq!(body["errcode"], 60121);
+    }
+}
@@ -0,0 +1,219 @@
+use crate::core::{execute_and_parse, Transport};
+use crate::{error::DingTalkError, organization::OrgApp, DingTalk};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tower_service::Service;
+
+/// A request to DingTalk, passed through a [DingTalkService].
+#[derive(Debug)]
+pub struct DingTalkRequest {
+    pub request: reqwest::Request,
+    /// Describes the call in errors and logs.
+    pub context: String,
+}
+
+impl DingTalkRequest {
+    pub fn new(request: reqwest::Request, context: impl Into<String>) -> Self {
+        DingTalkRequest {
+            request,
+            context: context.into(),
+        }
+    }
+}
+
+/// The HTTP layer of the crate as a [tower_service::Service], for wrapping DingTalk calls into
+/// standard tower middleware such as timeouts, load shedding or buffering.
+///
+/// Responses are checked like those of the API methods: unsuccessful HTTP statuses and non-zero
+/// `errcode`s become errors, otherwise the service yields the JSON body. A service wrapping it
+/// can be installed with [DingTalk::with_service] or [OrgApp::with_service], so that every API
+/// method sends its requests through the middleware.
+#[derive(Debug, Clone)]
+pub struct DingTalkService {
+    client: reqwest::Client,
+}
+
+impl DingTalkService {
+    pub fn new(client: reqwest::Client) -> Self {
+        DingTalkService { client }
+    }
+}
+
+impl Service<DingTalkRequest> for DingTalkService {
+    type Response = serde_json::Value;
+    type Error = DingTalkError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: DingTalkRequest) -> Self::Future {
+        let client = self.client.clone();
+        Box::pin(async move { execute_and_parse(&client, req.request, &req.context).await })
+    }
+}
+
+/// Turns a service into the transport of a `DingTalk` or `OrgApp`, cloning it for every request.
+fn transport<S>(service: S) -> Transport
+where
+    S: Service<DingTalkRequest, Response = serde_json::Value, Error = DingTalkError>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    let service = Mutex::new(service);
+    Arc::new(move |request, context| {
+        let mut service = service.lock().unwrap().clone();
+        Box::pin(async move {
+            futures::future::poll_fn(|cx| service.poll_ready(cx)).await?;
+            service.call(DingTalkRequest::new(request, context)).await
+        })
+    })
+}
+
+impl DingTalk {
+    /// Returns the HTTP layer of this instance as a tower service.
+    pub fn service(&self) -> DingTalkService {
+        DingTalkService::new(self.client.clone())
+    }
+
+    /// Sends the requests of this instance and of the `OrgApp`s created from it through the given
+    /// service, e.g. [DingTalk::service] wrapped into tower middleware:
+    ///
+    /// ```no_run
+    /// # fn layer<S>(service: S) -> S { service }
+    /// use async_dingtalk::DingTalk;
+    ///
+    /// let dt = DingTalk::new("appid".to_string(), "app_secret".to_string());
+    /// let service = layer(dt.service());
+    /// let dt = dt.with_service(service);
+    /// ```
+    ///
+    /// The usage of the requests is counted before they enter the service.
+    pub fn with_service<S>(mut self, service: S) -> Self
+    where
+        S: Service<DingTalkRequest, Response = serde_json::Value, Error = DingTalkError>
+            + Clone
+            + Send
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        self.transport = Some(transport(service));
+        self
+    }
+}
+
+impl OrgApp {
+    /// Returns the HTTP layer of this organization as a tower service.
+    pub fn service(&self) -> DingTalkService {
+        DingTalkService::new(self.client.clone())
+    }
+
+    /// Sends the requests of this organization through the given service, see
+    /// [DingTalk::with_service].
+    pub fn with_service<S>(mut self, service: S) -> Self
+    where
+        S: Service<DingTalkRequest, Response = serde_json::Value, Error = DingTalkError>
+            + Clone
+            + Send
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        self.transport = Some(transport(service));
+        self
+    }
+
+    /// Builds a request to an `oapi.dingtalk.com` endpoint, authenticated with the organization
+    /// access token, for sending it through [OrgApp::service].
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the endpoint, e.g. `topapi/v2/user/get`.
+    /// * `params` - The JSON body of the request.
+    pub async fn oapi_request(
+        &self,
+        path: &str,
+        params: &impl serde::Serialize,
+    ) -> Result<DingTalkRequest, DingTalkError> {
+        let at = self.get_access_token().await?;
+        let request = self
+            .client
+            .post(format!("{}?access_token={}", self.endpoints.oapi(path), at))
+            .json(params)
+            .build()?;
+
+        Ok(DingTalkRequest::new(request, path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryStore;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn layers_see_the_requests_of_api_methods() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorded = seen.clone();
+        let service = tower::service_fn(|_: DingTalkRequest| async {
+            Ok::<_, DingTalkError>(serde_json::json!({ "errcode": 0, "result": { "count": 2 } }))
+        })
+        .map_request(move |req: DingTalkRequest| {
+            let path = req.request.url().path().to_string();
+            recorded.lock().unwrap().push((path, req.context.clone()));
+            req
+        });
+
+        let store = Arc::new(MemoryStore::new());
+        let app = OrgApp::new(
+            "app".to_string(),
+            "secret".to_string(),
+            "corp".to_string(),
+            store,
+        )
+        .with_service(service);
+        app.store
+            .set(&app.token_key(), "token", None)
+            .await
+            .unwrap();
+
+        assert_eq!(app.get_employee_count(Some(true)).await.unwrap(), 2);
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![(
+                "/topapi/user/count".to_string(),
+                "Failed to response topapi/user/count".to_string()
+            )]
+        );
+        assert_eq!(app.usage().endpoints["topapi/user/count"], 1);
+    }
+
+    #[tokio::test]
+    async fn errcodes_of_the_service_are_errors() {
+        let service = tower::service_fn(|_: DingTalkRequest| async {
+            Ok::<_, DingTalkError>(
+                serde_json::json!({ "errcode": 60011, "errmsg": "no permission" }),
+            )
+        });
+        let store = Arc::new(MemoryStore::new());
+        let app = OrgApp::new(
+            "app".to_string(),
+            "secret".to_string(),
+            "corp".to_string(),
+            store,
+        )
+        .with_service(service);
+        app.store
+            .set(&app.token_key(), "token", None)
+            .await
+            .unwrap();
+
+        let error = app.get_employee_count(None).await.unwrap_err();
+        assert_eq!(error.kind(), Some(crate::ApiErrorKind::PermissionDenied));
+    }
+}
@@ -0,0 +1,300 @@
+use crate::{department::DeptInfo, organization::EmployeeUser, organization::Mobile};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// A point-in-time copy of an organization's users and departments.
+///
+/// Snapshots are meant to be persisted between sync runs and compared with [diff_snapshots] by
+/// tenants that cannot subscribe to org change callbacks.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct OrgSnapshot {
+    pub users: Vec<EmployeeUser>,
+    pub departments: Vec<DeptInfo>,
+}
+
+impl OrgSnapshot {
+    /// Replaces the [hidden](Mobile::Hidden) mobile numbers of users with the numbers of the
+    /// `previous` snapshot, so that persisting this snapshot keeps them.
+    pub fn keep_known_mobiles(&mut self, previous: &OrgSnapshot) {
+        let known: HashMap<&str, &str> = previous
+            .users
+            .iter()
+            .filter_map(|user| Some((user.user_id.as_str(), user.mobile.as_visible()?)))
+            .collect();
+
+        for user in self.users.iter_mut().filter(|user| user.mobile.is_hidden()) {
+            if let Some(mobile) = known.get(user.user_id.as_str()) {
+                user.mobile = Mobile::Visible(mobile.to_string());
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Changed<T> {
+    pub old: T,
+    pub new: T,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Changes<T> {
+    pub added: Vec<T>,
+    pub removed: Vec<T>,
+    pub changed: Vec<Changed<T>>,
+}
+
+impl<T> Changes<T> {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// A user whose mobile number changed from one visible number to another.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MobileChange {
+    pub user_id: String,
+    pub old: String,
+    pub new: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SnapshotDiff {
+    pub users: Changes<EmployeeUser>,
+    pub departments: Changes<DeptInfo>,
+    /// The changed mobile numbers, also part of the changed `users`.
+    pub mobiles: Vec<MobileChange>,
+}
+
+impl SnapshotDiff {
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty() && self.departments.is_empty()
+    }
+}
+
+/// Compares two organization snapshots.
+///
+/// Users are matched by `user_id` and departments by `dept_id`. Entries only present in `new` are
+/// added, entries only present in `old` are removed, and entries present in both but no longer
+/// equal are reported as changed with both versions.
+///
+/// Mobile numbers hidden in `new` are taken from `old` first, see
+/// [OrgSnapshot::keep_known_mobiles], so hiding a number is not reported as a change.
+///
+/// # Arguments
+///
+/// * `old` - The previously stored snapshot.
+/// * `new` - The freshly fetched snapshot.
+///
+/// # Returns
+///
+/// A `SnapshotDiff` with the added, removed and changed users and departments, in the order they
+/// appear in the snapshots.
+pub fn diff_snapshots(old: OrgSnapshot, mut new: OrgSnapshot) -> SnapshotDiff {
+    new.keep_known_mobiles(&old);
+
+    let previous: HashMap<&str, &str> = old
+        .users
+        .iter()
+        .filter_map(|user| Some((user.user_id.as_str(), user.mobile.as_visible()?)))
+        .collect();
+    let mobiles = new
+        .users
+        .iter()
+        .filter_map(|user| {
+            let old = *previous.get(user.user_id.as_str())?;
+            let new = user.mobile.as_visible()?;
+            if old == new {
+                return None;
+            }
+            Some(MobileChange {
+                user_id: user.user_id.clone(),
+                old: old.to_string(),
+                new: new.to_string(),
+            })
+        })
+        .collect();
+
+    SnapshotDiff {
+        mobiles,
+        users: diff_by(old.users, new.users, |user| user.user_id.clone()),
+        departments: diff_by(old.departments, new.departments, |dept| dept.dept_id),
+    }
+}
+
+fn diff_by<T, K, F>(old: Vec<T>, new: Vec<T>, key: F) -> Changes<T>
+where
+    T: PartialEq,
+    K: Eq + Hash,
+    F: Fn(&T) -> K,
+{
+    let mut previous: HashMap<K, (usize, T)> = old
+        .into_iter()
+        .enumerate()
+        .map(|(index, item)| (key(&item), (index, item)))
+        .collect();
+
+    let mut added = Vec::new();
+    let mut changed = Vec::new();
+    for item in new {
+        match previous.remove(&key(&item)) {
+            None => added.push(item),
+            Some((_, old)) if old != item => changed.push(Changed { old, new: item }),
+            Some(_) => {}
+        }
+    }
+
+    let mut removed: Vec<(usize, T)> = previous.into_values().collect();
+    removed.sort_by_key(|(index, _)| *index);
+
+    Changes {
+        added,
+        removed: removed.into_iter().map(|(_, item)| item).collect(),
+        changed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    #[test]
+    fn hidden_mobiles_are_not_reported_as_changes() {
+        let user = fixtures::employee_user();
+        let old = OrgSnapshot {
+            users: vec![user.clone()],
+            departments: Vec::new(),
+        };
+
+        let mut hidden = user.clone();
+        hidden.mobile = Mobile::from("138****0000".to_string());
+        hidden.hide_mobile = true;
+        let diff = diff_snapshots(
+            old.clone(),
+            OrgSnapshot {
+                users: vec![hidden],
+                departments: Vec::new(),
+            },
+        );
+        assert_eq!(diff.users.changed.len(), 1);
+        assert_eq!(diff.users.changed[0].new.mobile, user.mobile);
+        assert!(diff.mobiles.is_empty());
+
+        let mut moved = user.clone();
+        moved.mobile = Mobile::Visible("13900000000".to_string());
+        let diff = diff_snapshots(
+            old,
+            OrgSnapshot {
+                users: vec![moved],
+                departments: Vec::new(),
+            },
+        );
+        assert_eq!(
+            diff.mobiles,
+            vec![MobileChange {
+                user_id: user.user_id,
+                old: "13800000000".to_string(),
+                new: "13900000000".to_string(),
+            }]
+        );
+    }
+
+    fn user(user_id: &str) -> EmployeeUser {
+        let mut user = fixtures::employee_user();
+        user.user_id = user_id.to_string();
+        user
+    }
+
+    fn dept(dept_id: i64, name: &str) -> DeptInfo {
+        let mut dept = fixtures::dept_info();
+        dept.dept_id = dept_id;
+        dept.name = name.to_string();
+        dept
+    }
+
+    fn snapshot(users: &[EmployeeUser], departments: &[DeptInfo]) -> OrgSnapshot {
+        OrgSnapshot {
+            users: users.to_vec(),
+            departments: departments.to_vec(),
+        }
+    }
+
+    #[test]
+    fn identical_snapshots_have_no_changes() {
+        let old = snapshot(&[user("zhangsan")], &[dept(2, "研发部")]);
+
+        assert!(diff_snapshots(old.clone(), old).is_empty());
+    }
+
+    #[test]
+    fn new_entries_are_added() {
+        let old = snapshot(&[user("zhangsan")], &[dept(2, "研发部")]);
+        let new = snapshot(
+            &[user("lisi"), user("zhangsan"), user("wangwu")],
+            &[dept(2, "研发部"), dept(3, "市场部")],
+        );
+
+        let diff = diff_snapshots(old, new);
+
+        assert_eq!(diff.users.added, vec![user("lisi"), user("wangwu")]);
+        assert!(diff.users.removed.is_empty() && diff.users.changed.is_empty());
+        assert_eq!(diff.departments.added, vec![dept(3, "市场部")]);
+        assert!(diff.departments.removed.is_empty() && diff.departments.changed.is_empty());
+    }
+
+    #[test]
+    fn missing_entries_are_removed_in_their_old_order() {
+        let old = snapshot(
+            &[user("zhangsan"), user("lisi"), user("wangwu")],
+            &[dept(2, "研发部"), dept(3, "市场部"), dept(4, "行政部")],
+        );
+        let new = snapshot(&[user("lisi")], &[dept(3, "市场部")]);
+
+        let diff = diff_snapshots(old, new);
+
+        assert_eq!(diff.users.removed, vec![user("zhangsan"), user("wangwu")]);
+        assert!(diff.users.added.is_empty() && diff.users.changed.is_empty());
+        assert_eq!(
+            diff.departments.removed,
+            vec![dept(2, "研发部"), dept(4, "行政部")]
+        );
+        assert!(diff.departments.added.is_empty() && diff.departments.changed.is_empty());
+    }
+
+    #[test]
+    fn differing_entries_are_changed() {
+        let old = snapshot(
+            &[user("zhangsan"), user("lisi")],
+            &[dept(2, "研发部"), dept(3, "市场部")],
+        );
+        let mut promoted = user("zhangsan");
+        promoted.title = "架构师".to_string();
+        let mut moved = dept(3, "市场部");
+        moved.parent_id = 2;
+        let new = snapshot(
+            &[promoted.clone(), user("lisi")],
+            &[dept(2, "研发部"), moved.clone()],
+        );
+
+        let diff = diff_snapshots(old, new);
+
+        assert_eq!(
+            diff.users.changed,
+            vec![Changed {
+                old: user("zhangsan"),
+                new: promoted,
+            }]
+        );
+        assert!(diff.users.added.is_empty() && diff.users.removed.is_empty());
+        assert_eq!(
+            diff.departments.changed,
+            vec![Changed {
+                old: dept(3, "市场部"),
+                new: moved,
+            }]
+        );
+        assert!(diff.departments.added.is_empty() && diff.departments.removed.is_empty());
+        assert!(diff.mobiles.is_empty());
+    }
+}
@@ -0,0 +1,166 @@
+use async_trait::async_trait;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+#[cfg(feature = "redis-cache")]
+mod redis;
+
+#[cfg(feature = "redis-cache")]
+pub use self::redis::RedisStore;
+
+pub type StoreError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A cache for access tokens and other short-lived values.
+///
+/// `RedisStore` (feature `redis-cache`) shares tokens between processes; [MemoryStore] keeps them
+/// in the current process and needs no external service.
+#[async_trait]
+pub trait TokenStore: Send + Sync {
+    /// Returns the value of `key`, or `None` if it is missing or expired.
+    async fn get(&self, key: &str) -> Result<Option<String>, StoreError>;
+
+    /// Stores `value` under `key`, expiring after `ttl` if given.
+    async fn set(&self, key: &str, value: &str, ttl: Option<Duration>) -> Result<(), StoreError>;
+
+    /// Stores `value` under `key` unless the key holds a value already, atomically, so that
+    /// concurrent callers can claim the key.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the value was stored, `false` if the key was taken.
+    async fn set_if_absent(
+        &self,
+        key: &str,
+        value: &str,
+        ttl: Option<Duration>,
+    ) -> Result<bool, StoreError>;
+
+    /// Removes `key`.
+    async fn delete(&self, key: &str) -> Result<(), StoreError>;
+
+    /// Returns the remaining time to live of `key`, or `None` if it has no expiry or is missing.
+    ///
+    /// Stores which cannot tell return `None`.
+    async fn ttl(&self, _key: &str) -> Result<Option<Duration>, StoreError> {
+        Ok(None)
+    }
+}
+
+/// Reads a cached token, treating a failing store as a miss so that the token is fetched again.
+pub(crate) async fn get_cached(store: &dyn TokenStore, key: &str) -> Option<String> {
+    match store.get(key).await {
+        Ok(value) => value,
+        Err(e) => {
+            warn!(
+                key,
+                "failed to read the token store, fetching a new token: {}", e
+            );
+            None
+        }
+    }
+}
+
+/// A [TokenStore] keeping values in the memory of the current process.
+#[derive(Default)]
+pub struct MemoryStore {
+    entries: RwLock<HashMap<String, (String, Option<Instant>)>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+}
+
+#[async_trait]
+impl TokenStore for MemoryStore {
+    async fn get(&self, key: &str) -> Result<Option<String>, StoreError> {
+        let entries = self.entries.read().unwrap();
+        let value = entries
+            .get(key)
+            .filter(|(_, expires_at)| expires_at.map_or(true, |at| at > Instant::now()))
+            .map(|(value, _)| value.clone());
+
+        Ok(value)
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Option<Duration>) -> Result<(), StoreError> {
+        let mut entries = self.entries.write().unwrap();
+        let now = Instant::now();
+        entries.retain(|_, (_, expires_at)| expires_at.map_or(true, |at| at > now));
+        entries.insert(
+            key.to_string(),
+            (value.to_string(), ttl.map(|ttl| now + ttl)),
+        );
+
+        Ok(())
+    }
+
+    async fn set_if_absent(
+        &self,
+        key: &str,
+        value: &str,
+        ttl: Option<Duration>,
+    ) -> Result<bool, StoreError> {
+        let mut entries = self.entries.write().unwrap();
+        let now = Instant::now();
+        entries.retain(|_, (_, expires_at)| expires_at.map_or(true, |at| at > now));
+        match entries.entry(key.to_string()) {
+            Entry::Occupied(_) => Ok(false),
+            Entry::Vacant(entry) => {
+                entry.insert((value.to_string(), ttl.map(|ttl| now + ttl)));
+                Ok(true)
+            }
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StoreError> {
+        self.entries.write().unwrap().remove(key);
+
+        Ok(())
+    }
+
+    async fn ttl(&self, key: &str) -> Result<Option<Duration>, StoreError> {
+        let entries = self.entries.read().unwrap();
+        let ttl = entries
+            .get(key)
+            .and_then(|(_, expires_at)| *expires_at)
+            .and_then(|at| at.checked_duration_since(Instant::now()));
+
+        Ok(ttl)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::FailingStore;
+
+    #[tokio::test]
+    async fn failing_reads_are_misses() {
+        assert_eq!(get_cached(&FailingStore, "token").await, None);
+
+        let store = MemoryStore::new();
+        store.set("token", "abc", None).await.unwrap();
+        assert_eq!(get_cached(&store, "token").await.as_deref(), Some("abc"));
+    }
+
+    #[tokio::test]
+    async fn only_absent_keys_are_set() {
+        let store = MemoryStore::new();
+
+        assert!(store.set_if_absent("claim", "a", None).await.unwrap());
+        assert!(!store.set_if_absent("claim", "b", None).await.unwrap());
+        assert_eq!(store.get("claim").await.unwrap().as_deref(), Some("a"));
+
+        store
+            .set("expired", "a", Some(Duration::from_millis(1)))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert!(store.set_if_absent("expired", "b", None).await.unwrap());
+    }
+}
@@ -0,0 +1,102 @@
+use super::{StoreError, TokenStore};
+use async_trait::async_trait;
+use deadpool_redis::redis::{cmd, Cmd, FromRedisValue};
+use deadpool_redis::Pool;
+use std::time::Duration;
+
+/// A [TokenStore] backed by a Redis connection pool.
+///
+/// Besides a single server, Redis Cluster (feature `redis-cluster`) and Sentinel (feature
+/// `redis-sentinel`) setups are supported. `rediss://` URLs need the feature `redis-tls`.
+pub struct RedisStore {
+    pool: RedisPool,
+}
+
+enum RedisPool {
+    Single(Pool),
+    #[cfg(feature = "redis-cluster")]
+    Cluster(deadpool_redis::cluster::Pool),
+    #[cfg(feature = "redis-sentinel")]
+    Sentinel(deadpool_redis::sentinel::Pool),
+}
+
+impl RedisStore {
+    pub fn new(pool: Pool) -> Self {
+        RedisStore {
+            pool: RedisPool::Single(pool),
+        }
+    }
+
+    /// Creates a store using a Redis Cluster.
+    #[cfg(feature = "redis-cluster")]
+    pub fn cluster(pool: deadpool_redis::cluster::Pool) -> Self {
+        RedisStore {
+            pool: RedisPool::Cluster(pool),
+        }
+    }
+
+    /// Creates a store using the master found by Redis Sentinel.
+    #[cfg(feature = "redis-sentinel")]
+    pub fn sentinel(pool: deadpool_redis::sentinel::Pool) -> Self {
+        RedisStore {
+            pool: RedisPool::Sentinel(pool),
+        }
+    }
+
+    async fn query<T: FromRedisValue>(&self, cmd: &Cmd) -> Result<T, StoreError> {
+        let value = match &self.pool {
+            RedisPool::Single(pool) => cmd.query_async(&mut pool.get().await?).await?,
+            #[cfg(feature = "redis-cluster")]
+            RedisPool::Cluster(pool) => cmd.query_async(&mut pool.get().await?).await?,
+            #[cfg(feature = "redis-sentinel")]
+            RedisPool::Sentinel(pool) => cmd.query_async(&mut pool.get().await?).await?,
+        };
+
+        Ok(value)
+    }
+}
+
+#[async_trait]
+impl TokenStore for RedisStore {
+    async fn get(&self, key: &str) -> Result<Option<String>, StoreError> {
+        self.query(cmd("GET").arg(key)).await
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Option<Duration>) -> Result<(), StoreError> {
+        match ttl {
+            Some(ttl) => {
+                self.query(cmd("SETEX").arg(key).arg(ttl.as_secs().max(1)).arg(value))
+                    .await
+            }
+            None => self.query(cmd("SET").arg(key).arg(value)).await,
+        }
+    }
+
+    async fn set_if_absent(
+        &self,
+        key: &str,
+        value: &str,
+        ttl: Option<Duration>,
+    ) -> Result<bool, StoreError> {
+        let mut cmd = cmd("SET");
+        cmd.arg(key).arg(value).arg("NX");
+        if let Some(ttl) = ttl {
+            cmd.arg("PX").arg(ttl.as_millis().max(1) as u64);
+        }
+        // SET NX answers OK when the value was stored and nil otherwise.
+        let stored: Option<String> = self.query(&cmd).await?;
+
+        Ok(stored.is_some())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StoreError> {
+        self.query(cmd("DEL").arg(key)).await
+    }
+
+    async fn ttl(&self, key: &str) -> Result<Option<Duration>, StoreError> {
+        // PTTL answers -1 for keys without expiry and -2 for missing keys.
+        let millis: i64 = self.query(cmd("PTTL").arg(key)).await?;
+
+        Ok(u64::try_from(millis).ok().map(Duration::from_millis))
+    }
+}
@@ -0,0 +1,420 @@
+//! A client of the Stream Mode of DingTalk, enabled with the `stream` feature.
+//!
+//! In Stream Mode, DingTalk delivers events and robot messages over a WebSocket opened by the
+//! app instead of pushing them to a public callback URL:
+//!
+//! ```no_run
+//! # async fn run(dt: async_dingtalk::DingTalk) -> Result<(), async_dingtalk::DingTalkError> {
+//! dt.stream_client()
+//!     .on_event(|event| async move {
+//!         println!("{}", event.event_type());
+//!         Ok(())
+//!     })
+//!     .on_bot_message(|message| async move {
+//!         println!("{:?}", message.text);
+//!         Ok(())
+//!     })
+//!     .run()
+//!     .await
+//! # }
+//! ```
+
+use crate::core::send_and_parse;
+use crate::{config::Endpoints, error::DingTalkError, event::Event, DingTalk};
+use futures::future::BoxFuture;
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::{interval, sleep, Instant};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+use tracing::{debug, info, warn};
+
+/// The topic of robot messages.
+const BOT_MESSAGE_TOPIC: &str = "/v1.0/im/bot/messages/get";
+/// How often a ping is sent; the connection is reopened after three intervals of silence.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+type Handler<T> = Arc<dyn Fn(T) -> BoxFuture<'static, Result<(), DingTalkError>> + Send + Sync>;
+
+/// A message @-mentioning a robot or sent to it in a single chat.
+///
+/// [机器人接收消息](https://open.dingtalk.com/document/orgapp/receive-message)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct BotMessage {
+    pub msg_id: String,
+    /// `text`, `picture`, `richText`, `audio`, `video` or `file`.
+    pub msgtype: String,
+    pub conversation_id: String,
+    /// `1` for single chats, `2` for group chats.
+    #[serde(default)]
+    pub conversation_type: Option<String>,
+    #[serde(default)]
+    pub conversation_title: Option<String>,
+    #[serde(default)]
+    pub sender_id: Option<String>,
+    #[serde(default)]
+    pub sender_nick: Option<String>,
+    /// The user ID of the sender, for members of the organization of the robot.
+    #[serde(default)]
+    pub sender_staff_id: Option<String>,
+    #[serde(default)]
+    pub robot_code: Option<String>,
+    /// A webhook answering into the conversation, valid until `session_webhook_expired_time`.
+    #[serde(default)]
+    pub session_webhook: Option<String>,
+    #[serde(default)]
+    pub session_webhook_expired_time: i64,
+    #[serde(default)]
+    pub text: Option<BotText>,
+    #[serde(default)]
+    pub create_at: i64,
+    /// Fields without a model, e.g. the `content` of other message types.
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
+}
+
+/// The content of a `text` robot message.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BotText {
+    pub content: String,
+}
+
+/// Receives events and robot messages in Stream Mode, see [DingTalk::stream_client].
+///
+/// Handlers run concurrently in their own tasks. An event whose handler fails is acknowledged
+/// with `LATER`, so DingTalk delivers it again.
+pub struct StreamClient {
+    client_id: String,
+    client_secret: String,
+    client: reqwest::Client,
+    endpoints: Endpoints,
+    event_handler: Option<Handler<Event>>,
+    bot_handler: Option<Handler<BotMessage>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Frame {
+    #[serde(rename = "type")]
+    kind: String,
+    headers: FrameHeaders,
+    #[serde(default)]
+    data: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct FrameHeaders {
+    #[serde(default)]
+    message_id: String,
+    #[serde(default)]
+    topic: String,
+    #[serde(default)]
+    event_type: Option<String>,
+    #[serde(default)]
+    event_corp_id: Option<String>,
+}
+
+impl DingTalk {
+    /// Returns a Stream Mode client authenticated with the app ID and secret of this instance.
+    pub fn stream_client(&self) -> StreamClient {
+        StreamClient {
+            client_id: self.appid.clone(),
+            client_secret: self.app_secret.clone(),
+            client: self.client.clone(),
+            endpoints: self.endpoints.clone(),
+            event_handler: None,
+            bot_handler: None,
+        }
+    }
+}
+
+impl StreamClient {
+    /// Subscribes to every event of the app and handles them with `handler`.
+    pub fn on_event<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn(Event) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), DingTalkError>> + Send + 'static,
+    {
+        self.event_handler = Some(Arc::new(move |event| Box::pin(handler(event))));
+        self
+    }
+
+    /// Subscribes to the messages sent to the robots of the app and handles them with `handler`.
+    pub fn on_bot_message<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn(BotMessage) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), DingTalkError>> + Send + 'static,
+    {
+        self.bot_handler = Some(Arc::new(move |message| Box::pin(handler(message))));
+        self
+    }
+
+    /// Connects to DingTalk and handles messages until the credentials are rejected.
+    ///
+    /// Dropped connections are reopened with an exponential backoff of up to a minute.
+    ///
+    /// # Errors
+    ///
+    /// Returns [DingTalkError::HttpStatus] if DingTalk refuses to open a connection, e.g. for
+    /// invalid credentials.
+    pub async fn run(&self) -> Result<(), DingTalkError> {
+        let mut backoff = MIN_BACKOFF;
+        loop {
+            match self.connect().await {
+                Ok(()) => {
+                    info!("stream connection closed by DingTalk, reconnecting");
+                    backoff = MIN_BACKOFF;
+                }
+                Err(e @ DingTalkError::HttpStatus { .. }) if is_rejection(&e) => return Err(e),
+                Err(e) => warn!("stream connection failed: {}", e),
+            }
+
+            sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    /// Requests the WebSocket endpoint and its ticket.
+    ///
+    /// [Stream模式](https://open.dingtalk.com/document/direction/stream-mode-protocol-access-description)
+    async fn open_connection(&self) -> Result<String, DingTalkError> {
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Response {
+            endpoint: String,
+            ticket: String,
+        }
+
+        let mut subscriptions = Vec::new();
+        if self.event_handler.is_some() {
+            subscriptions.push(json!({ "type": "EVENT", "topic": "*" }));
+        }
+        if self.bot_handler.is_some() {
+            subscriptions.push(json!({ "type": "CALLBACK", "topic": BOT_MESSAGE_TOPIC }));
+        }
+
+        let params = json!({
+            "clientId": self.client_id,
+            "clientSecret": self.client_secret,
+            "subscriptions": subscriptions,
+            "ua": concat!("async-dingtalk/", env!("CARGO_PKG_VERSION")),
+        });
+        let request = self
+            .client
+            .post(self.endpoints.api("v1.0/gateway/connections/open"))
+            .json(&params);
+        let res: Response = send_and_parse(request, "Failed to open stream connection").await?;
+
+        let mut url = url::Url::parse(&res.endpoint)
+            .map_err(|e| DingTalkError::Stream(format!("invalid stream endpoint: {}", e)))?;
+        url.query_pairs_mut().append_pair("ticket", &res.ticket);
+        Ok(url.to_string())
+    }
+
+    /// Handles one connection until it is closed.
+    async fn connect(&self) -> Result<(), DingTalkError> {
+        let url = self.open_connection().await?;
+        let (socket, _) = connect_async(url.as_str())
+            .await
+            .map_err(|e| DingTalkError::Stream(e.to_string()))?;
+        debug!("stream connected");
+
+        let (mut sink, mut source) = socket.split();
+        let (acks, mut pending_acks) = mpsc::unbounded_channel::<String>();
+        let mut heartbeat = interval(HEARTBEAT_INTERVAL);
+        let mut last_seen = Instant::now();
+
+        loop {
+            tokio::select! {
+                message = source.next() => {
+                    let message = match message {
+                        Some(message) => message.map_err(|e| DingTalkError::Stream(e.to_string()))?,
+                        None => return Ok(()),
+                    };
+                    last_seen = Instant::now();
+                    match message {
+                        Message::Text(text) if self.handle_frame(&text, &acks) => return Ok(()),
+                        Message::Ping(payload) => sink
+                            .send(Message::Pong(payload))
+                            .await
+                            .map_err(|e| DingTalkError::Stream(e.to_string()))?,
+                        Message::Close(_) => return Ok(()),
+                        _ => {}
+                    }
+                }
+                Some(ack) = pending_acks.recv() => {
+                    sink.send(Message::Text(ack))
+                        .await
+                        .map_err(|e| DingTalkError::Stream(e.to_string()))?;
+                }
+                _ = heartbeat.tick() => {
+                    if last_seen.elapsed() > HEARTBEAT_INTERVAL * 3 {
+                        return Err(DingTalkError::Stream("heartbeat timed out".to_string()));
+                    }
+                    sink.send(Message::Ping(Vec::new()))
+                        .await
+                        .map_err(|e| DingTalkError::Stream(e.to_string()))?;
+                }
+            }
+        }
+    }
+
+    /// Dispatches a frame, sending its acknowledgement through `acks`. Returns `true` when
+    /// DingTalk asks to reconnect.
+    fn handle_frame(&self, text: &str, acks: &mpsc::UnboundedSender<String>) -> bool {
+        let frame: Frame = match serde_json::from_str(text) {
+            Ok(frame) => frame,
+            Err(e) => {
+                warn!("ignoring unreadable stream frame: {}", e);
+                return false;
+            }
+        };
+
+        match (frame.kind.as_str(), frame.headers.topic.as_str()) {
+            ("SYSTEM", "ping") => {
+                let _ = acks.send(ack(&frame.headers.message_id, 200, &frame.data));
+            }
+            ("SYSTEM", "disconnect") => return true,
+            ("EVENT", _) => self.dispatch_event(frame, acks.clone()),
+            ("CALLBACK", BOT_MESSAGE_TOPIC) => self.dispatch_bot_message(frame, acks.clone()),
+            (kind, topic) => debug!(kind, topic, "ignoring stream frame"),
+        }
+        false
+    }
+
+    fn dispatch_event(&self, frame: Frame, acks: mpsc::UnboundedSender<String>) {
+        let mut value: Value = serde_json::from_str(&frame.data).unwrap_or(Value::Null);
+        if let Value::Object(fields) = &mut value {
+            if let Some(event_type) = frame.headers.event_type {
+                fields.insert("EventType".to_string(), event_type.into());
+            }
+            if let Some(corp_id) = frame.headers.event_corp_id {
+                fields.entry("CorpId").or_insert_with(|| corp_id.into());
+            }
+        }
+        let event = Event::from_value(value.clone()).unwrap_or(Event::Unknown(value));
+
+        let handler = self.event_handler.clone();
+        let message_id = frame.headers.message_id;
+        tokio::spawn(async move {
+            let status = match handler {
+                Some(handler) => match handler(event).await {
+                    Ok(()) => "SUCCESS",
+                    Err(e) => {
+                        warn!("stream event handler failed: {}", e);
+                        "LATER"
+                    }
+                },
+                None => "SUCCESS",
+            };
+            let data = json!({ "status": status, "message": status.to_lowercase() });
+            let _ = acks.send(ack(&message_id, 200, &data.to_string()));
+        });
+    }
+
+    fn dispatch_bot_message(&self, frame: Frame, acks: mpsc::UnboundedSender<String>) {
+        let handler = self.bot_handler.clone();
+        let message_id = frame.headers.message_id;
+        tokio::spawn(async move {
+            let message: BotMessage = match serde_json::from_str(&frame.data) {
+                Ok(message) => message,
+                Err(e) => {
+                    warn!("ignoring unreadable robot message: {}", e);
+                    let _ = acks.send(ack(&message_id, 400, "{}"));
+                    return;
+                }
+            };
+            let code = match handler {
+                Some(handler) => match handler(message).await {
+                    Ok(()) => 200,
+                    Err(e) => {
+                        warn!("stream robot message handler failed: {}", e);
+                        500
+                    }
+                },
+                None => 200,
+            };
+            let _ = acks.send(ack(&message_id, code, r#"{"response":null}"#));
+        });
+    }
+}
+
+/// Whether DingTalk refused to open a connection, e.g. for invalid credentials, so that
+/// reconnecting is pointless.
+fn is_rejection(e: &DingTalkError) -> bool {
+    match e {
+        DingTalkError::HttpStatus { status, .. } => status.is_client_error(),
+        _ => false,
+    }
+}
+
+/// Builds the acknowledgement of the frame with the given message ID.
+fn ack(message_id: &str, code: u16, data: &str) -> String {
+    json!({
+        "code": code,
+        "headers": { "contentType": "application/json", "messageId": message_id },
+        "message": if code == 200 { "OK" } else { "ERROR" },
+        "data": data,
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryStore;
+
+    fn client() -> StreamClient {
+        DingTalk::with_store(
+            "app".to_string(),
+            "secret".to_string(),
+            Arc::new(MemoryStore::new()),
+        )
+        .stream_client()
+    }
+
+    #[tokio::test]
+    async fn pings_are_answered_with_their_data() {
+        let (acks, mut pending_acks) = mpsc::unbounded_channel();
+        let frame = r#"{"specVersion":"1.0","type":"SYSTEM","headers":{"topic":"ping","messageId":"m1"},"data":"{\"opaque\":\"x\"}"}"#;
+
+        assert!(!client().handle_frame(frame, &acks));
+        let ack: Value = serde_json::from_str(&pending_acks.recv().await.unwrap()).unwrap();
+        assert_eq!(ack["headers"]["messageId"], "m1");
+        assert_eq!(ack["data"], r#"{"opaque":"x"}"#);
+    }
+
+    #[tokio::test]
+    async fn events_are_typed_from_their_headers() {
+        let (events, mut received) = mpsc::unbounded_channel();
+        let client = client().on_event(move |event| {
+            let events = events.clone();
+            async move {
+                events.send(event).unwrap();
+                Ok(())
+            }
+        });
+
+        let (acks, mut pending_acks) = mpsc::unbounded_channel();
+        let frame = r#"{"type":"EVENT","headers":{"topic":"*","messageId":"m2","eventType":"user_add_org","eventCorpId":"ding123"},"data":"{\"UserId\":[\"zhangsan\"]}"}"#;
+        assert!(!client.handle_frame(frame, &acks));
+
+        match received.recv().await.unwrap() {
+            Event::UserAddOrg(event) => {
+                assert_eq!(event.corp_id.as_deref(), Some("ding123"));
+                assert_eq!(event.user_ids, vec!["zhangsan".to_string()]);
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+        let ack: Value = serde_json::from_str(&pending_acks.recv().await.unwrap()).unwrap();
+        let data: Value = serde_json::from_str(ack["data"].as_str().unwrap()).unwrap();
+        assert_eq!(data["status"], "SUCCESS");
+    }
+}
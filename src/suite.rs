@@ -0,0 +1,397 @@
+use crate::organization::{OrgApp, TokenFlow};
+use crate::{
+    error::DingTalkError,
+    store::{get_cached, TokenStore},
+    telemetry, DingTalk,
+};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+/// Returns the token store key of the `suite_ticket` pushed to a suite, `{prefix}suite_ticket:{suite_key}`.
+fn suite_ticket_key(prefix: &str, suite_key: &str) -> String {
+    format!("{}suite_ticket:{}", prefix, suite_key)
+}
+
+impl DingTalk {
+    /// Stores the `suite_ticket` pushed by DingTalk to the callback of a third-party suite.
+    ///
+    /// The app ID and secret of a `DingTalk` used by an ISV are the suite key and secret. DingTalk
+    /// pushes a new ticket every 20 minutes; the latest one replaces the stored ticket.
+    ///
+    /// [推送suite_ticket](https://open.dingtalk.com/document/isvapp/push-suite-ticket)
+    ///
+    /// # Arguments
+    ///
+    /// * `suite_ticket` - The ticket of the `suite_ticket` event.
+    pub async fn set_suite_ticket(&self, suite_ticket: &str) -> Result<(), DingTalkError> {
+        self.store
+            .set(
+                &suite_ticket_key(&self.key_prefix, &self.appid),
+                suite_ticket,
+                None,
+            )
+            .await
+            .map_err(DingTalkError::Cache)
+    }
+
+    /// Retrieves the suite access token, cached in the token store until shortly before it
+    /// expires.
+    ///
+    /// [获取第三方企业应用的suite_access_token](https://open.dingtalk.com/document/isvapp/obtains-the-suite_access_token-of-third-party-enterprise-applications)
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the suite access token.
+    ///
+    /// # Errors
+    ///
+    /// Returns [DingTalkError::TokenMissing] if no ticket was stored by
+    /// [DingTalk::set_suite_ticket] yet.
+    pub async fn get_suite_access_token(&self) -> Result<String, DingTalkError> {
+        let key = format!("{}suite:{}", self.key_prefix, self.appid);
+        let value = get_cached(self.store.as_ref(), &key).await;
+
+        telemetry::record_token_cache("suite", value.is_some());
+        if let Some(token) = value {
+            return Ok(token);
+        }
+
+        let params = serde_json::json!({
+            "suite_key": self.appid,
+            "suite_secret": self.app_secret,
+            "suite_ticket": read_suite_ticket(self.store.as_ref(), &self.key_prefix, &self.appid).await?,
+        });
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Response {
+            suite_access_token: String,
+            expires_in: i64,
+        }
+
+        let request = self
+            .client
+            .post(self.endpoints.oapi("service/get_suite_token"))
+            .json(&params);
+        let res: Response = self
+            .send_and_parse(request, "Failed to get suite access token")
+            .await?;
+        debug!(expires_in = res.expires_in, "fetched suite access token");
+
+        if let Some(ttl) = self.token_config.cache_ttl(res.expires_in) {
+            self.store
+                .set(&key, &res.suite_access_token, Some(ttl))
+                .await
+                .map_err(DingTalkError::Cache)?;
+        }
+
+        Ok(res.suite_access_token)
+    }
+
+    /// Creates an `OrgApp` for a corp which authorized the suite, obtaining its access token
+    /// with [TokenFlow::Suite].
+    ///
+    /// # Arguments
+    ///
+    /// * `auth_corp_id` - The corp ID of the authorizing organization.
+    pub fn suite_corp(&self, auth_corp_id: String) -> OrgApp {
+        self.set_corp_id(auth_corp_id)
+            .with_token_flow(TokenFlow::Suite)
+    }
+
+    /// Retrieves the access token of a corp which authorized the suite, cached like the token of
+    /// any `OrgApp`.
+    ///
+    /// [获取第三方应用授权企业的accessToken](https://open.dingtalk.com/document/isvapp/obtain-the-access_token-of-the-authorized-enterprise)
+    ///
+    /// # Arguments
+    ///
+    /// * `auth_corp_id` - The corp ID of the authorizing organization.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the corp access token.
+    pub async fn get_suite_corp_access_token(
+        &self,
+        auth_corp_id: String,
+    ) -> Result<String, DingTalkError> {
+        self.suite_corp(auth_corp_id).get_access_token().await
+    }
+}
+
+impl OrgApp {
+    /// Returns the stored `suite_ticket` of the suite this app belongs to.
+    pub(crate) async fn suite_ticket(&self) -> Result<String, DingTalkError> {
+        read_suite_ticket(self.store.as_ref(), &self.key_prefix, &self.appid).await
+    }
+}
+
+async fn read_suite_ticket(
+    store: &dyn TokenStore,
+    prefix: &str,
+    suite_key: &str,
+) -> Result<String, DingTalkError> {
+    store
+        .get(&suite_ticket_key(prefix, suite_key))
+        .await
+        .map_err(DingTalkError::Cache)?
+        .ok_or_else(|| {
+            DingTalkError::TokenMissing(
+                "no suite ticket stored, call set_suite_ticket first".to_string(),
+            )
+        })
+}
+
+/// An organization which authorized the suite.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AuthCorpInfo {
+    pub corpid: String,
+    pub corp_name: String,
+    #[serde(default)]
+    pub full_corp_name: String,
+    #[serde(default)]
+    pub industry: String,
+    #[serde(default)]
+    pub corp_logo_url: String,
+    #[serde(default)]
+    pub auth_level: i32,
+    #[serde(default)]
+    pub is_authenticated: bool,
+}
+
+/// An app of the suite enabled in an authorizing organization.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AuthAgent {
+    pub agentid: i64,
+    #[serde(default)]
+    pub agent_name: String,
+    #[serde(default)]
+    pub appid: i64,
+}
+
+/// The authorization of the suite by an organization.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AuthInfo {
+    pub corp: AuthCorpInfo,
+    /// The user ID of the administrator who authorized the suite.
+    pub authorizer_user_id: Option<String>,
+    pub agents: Vec<AuthAgent>,
+}
+
+/// The permanent authorization code of an organization, see [DingTalk::get_permanent_code].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PermanentCode {
+    pub permanent_code: String,
+    pub corpid: String,
+    pub corp_name: String,
+}
+
+impl DingTalk {
+    /// Returns the token store key of the permanent code of an authorizing organization.
+    fn permanent_code_key(&self, auth_corp_id: &str) -> String {
+        format!(
+            "{}permanent_code:{}:{}",
+            self.key_prefix, self.appid, auth_corp_id
+        )
+    }
+
+    /// Sends a POST request to a `service/*` endpoint of `oapi.dingtalk.com` with the suite
+    /// access token.
+    async fn post_suite<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+        params: &impl Serialize,
+    ) -> Result<T, DingTalkError> {
+        let token = self.get_suite_access_token().await?;
+        let request = self
+            .client
+            .post(self.endpoints.oapi(path))
+            .query(&[("suite_access_token", token)])
+            .json(params);
+
+        self.send_and_parse(request, &format!("Failed to response {}", path))
+            .await
+    }
+
+    /// Exchanges the temporary code of an authorization event for the permanent code of the
+    /// organization, which is kept in the token store.
+    ///
+    /// [获取企业永久授权码](https://open.dingtalk.com/document/isvapp/obtain-a-permanent-authorization-code)
+    ///
+    /// # Arguments
+    ///
+    /// * `tmp_auth_code` - The temporary authorization code pushed to the callback.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the permanent code and the organization.
+    pub async fn get_permanent_code(
+        &self,
+        tmp_auth_code: &str,
+    ) -> Result<PermanentCode, DingTalkError> {
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Corp {
+            corpid: String,
+            corp_name: String,
+        }
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Response {
+            permanent_code: String,
+            auth_corp_info: Corp,
+        }
+
+        let params = serde_json::json!({ "tmp_auth_code": tmp_auth_code });
+        let res: Response = self
+            .post_suite("service/get_permanent_code", &params)
+            .await?;
+
+        self.store
+            .set(
+                &self.permanent_code_key(&res.auth_corp_info.corpid),
+                &res.permanent_code,
+                None,
+            )
+            .await
+            .map_err(DingTalkError::Cache)?;
+
+        Ok(PermanentCode {
+            permanent_code: res.permanent_code,
+            corpid: res.auth_corp_info.corpid,
+            corp_name: res.auth_corp_info.corp_name,
+        })
+    }
+
+    /// Returns the permanent code of an organization stored by [DingTalk::get_permanent_code].
+    pub async fn stored_permanent_code(
+        &self,
+        auth_corp_id: &str,
+    ) -> Result<Option<String>, DingTalkError> {
+        self.store
+            .get(&self.permanent_code_key(auth_corp_id))
+            .await
+            .map_err(DingTalkError::Cache)
+    }
+
+    /// Activates the suite for an authorizing organization.
+    ///
+    /// [激活应用](https://open.dingtalk.com/document/isvapp/activate-suite)
+    ///
+    /// # Arguments
+    ///
+    /// * `auth_corp_id` - The corp ID of the organization.
+    /// * `permanent_code` - Its permanent code.
+    pub async fn activate_suite(
+        &self,
+        auth_corp_id: &str,
+        permanent_code: &str,
+    ) -> Result<(), DingTalkError> {
+        let params = serde_json::json!({
+            "suite_key": self.appid,
+            "auth_corpid": auth_corp_id,
+            "permanent_code": permanent_code,
+        });
+        self.post_suite::<serde_json::Value>("service/activate_suite", &params)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Handles a new authorization: exchanges the temporary code and activates the suite.
+    ///
+    /// # Arguments
+    ///
+    /// * `tmp_auth_code` - The temporary authorization code pushed to the callback.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the permanent code and the onboarded organization.
+    pub async fn onboard_corp(&self, tmp_auth_code: &str) -> Result<PermanentCode, DingTalkError> {
+        let code = self.get_permanent_code(tmp_auth_code).await?;
+        self.activate_suite(&code.corpid, &code.permanent_code)
+            .await?;
+
+        Ok(code)
+    }
+
+    /// Retrieves how an organization authorized the suite.
+    ///
+    /// [获取企业授权信息](https://open.dingtalk.com/document/isvapp/obtains-the-basic-information-of-an-enterprise)
+    ///
+    /// # Arguments
+    ///
+    /// * `auth_corp_id` - The corp ID of the organization.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the organization, the authorizing administrator and the enabled apps.
+    pub async fn get_auth_info(&self, auth_corp_id: &str) -> Result<AuthInfo, DingTalkError> {
+        #[derive(Serialize, Deserialize, Debug, Default)]
+        struct AuthUser {
+            #[serde(rename = "userId", default)]
+            user_id: Option<String>,
+        }
+        #[derive(Serialize, Deserialize, Debug, Default)]
+        struct Agents {
+            #[serde(default)]
+            agent: Vec<AuthAgent>,
+        }
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Response {
+            auth_corp_info: AuthCorpInfo,
+            #[serde(default)]
+            auth_user_info: AuthUser,
+            #[serde(default)]
+            auth_info: Agents,
+        }
+
+        let params = serde_json::json!({
+            "suite_key": self.appid,
+            "auth_corpid": auth_corp_id,
+        });
+        let res: Response = self.post_suite("service/get_auth_info", &params).await?;
+
+        Ok(AuthInfo {
+            corp: res.auth_corp_info,
+            authorizer_user_id: res.auth_user_info.user_id,
+            agents: res.auth_info.agent,
+        })
+    }
+
+    /// Retrieves the organization information of an authorizing organization.
+    ///
+    /// [获取企业授权信息](https://open.dingtalk.com/document/isvapp/obtains-the-basic-information-of-an-enterprise)
+    pub async fn get_auth_corp_info(
+        &self,
+        auth_corp_id: &str,
+    ) -> Result<AuthCorpInfo, DingTalkError> {
+        Ok(self.get_auth_info(auth_corp_id).await?.corp)
+    }
+
+    /// Lists the organizations which authorized an app of the suite but are not activated yet,
+    /// e.g. because the authorization event was missed.
+    ///
+    /// DingTalk returns at most 100 organizations; activating them reveals the next ones.
+    ///
+    /// [获取应用未激活的企业列表](https://open.dingtalk.com/document/isvapp/obtains-the-list-of-unactivated-enterprises)
+    ///
+    /// # Arguments
+    ///
+    /// * `app_id` - The ID of the app in the suite.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the corp IDs.
+    pub async fn list_unactivated_corps(&self, app_id: i64) -> Result<Vec<String>, DingTalkError> {
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Response {
+            #[serde(default)]
+            corp_list: Vec<String>,
+        }
+
+        let params = serde_json::json!({ "app_id": app_id });
+        let res: Response = self
+            .post_suite("service/get_unactive_corp", &params)
+            .await?;
+
+        Ok(res.corp_list)
+    }
+}
@@ -0,0 +1,220 @@
+use crate::cache::ProfileKind;
+use crate::coalesce::EndpointClass;
+use crate::error::DingTalkError;
+use crate::organization::{EmployeeUser, Mobile, OrgApp};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+
+/// How many employee profiles are requested at the same time.
+const MAX_CONCURRENT_REQUESTS: usize = 8;
+
+/// A field of an employee profile kept in an [EmployeeSummary].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EmployeeField {
+    Name,
+    Mobile,
+    Email,
+    Title,
+    JobNumber,
+    Departments,
+    HiredDate,
+    Active,
+    Manager,
+}
+
+/// The fields of an employee profile needed by a sync, see [OrgApp::get_employee_summaries].
+///
+/// `user_id` and `union_id` are always set; the other fields are `None` unless requested, or
+/// when the profile has no value.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct EmployeeSummary {
+    #[serde(rename = "userid")]
+    pub user_id: String,
+    #[serde(rename = "unionid", default)]
+    pub union_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// `None` as well when the employee hides it, see [crate::Mobile].
+    #[serde(
+        default,
+        deserialize_with = "visible_mobile",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub mobile: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job_number: Option<String>,
+    #[serde(
+        rename = "dept_id_list",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub dept_ids: Option<Vec<i64>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hired_date: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active: Option<bool>,
+    #[serde(
+        rename = "manager_userid",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub manager_user_id: Option<String>,
+}
+
+fn visible_mobile<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    Ok(Mobile::deserialize(deserializer)?
+        .as_visible()
+        .map(str::to_string))
+}
+
+impl EmployeeSummary {
+    /// Clears the fields which are not requested.
+    fn project(mut self, fields: &[EmployeeField]) -> Self {
+        let keep = |field| fields.contains(&field);
+        if !keep(EmployeeField::Name) {
+            self.name = None;
+        }
+        if !keep(EmployeeField::Mobile) {
+            self.mobile = None;
+        }
+        if !keep(EmployeeField::Email) {
+            self.email = None;
+        }
+        if !keep(EmployeeField::Title) {
+            self.title = None;
+        }
+        if !keep(EmployeeField::JobNumber) {
+            self.job_number = None;
+        }
+        if !keep(EmployeeField::Departments) {
+            self.dept_ids = None;
+        }
+        if !keep(EmployeeField::HiredDate) {
+            self.hired_date = None;
+        }
+        if !keep(EmployeeField::Active) {
+            self.active = None;
+        }
+        if !keep(EmployeeField::Manager) {
+            self.manager_user_id = None;
+        }
+        self
+    }
+}
+
+impl From<&EmployeeUser> for EmployeeSummary {
+    fn from(user: &EmployeeUser) -> Self {
+        EmployeeSummary {
+            user_id: user.user_id.clone(),
+            union_id: user.union_id.clone(),
+            name: Some(user.username.clone()),
+            mobile: user.mobile.as_visible().map(str::to_string),
+            email: user.email.clone(),
+            title: Some(user.title.clone()),
+            job_number: Some(user.job_number.clone()),
+            dept_ids: Some(user.dept_id_list.iter().map(|id| *id as i64).collect()),
+            hired_date: user.hired_date,
+            active: Some(user.active),
+            manager_user_id: user.manager_userid.clone(),
+        }
+    }
+}
+
+impl OrgApp {
+    /// Retrieves the given fields of an employee profile.
+    ///
+    /// [查询用户详情](https://open.dingtalk.com/document/orgapp/query-user-details)
+    ///
+    /// DingTalk always returns the whole profile; only the fields of [EmployeeSummary] are
+    /// deserialized, and those which are not requested are cleared afterwards. Profiles in the
+    /// profile cache are used as they are.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user ID of the employee.
+    /// * `fields` - The fields to keep.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `EmployeeSummary` if successful, otherwise a `DingTalkError`.
+    pub async fn get_employee_summary(
+        &self,
+        user_id: String,
+        fields: &[EmployeeField],
+    ) -> Result<EmployeeSummary, DingTalkError> {
+        if let Some(cache) = &self.profile_cache {
+            if let Some(user) = cache
+                .get::<EmployeeUser>(
+                    &self.key_prefix,
+                    ProfileKind::Employee,
+                    &self.corp_id,
+                    &user_id,
+                )
+                .await
+            {
+                return Ok(EmployeeSummary::from(&user).project(fields));
+            }
+        }
+
+        let key = format!("{}:topapi/v2/user/get:summary:{}", self.corp_id, user_id);
+        let summary: EmployeeSummary = self
+            .coalescer
+            .run(EndpointClass::User, key, || async {
+                let mut params: HashMap<&str, String> = HashMap::new();
+                params.insert("language", self.language.clone());
+                params.insert("userid", user_id.clone());
+                self.post_oapi("topapi/v2/user/get", &params).await
+            })
+            .await?;
+
+        Ok(summary.project(fields))
+    }
+
+    /// Retrieves the given fields of the profiles of several employees.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_ids` - The user IDs of the employees.
+    /// * `fields` - The fields to keep, see [OrgApp::get_employee_summary].
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the summaries in the order of `user_ids`.
+    pub async fn get_employee_summaries(
+        &self,
+        user_ids: &[String],
+        fields: &[EmployeeField],
+    ) -> Result<Vec<EmployeeSummary>, DingTalkError> {
+        stream::iter(user_ids.iter().cloned())
+            .map(|user_id| self.get_employee_summary(user_id, fields))
+            .buffered(MAX_CONCURRENT_REQUESTS)
+            .try_collect()
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    #[test]
+    fn summaries_keep_the_requested_fields() {
+        let user = fixtures::employee_user();
+        let full = EmployeeSummary::from(&user);
+
+        let raw: EmployeeSummary = fixtures::load(fixtures::EMPLOYEE_USER);
+        assert_eq!(raw, full);
+
+        let summary = full.project(&[EmployeeField::Name, EmployeeField::Departments]);
+        assert_eq!(summary.user_id, user.user_id);
+        assert_eq!(summary.name, Some(user.username));
+        assert!(summary.dept_ids.is_some());
+        assert_eq!(summary.mobile, None);
+    }
+}
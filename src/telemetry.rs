@@ -0,0 +1,60 @@
+//! Metrics about the DingTalk integration, recorded through the `metrics` facade when the
+//! `metrics` feature is enabled:
+//!
+//! * `dingtalk_requests_total` - counter of requests by `endpoint` and `outcome`
+//!   (`ok`, `http_error`, `api_error` or `transport_error`).
+//! * `dingtalk_request_errors_total` - counter of `errcode` responses by `endpoint` and `errcode`.
+//! * `dingtalk_request_duration_seconds` - histogram of the request latency by `endpoint`.
+//! * `dingtalk_token_cache_total` - counter of token lookups by `token` (`user`, `corp`, `suite` or
+//!   `jsapi`) and `result` (`hit` or `miss`).
+//!
+//! Endpoints are labelled with their path in the [crate::catalog], so that path parameters do
+//! not create new series.
+
+use crate::error::DingTalkError;
+use std::time::Duration;
+
+/// Records the outcome of a request.
+#[cfg(feature = "metrics")]
+pub(crate) fn record_request<T>(path: &str, result: &Result<T, DingTalkError>, elapsed: Duration) {
+    let endpoint = crate::catalog::endpoint_path(path).unwrap_or("other");
+    let outcome = match result {
+        Ok(_) => "ok",
+        Err(DingTalkError::HttpStatus { .. }) => "http_error",
+        Err(DingTalkError::Http(_)) => "transport_error",
+        Err(_) => "api_error",
+    };
+
+    metrics::counter!("dingtalk_requests_total", "endpoint" => endpoint, "outcome" => outcome)
+        .increment(1);
+    metrics::histogram!("dingtalk_request_duration_seconds", "endpoint" => endpoint)
+        .record(elapsed.as_secs_f64());
+    if let Some(errcode) = result.as_ref().err().and_then(DingTalkError::errcode) {
+        metrics::counter!(
+            "dingtalk_request_errors_total",
+            "endpoint" => endpoint,
+            "errcode" => errcode.to_string()
+        )
+        .increment(1);
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn record_request<T>(
+    _path: &str,
+    _result: &Result<T, DingTalkError>,
+    _elapsed: Duration,
+) {
+}
+
+/// Records a token store lookup of the `user`, `corp` or `suite` access token or
+/// the `jsapi` ticket.
+#[cfg(feature = "metrics")]
+pub(crate) fn record_token_cache(token: &'static str, hit: bool) {
+    let result = if hit { "hit" } else { "miss" };
+    metrics::counter!("dingtalk_token_cache_total", "token" => token, "result" => result)
+        .increment(1);
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn record_token_cache(_token: &'static str, _hit: bool) {}
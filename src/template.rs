@@ -0,0 +1,255 @@
+use crate::error::DingTalkError;
+use crate::robot::{ActionCardContent, At, MarkdownContent, RobotMessage};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Characters with a meaning in DingTalk markdown that must be escaped in substituted values.
+const MARKDOWN_SPECIAL: &[char] = &[
+    '\\', '`', '*', '_', '{', '}', '[', ']', '(', ')', '#', '+', '-', '.', '!', '|', '>', '<', '~',
+];
+/// Characters percent-encoded in substituted URL values: all but the unreserved characters of
+/// RFC 3986, so that values are safe in paths as well as in queries.
+const URL_ENCODED: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// A robot message template kept in configuration.
+///
+/// Placeholders are written as `{{name}}` and replaced with the value of `name` from the render
+/// context. In markdown bodies values are escaped so they render literally; use `{{&name}}` to
+/// insert a value verbatim, e.g. when it already contains markdown. Titles are plain text and URLs
+/// have their substituted values percent-encoded.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "msgtype")]
+pub enum MessageTemplate {
+    #[serde(rename = "markdown")]
+    Markdown { title: String, text: String },
+    #[serde(rename = "actionCard")]
+    ActionCard {
+        title: String,
+        text: String,
+        #[serde(rename = "singleTitle")]
+        single_title: String,
+        #[serde(rename = "singleURL")]
+        single_url: String,
+    },
+}
+
+#[derive(Clone, Copy)]
+enum Escape {
+    Plain,
+    Markdown,
+    Url,
+}
+
+impl MessageTemplate {
+    /// Renders the template into a robot message.
+    ///
+    /// # Arguments
+    ///
+    /// * `context` - The variables available to the placeholders.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the rendered `RobotMessage`, or an error if a placeholder is not
+    /// closed or refers to a variable missing from the context.
+    pub fn render(&self, context: &HashMap<String, String>) -> Result<RobotMessage, DingTalkError> {
+        let message = match self {
+            MessageTemplate::Markdown { title, text } => RobotMessage::Markdown {
+                markdown: MarkdownContent {
+                    title: substitute(title, context, Escape::Plain)?,
+                    text: substitute(text, context, Escape::Markdown)?,
+                },
+                at: At::default(),
+            },
+            MessageTemplate::ActionCard {
+                title,
+                text,
+                single_title,
+                single_url,
+            } => RobotMessage::ActionCard {
+                action_card: ActionCardContent {
+                    title: substitute(title, context, Escape::Plain)?,
+                    text: substitute(text, context, Escape::Markdown)?,
+                    single_title: substitute(single_title, context, Escape::Plain)?,
+                    single_url: substitute(single_url, context, Escape::Url)?,
+                    btn_orientation: "0".to_string(),
+                },
+            },
+        };
+
+        Ok(message)
+    }
+}
+
+fn substitute(
+    template: &str,
+    context: &HashMap<String, String>,
+    escape: Escape,
+) -> Result<String, DingTalkError> {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+
+        let after = &rest[start + 2..];
+        let end = match after.find("}}") {
+            Some(end) => end,
+            None => {
+                return Err(DingTalkError::Template(format!(
+                    "Unclosed placeholder in template: {}",
+                    template
+                )))
+            }
+        };
+
+        let name = after[..end].trim();
+        let (name, raw) = match name.strip_prefix('&') {
+            Some(name) => (name.trim(), true),
+            None => (name, false),
+        };
+
+        let value = match context.get(name) {
+            Some(value) => value,
+            None => {
+                return Err(DingTalkError::Template(format!(
+                    "Missing template variable: {}",
+                    name
+                )))
+            }
+        };
+
+        match (raw, escape) {
+            (true, _) | (false, Escape::Plain) => output.push_str(value),
+            (false, Escape::Markdown) => output.push_str(&escape_markdown(value)),
+            (false, Escape::Url) => output.extend(utf8_percent_encode(value, URL_ENCODED)),
+        }
+
+        rest = &after[end + 2..];
+    }
+    output.push_str(rest);
+
+    Ok(output)
+}
+
+/// Escapes a value so that it renders literally inside a DingTalk markdown body.
+///
+/// Markdown control characters are backslash-escaped and line breaks are turned into hard
+/// breaks, since DingTalk otherwise joins consecutive lines.
+pub fn escape_markdown(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\n' => escaped.push_str("  \n"),
+            '\r' => {}
+            c if MARKDOWN_SPECIAL.contains(&c) => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn placeholders_are_substituted() {
+        let context = context(&[("name", "张三"), ("dept", "研发部")]);
+
+        assert_eq!(
+            substitute("欢迎 {{name}} 加入{{ dept }}!", &context, Escape::Plain).unwrap(),
+            "欢迎 张三 加入研发部!"
+        );
+        assert_eq!(
+            substitute("没有占位符", &context, Escape::Plain).unwrap(),
+            "没有占位符"
+        );
+    }
+
+    #[test]
+    fn markdown_values_are_escaped() {
+        assert_eq!(
+            escape_markdown("*v1.2* [beta] #3"),
+            r"\*v1\.2\* \[beta\] \#3"
+        );
+
+        let context = context(&[("title", "**发布**_v2_")]);
+        assert_eq!(
+            substitute("## {{title}}", &context, Escape::Markdown).unwrap(),
+            r"## \*\*发布\*\*\_v2\_"
+        );
+    }
+
+    #[test]
+    fn raw_placeholders_are_inserted_verbatim() {
+        let context = context(&[("body", "**加粗**")]);
+
+        assert_eq!(
+            substitute("{{&body}} {{ & body }}", &context, Escape::Markdown).unwrap(),
+            "**加粗** **加粗**"
+        );
+    }
+
+    #[test]
+    fn newlines_become_hard_breaks() {
+        assert_eq!(
+            escape_markdown("第一行\r\n第二行\n"),
+            "第一行  \n第二行  \n"
+        );
+    }
+
+    #[test]
+    fn unclosed_placeholders_are_rejected() {
+        let error = substitute("你好 {{name", &context(&[("name", "张三")]), Escape::Plain);
+
+        match error {
+            Err(DingTalkError::Template(message)) => {
+                assert!(message.starts_with("Unclosed placeholder"))
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn missing_variables_are_rejected() {
+        let error = substitute("你好 {{name}}", &HashMap::new(), Escape::Plain);
+
+        match error {
+            Err(DingTalkError::Template(message)) => {
+                assert_eq!(message, "Missing template variable: name")
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn url_values_are_percent_encoded() {
+        let context = context(&[("file", "周报 v1.0&final/张三.md")]);
+
+        assert_eq!(
+            substitute(
+                "https://example.com/files/{{file}}?from={{file}}",
+                &context,
+                Escape::Url
+            )
+            .unwrap(),
+            "https://example.com/files/%E5%91%A8%E6%8A%A5%20v1.0%26final%2F%E5%BC%A0%E4%B8%89.md\
+             ?from=%E5%91%A8%E6%8A%A5%20v1.0%26final%2F%E5%BC%A0%E4%B8%89.md"
+        );
+    }
+}
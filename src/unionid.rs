@@ -0,0 +1,119 @@
+use crate::error::{ApiErrorKind, DingTalkError};
+use crate::organization::OrgApp;
+use crate::pacing::Pacer;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::info;
+
+/// The wait between the conversions of [OrgApp::convert_open_ids] without a
+/// [pace](OrgApp::with_pace), well below the QPS limit of the endpoint.
+const DEFAULT_CONVERSION_PACE: Duration = Duration::from_millis(50);
+
+impl OrgApp {
+    /// Converts the open ID of a user of a legacy app, e.g. a scan login website, to the union
+    /// ID.
+    ///
+    /// [openId转换unionId](https://open.dingtalk.com/document/orgapp/convert-openid-to-unionid)
+    ///
+    /// # Arguments
+    ///
+    /// * `openid` - The open ID of the user.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the union ID if successful, otherwise an error.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [ApiErrorKind::UserNotFound] if the open ID is unknown.
+    pub async fn get_unionid_by_openid(&self, openid: String) -> Result<String, DingTalkError> {
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Response {
+            unionid: String,
+        }
+
+        let params = serde_json::json!({ "openid": openid });
+        let res: Response = self
+            .post_oapi("topapi/user/getunionidbyopenid", &params)
+            .await?;
+        Ok(res.unionid)
+    }
+
+    /// Converts the open IDs of a legacy user table to union IDs with
+    /// [OrgApp::get_unionid_by_openid].
+    ///
+    /// The open IDs are converted one after another, waiting the [pace](OrgApp::with_pace) of the
+    /// organization, 50ms by default, between them. Throttled conversions are retried with an
+    /// exponential backoff.
+    ///
+    /// # Arguments
+    ///
+    /// * `open_ids` - The open IDs to convert.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the union IDs keyed by open ID, otherwise the first error. Unknown
+    /// open IDs are left out.
+    pub async fn convert_open_ids(
+        &self,
+        open_ids: &[String],
+    ) -> Result<HashMap<String, String>, DingTalkError> {
+        let pace = self.pace.unwrap_or(DEFAULT_CONVERSION_PACE);
+
+        let mut pacer = Pacer::new(Some(pace));
+        let mut union_ids = HashMap::new();
+        for open_id in open_ids {
+            match pacer
+                .run(|| self.get_unionid_by_openid(open_id.clone()))
+                .await
+            {
+                Ok(union_id) => {
+                    union_ids.insert(open_id.clone(), union_id);
+                }
+                Err(e) if e.kind() == Some(ApiErrorKind::UserNotFound) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        info!(
+            open_ids = open_ids.len(),
+            converted = union_ids.len(),
+            "converted open ids"
+        );
+
+        Ok(union_ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mock::Mock;
+    use serde_json::json;
+
+    #[tokio::test(start_paused = true)]
+    async fn conversions_retry_throttled_and_skip_unknown_open_ids() {
+        let mock = Mock::new();
+        let path = "topapi/user/getunionidbyopenid";
+        mock.respond(path, json!({ "errcode": 90018, "errmsg": "调用频率超限" }));
+        mock.respond(
+            path,
+            json!({ "errcode": 0, "result": { "unionid": "union-1" } }),
+        );
+        mock.respond(path, json!({ "errcode": 60121, "errmsg": "找不到该用户" }));
+        let app = mock.app().await;
+
+        let open_ids = vec!["open-1".to_string(), "open-2".to_string()];
+        let union_ids = app.convert_open_ids(&open_ids).await.unwrap();
+
+        assert_eq!(union_ids.len(), 1);
+        assert_eq!(union_ids["open-1"], "union-1");
+        assert_eq!(
+            mock.bodies(path),
+            vec![
+                json!({ "openid": "open-1" }),
+                json!({ "openid": "open-1" }),
+                json!({ "openid": "open-2" }),
+            ]
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+/// Counts the requests sent for each organization, to spot tenants close to the call quotas of
+/// DingTalk before they are throttled.
+///
+/// A `DingTalk` instance shares its tracker with the `OrgApp`s created from it. Counts live in
+/// memory and start at zero in every process.
+pub struct UsageTracker {
+    inner: Mutex<Counts>,
+}
+
+struct Counts {
+    since: DateTime<Utc>,
+    calls: HashMap<String, HashMap<&'static str, u64>>,
+    app: HashMap<&'static str, u64>,
+}
+
+/// The requests counted by a [UsageTracker] since `since`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Usage {
+    pub since: DateTime<Utc>,
+    /// The usage of each organization, keyed by corp ID.
+    pub corps: BTreeMap<String, CorpUsage>,
+    /// The requests of the app itself rather than of an organization, e.g. for user tokens,
+    /// contact profiles and suite tokens.
+    pub app: CorpUsage,
+}
+
+/// The requests sent for an organization.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CorpUsage {
+    pub total: u64,
+    /// The requests per endpoint, keyed by their path in the [crate::catalog]; paths outside the
+    /// catalog are counted as `other`.
+    pub endpoints: BTreeMap<String, u64>,
+}
+
+impl Default for UsageTracker {
+    fn default() -> Self {
+        UsageTracker::new()
+    }
+}
+
+impl UsageTracker {
+    pub fn new() -> Self {
+        UsageTracker {
+            inner: Mutex::new(Counts {
+                since: Utc::now(),
+                calls: HashMap::new(),
+                app: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Counts a request of an organization to the given URL path.
+    pub(crate) fn record(&self, corp_id: &str, path: &str) {
+        let endpoint = crate::catalog::endpoint_path(path).unwrap_or("other");
+        let mut counts = self.inner.lock().unwrap();
+        *counts
+            .calls
+            .entry(corp_id.to_string())
+            .or_default()
+            .entry(endpoint)
+            .or_insert(0) += 1;
+    }
+
+    /// Counts a request of the app itself to the given URL path.
+    pub(crate) fn record_app(&self, path: &str) {
+        let endpoint = crate::catalog::endpoint_path(path).unwrap_or("other");
+        let mut counts = self.inner.lock().unwrap();
+        *counts.app.entry(endpoint).or_insert(0) += 1;
+    }
+
+    /// Returns the requests counted so far.
+    pub fn snapshot(&self) -> Usage {
+        let counts = self.inner.lock().unwrap();
+        usage(&counts)
+    }
+
+    /// Returns the requests counted so far and starts counting from zero, e.g. once a day when
+    /// the daily quotas of DingTalk are reset.
+    pub fn reset(&self) -> Usage {
+        let mut counts = self.inner.lock().unwrap();
+        let usage = usage(&counts);
+        counts.since = Utc::now();
+        counts.calls.clear();
+        counts.app.clear();
+        usage
+    }
+}
+
+fn usage(counts: &Counts) -> Usage {
+    let corps = counts
+        .calls
+        .iter()
+        .map(|(corp_id, calls)| (corp_id.clone(), corp_usage(calls)))
+        .collect();
+
+    Usage {
+        since: counts.since,
+        corps,
+        app: corp_usage(&counts.app),
+    }
+}
+
+fn corp_usage(calls: &HashMap<&'static str, u64>) -> CorpUsage {
+    let endpoints: BTreeMap<String, u64> = calls
+        .iter()
+        .map(|(endpoint, count)| (endpoint.to_string(), *count))
+        .collect();
+
+    CorpUsage {
+        total: endpoints.values().sum(),
+        endpoints,
+    }
+}
@@ -0,0 +1,1139 @@
+use crate::cache::ProfileKind;
+use crate::contact::AccountKind;
+use crate::core::redact_mobile;
+use crate::error::{ApiErrorKind, DingTalkError};
+use crate::job_number::JobNumberIndex;
+use crate::organization::{EmployeeUser, OrgApp, PageResult};
+use crate::pacing::{offset_page, paginate};
+use crate::roster::Dismissal;
+use chrono::NaiveDate;
+use futures::future::TryFutureExt;
+use futures::stream::Stream;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
+use tracing::{debug, info};
+
+/// The largest page of `topapi/v2/user/list`.
+const MAX_PAGE_SIZE: i64 = 100;
+
+/// DingTalk expects department id lists of the user write APIs as a comma separated string.
+fn serialize_dept_ids<S: Serializer>(
+    ids: &Option<Vec<i64>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match ids {
+        Some(ids) => serializer.serialize_str(
+            &ids.iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<String>>()
+                .join(","),
+        ),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Custom attributes (`extension`) of a user.
+///
+/// DingTalk transfers them as a JSON object encoded into a string, e.g.
+/// `"{\"爱好\":\"旅游\"}"`. This type takes care of the encoding and escaping so that arbitrary
+/// keys and values round-trip safely.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExtensionAttrs {
+    attrs: BTreeMap<String, String>,
+}
+
+impl ExtensionAttrs {
+    pub fn new() -> Self {
+        ExtensionAttrs::default()
+    }
+
+    /// Adds an attribute, replacing any previous value of the same key.
+    pub fn with(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.insert(key, value);
+        self
+    }
+
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) -> Option<String> {
+        self.attrs.insert(key.into(), value.into())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.attrs.get(key).map(String::as_str)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.attrs.remove(key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.attrs.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.attrs.is_empty()
+    }
+
+    /// Parses the string encoded form returned by DingTalk.
+    ///
+    /// Non-string values are kept in their JSON representation.
+    pub fn parse(value: &str) -> Result<Self, serde_json::Error> {
+        if value.trim().is_empty() {
+            return Ok(ExtensionAttrs::default());
+        }
+
+        let object: BTreeMap<String, serde_json::Value> = serde_json::from_str(value)?;
+        let attrs = object
+            .into_iter()
+            .map(|(key, value)| match value {
+                serde_json::Value::String(value) => (key, value),
+                value => (key, value.to_string()),
+            })
+            .collect();
+
+        Ok(ExtensionAttrs { attrs })
+    }
+
+    /// Encodes the attributes into the string form expected by DingTalk.
+    pub fn to_json_string(&self) -> String {
+        serde_json::to_string(&self.attrs).unwrap_or_default()
+    }
+}
+
+impl Serialize for ExtensionAttrs {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_json_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for ExtensionAttrs {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Encoded {
+            String(String),
+            Object(BTreeMap<String, String>),
+        }
+
+        match Encoded::deserialize(deserializer)? {
+            Encoded::String(value) => {
+                ExtensionAttrs::parse(&value).map_err(serde::de::Error::custom)
+            }
+            Encoded::Object(attrs) => Ok(ExtensionAttrs { attrs }),
+        }
+    }
+}
+
+impl EmployeeUser {
+    /// Parses the custom attributes of the employee.
+    pub fn extension_attrs(&self) -> Result<ExtensionAttrs, serde_json::Error> {
+        match &self.extension {
+            Some(value) => ExtensionAttrs::parse(value),
+            None => Ok(ExtensionAttrs::default()),
+        }
+    }
+}
+
+/// Parameters of [OrgApp::create_user], built from the required name and mobile number:
+///
+/// ```
+/// use async_dingtalk::{CreateUserRequest, ExtensionAttrs};
+///
+/// let request = CreateUserRequest::new("张三", "13800000000")
+///     .dept_ids(vec![1, 500123456])
+///     .title("技术总监")
+///     .hired_date(1677600000000)
+///     .extension(ExtensionAttrs::new().with("爱好", "旅游"))
+///     .idempotency_key("hr-4");
+/// ```
+///
+/// [Documents](https://open.dingtalk.com/document/orgapp/user-information-creation)
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CreateUserRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub userid: Option<String>,
+    pub name: String,
+    pub mobile: String,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_dept_ids"
+    )]
+    pub dept_id_list: Option<Vec<i64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hide_mobile: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub telephone: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_number: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub org_email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub work_place: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remark: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extension: Option<ExtensionAttrs>,
+    /// The hire date as a millisecond timestamp.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hired_date: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manager_userid: Option<String>,
+    /// A key making retries of [OrgApp::create_user] safe: a request with a key already used
+    /// within 24 hours returns the user created then instead of creating another one.
+    #[serde(skip)]
+    pub idempotency_key: Option<String>,
+}
+
+impl CreateUserRequest {
+    pub fn new(name: impl Into<String>, mobile: impl Into<String>) -> Self {
+        CreateUserRequest {
+            name: name.into(),
+            mobile: mobile.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Sets the user ID instead of letting DingTalk generate one.
+    pub fn userid(mut self, userid: impl Into<String>) -> Self {
+        self.userid = Some(userid.into());
+        self
+    }
+
+    /// Sets the departments of the user, the root department `1` by default.
+    pub fn dept_ids(mut self, dept_ids: Vec<i64>) -> Self {
+        self.dept_id_list = Some(dept_ids);
+        self
+    }
+
+    /// Hides the mobile number from the other members.
+    pub fn hide_mobile(mut self, hide_mobile: bool) -> Self {
+        self.hide_mobile = Some(hide_mobile);
+        self
+    }
+
+    pub fn telephone(mut self, telephone: impl Into<String>) -> Self {
+        self.telephone = Some(telephone.into());
+        self
+    }
+
+    pub fn job_number(mut self, job_number: impl Into<String>) -> Self {
+        self.job_number = Some(job_number.into());
+        self
+    }
+
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    pub fn email(mut self, email: impl Into<String>) -> Self {
+        self.email = Some(email.into());
+        self
+    }
+
+    pub fn org_email(mut self, org_email: impl Into<String>) -> Self {
+        self.org_email = Some(org_email.into());
+        self
+    }
+
+    pub fn work_place(mut self, work_place: impl Into<String>) -> Self {
+        self.work_place = Some(work_place.into());
+        self
+    }
+
+    pub fn remark(mut self, remark: impl Into<String>) -> Self {
+        self.remark = Some(remark.into());
+        self
+    }
+
+    pub fn extension(mut self, extension: ExtensionAttrs) -> Self {
+        self.extension = Some(extension);
+        self
+    }
+
+    /// Sets the hire date as a millisecond timestamp.
+    pub fn hired_date(mut self, hired_date: u64) -> Self {
+        self.hired_date = Some(hired_date);
+        self
+    }
+
+    /// Sets the user ID of the direct manager.
+    pub fn manager(mut self, manager_userid: impl Into<String>) -> Self {
+        self.manager_userid = Some(manager_userid.into());
+        self
+    }
+
+    pub fn idempotency_key(mut self, key: impl Into<String>) -> Self {
+        self.idempotency_key = Some(key.into());
+        self
+    }
+}
+
+/// Parameters of [OrgApp::update_user]. Only fields that are set are sent to DingTalk:
+///
+/// ```
+/// use async_dingtalk::{ExtensionAttrs, UpdateUserRequest};
+///
+/// let request = UpdateUserRequest::new()
+///     .title("架构师")
+///     .dept_ids(vec![500123456])
+///     .extension(ExtensionAttrs::new().with("职级", "P7"));
+/// ```
+///
+/// [Documents](https://open.dingtalk.com/document/orgapp/user-information-update)
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct UpdateUserRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mobile: Option<String>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_dept_ids"
+    )]
+    pub dept_id_list: Option<Vec<i64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hide_mobile: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub telephone: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_number: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub org_email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub work_place: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remark: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extension: Option<ExtensionAttrs>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hired_date: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manager_userid: Option<String>,
+}
+
+impl UpdateUserRequest {
+    pub fn new() -> Self {
+        UpdateUserRequest::default()
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn mobile(mut self, mobile: impl Into<String>) -> Self {
+        self.mobile = Some(mobile.into());
+        self
+    }
+
+    /// Replaces the departments of the user.
+    pub fn dept_ids(mut self, dept_ids: Vec<i64>) -> Self {
+        self.dept_id_list = Some(dept_ids);
+        self
+    }
+
+    pub fn hide_mobile(mut self, hide_mobile: bool) -> Self {
+        self.hide_mobile = Some(hide_mobile);
+        self
+    }
+
+    pub fn telephone(mut self, telephone: impl Into<String>) -> Self {
+        self.telephone = Some(telephone.into());
+        self
+    }
+
+    pub fn job_number(mut self, job_number: impl Into<String>) -> Self {
+        self.job_number = Some(job_number.into());
+        self
+    }
+
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    pub fn email(mut self, email: impl Into<String>) -> Self {
+        self.email = Some(email.into());
+        self
+    }
+
+    pub fn org_email(mut self, org_email: impl Into<String>) -> Self {
+        self.org_email = Some(org_email.into());
+        self
+    }
+
+    pub fn work_place(mut self, work_place: impl Into<String>) -> Self {
+        self.work_place = Some(work_place.into());
+        self
+    }
+
+    pub fn remark(mut self, remark: impl Into<String>) -> Self {
+        self.remark = Some(remark.into());
+        self
+    }
+
+    /// Replaces all custom attributes of the user.
+    pub fn extension(mut self, extension: ExtensionAttrs) -> Self {
+        self.extension = Some(extension);
+        self
+    }
+
+    /// Sets the hire date as a millisecond timestamp.
+    pub fn hired_date(mut self, hired_date: u64) -> Self {
+        self.hired_date = Some(hired_date);
+        self
+    }
+
+    /// Sets the user ID of the direct manager.
+    pub fn manager(mut self, manager_userid: impl Into<String>) -> Self {
+        self.manager_userid = Some(manager_userid.into());
+        self
+    }
+}
+
+impl From<CreateUserRequest> for UpdateUserRequest {
+    fn from(profile: CreateUserRequest) -> Self {
+        UpdateUserRequest {
+            name: Some(profile.name),
+            mobile: Some(profile.mobile),
+            dept_id_list: profile.dept_id_list,
+            hide_mobile: profile.hide_mobile,
+            telephone: profile.telephone,
+            job_number: profile.job_number,
+            title: profile.title,
+            email: profile.email,
+            org_email: profile.org_email,
+            work_place: profile.work_place,
+            remark: profile.remark,
+            extension: profile.extension,
+            hired_date: profile.hired_date,
+            manager_userid: profile.manager_userid,
+        }
+    }
+}
+
+/// A member of a department as listed by [OrgApp::list_department_users_simple].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SimpleUser {
+    #[serde(rename = "userid")]
+    pub user_id: String,
+    pub name: String,
+}
+
+/// The user found by [OrgApp::get_userid_by_unionid].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct UnionIdUser {
+    #[serde(rename = "userid")]
+    pub user_id: String,
+    /// `0` for members of the organization, `1` for external contacts.
+    #[serde(default)]
+    pub contact_type: i64,
+}
+
+impl UnionIdUser {
+    pub fn account_kind(&self) -> AccountKind {
+        if self.contact_type == 1 {
+            AccountKind::External
+        } else {
+            AccountKind::Internal
+        }
+    }
+}
+
+/// An administrator of the organization, see [OrgApp::list_admins].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Admin {
+    #[serde(rename = "userid")]
+    pub user_id: String,
+    /// `1` for the primary administrator, `2` for sub-administrators.
+    pub sys_level: i64,
+}
+
+impl Admin {
+    /// Returns whether the administrator is the primary administrator (主管理员), whose scope is
+    /// the whole organization.
+    pub fn is_primary(&self) -> bool {
+        self.sys_level == 1
+    }
+}
+
+/// The action taken by [OrgApp::upsert_user].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum UpsertOutcome {
+    Created(String),
+    Updated(String),
+}
+
+impl UpsertOutcome {
+    pub fn user_id(&self) -> &str {
+        match self {
+            UpsertOutcome::Created(id) | UpsertOutcome::Updated(id) => id,
+        }
+    }
+}
+
+impl OrgApp {
+    /// Creates a user in the organization.
+    ///
+    /// [Documents](https://open.dingtalk.com/document/orgapp/user-information-creation)
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The profile of the user to create.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the user ID of the new user if successful, otherwise an error. The
+    /// job number is added to the cached [job number index](OrgApp::get_job_number_index).
+    ///
+    /// With an [idempotency key](CreateUserRequest::idempotency_key), a retry after an unanswered
+    /// request looks the user up by mobile number before creating it again, and a retry while the
+    /// first call is still running fails with [DingTalkError::InProgress].
+    pub async fn create_user(&self, request: CreateUserRequest) -> Result<String, DingTalkError> {
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Response {
+            userid: String,
+        }
+
+        let mobile = request.mobile.clone();
+        let job_number = request.job_number.clone();
+        let user_id = self
+            .run_idempotent(
+                "create_user",
+                request.idempotency_key.as_deref(),
+                || self.get_userid_by_mobile(mobile),
+                || async {
+                    let res: Response = self.post_oapi("topapi/v2/user/create", &request).await?;
+                    Ok(res.userid)
+                },
+            )
+            .await?;
+        if let Some(job_number) = job_number {
+            self.update_job_number_index(|index| index.assign(&user_id, &job_number))
+                .await;
+        }
+
+        info!("create_user: {}", user_id);
+
+        Ok(user_id)
+    }
+
+    /// Updates the given fields of a user.
+    ///
+    /// [Documents](https://open.dingtalk.com/document/orgapp/user-information-update)
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user to update.
+    /// * `request` - The fields to update; unset fields are left untouched.
+    ///
+    /// # Returns
+    ///
+    /// An empty `Result` if successful, otherwise an error. The cached profile of the user is
+    /// discarded and a changed job number is applied to the cached
+    /// [job number index](OrgApp::get_job_number_index).
+    pub async fn update_user(
+        &self,
+        user_id: String,
+        request: UpdateUserRequest,
+    ) -> Result<(), DingTalkError> {
+        #[derive(Serialize, Debug)]
+        struct Params {
+            userid: String,
+            #[serde(flatten)]
+            request: UpdateUserRequest,
+        }
+        let job_number = request.job_number.clone();
+        let params = Params {
+            userid: user_id.clone(),
+            request,
+        };
+        self.post_oapi::<serde_json::Value>("topapi/v2/user/update", &params)
+            .await?;
+
+        if let Some(cache) = &self.profile_cache {
+            cache
+                .discard(
+                    &self.key_prefix,
+                    ProfileKind::Employee,
+                    &self.corp_id,
+                    &user_id,
+                )
+                .await;
+        }
+        if let Some(job_number) = job_number {
+            self.update_job_number_index(|index| index.assign(&user_id, &job_number))
+                .await;
+        }
+        info!("update_user: {}", user_id);
+
+        Ok(())
+    }
+
+    /// Deletes a user from the organization.
+    ///
+    /// [删除用户](https://open.dingtalk.com/document/orgapp/delete-a-user)
+    ///
+    /// # Arguments
+    ///
+    /// * `userid` - The user ID of the user to delete.
+    ///
+    /// # Returns
+    ///
+    /// An empty `Result` if successful, otherwise an error. The cached profile of the user is
+    /// discarded and the user is removed from the cached
+    /// [job number index](OrgApp::get_job_number_index).
+    pub async fn delete_user(&self, userid: String) -> Result<(), DingTalkError> {
+        let params = serde_json::json!({ "userid": userid });
+        self.post_oapi::<serde_json::Value>("topapi/v2/user/delete", &params)
+            .await?;
+
+        if let Some(cache) = &self.profile_cache {
+            cache
+                .discard(
+                    &self.key_prefix,
+                    ProfileKind::Employee,
+                    &self.corp_id,
+                    &userid,
+                )
+                .await;
+        }
+        self.update_job_number_index(|index| index.remove_user(&userid))
+            .await;
+        info!("delete_user: {}", userid);
+
+        Ok(())
+    }
+
+    /// Records the departure of an employee in the smart work roster (智能人事) before deleting
+    /// the user, for organizations keeping their employee records there.
+    ///
+    /// # Arguments
+    ///
+    /// * `userid` - The user ID of the user to delete.
+    /// * `dismissal` - The last work day and the reason of the departure.
+    ///
+    /// # Returns
+    ///
+    /// An empty `Result` if successful, otherwise an error. The user is not deleted if the
+    /// departure could not be recorded.
+    pub async fn delete_user_with_dismissal(
+        &self,
+        userid: String,
+        dismissal: &Dismissal,
+    ) -> Result<(), DingTalkError> {
+        self.dismiss_employee(
+            userid.clone(),
+            dismissal.last_work_day,
+            dismissal.reason.clone(),
+        )
+        .await?;
+        self.delete_user(userid).await
+    }
+
+    /// Lists the administrators of the organization.
+    ///
+    /// [获取管理员列表](https://open.dingtalk.com/document/orgapp/query-the-administrator-list)
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the primary administrator and the sub-administrators.
+    pub async fn list_admins(&self) -> Result<Vec<Admin>, DingTalkError> {
+        self.post_oapi("topapi/user/listadmin", &serde_json::json!({}))
+            .await
+    }
+
+    /// Retrieves the departments a sub-administrator manages.
+    ///
+    /// [获取管理员通讯录权限范围](https://open.dingtalk.com/document/orgapp/query-the-scope-of-administrator-permissions)
+    ///
+    /// # Arguments
+    ///
+    /// * `userid` - The user ID of the administrator.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the IDs of the departments; the primary administrator manages the
+    /// root department `1`.
+    pub async fn get_admin_scope(&self, userid: String) -> Result<Vec<i64>, DingTalkError> {
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Response {
+            #[serde(default)]
+            dept_ids: Vec<i64>,
+        }
+
+        let res: Response = self
+            .get_oapi_body("topapi/user/get_admin_scope", &[("userid", &userid)])
+            .await?;
+        Ok(res.dept_ids)
+    }
+
+    /// Lists the employees who did or did not log in to DingTalk on a day.
+    ///
+    /// [获取未登录钉钉的员工列表](https://open.dingtalk.com/document/orgapp/queries-the-inactive-users-or-active-users-under-an-enterprise)
+    ///
+    /// # Arguments
+    ///
+    /// * `date` - The day to report, at most 31 days ago.
+    /// * `dept_ids` - The departments to report, all departments when empty.
+    /// * `is_active` - `false` for the employees who did not log in, `true` for those who did.
+    /// * `offset` - The offset of the page, `0` for the first page.
+    /// * `size` - The page size, at most 100.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing a page of user IDs; the offset of the next page is
+    /// [PageResult::next_offset].
+    pub async fn list_inactive_users(
+        &self,
+        date: NaiveDate,
+        dept_ids: &[i64],
+        is_active: bool,
+        offset: i64,
+        size: i64,
+    ) -> Result<PageResult, DingTalkError> {
+        let mut params = serde_json::json!({
+            "query_date": date.format("%Y%m%d").to_string(),
+            "is_active": is_active,
+            "offset": offset,
+            "size": size,
+        });
+        if !dept_ids.is_empty() {
+            params["dept_ids"] = dept_ids.into();
+        }
+        self.post_oapi("topapi/inactive/user/v2/get", &params).await
+    }
+
+    /// Lists the user IDs and names of the direct members of a department, e.g. for user
+    /// pickers.
+    ///
+    /// [获取部门用户基础信息](https://open.dingtalk.com/document/orgapp/queries-the-simple-information-of-a-department-user)
+    ///
+    /// # Arguments
+    ///
+    /// * `dept_id` - The ID of the department, `1` for the root department.
+    /// * `cursor` - The offset of the page, `0` for the first page.
+    /// * `size` - The page size, at most 100.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the page; the cursor of the next page is
+    /// [PageResult::next_offset].
+    pub async fn list_department_users_simple(
+        &self,
+        dept_id: i64,
+        cursor: i64,
+        size: i64,
+    ) -> Result<PageResult<SimpleUser>, DingTalkError> {
+        let params = serde_json::json!({
+            "dept_id": dept_id,
+            "cursor": cursor,
+            "size": size,
+            "language": self.language,
+        });
+        self.post_oapi("topapi/user/listsimple", &params).await
+    }
+
+    /// Lists the profiles of the direct members of a department.
+    ///
+    /// [获取部门用户详情](https://open.dingtalk.com/document/orgapp/queries-the-complete-information-of-a-department-user)
+    ///
+    /// The entries lack `dept_order_list`, `leader_in_dept` and `role_list`, and omit the other
+    /// fields DingTalk has no value for, which are left empty.
+    ///
+    /// # Arguments
+    ///
+    /// * `dept_id` - The ID of the department, `1` for the root department.
+    /// * `cursor` - The offset of the page, `0` for the first page.
+    /// * `size` - The page size, at most 100.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the page; the cursor of the next page is
+    /// [PageResult::next_offset].
+    pub async fn list_department_users(
+        &self,
+        dept_id: i64,
+        cursor: i64,
+        size: i64,
+    ) -> Result<PageResult<EmployeeUser>, DingTalkError> {
+        self.fetch_department_users(dept_id, cursor, size).await
+    }
+
+    /// Like [OrgApp::list_department_users], deserializing the entries into `T`, e.g. a struct
+    /// with the few fields a caller needs.
+    async fn fetch_department_users<T: DeserializeOwned>(
+        &self,
+        dept_id: i64,
+        cursor: i64,
+        size: i64,
+    ) -> Result<PageResult<T>, DingTalkError> {
+        let params = serde_json::json!({
+            "dept_id": dept_id,
+            "cursor": cursor,
+            "size": size,
+            "language": self.language,
+        });
+        self.post_oapi("topapi/v2/user/list", &params).await
+    }
+
+    /// Streams the profiles of the direct members of a department, requesting the pages of
+    /// [OrgApp::list_department_users] at the [pace](OrgApp::with_pace) of the organization as
+    /// the stream is consumed.
+    pub(crate) fn department_users<T: DeserializeOwned + 'static>(
+        &self,
+        dept_id: i64,
+    ) -> impl Stream<Item = Result<T, DingTalkError>> + '_ {
+        paginate(self.pace, 0, move |cursor| {
+            self.fetch_department_users(dept_id, cursor, MAX_PAGE_SIZE)
+                .map_ok(offset_page)
+        })
+    }
+
+    /// Retrieves the user ID of the user with the given mobile number.
+    ///
+    /// [Documents](https://open.dingtalk.com/document/orgapp/query-users-by-phone-number)
+    ///
+    /// # Arguments
+    ///
+    /// * `mobile` - The mobile number of the user.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the user ID, or `None` if no user in the organization has this mobile
+    /// number.
+    pub async fn get_userid_by_mobile(
+        &self,
+        mobile: String,
+    ) -> Result<Option<String>, DingTalkError> {
+        debug!(mobile = %redact_mobile(&mobile), "looking up user by mobile");
+
+        let mut params = HashMap::new();
+        params.insert("mobile", mobile);
+
+        #[derive(Serialize, Deserialize, Debug)]
+        struct UserId {
+            userid: String,
+        }
+        let res: Result<Option<UserId>, DingTalkError> =
+            self.post_oapi("topapi/v2/user/getbymobile", &params).await;
+
+        match res {
+            Ok(user) => Ok(user.map(|user| user.userid)),
+            Err(e) if e.kind() == Some(ApiErrorKind::UserNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Retrieves the user ID of the user with the given union ID, e.g. from the login flow of
+    /// ISV apps.
+    ///
+    /// [根据unionid获取用户userid](https://open.dingtalk.com/document/orgapp/query-a-user-by-the-union-id)
+    ///
+    /// # Arguments
+    ///
+    /// * `unionid` - The union ID of the user.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the user ID and whether the user is an external contact.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [ApiErrorKind::UserNotFound] if the user is not in the organization.
+    pub async fn get_userid_by_unionid(
+        &self,
+        unionid: String,
+    ) -> Result<UnionIdUser, DingTalkError> {
+        let params = serde_json::json!({ "unionid": unionid });
+        self.post_oapi("topapi/user/getbyunionid", &params).await
+    }
+
+    /// Creates the user, or updates it if it already exists.
+    ///
+    /// The existing user is looked up by mobile number first and by job number second, with
+    /// [OrgApp::get_job_number_index]. To sync many users, build the index once and use
+    /// [OrgApp::upsert_user_with_index] instead.
+    ///
+    /// # Arguments
+    ///
+    /// * `profile` - The desired profile of the user.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing an `UpsertOutcome` telling whether the user was created or updated,
+    /// together with its user ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns [DingTalkError::AmbiguousJobNumber] without changing any user if the job number
+    /// is shared by several employees.
+    pub async fn upsert_user(
+        &self,
+        profile: CreateUserRequest,
+    ) -> Result<UpsertOutcome, DingTalkError> {
+        self.upsert(profile, None).await
+    }
+
+    /// Like [OrgApp::upsert_user], looking users up by job number in the given index, which is
+    /// kept up to date with the created and updated users.
+    ///
+    /// # Arguments
+    ///
+    /// * `profile` - The desired profile of the user.
+    /// * `index` - The index of [OrgApp::get_job_number_index], shared by the upserts of a batch.
+    pub async fn upsert_user_with_index(
+        &self,
+        profile: CreateUserRequest,
+        index: &mut JobNumberIndex,
+    ) -> Result<UpsertOutcome, DingTalkError> {
+        self.upsert(profile, Some(index)).await
+    }
+
+    async fn upsert(
+        &self,
+        profile: CreateUserRequest,
+        index: Option<&mut JobNumberIndex>,
+    ) -> Result<UpsertOutcome, DingTalkError> {
+        let job_number = profile.job_number.clone().filter(|n| !n.is_empty());
+        let mut existing = self.get_userid_by_mobile(profile.mobile.clone()).await?;
+        if existing.is_none() {
+            if let Some(job_number) = &job_number {
+                existing = match index.as_deref() {
+                    Some(index) => index.get(job_number)?.map(str::to_string),
+                    None => self
+                        .get_job_number_index()
+                        .await?
+                        .get(job_number)?
+                        .map(str::to_string),
+                };
+            }
+        }
+
+        let outcome = match existing {
+            Some(user_id) => {
+                self.update_user(user_id.clone(), profile.into()).await?;
+                UpsertOutcome::Updated(user_id)
+            }
+            None => UpsertOutcome::Created(self.create_user(profile).await?),
+        };
+        if let (Some(index), Some(job_number)) = (index, &job_number) {
+            index.assign(outcome.user_id(), job_number);
+        }
+
+        Ok(outcome)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{FailingStore, Mock};
+    use crate::ProfileCache;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    #[test]
+    fn create_requests_send_only_the_set_fields() {
+        let request = CreateUserRequest::new("张三", "13800000000")
+            .dept_ids(vec![1, 500123456])
+            .hide_mobile(true)
+            .hired_date(1677600000000)
+            .idempotency_key("hr-4");
+
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            serde_json::json!({
+                "name": "张三",
+                "mobile": "13800000000",
+                "dept_id_list": "1,500123456",
+                "hide_mobile": true,
+                "hired_date": 1677600000000u64,
+            })
+        );
+    }
+
+    #[test]
+    fn update_requests_send_only_the_set_fields() {
+        let request = UpdateUserRequest::new()
+            .title("架构师")
+            .extension(ExtensionAttrs::new().with("职级", "P7"));
+
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            serde_json::json!({
+                "title": "架构师",
+                "extension": r#"{"职级":"P7"}"#,
+            })
+        );
+    }
+
+    #[test]
+    fn extension_attrs_round_trip() {
+        let attrs = ExtensionAttrs::new()
+            .with("签名", r#"他说"你好""#)
+            .with("路径", r"C:\Users\张三")
+            .with("地址", "第一行\n第二行\t缩进")
+            .with("备注", "")
+            .with("", "空键");
+
+        let encoded = attrs.to_json_string();
+        assert_eq!(ExtensionAttrs::parse(&encoded).unwrap(), attrs);
+
+        // As sent in request bodies and read back from profiles.
+        let value = serde_json::to_value(&attrs).unwrap();
+        assert_eq!(value, json!(encoded));
+        let decoded: ExtensionAttrs = serde_json::from_value(value).unwrap();
+        assert_eq!(decoded, attrs);
+        assert_eq!(decoded.get("签名"), Some(r#"他说"你好""#));
+        assert_eq!(decoded.get("备注"), Some(""));
+    }
+
+    #[test]
+    fn extension_attrs_keep_non_string_values_as_json() {
+        let attrs = ExtensionAttrs::parse(
+            r#"{"工龄":3,"转正":true,"离职":null,"技能":["rust","go"],"薪资":{"币种":"CNY"}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(attrs.get("工龄"), Some("3"));
+        assert_eq!(attrs.get("转正"), Some("true"));
+        assert_eq!(attrs.get("离职"), Some("null"));
+        assert_eq!(attrs.get("技能"), Some(r#"["rust","go"]"#));
+        assert_eq!(attrs.get("薪资"), Some(r#"{"币种":"CNY"}"#));
+        assert_eq!(
+            ExtensionAttrs::parse(&attrs.to_json_string()).unwrap(),
+            attrs
+        );
+    }
+
+    #[test]
+    fn empty_extensions_have_no_attrs() {
+        assert!(ExtensionAttrs::parse("").unwrap().is_empty());
+        assert!(ExtensionAttrs::parse("  ").unwrap().is_empty());
+
+        let empty = ExtensionAttrs::new();
+        assert_eq!(empty.to_json_string(), "{}");
+        assert_eq!(ExtensionAttrs::parse("{}").unwrap(), empty);
+        let decoded: ExtensionAttrs = serde_json::from_value(json!("")).unwrap();
+        assert_eq!(decoded, empty);
+    }
+
+    #[tokio::test]
+    async fn updates_succeed_when_the_cache_fails() {
+        let mock = Mock::new();
+        let cache = Arc::new(ProfileCache::new(Arc::new(FailingStore)));
+        let app = mock.app().await.with_profile_cache(cache);
+
+        app.update_user(
+            "zhangsan".to_string(),
+            UpdateUserRequest::new().title("架构师"),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(mock.bodies("topapi/v2/user/update").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn deletions_succeed_when_the_cache_fails() {
+        let mock = Mock::new();
+        let cache = Arc::new(ProfileCache::new(Arc::new(FailingStore)));
+        let app = mock.app().await.with_profile_cache(cache);
+
+        app.delete_user("wangwu".to_string()).await.unwrap();
+
+        assert_eq!(mock.bodies("topapi/v2/user/delete").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn dismissals_are_recorded_before_deleting_the_user() {
+        let mock = Mock::new();
+        let app = mock.app().await;
+
+        let dismissal = Dismissal {
+            last_work_day: NaiveDate::from_ymd_opt(2024, 3, 31).unwrap(),
+            reason: None,
+        };
+        app.delete_user_with_dismissal("wangwu".to_string(), &dismissal)
+            .await
+            .unwrap();
+
+        let paths: Vec<String> = mock.requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "topapi/smartwork/hrm/employee/dismission",
+                "topapi/v2/user/delete"
+            ]
+        );
+        assert_eq!(
+            mock.bodies("topapi/v2/user/delete"),
+            vec![json!({ "userid": "wangwu" })]
+        );
+    }
+
+    #[tokio::test]
+    async fn lookups_send_their_parameters() {
+        let mock = Mock::new();
+        mock.respond(
+            "topapi/user/getbyunionid",
+            json!({ "errcode": 0, "result": { "contact_type": 1, "userid": "ext-1" } }),
+        );
+        mock.respond(
+            "topapi/user/get_admin_scope",
+            json!({ "errcode": 0, "dept_ids": [2] }),
+        );
+        mock.respond(
+            "topapi/inactive/user/v2/get",
+            json!({ "errcode": 0, "result": { "has_more": false, "list": ["wangwu"] } }),
+        );
+        let app = mock.app().await;
+
+        let user = app
+            .get_userid_by_unionid("union-1".to_string())
+            .await
+            .unwrap();
+        let scope = app.get_admin_scope("lisi".to_string()).await.unwrap();
+        let day = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        let inactive = app
+            .list_inactive_users(day, &[2, 3], false, 0, 100)
+            .await
+            .unwrap();
+
+        assert_eq!(user.account_kind(), AccountKind::External);
+        assert_eq!(
+            mock.bodies("topapi/user/getbyunionid"),
+            vec![json!({ "unionid": "union-1" })]
+        );
+        assert_eq!(scope, vec![2]);
+        let request = mock
+            .requests()
+            .into_iter()
+            .find(|r| r.path == "topapi/user/get_admin_scope")
+            .unwrap();
+        assert_eq!(request.method, reqwest::Method::GET);
+        assert!(request
+            .query
+            .contains(&("userid".to_string(), "lisi".to_string())));
+        assert_eq!(inactive.data, vec!["wangwu"]);
+        assert_eq!(
+            mock.bodies("topapi/inactive/user/v2/get"),
+            vec![json!({
+                "query_date": "20240301",
+                "is_active": false,
+                "offset": 0,
+                "size": 100,
+                "dept_ids": [2, 3],
+            })]
+        );
+    }
+}
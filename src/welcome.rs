@@ -0,0 +1,392 @@
+use crate::notification::{WorkMessage, WorkNotification};
+use crate::recipient::Recipient;
+use crate::{error::DingTalkError, event::Event, organization::OrgApp, template::MessageTemplate};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::info;
+
+/// A task on the onboarding checklist of new employees.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OnboardingTodo {
+    pub subject: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// How long after the welcome the task is due.
+    #[serde(default)]
+    pub due_in: Option<Duration>,
+    /// Who follows the task besides the employee, e.g. their onboarding buddy.
+    #[serde(default)]
+    pub participants: Vec<Recipient>,
+}
+
+/// What [OrgApp::welcome_employee] does for a new employee.
+///
+/// The card is a [MessageTemplate] rendered with `name`, `user_id`, `title` and `job_number` of
+/// the employee and sent as a work notification of `agent_id`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WelcomeAutomation {
+    pub agent_id: i64,
+    pub card: MessageTemplate,
+    #[serde(default)]
+    pub todos: Vec<OnboardingTodo>,
+    /// Adds the employee to the group chats of their departments which do not add new members
+    /// on their own (`auto_add_user`).
+    #[serde(default)]
+    pub join_department_groups: bool,
+}
+
+/// What was done for a new employee.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WelcomeOutcome {
+    pub user_id: String,
+    /// The department group chats the employee was added to.
+    pub joined_chats: Vec<String>,
+    /// The task ID of the work notification with the card.
+    pub card_task_id: i64,
+    /// The IDs of the created todos.
+    pub todo_ids: Vec<String>,
+}
+
+impl OrgApp {
+    /// Welcomes the employees who joined with an event: `user_add_org` or the smart work
+    /// `hrm_user_entry`. Other events are ignored.
+    ///
+    /// Fits an [crate::EventDispatcher] handler:
+    ///
+    /// ```no_run
+    /// # fn handler(app: async_dingtalk::OrgApp, automation: async_dingtalk::WelcomeAutomation) {
+    /// use async_dingtalk::{Event, EventDispatcher};
+    /// use std::sync::Arc;
+    ///
+    /// let (app, automation) = (Arc::new(app), Arc::new(automation));
+    /// let dispatcher = EventDispatcher::new().on("user_add_org", move |event: Event| {
+    ///     let (app, automation) = (app.clone(), automation.clone());
+    ///     async move { app.welcome_new_employees(&automation, &event).await.map(|_| ()) }
+    /// });
+    /// # }
+    /// ```
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the outcome for each employee, or the first error. The steps are
+    /// run once per event, so an event delivered again, e.g. after an error, only does what was
+    /// not done yet.
+    pub async fn welcome_new_employees(
+        &self,
+        automation: &WelcomeAutomation,
+        event: &Event,
+    ) -> Result<Vec<WelcomeOutcome>, DingTalkError> {
+        let (user_ids, time_stamp) = match event {
+            Event::UserAddOrg(e) => (&e.user_ids, e.time_stamp),
+            Event::HrmUserEntry(e) => (&e.user_ids, e.time_stamp),
+            _ => return Ok(Vec::new()),
+        };
+
+        let mut outcomes = Vec::new();
+        for user_id in user_ids {
+            // Callbacks carry no event ID, but a redelivered event keeps its time stamp.
+            let key = (time_stamp != 0)
+                .then(|| format!("{}:{}:{}", event.event_type(), time_stamp, user_id));
+            outcomes.push(self.welcome(automation, user_id, key.as_deref()).await?);
+        }
+        Ok(outcomes)
+    }
+
+    /// Welcomes a new employee: adds them to the group chats of their departments, sends the
+    /// welcome card and creates their onboarding todos.
+    ///
+    /// [修改群会话](https://open.dingtalk.com/document/orgapp/modify-a-group-session)
+    /// [创建待办](https://open.dingtalk.com/document/orgapp/add-dingtalk-to-do-task)
+    ///
+    /// # Arguments
+    ///
+    /// * `automation` - What to do.
+    /// * `user_id` - The user ID of the new employee.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing what was done, or the first error. Steps done before an error are
+    /// not undone.
+    pub async fn welcome_employee(
+        &self,
+        automation: &WelcomeAutomation,
+        user_id: &str,
+    ) -> Result<WelcomeOutcome, DingTalkError> {
+        self.welcome(automation, user_id, None).await
+    }
+
+    /// Welcomes a new employee, running each step at most once per `key`.
+    async fn welcome(
+        &self,
+        automation: &WelcomeAutomation,
+        user_id: &str,
+        key: Option<&str>,
+    ) -> Result<WelcomeOutcome, DingTalkError> {
+        let employee = self.get_employee_userinfo(user_id.to_string()).await?;
+        let step_key = |step: &str| key.map(|key| format!("{}:{}", key, step));
+
+        let mut joined_chats = Vec::new();
+        if automation.join_department_groups {
+            let dept_ids: Vec<i64> = employee.dept_id_list.iter().map(|id| *id as i64).collect();
+            for dept in self.get_departments(&dept_ids).await?.values() {
+                if let (Some(chat_id), false) = (&dept.dept_group_chat_id, dept.auto_add_user) {
+                    self.run_idempotent(
+                        "welcome_chat",
+                        step_key(chat_id).as_deref(),
+                        || async { Ok(None) },
+                        || self.add_chat_members(chat_id, user_id),
+                    )
+                    .await?;
+                    joined_chats.push(chat_id.clone());
+                }
+            }
+        }
+
+        let mut context = HashMap::new();
+        context.insert("name".to_string(), employee.username.clone());
+        context.insert("user_id".to_string(), employee.user_id.clone());
+        context.insert("title".to_string(), employee.title.clone());
+        context.insert("job_number".to_string(), employee.job_number.clone());
+        let card = WorkMessage::from(automation.card.render(&context)?);
+        let notification =
+            WorkNotification::to_users(automation.agent_id, vec![user_id.to_string()], card);
+        let card_task_id = self
+            .run_idempotent(
+                "welcome_card",
+                key,
+                || async { Ok(None) },
+                || async {
+                    // A single user is always sent in one batch.
+                    Ok(self.send_work_notification(&notification).await?[0])
+                },
+            )
+            .await?;
+
+        let executors = [Recipient::UnionIds(vec![employee.union_id.clone()])];
+        let mut todo_ids = Vec::new();
+        for (i, todo) in automation.todos.iter().enumerate() {
+            let todo_id = self
+                .run_idempotent(
+                    "welcome_todo",
+                    step_key(&i.to_string()).as_deref(),
+                    || async { Ok(None) },
+                    || self.create_todo(&employee.union_id, &executors, todo),
+                )
+                .await?;
+            todo_ids.push(todo_id);
+        }
+
+        info!(
+            user_id = %user_id,
+            chats = joined_chats.len(),
+            todos = todo_ids.len(),
+            "welcomed employee"
+        );
+
+        Ok(WelcomeOutcome {
+            user_id: user_id.to_string(),
+            joined_chats,
+            card_task_id,
+            todo_ids,
+        })
+    }
+
+    async fn add_chat_members(&self, chat_id: &str, user_id: &str) -> Result<(), DingTalkError> {
+        let params = serde_json::json!({ "chatid": chat_id, "add_useridlist": [user_id] });
+        self.post_oapi_body::<serde_json::Value>("chat/update", &params)
+            .await?;
+        Ok(())
+    }
+
+    /// Creates a todo in the name of the user with `creator_union_id`.
+    async fn create_todo(
+        &self,
+        creator_union_id: &str,
+        executors: &[Recipient],
+        todo: &OnboardingTodo,
+    ) -> Result<String, DingTalkError> {
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Response {
+            id: String,
+        }
+
+        let mut body = serde_json::json!({
+            "subject": todo.subject,
+            "executorIds": self.resolve_union_ids(executors).await?,
+        });
+        if !todo.participants.is_empty() {
+            body["participantIds"] = self.resolve_union_ids(&todo.participants).await?.into();
+        }
+        if let Some(description) = &todo.description {
+            body["description"] = description.clone().into();
+        }
+        if let Some(due_in) = todo.due_in {
+            let due = chrono::Utc::now()
+                + chrono::Duration::from_std(due_in).unwrap_or_else(|_| chrono::Duration::zero());
+            body["dueTime"] = due.timestamp_millis().into();
+        }
+
+        let res: Response = self
+            .request(
+                reqwest::Method::POST,
+                &format!("v1.0/todo/users/{}/tasks", creator_union_id),
+                &[],
+                Some(&body),
+            )
+            .await?;
+
+        Ok(res.id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::UserEvent;
+    use crate::{fixtures, mock::Mock};
+    use serde_json::{json, Value};
+
+    fn automation() -> WelcomeAutomation {
+        WelcomeAutomation {
+            agent_id: 1,
+            card: MessageTemplate::Markdown {
+                title: "欢迎".to_string(),
+                text: "欢迎 {{name}}".to_string(),
+            },
+            todos: vec![OnboardingTodo {
+                subject: "入职培训".to_string(),
+                description: None,
+                due_in: None,
+                participants: vec![Recipient::Users(vec!["manager4220".to_string()])],
+            }],
+            join_department_groups: true,
+        }
+    }
+
+    fn event(time_stamp: i64) -> Event {
+        Event::UserAddOrg(UserEvent {
+            corp_id: Some("corp".to_string()),
+            user_ids: vec!["zhangsan".to_string()],
+            time_stamp,
+        })
+    }
+
+    async fn mock_app() -> (std::sync::Arc<Mock>, OrgApp) {
+        mock_app_with_todos(&[json!({ "id": "todo-1" })]).await
+    }
+
+    async fn mock_app_with_todos(todo_responses: &[Value]) -> (std::sync::Arc<Mock>, OrgApp) {
+        let mock = Mock::new();
+        let mut employee: Value = serde_json::from_str(fixtures::EMPLOYEE_USER).unwrap();
+        employee["dept_id_list"] = json!([500123456]);
+        mock.respond(
+            "topapi/v2/user/get",
+            json!({ "errcode": 0, "result": employee }),
+        );
+        let mut dept: Value = serde_json::from_str(fixtures::DEPT_DETAIL).unwrap();
+        dept["auto_add_user"] = false.into();
+        mock.respond(
+            "topapi/v2/department/get",
+            json!({ "errcode": 0, "result": dept }),
+        );
+        mock.respond(
+            "topapi/message/corpconversation/asyncsend_v2",
+            json!({ "errcode": 0, "task_id": 7 }),
+        );
+        let todos = format!(
+            "v1.0/todo/users/{}/tasks",
+            employee["unionid"].as_str().unwrap()
+        );
+        for response in todo_responses {
+            mock.respond(&todos, response.clone());
+        }
+        let app = mock.app().await;
+        (mock, app)
+    }
+
+    fn count(mock: &Mock, prefix: &str) -> usize {
+        mock.requests()
+            .iter()
+            .filter(|request| request.path.starts_with(prefix))
+            .count()
+    }
+
+    #[tokio::test]
+    async fn redelivered_events_welcome_once() {
+        let (mock, app) = mock_app().await;
+        let automation = automation();
+
+        let first = app
+            .welcome_new_employees(&automation, &event(1700000000000))
+            .await
+            .unwrap();
+        let second = app
+            .welcome_new_employees(&automation, &event(1700000000000))
+            .await
+            .unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(first[0].joined_chats, vec!["chat3a7fxxxxxxxxxxxxxxxx"]);
+        assert_eq!(first[0].card_task_id, 7);
+        assert_eq!(first[0].todo_ids, vec!["todo-1"]);
+        assert_eq!(count(&mock, "chat/update"), 1);
+        assert_eq!(count(&mock, "topapi/message/corpconversation"), 1);
+        assert_eq!(count(&mock, "v1.0/todo"), 1);
+        let todo = mock
+            .requests()
+            .into_iter()
+            .find(|request| request.path.starts_with("v1.0/todo"))
+            .unwrap();
+        let union_id = fixtures::employee_user().union_id;
+        assert_eq!(
+            todo.body,
+            json!({
+                "subject": "入职培训",
+                "executorIds": [union_id],
+                // The participant resolves to the same sample profile.
+                "participantIds": [union_id],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn other_events_welcome_again() {
+        let (mock, app) = mock_app().await;
+        let automation = automation();
+
+        for time_stamp in [1700000000000, 1700000001000] {
+            app.welcome_new_employees(&automation, &event(time_stamp))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(count(&mock, "chat/update"), 2);
+        assert_eq!(count(&mock, "topapi/message/corpconversation"), 2);
+        assert_eq!(count(&mock, "v1.0/todo"), 2);
+    }
+
+    #[tokio::test]
+    async fn redelivered_events_finish_failed_welcomes() {
+        let (mock, app) = mock_app_with_todos(&[
+            json!({ "errcode": 500, "errmsg": "系统繁忙" }),
+            json!({ "id": "todo-1" }),
+        ])
+        .await;
+        let automation = automation();
+
+        assert!(app
+            .welcome_new_employees(&automation, &event(1700000000000))
+            .await
+            .is_err());
+        let outcomes = app
+            .welcome_new_employees(&automation, &event(1700000000000))
+            .await
+            .unwrap();
+
+        assert_eq!(outcomes[0].todo_ids, vec!["todo-1"]);
+        assert_eq!(count(&mock, "chat/update"), 1);
+        assert_eq!(count(&mock, "topapi/message/corpconversation"), 1);
+        assert_eq!(count(&mock, "v1.0/todo"), 2);
+    }
+}